@@ -0,0 +1,218 @@
+//! Long-lived, user-managed API tokens that can be used in place of a session key on the cargo
+//! API, so machines such as CI runners can publish crates without needing an SSH key to clone
+//! the index with first.
+//!
+//! Only a SHA-256 hash of each token is stored, so the token itself is only ever shown to the
+//! user once when it's created.
+
+use super::{
+    audit::{record_blocking, Actor, AuditAction, NewAuditEvent},
+    schema::{user_api_tokens, users},
+    scopes::TokenScope,
+    users::User,
+    uuid::SqlUuid,
+    ConnectionPool, Error, Result,
+};
+use chrono::{NaiveDateTime, Utc};
+use diesel::{insert_into, prelude::*, Associations, Identifiable, Queryable};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+/// Prefix given to every token, so they're easily identifiable by secret scanners if they're
+/// accidentally committed somewhere.
+pub const TOKEN_PREFIX: &str = "chartered_";
+
+/// Number of random characters following the prefix.
+const TOKEN_LENGTH: usize = 48;
+
+/// Hashes a token for storage or lookup.
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[derive(Identifiable, Queryable, Associations, PartialEq, Eq, Hash, Debug)]
+#[belongs_to(User)]
+pub struct UserApiToken {
+    pub id: i32,
+    pub uuid: SqlUuid,
+    pub user_id: i32,
+    pub name: String,
+    pub token_hash: String,
+    pub expires_at: Option<NaiveDateTime>,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub scope: Option<TokenScope>,
+}
+
+impl UserApiToken {
+    /// The restrictions placed on what this token can do, tokens without a scope can do
+    /// anything the user can.
+    #[must_use]
+    pub fn scope(&self) -> TokenScope {
+        self.scope.clone().unwrap_or_default()
+    }
+
+    /// Whether the given key looks like an API token rather than a session key.
+    #[must_use]
+    pub fn is_api_token(key: &str) -> bool {
+        key.starts_with(TOKEN_PREFIX)
+    }
+
+    /// Finds the unexpired token and the user that owns it, bumping the token's `last_used_at`.
+    pub async fn find_by_token(
+        conn: ConnectionPool,
+        given_token: String,
+    ) -> Result<Option<(UserApiToken, User)>> {
+        use crate::schema::user_api_tokens::dsl::{expires_at, id, last_used_at, token_hash};
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+            let now = Utc::now().naive_utc();
+
+            let found: Option<(UserApiToken, User)> = user_api_tokens::table
+                .filter(expires_at.is_null().or(expires_at.gt(now)))
+                .filter(token_hash.eq(hash_token(&given_token)))
+                .inner_join(users::table)
+                .select((user_api_tokens::all_columns, users::all_columns))
+                .get_result(&conn)
+                .optional()?;
+
+            if let Some((token, _)) = &found {
+                diesel::update(user_api_tokens::table.filter(id.eq(token.id)))
+                    .set(last_used_at.eq(now))
+                    .execute(&conn)?;
+            }
+
+            Ok(found)
+        })
+        .await?
+    }
+
+    /// Lists all the tokens belonging to the user, including expired ones so the user can see
+    /// why a machine might have stopped working.
+    pub async fn list(conn: ConnectionPool, user: Arc<User>) -> Result<Vec<UserApiToken>> {
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            Ok(UserApiToken::belonging_to(&*user)
+                .order_by(user_api_tokens::created_at.desc())
+                .load(&conn)?)
+        })
+        .await?
+    }
+
+    /// Generates a new token for the user, returning the token itself alongside the stored
+    /// record. The token can't be retrieved again after this.
+    pub async fn generate(
+        conn: ConnectionPool,
+        user: Arc<User>,
+        actor: &Actor,
+        given_name: String,
+        given_expires_at: Option<NaiveDateTime>,
+        given_scope: Option<TokenScope>,
+    ) -> Result<(String, UserApiToken)> {
+        use crate::schema::user_api_tokens::dsl::{
+            expires_at, name, scope, token_hash, user_id, uuid,
+        };
+
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            let token: String = TOKEN_PREFIX
+                .chars()
+                .chain(
+                    thread_rng()
+                        .sample_iter(&Alphanumeric)
+                        .take(TOKEN_LENGTH)
+                        .map(char::from),
+                )
+                .collect();
+
+            let inserted = conn.transaction::<_, Error, _>(|| {
+                let generated_uuid = SqlUuid::random();
+
+                insert_into(user_api_tokens::table)
+                    .values((
+                        uuid.eq(generated_uuid),
+                        user_id.eq(user.id),
+                        name.eq(given_name),
+                        token_hash.eq(hash_token(&token)),
+                        expires_at.eq(given_expires_at),
+                        scope.eq(given_scope),
+                    ))
+                    .execute(&conn)?;
+
+                let inserted: UserApiToken = user_api_tokens::table
+                    .filter(uuid.eq(generated_uuid))
+                    .get_result(&conn)?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::ApiTokenInsert)
+                        .target_user(user.id)
+                        .api_token(inserted.uuid.0),
+                )?;
+
+                Ok(inserted)
+            })?;
+
+            Ok((token, inserted))
+        })
+        .await?
+    }
+
+    /// Revokes one of the user's tokens, returning `false` if the user has no such token.
+    pub async fn delete_by_uuid(
+        conn: ConnectionPool,
+        user: Arc<User>,
+        actor: &Actor,
+        given_uuid: uuid::Uuid,
+    ) -> Result<bool> {
+        use crate::schema::user_api_tokens::dsl::{user_id, uuid};
+
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let deleted = diesel::delete(
+                    user_api_tokens::table
+                        .filter(user_id.eq(user.id))
+                        .filter(uuid.eq(SqlUuid(given_uuid))),
+                )
+                .execute(&conn)?;
+
+                if deleted == 0 {
+                    return Ok(false);
+                }
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::ApiTokenDelete)
+                        .target_user(user.id)
+                        .api_token(given_uuid),
+                )?;
+
+                Ok(true)
+            })
+        })
+        .await?
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn hash_token() {
+        assert_eq!(
+            super::hash_token("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
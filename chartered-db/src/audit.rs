@@ -0,0 +1,615 @@
+//! Records security-relevant actions so they can later be reviewed by organisation
+//! administrators, answering questions like "who yanked this version?" or "who gave this
+//! user publish rights?".
+
+use super::{
+    permissions::UserPermission,
+    schema::{audit_events, users},
+    users::User,
+    uuid::SqlUuid,
+    ConnectionPool, Result,
+};
+use chrono::NaiveDateTime;
+use diesel::serialize::ToSql;
+use diesel::{insert_into, prelude::*, sql_types::Text, Identifiable, Queryable};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, io::Write};
+
+/// The user, IP address and user agent responsible for an action.
+#[derive(Debug, Clone, Default)]
+pub struct Actor {
+    pub user_id: Option<i32>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl Actor {
+    /// Actions taken by chartered itself rather than a user, such as background cleanup tasks.
+    #[must_use]
+    pub fn system() -> Self {
+        Self::default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromSqlRow, AsExpression)]
+#[sql_type = "Text"]
+pub enum AuditAction {
+    PublishVersion,
+    YankVersion,
+    UnyankVersion,
+    CrateMemberInsert,
+    CrateMemberUpdate,
+    CrateMemberDelete,
+    OrganisationMemberInsert,
+    OrganisationMemberUpdate,
+    OrganisationMemberDelete,
+    SshKeyInsert,
+    SshKeyDelete,
+    Login,
+    SessionDelete,
+    ApiTokenInsert,
+    ApiTokenDelete,
+    CargoKeyInsert,
+    CargoKeyDelete,
+    TrustedPublisherInsert,
+    TrustedPublisherDelete,
+    GroupInsert,
+    GroupUpdate,
+    GroupDelete,
+    GroupMemberInsert,
+    GroupMemberDelete,
+    CrateGroupUpdate,
+    CrateGroupDelete,
+    InviteInsert,
+    InviteDelete,
+    InviteAccept,
+    InviteDecline,
+    OrganisationUpdate,
+    OrganisationRename,
+    OrganisationDelete,
+    OrganisationRestore,
+    CrateMove,
+    VersionDelete,
+    CrateDelete,
+    OrganisationMemberExpire,
+    CrateMemberExpire,
+    UserDisable,
+    UserEnable,
+    UserLock,
+    TwoFactorEnable,
+    TwoFactorDisable,
+    RecoveryCodeUse,
+    RecoveryCodesRegenerate,
+    PasskeyInsert,
+    PasskeyDelete,
+    AdminGrant,
+    AdminRevoke,
+    SessionRevokeAll,
+    SshKeyRevokeAll,
+    OrganisationOwnerGrant,
+}
+
+impl AuditAction {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::PublishVersion => "publish_version",
+            Self::YankVersion => "yank_version",
+            Self::UnyankVersion => "unyank_version",
+            Self::CrateMemberInsert => "crate_member_insert",
+            Self::CrateMemberUpdate => "crate_member_update",
+            Self::CrateMemberDelete => "crate_member_delete",
+            Self::OrganisationMemberInsert => "organisation_member_insert",
+            Self::OrganisationMemberUpdate => "organisation_member_update",
+            Self::OrganisationMemberDelete => "organisation_member_delete",
+            Self::SshKeyInsert => "ssh_key_insert",
+            Self::SshKeyDelete => "ssh_key_delete",
+            Self::Login => "login",
+            Self::SessionDelete => "session_delete",
+            Self::ApiTokenInsert => "api_token_insert",
+            Self::ApiTokenDelete => "api_token_delete",
+            Self::CargoKeyInsert => "cargo_key_insert",
+            Self::CargoKeyDelete => "cargo_key_delete",
+            Self::TrustedPublisherInsert => "trusted_publisher_insert",
+            Self::TrustedPublisherDelete => "trusted_publisher_delete",
+            Self::GroupInsert => "group_insert",
+            Self::GroupUpdate => "group_update",
+            Self::GroupDelete => "group_delete",
+            Self::GroupMemberInsert => "group_member_insert",
+            Self::GroupMemberDelete => "group_member_delete",
+            Self::CrateGroupUpdate => "crate_group_update",
+            Self::CrateGroupDelete => "crate_group_delete",
+            Self::InviteInsert => "invite_insert",
+            Self::InviteDelete => "invite_delete",
+            Self::InviteAccept => "invite_accept",
+            Self::InviteDecline => "invite_decline",
+            Self::OrganisationUpdate => "organisation_update",
+            Self::OrganisationRename => "organisation_rename",
+            Self::OrganisationDelete => "organisation_delete",
+            Self::OrganisationRestore => "organisation_restore",
+            Self::CrateMove => "crate_move",
+            Self::VersionDelete => "version_delete",
+            Self::CrateDelete => "crate_delete",
+            Self::OrganisationMemberExpire => "organisation_member_expire",
+            Self::CrateMemberExpire => "crate_member_expire",
+            Self::UserDisable => "user_disable",
+            Self::UserEnable => "user_enable",
+            Self::UserLock => "user_lock",
+            Self::TwoFactorEnable => "two_factor_enable",
+            Self::TwoFactorDisable => "two_factor_disable",
+            Self::RecoveryCodeUse => "recovery_code_use",
+            Self::RecoveryCodesRegenerate => "recovery_codes_regenerate",
+            Self::PasskeyInsert => "passkey_insert",
+            Self::PasskeyDelete => "passkey_delete",
+            Self::AdminGrant => "admin_grant",
+            Self::AdminRevoke => "admin_revoke",
+            Self::SessionRevokeAll => "session_revoke_all",
+            Self::SshKeyRevokeAll => "ssh_key_revoke_all",
+            Self::OrganisationOwnerGrant => "organisation_owner_grant",
+        }
+    }
+}
+
+impl std::str::FromStr for AuditAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "publish_version" => Self::PublishVersion,
+            "yank_version" => Self::YankVersion,
+            "unyank_version" => Self::UnyankVersion,
+            "crate_member_insert" => Self::CrateMemberInsert,
+            "crate_member_update" => Self::CrateMemberUpdate,
+            "crate_member_delete" => Self::CrateMemberDelete,
+            "organisation_member_insert" => Self::OrganisationMemberInsert,
+            "organisation_member_update" => Self::OrganisationMemberUpdate,
+            "organisation_member_delete" => Self::OrganisationMemberDelete,
+            "ssh_key_insert" => Self::SshKeyInsert,
+            "ssh_key_delete" => Self::SshKeyDelete,
+            "login" => Self::Login,
+            "session_delete" => Self::SessionDelete,
+            "api_token_insert" => Self::ApiTokenInsert,
+            "api_token_delete" => Self::ApiTokenDelete,
+            "cargo_key_insert" => Self::CargoKeyInsert,
+            "cargo_key_delete" => Self::CargoKeyDelete,
+            "trusted_publisher_insert" => Self::TrustedPublisherInsert,
+            "trusted_publisher_delete" => Self::TrustedPublisherDelete,
+            "group_insert" => Self::GroupInsert,
+            "group_update" => Self::GroupUpdate,
+            "group_delete" => Self::GroupDelete,
+            "group_member_insert" => Self::GroupMemberInsert,
+            "group_member_delete" => Self::GroupMemberDelete,
+            "crate_group_update" => Self::CrateGroupUpdate,
+            "crate_group_delete" => Self::CrateGroupDelete,
+            "invite_insert" => Self::InviteInsert,
+            "invite_delete" => Self::InviteDelete,
+            "invite_accept" => Self::InviteAccept,
+            "invite_decline" => Self::InviteDecline,
+            "organisation_update" => Self::OrganisationUpdate,
+            "organisation_rename" => Self::OrganisationRename,
+            "organisation_delete" => Self::OrganisationDelete,
+            "organisation_restore" => Self::OrganisationRestore,
+            "crate_move" => Self::CrateMove,
+            "version_delete" => Self::VersionDelete,
+            "crate_delete" => Self::CrateDelete,
+            "organisation_member_expire" => Self::OrganisationMemberExpire,
+            "crate_member_expire" => Self::CrateMemberExpire,
+            "user_disable" => Self::UserDisable,
+            "user_enable" => Self::UserEnable,
+            "user_lock" => Self::UserLock,
+            "two_factor_enable" => Self::TwoFactorEnable,
+            "two_factor_disable" => Self::TwoFactorDisable,
+            "recovery_code_use" => Self::RecoveryCodeUse,
+            "recovery_codes_regenerate" => Self::RecoveryCodesRegenerate,
+            "passkey_insert" => Self::PasskeyInsert,
+            "passkey_delete" => Self::PasskeyDelete,
+            "admin_grant" => Self::AdminGrant,
+            "admin_revoke" => Self::AdminRevoke,
+            "session_revoke_all" => Self::SessionRevokeAll,
+            "ssh_key_revoke_all" => Self::SshKeyRevokeAll,
+            "organisation_owner_grant" => Self::OrganisationOwnerGrant,
+            _ => return Err(format!("unknown audit action `{}`", s)),
+        })
+    }
+}
+
+impl std::fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for AuditAction {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<B: diesel::backend::Backend> diesel::deserialize::FromSql<Text, B> for AuditAction
+where
+    String: diesel::deserialize::FromSql<Text, B>,
+{
+    fn from_sql(bytes: Option<&B::RawValue>) -> diesel::deserialize::Result<Self> {
+        String::from_sql(bytes)?.parse().map_err(Into::into)
+    }
+}
+
+impl<B: diesel::backend::Backend> diesel::serialize::ToSql<Text, B> for AuditAction
+where
+    str: diesel::serialize::ToSql<Text, B>,
+{
+    fn to_sql<W: Write>(
+        &self,
+        out: &mut diesel::serialize::Output<'_, W, B>,
+    ) -> diesel::serialize::Result {
+        self.as_str().to_sql(out)
+    }
+}
+
+/// Any extra context about the action, names are stored rather than ids so the details still
+/// make sense if the crate or key they refer to is later removed.
+#[derive(
+    Serialize, Deserialize, FromSqlRow, AsExpression, Debug, Clone, Default, PartialEq, Eq,
+)]
+#[sql_type = "diesel::sql_types::Blob"]
+pub struct AuditDetails {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crate_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<UserPermission>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh_key_fingerprint: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_uuid: Option<uuid::Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_token_uuid: Option<uuid::Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passkey_uuid: Option<uuid::Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cargo_key_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trusted_publisher: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invite_uuid: Option<uuid::Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_organisation: Option<String>,
+}
+
+derive_diesel_json!(AuditDetails);
+
+/// An action that's about to be recorded to the audit log.
+#[derive(Debug, Clone)]
+pub struct NewAuditEvent {
+    pub action: AuditAction,
+    pub organisation_id: Option<i32>,
+    pub crate_id: Option<i32>,
+    pub target_user_id: Option<i32>,
+    pub details: AuditDetails,
+}
+
+impl NewAuditEvent {
+    #[must_use]
+    pub fn new(action: AuditAction) -> Self {
+        Self {
+            action,
+            organisation_id: None,
+            crate_id: None,
+            target_user_id: None,
+            details: AuditDetails::default(),
+        }
+    }
+
+    /// Attaches the event to the given crate, and the organisation that owns it.
+    #[must_use]
+    pub fn for_crate(mut self, crate_: &crate::crates::Crate) -> Self {
+        self.organisation_id = Some(crate_.organisation_id);
+        self.crate_id = Some(crate_.id);
+        self.details.crate_name = Some(crate_.name.clone());
+        self
+    }
+
+    #[must_use]
+    pub fn for_organisation(mut self, organisation_id: i32) -> Self {
+        self.organisation_id = Some(organisation_id);
+        self
+    }
+
+    #[must_use]
+    pub fn target_user(mut self, user_id: i32) -> Self {
+        self.target_user_id = Some(user_id);
+        self
+    }
+
+    #[must_use]
+    pub fn version(mut self, version: String) -> Self {
+        self.details.version = Some(version);
+        self
+    }
+
+    #[must_use]
+    pub fn permissions(mut self, permissions: UserPermission) -> Self {
+        self.details.permissions = Some(permissions);
+        self
+    }
+
+    #[must_use]
+    pub fn ssh_key_fingerprint(mut self, fingerprint: String) -> Self {
+        self.details.ssh_key_fingerprint = Some(fingerprint);
+        self
+    }
+
+    #[must_use]
+    pub fn session(mut self, session_uuid: uuid::Uuid) -> Self {
+        self.details.session_uuid = Some(session_uuid);
+        self
+    }
+
+    #[must_use]
+    pub fn api_token(mut self, api_token_uuid: uuid::Uuid) -> Self {
+        self.details.api_token_uuid = Some(api_token_uuid);
+        self
+    }
+
+    #[must_use]
+    pub fn passkey(mut self, passkey_uuid: uuid::Uuid) -> Self {
+        self.details.passkey_uuid = Some(passkey_uuid);
+        self
+    }
+
+    /// The PASERK id of the public key used for cargo's asymmetric tokens.
+    #[must_use]
+    pub fn cargo_key_id(mut self, key_id: String) -> Self {
+        self.details.cargo_key_id = Some(key_id);
+        self
+    }
+
+    /// The CI provider and repository a trusted publishing policy was created for, ie.
+    /// `github:my-org/my-repo`.
+    #[must_use]
+    pub fn trusted_publisher(mut self, provider: &str, repository: &str) -> Self {
+        self.details.trusted_publisher = Some(format!("{}:{}", provider, repository));
+        self
+    }
+
+    #[must_use]
+    pub fn group(mut self, name: String) -> Self {
+        self.details.group_name = Some(name);
+        self
+    }
+
+    #[must_use]
+    pub fn invite(mut self, uuid: uuid::Uuid) -> Self {
+        self.details.invite_uuid = Some(uuid);
+        self
+    }
+
+    /// The name the organisation was known by before it was renamed.
+    #[must_use]
+    pub fn previous_name(mut self, name: String) -> Self {
+        self.details.previous_name = Some(name);
+        self
+    }
+
+    /// The name of the organisation a crate was moved out of.
+    #[must_use]
+    pub fn previous_organisation(mut self, name: String) -> Self {
+        self.details.previous_organisation = Some(name);
+        self
+    }
+}
+
+#[derive(Identifiable, Queryable, PartialEq, Eq, Debug)]
+pub struct AuditEvent {
+    pub id: i32,
+    pub uuid: SqlUuid,
+    pub action: AuditAction,
+    pub actor_user_id: Option<i32>,
+    pub target_user_id: Option<i32>,
+    pub organisation_id: Option<i32>,
+    pub crate_id: Option<i32>,
+    pub details: AuditDetails,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+/// A single page of the audit log.
+#[derive(Debug)]
+pub struct AuditLog {
+    pub events: Vec<AuditEvent>,
+    /// Every user referenced by the events on this page, keyed by their id.
+    pub users: HashMap<i32, User>,
+    /// Total number of events across every page.
+    pub total: i64,
+}
+
+impl AuditEvent {
+    /// Fetches a page of the events the user either performed or was the target of, newest
+    /// first. Unlike an organisation's audit log this includes events that don't belong to any
+    /// organisation, such as logins and changes to the user's SSH keys.
+    pub async fn list_for_user(
+        conn: ConnectionPool,
+        given_user_id: i32,
+        page: i64,
+        per_page: i64,
+    ) -> Result<AuditLog> {
+        use crate::schema::audit_events::dsl::{actor_user_id, created_at, id, target_user_id};
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            let total = audit_events::table
+                .filter(
+                    actor_user_id
+                        .eq(given_user_id)
+                        .or(target_user_id.eq(given_user_id)),
+                )
+                .count()
+                .get_result(&conn)?;
+
+            let events = audit_events::table
+                .filter(
+                    actor_user_id
+                        .eq(given_user_id)
+                        .or(target_user_id.eq(given_user_id)),
+                )
+                .order_by((created_at.desc(), id.desc()))
+                .limit(per_page)
+                .offset(page.saturating_sub(1) * per_page)
+                .load(&conn)?;
+
+            audit_log_blocking(&conn, events, total)
+        })
+        .await?
+    }
+
+    /// Fetches a page of every event on the instance, newest first, for instance
+    /// administrators.
+    pub async fn list_all(conn: ConnectionPool, page: i64, per_page: i64) -> Result<AuditLog> {
+        use crate::schema::audit_events::dsl::{created_at, id};
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            let total = audit_events::table.count().get_result(&conn)?;
+
+            let events = audit_events::table
+                .order_by((created_at.desc(), id.desc()))
+                .limit(per_page)
+                .offset(page.saturating_sub(1) * per_page)
+                .load(&conn)?;
+
+            audit_log_blocking(&conn, events, total)
+        })
+        .await?
+    }
+}
+
+/// Builds a page of the audit log from the given events, looking up every user they reference.
+pub(crate) fn audit_log_blocking(
+    conn: &crate::Connection,
+    events: Vec<AuditEvent>,
+    total: i64,
+) -> Result<AuditLog> {
+    let user_ids: Vec<i32> = events
+        .iter()
+        .flat_map(|v| [v.actor_user_id, v.target_user_id])
+        .flatten()
+        .collect();
+
+    let users = users::table
+        .filter(users::id.eq_any(user_ids))
+        .load::<User>(conn)?
+        .into_iter()
+        .map(|v| (v.id, v))
+        .collect();
+
+    Ok(AuditLog {
+        events,
+        users,
+        total,
+    })
+}
+
+/// Writes a new event to the audit log using an existing connection, so events can be
+/// recorded as part of a larger transaction.
+pub(crate) fn record_blocking(
+    conn: &crate::Connection,
+    actor: &Actor,
+    event: NewAuditEvent,
+) -> Result<()> {
+    use crate::schema::audit_events::dsl::{
+        action, actor_user_id, crate_id, details, ip, organisation_id, target_user_id, user_agent,
+        uuid,
+    };
+
+    insert_into(audit_events::table)
+        .values((
+            uuid.eq(SqlUuid::random()),
+            action.eq(event.action),
+            actor_user_id.eq(actor.user_id),
+            target_user_id.eq(event.target_user_id),
+            organisation_id.eq(event.organisation_id),
+            crate_id.eq(event.crate_id),
+            details.eq(event.details),
+            ip.eq(&actor.ip),
+            user_agent.eq(&actor.user_agent),
+        ))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod test {
+    use super::{Actor, AuditAction, AuditEvent};
+    use crate::{testing, users::UserSession};
+
+    #[tokio::test]
+    async fn events_without_an_organisation_are_listed_for_the_user() {
+        let conn = testing::pool();
+        let user = testing::user(&conn, "user").await;
+        let other = testing::user(&conn, "other").await;
+
+        let actor = Actor {
+            user_id: Some(user.id),
+            ..Actor::default()
+        };
+        UserSession::generate(conn.clone(), &actor, user.id, None, None, None, None, None)
+            .await
+            .unwrap();
+
+        let log = AuditEvent::list_for_user(conn.clone(), user.id, 1, 10)
+            .await
+            .unwrap();
+        assert_eq!(log.total, 1);
+        assert_eq!(log.events[0].action, AuditAction::Login);
+        assert_eq!(log.events[0].organisation_id, None);
+        assert!(log.users.contains_key(&user.id));
+
+        let log = AuditEvent::list_for_user(conn, other.id, 1, 10)
+            .await
+            .unwrap();
+        assert_eq!(log.total, 0);
+    }
+
+    #[tokio::test]
+    async fn every_event_is_listed_for_admins() {
+        let conn = testing::pool();
+        let user = testing::user(&conn, "user").await;
+        let other = testing::user(&conn, "other").await;
+
+        for given_user in [&user, &other] {
+            UserSession::generate(
+                conn.clone(),
+                &testing::actor(given_user),
+                given_user.id,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        }
+
+        let log = AuditEvent::list_all(conn.clone(), 1, 10).await.unwrap();
+        assert_eq!(log.total, 2);
+        assert!(log.users.contains_key(&user.id));
+        assert!(log.users.contains_key(&other.id));
+
+        let log = AuditEvent::list_all(conn, 2, 1).await.unwrap();
+        assert_eq!(log.total, 2);
+        assert_eq!(log.events.len(), 1);
+    }
+}
@@ -0,0 +1,214 @@
+//! Public keys registered by users for cargo's asymmetric token authentication, where cargo
+//! signs a short-lived token with a private key that never leaves the user's machine and we
+//! only ever see the public half.
+//!
+//! Keys are stored in their PASERK form (`k3.public.…`) alongside their PASERK id
+//! (`k3.pid.…`), which is what cargo sends in each token's footer to identify the key. Parsing
+//! and verifying keys is left to `chartered-web`.
+
+use super::{
+    audit::{record_blocking, Actor, AuditAction, NewAuditEvent},
+    schema::{used_cargo_tokens, user_cargo_keys, users},
+    users::User,
+    uuid::SqlUuid,
+    ConnectionPool, Error, Result,
+};
+use chrono::{NaiveDateTime, Utc};
+use diesel::{
+    insert_into,
+    prelude::*,
+    result::{DatabaseErrorKind, Error as DieselError},
+    Associations, Identifiable, Queryable,
+};
+use std::sync::Arc;
+
+#[derive(Identifiable, Queryable, Associations, PartialEq, Eq, Hash, Debug)]
+#[belongs_to(User)]
+pub struct UserCargoKey {
+    pub id: i32,
+    pub uuid: SqlUuid,
+    pub user_id: i32,
+    pub name: String,
+    pub public_key: String,
+    pub key_id: String,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+impl UserCargoKey {
+    /// Finds the key with the given PASERK id and the user that owns it, bumping the key's
+    /// `last_used_at`.
+    pub async fn find_by_key_id(
+        conn: ConnectionPool,
+        given_key_id: String,
+    ) -> Result<Option<(UserCargoKey, User)>> {
+        use crate::schema::user_cargo_keys::dsl::{id, key_id, last_used_at};
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            let found: Option<(UserCargoKey, User)> = user_cargo_keys::table
+                .filter(key_id.eq(given_key_id))
+                .inner_join(users::table)
+                .select((user_cargo_keys::all_columns, users::all_columns))
+                .get_result(&conn)
+                .optional()?;
+
+            if let Some((key, _)) = &found {
+                diesel::update(user_cargo_keys::table.filter(id.eq(key.id)))
+                    .set(last_used_at.eq(Utc::now().naive_utc()))
+                    .execute(&conn)?;
+            }
+
+            Ok(found)
+        })
+        .await?
+    }
+
+    pub async fn list(conn: ConnectionPool, user: Arc<User>) -> Result<Vec<UserCargoKey>> {
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            Ok(UserCargoKey::belonging_to(&*user)
+                .order_by(user_cargo_keys::created_at.desc())
+                .load(&conn)?)
+        })
+        .await?
+    }
+
+    /// Registers an already validated public key for the user.
+    pub async fn insert(
+        conn: ConnectionPool,
+        user: Arc<User>,
+        actor: &Actor,
+        given_name: String,
+        given_public_key: String,
+        given_key_id: String,
+    ) -> Result<UserCargoKey> {
+        use crate::schema::user_cargo_keys::dsl::{key_id, name, public_key, user_id, uuid};
+
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let generated_uuid = SqlUuid::random();
+
+                let res = insert_into(user_cargo_keys::table)
+                    .values((
+                        uuid.eq(generated_uuid),
+                        user_id.eq(user.id),
+                        name.eq(given_name),
+                        public_key.eq(given_public_key),
+                        key_id.eq(&given_key_id),
+                    ))
+                    .execute(&conn);
+
+                match res {
+                    Ok(_) => {}
+                    Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                        return Err(Error::DuplicateCargoKey);
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+
+                let inserted: UserCargoKey = user_cargo_keys::table
+                    .filter(uuid.eq(generated_uuid))
+                    .get_result(&conn)?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::CargoKeyInsert)
+                        .target_user(user.id)
+                        .cargo_key_id(given_key_id),
+                )?;
+
+                Ok(inserted)
+            })
+        })
+        .await?
+    }
+
+    /// Removes one of the user's keys, returning `false` if the user has no such key.
+    pub async fn delete_by_uuid(
+        conn: ConnectionPool,
+        user: Arc<User>,
+        actor: &Actor,
+        given_uuid: uuid::Uuid,
+    ) -> Result<bool> {
+        use crate::schema::user_cargo_keys::dsl::{user_id, uuid};
+
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let key: Option<UserCargoKey> = user_cargo_keys::table
+                    .filter(user_id.eq(user.id))
+                    .filter(uuid.eq(SqlUuid(given_uuid)))
+                    .get_result(&conn)
+                    .optional()?;
+
+                let key = match key {
+                    Some(key) => key,
+                    None => return Ok(false),
+                };
+
+                diesel::delete(user_cargo_keys::table.filter(user_cargo_keys::id.eq(key.id)))
+                    .execute(&conn)?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::CargoKeyDelete)
+                        .target_user(user.id)
+                        .cargo_key_id(key.key_id),
+                )?;
+
+                Ok(true)
+            })
+        })
+        .await?
+    }
+}
+
+/// Signed tokens that have already been used to perform a mutation, kept until the token
+/// would've expired anyway so they can't be replayed.
+pub struct UsedCargoToken;
+
+impl UsedCargoToken {
+    /// Marks the token as used, returning `false` if it's already been used before.
+    pub async fn claim(
+        conn: ConnectionPool,
+        given_token_hash: String,
+        given_expires_at: NaiveDateTime,
+    ) -> Result<bool> {
+        use crate::schema::used_cargo_tokens::dsl::{expires_at, token_hash};
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            // tokens that have expired can't be replayed anyway, so there's no point keeping
+            // them around
+            diesel::delete(used_cargo_tokens::table.filter(expires_at.lt(Utc::now().naive_utc())))
+                .execute(&conn)?;
+
+            let res = insert_into(used_cargo_tokens::table)
+                .values((
+                    token_hash.eq(given_token_hash),
+                    expires_at.eq(given_expires_at),
+                ))
+                .execute(&conn);
+
+            match res {
+                Ok(_) => Ok(true),
+                Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Ok(false),
+                Err(e) => Err(e.into()),
+            }
+        })
+        .await?
+    }
+}
@@ -1,15 +1,26 @@
 use super::{
+    audit::{record_blocking, Actor, AuditAction, NewAuditEvent},
     coalesce,
-    organisations::Organisation,
-    permissions::UserPermission,
-    schema::{crate_versions, crates, organisations, user_crate_permissions, users},
+    organisations::{Organisation, OrganisationWithPermissions},
+    permissions::{ensure_grantable, UserPermission},
+    schema::{
+        crate_redirects, crate_trusted_publishers, crate_version_downloads,
+        crate_version_reservations, crate_versions, crates, group_crate_permissions, organisations,
+        storage_deletions, user_crate_permissions, users,
+    },
+    scopes::TokenScope,
+    storage_deletions::StorageDeletionReason,
     users::User,
+    webhooks::{enqueue_blocking as enqueue_webhook, WebhookPayload},
     BitwiseExpressionMethods, ConnectionPool, Error, Result,
 };
 use diesel::{insert_into, prelude::*, Associations, Identifiable, Queryable};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
 
 #[derive(Identifiable, Queryable, Associations, Default, PartialEq, Eq, Hash, Debug)]
 #[belongs_to(User)]
@@ -19,6 +30,12 @@ pub struct UserCratePermission {
     pub user_id: i32,
     pub crate_id: i32,
     pub permissions: UserPermission,
+    /// Permissions taken away from the user on this crate, even if they've been granted them
+    /// by the organisation or one of their groups.
+    pub restricted_permissions: UserPermission,
+    /// When the grant lapses, after which its permissions are ignored and eventually removed.
+    /// Its restrictions outlive it.
+    pub expires_at: Option<chrono::NaiveDateTime>,
 }
 
 impl UserCratePermission {
@@ -35,6 +52,9 @@ impl UserCratePermission {
             Ok(crate::schema::user_crate_permissions::table
                 .filter(user_id.eq(given_user_id))
                 .filter(crate_id.eq(given_crate_id))
+                .filter(unexpired!(
+                    crate::schema::user_crate_permissions::expires_at
+                ))
                 .get_result(&conn)
                 .optional()?)
         })
@@ -74,27 +94,52 @@ macro_rules! crate_with_permissions {
                         .and(
                             crate::schema::user_organisation_permissions::organisation_id
                                 .eq(crates::organisation_id),
-                        ),
+                        )
+                        .and(unexpired!(
+                            crate::schema::user_organisation_permissions::expires_at
+                        )),
                 ),
             )
     };
 }
 
+/// Selects the effective permissions the user has on the crate. Permissions granted on the
+/// organisation, either directly or through a group, are inherited by every crate within it and
+/// crate-level grants add to those, after which any restrictions set on the user for this
+/// specific crate are taken away. None of these grants apply if the organisation requires
+/// two-factor authentication and the user hasn't enabled it.
+///
+/// An expired crate-level grant stops adding permissions but its restrictions still apply, so a
+/// restriction can't be lifted by waiting for the grant alongside it to lapse.
 macro_rules! select_permissions {
-    () => {
-        coalesce(
-            crate::schema::user_crate_permissions::permissions.nullable(),
-            0,
+    ($user_id:ident) => {
+        diesel::dsl::sql::<diesel::sql_types::Integer>(
+            "COALESCE(CASE WHEN user_crate_permissions.expires_at IS NULL \
+             OR user_crate_permissions.expires_at > ",
         )
+        .bind::<diesel::sql_types::Timestamp, _>(chrono::Utc::now().naive_utc())
+        .sql(" THEN user_crate_permissions.permissions ELSE 0 END, 0)")
         .bitwise_or(coalesce(
             crate::schema::user_organisation_permissions::permissions.nullable(),
             0,
         ))
+        .bitwise_or(diesel::dsl::sql::<diesel::sql_types::Integer>(
+            &crate::groups::crate_group_permissions_sql($user_id, "crates.id"),
+        ))
+        .bitwise_or(diesel::dsl::sql::<diesel::sql_types::Integer>(
+            &crate::groups::organisation_group_permissions_sql($user_id, "crates.organisation_id"),
+        ))
+        .bitwise_and(diesel::dsl::sql::<diesel::sql_types::Integer>(
+            &crate::two_factor::two_factor_mask_sql($user_id, "organisations"),
+        ))
         .bitwise_or(diesel::dsl::sql::<diesel::sql_types::Integer>(&format!(
             "COALESCE(CASE WHEN {} THEN {} ELSE 0 END, 0)",
             "organisations.public",
             UserPermission::VISIBLE.bits(),
         )))
+        .bitwise_and(diesel::dsl::sql::<diesel::sql_types::Integer>(
+            "~COALESCE(user_crate_permissions.restricted_permissions, 0)",
+        ))
     };
 }
 
@@ -110,8 +155,9 @@ impl Crate {
 
             let crates = crate_with_permissions!(requesting_user_id)
                 .inner_join(organisations::table)
+                .filter(organisations::deleted_at.is_null())
                 .filter(
-                    select_permissions!()
+                    select_permissions!(requesting_user_id)
                         .bitwise_and(UserPermission::VISIBLE.bits())
                         .eq(UserPermission::VISIBLE.bits()),
                 )
@@ -122,7 +168,7 @@ impl Crate {
                 .select((
                     organisations::all_columns,
                     crates::all_columns,
-                    select_permissions!(),
+                    select_permissions!(requesting_user_id),
                 ))
                 .limit(limit)
                 .load(&conn)?
@@ -143,29 +189,69 @@ impl Crate {
         .await?
     }
 
+    /// Lists every crate in the organisation visible to the user, along with all their
+    /// versions. Crates outside of the given `scope` are left out entirely.
     pub async fn list_with_versions(
         conn: ConnectionPool,
         requesting_user_id: i32,
+        scope: Arc<TokenScope>,
         given_org_name: String,
     ) -> Result<HashMap<Crate, Vec<CrateVersion<'static>>>> {
         use crate::schema::organisations::dsl::{name as org_name, organisations};
 
+        if !scope.permissions.contains(UserPermission::VISIBLE) {
+            return Ok(HashMap::new());
+        }
+
         tokio::task::spawn_blocking(move || {
             let conn = conn.get()?;
 
-            let crate_versions = crate_with_permissions!(requesting_user_id)
-                .inner_join(organisations)
-                .filter(org_name.eq(given_org_name))
-                .filter(
-                    select_permissions!()
-                        .bitwise_and(UserPermission::VISIBLE.bits())
-                        .eq(UserPermission::VISIBLE.bits()),
-                )
-                .inner_join(crate_versions::table)
-                .select((crates::all_columns, crate_versions::all_columns))
-                .load(&conn)?;
+            let mut crate_versions: Vec<(Crate, CrateVersion<'static>, String)> =
+                crate_with_permissions!(requesting_user_id)
+                    .inner_join(organisations)
+                    .filter(organisation_by_name!(&given_org_name))
+                    .filter(
+                        select_permissions!(requesting_user_id)
+                            .bitwise_and(UserPermission::VISIBLE.bits())
+                            .eq(UserPermission::VISIBLE.bits()),
+                    )
+                    .inner_join(crate_versions::table)
+                    .select((crates::all_columns, crate_versions::all_columns, org_name))
+                    .load(&conn)?;
 
-            Ok(crate_versions.into_iter().into_grouping_map().collect())
+            // crates that have been moved out of the organisation stay in its index until their
+            // redirect expires, so long as the user can still see them in their new home
+            let redirected_crate_ids: Vec<i32> = redirects_blocking(&conn, &given_org_name)?
+                .into_iter()
+                .map(|(_, redirected_crate_id)| redirected_crate_id)
+                .collect();
+
+            if !redirected_crate_ids.is_empty() {
+                crate_versions.extend(
+                    crate_with_permissions!(requesting_user_id)
+                        .inner_join(organisations)
+                        .filter(crates::id.eq_any(redirected_crate_ids))
+                        .filter(
+                            select_permissions!(requesting_user_id)
+                                .bitwise_and(UserPermission::VISIBLE.bits())
+                                .eq(UserPermission::VISIBLE.bits()),
+                        )
+                        .inner_join(crate_versions::table)
+                        .select((crates::all_columns, crate_versions::all_columns, org_name))
+                        .load::<(Crate, CrateVersion<'static>, String)>(&conn)?,
+                );
+            }
+
+            // scopes refer to the organisation by its current name, which might not be the name
+            // it was requested by if it's since been renamed
+            Ok(crate_versions
+                .into_iter()
+                .filter(|(crate_, _, current_org_name)| {
+                    scope.allows_crate(current_org_name, &crate_.name)
+                })
+                .map(|(crate_, version, _)| (crate_, version))
+                .into_grouping_map()
+                .collect())
         })
         .await?
     }
@@ -179,11 +265,12 @@ impl Crate {
 
             let crates = crate_with_permissions!(requesting_user_id)
                 .filter(
-                    select_permissions!()
+                    select_permissions!(requesting_user_id)
                         .bitwise_and(UserPermission::VISIBLE.bits())
                         .eq(UserPermission::VISIBLE.bits()),
                 )
                 .inner_join(organisations::table)
+                .filter(organisations::deleted_at.is_null())
                 .select((crates::all_columns, organisations::all_columns))
                 .limit(10)
                 .order_by(crate::schema::crates::dsl::created_at.desc())
@@ -203,11 +290,12 @@ impl Crate {
 
             let crates = crate_with_permissions!(requesting_user_id)
                 .filter(
-                    select_permissions!()
+                    select_permissions!(requesting_user_id)
                         .bitwise_and(UserPermission::VISIBLE.bits())
                         .eq(UserPermission::VISIBLE.bits()),
                 )
                 .inner_join(organisations::table)
+                .filter(organisations::deleted_at.is_null())
                 .select((crates::all_columns, organisations::all_columns))
                 .limit(10)
                 .order_by(crate::schema::crates::dsl::downloads.desc())
@@ -227,11 +315,12 @@ impl Crate {
 
             let crates = crate_with_permissions!(requesting_user_id)
                 .filter(
-                    select_permissions!()
+                    select_permissions!(requesting_user_id)
                         .bitwise_and(UserPermission::VISIBLE.bits())
                         .eq(UserPermission::VISIBLE.bits()),
                 )
                 .inner_join(organisations::table)
+                .filter(organisations::deleted_at.is_null())
                 .inner_join(crate_versions::table)
                 .select((
                     crates::all_columns,
@@ -247,9 +336,12 @@ impl Crate {
         .await?
     }
 
+    /// Finds the crate along with the permissions the user has for it, restricted to what's
+    /// allowed by the `scope` of the session or token in use.
     pub async fn find_by_name(
         conn: ConnectionPool,
         requesting_user_id: i32,
+        scope: Arc<TokenScope>,
         given_org_name: String,
         given_crate_name: String,
     ) -> Result<CrateWithPermissions> {
@@ -259,14 +351,49 @@ impl Crate {
         tokio::task::spawn_blocking(move || {
             let conn = conn.get()?;
 
-            let (crate_, permissions) = crate_with_permissions!(requesting_user_id)
+            let found = crate_with_permissions!(requesting_user_id)
                 .inner_join(organisations)
-                .filter(org_name.eq(given_org_name))
-                .filter(crate_name.eq(given_crate_name))
-                .select((crate::schema::crates::all_columns, select_permissions!()))
-                .first::<(Crate, UserPermission)>(&conn)
-                .optional()?
-                .ok_or(Error::MissingCrate)?;
+                .filter(organisation_by_name!(&given_org_name))
+                .filter(crate_name.eq(&given_crate_name))
+                .select((
+                    crate::schema::crates::all_columns,
+                    select_permissions!(requesting_user_id),
+                    org_name,
+                ))
+                .first::<(Crate, UserPermission, String)>(&conn)
+                .optional()?;
+
+            let (crate_, permissions, current_org_name) = if let Some(found) = found {
+                found
+            } else {
+                // the crate might have been moved to another organisation, in which case it
+                // can still be fetched through this one until the redirect expires, but any
+                // changes to it have to be made through its new organisation
+                let redirected_crate_id = redirects_blocking(&conn, &given_org_name)?
+                    .into_iter()
+                    .find(|(name, _)| *name == given_crate_name)
+                    .map(|(_, redirected_crate_id)| redirected_crate_id)
+                    .ok_or(Error::MissingCrate)?;
+
+                let (crate_, permissions, current_org_name) =
+                    crate_with_permissions!(requesting_user_id)
+                        .inner_join(organisations)
+                        .filter(crates::id.eq(redirected_crate_id))
+                        .select((
+                            crate::schema::crates::all_columns,
+                            select_permissions!(requesting_user_id),
+                            org_name,
+                        ))
+                        .first::<(Crate, UserPermission, String)>(&conn)?;
+
+                (
+                    crate_,
+                    permissions & UserPermission::VISIBLE,
+                    current_org_name,
+                )
+            };
+
+            let permissions = scope.restrict(&current_org_name, &crate_.name, permissions);
 
             if permissions.contains(UserPermission::VISIBLE) {
                 Ok(CrateWithPermissions {
@@ -283,10 +410,13 @@ impl Crate {
     pub async fn create(
         conn: ConnectionPool,
         requesting_user_id: i32,
+        scope: Arc<TokenScope>,
         given_org_name: String,
         given_crate_name: String,
     ) -> Result<CrateWithPermissions> {
-        use crate::schema::organisations::dsl::{id, name as org_name, organisations};
+        use crate::schema::organisations::dsl::{
+            id, name as org_name, organisations, upstream_index_uri,
+        };
         use crate::schema::user_organisation_permissions::dsl::{
             organisation_id, permissions, user_id,
         };
@@ -294,20 +424,48 @@ impl Crate {
         tokio::task::spawn_blocking(move || {
             let conn = conn.get()?;
 
-            let (org_id, perms) = organisations
-                .filter(org_name.eq(given_org_name))
-                .inner_join(
-                    crate::schema::user_organisation_permissions::table
-                        .on(organisation_id.eq(id).and(user_id.eq(requesting_user_id))),
+            let (org_id, perms, upstream, current_org_name) = organisations
+                .filter(organisation_by_name!(&given_org_name))
+                .left_join(
+                    crate::schema::user_organisation_permissions::table.on(organisation_id
+                        .eq(id)
+                        .and(user_id.eq(requesting_user_id))
+                        .and(unexpired!(
+                            crate::schema::user_organisation_permissions::expires_at
+                        ))),
                 )
-                .select((id, permissions))
-                .first::<(i32, UserPermission)>(&conn)
+                .select((
+                    id,
+                    coalesce(permissions.nullable(), 0)
+                        .bitwise_or(diesel::dsl::sql::<diesel::sql_types::Integer>(
+                            &crate::groups::organisation_group_permissions_sql(
+                                requesting_user_id,
+                                "organisations.id",
+                            ),
+                        ))
+                        .bitwise_and(diesel::dsl::sql::<diesel::sql_types::Integer>(
+                            &crate::two_factor::two_factor_mask_sql(
+                                requesting_user_id,
+                                "organisations",
+                            ),
+                        )),
+                    upstream_index_uri,
+                    org_name,
+                ))
+                .first::<(i32, UserPermission, Option<String>, String)>(&conn)
                 .optional()?
+                // users that aren't a member of the organisation, either directly or through a
+                // group, shouldn't be able to tell it exists
+                .filter(|(_, perms, _, _)| !perms.is_empty())
                 .ok_or(Error::MissingOrganisation)?;
 
+            let perms = scope.restrict(&current_org_name, &given_crate_name, perms);
+
             #[allow(clippy::if_not_else)]
             if !perms.contains(UserPermission::VISIBLE) {
                 Err(Error::MissingCratePermission(UserPermission::VISIBLE))
+            } else if upstream.is_some() {
+                Err(Error::MirrorOrganisation)
             } else if !perms.contains(UserPermission::CREATE_CRATE) {
                 Err(Error::MissingCratePermission(UserPermission::CREATE_CRATE))
             } else {
@@ -332,6 +490,71 @@ impl Crate {
     }
 }
 
+/// Lists the unexpired redirects left behind in the organisation by crates that have since
+/// been moved out of it, as `(name, crate_id)` pairs.
+fn redirects_blocking(
+    conn: &crate::Connection,
+    given_org_name: &str,
+) -> Result<Vec<(String, i32)>> {
+    use crate::schema::crate_redirects::dsl::{crate_id, expires_at, name};
+
+    Ok(crate_redirects::table
+        .inner_join(organisations::table)
+        .filter(organisation_by_name!(given_org_name))
+        .filter(expires_at.gt(chrono::Utc::now().naive_utc()))
+        .select((name, crate_id))
+        .load(conn)?)
+}
+
+/// Where a grant of permissions on a crate came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PermissionSource {
+    /// Granted to the user on the crate itself.
+    Crate,
+    /// Granted to the user on the organisation, and inherited by every crate within it.
+    Organisation,
+    /// Granted to the named group the user is a member of, either on the crate itself or on
+    /// the organisation.
+    Group(String),
+}
+
+/// A user with permissions on a crate, along with every grant that gave them those permissions.
+#[derive(Debug)]
+pub struct CrateMember {
+    pub user: User,
+    /// Permissions granted to the user on the crate itself.
+    pub permissions: UserPermission,
+    /// Permissions taken away from the user on the crate, wherever they were granted from.
+    pub restricted_permissions: UserPermission,
+    /// When the permissions granted on the crate itself lapse.
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub grants: Vec<(PermissionSource, UserPermission)>,
+}
+
+impl CrateMember {
+    fn new(user: User) -> Self {
+        Self {
+            user,
+            permissions: UserPermission::empty(),
+            restricted_permissions: UserPermission::empty(),
+            expires_at: None,
+            grants: Vec::new(),
+        }
+    }
+
+    /// The permissions the user actually ends up with on the crate, the union of all their
+    /// grants less any restrictions.
+    #[must_use]
+    pub fn effective_permissions(&self) -> UserPermission {
+        let mut permissions = self
+            .grants
+            .iter()
+            .fold(UserPermission::empty(), |acc, (_, v)| acc | *v);
+        permissions.remove(self.restricted_permissions);
+        permissions
+    }
+}
+
 #[derive(Debug)]
 pub struct CrateWithPermissions {
     pub crate_: Crate,
@@ -387,16 +610,24 @@ impl CrateWithPermissions {
         .await?
     }
 
-    pub async fn increment_download_count(self: Arc<Self>, conn: ConnectionPool) -> Result<()> {
+    /// Returns the daily download counts for each version of this crate since the given date.
+    pub async fn version_downloads(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        since: chrono::NaiveDate,
+    ) -> Result<Vec<(String, chrono::NaiveDate, i32)>> {
+        use crate::schema::crate_version_downloads::dsl::{date, downloads};
+
         tokio::task::spawn_blocking(move || {
             let conn = conn.get()?;
 
-            let _ = diesel::update(crates::table)
-                .set(crates::downloads.eq(crates::downloads + 1))
-                .filter(crates::id.eq(self.crate_.id))
-                .execute(&conn)?;
-
-            Ok(())
+            Ok(crate_version_downloads::table
+                .inner_join(crate_versions::table)
+                .filter(crate_versions::crate_id.eq(self.crate_.id))
+                .filter(date.ge(since))
+                .select((crate_versions::version, date, downloads))
+                .order_by(date.asc())
+                .load(&conn)?)
         })
         .await?
     }
@@ -413,6 +644,9 @@ impl CrateWithPermissions {
                         .bitwise_and(UserPermission::MANAGE_USERS.bits())
                         .ne(0),
                 )
+                .filter(unexpired!(
+                    crate::schema::user_crate_permissions::expires_at
+                ))
                 .inner_join(crate::schema::users::dsl::users)
                 .select(crate::schema::users::all_columns)
                 .load::<crate::users::User>(&conn)?)
@@ -420,21 +654,77 @@ impl CrateWithPermissions {
         .await?
     }
 
-    pub async fn members(
-        self: Arc<Self>,
-        conn: ConnectionPool,
-    ) -> Result<Vec<(crate::users::User, UserPermission)>> {
+    /// Lists everyone with permissions on the crate, whether they were granted on the crate
+    /// itself, inherited from the organisation or granted through one of their groups.
+    pub async fn members(self: Arc<Self>, conn: ConnectionPool) -> Result<Vec<CrateMember>> {
         if !self.permissions.contains(UserPermission::MANAGE_USERS) {
             return Err(Error::MissingCratePermission(UserPermission::MANAGE_USERS));
         }
 
         tokio::task::spawn_blocking(move || {
+            use crate::schema::user_organisation_permissions;
+
             let conn = conn.get()?;
 
-            Ok(UserCratePermission::belonging_to(&self.crate_)
-                .inner_join(users::dsl::users)
-                .select((users::all_columns, user_crate_permissions::permissions))
-                .load(&conn)?)
+            // expired grants are kept here as their restrictions still apply
+            let direct = UserCratePermission::belonging_to(&self.crate_)
+                .inner_join(users::table)
+                .load::<(UserCratePermission, User)>(&conn)?;
+
+            let inherited = user_organisation_permissions::table
+                .filter(
+                    user_organisation_permissions::organisation_id.eq(self.crate_.organisation_id),
+                )
+                .filter(unexpired!(user_organisation_permissions::expires_at))
+                .inner_join(users::table)
+                .select((
+                    users::all_columns,
+                    user_organisation_permissions::permissions,
+                ))
+                .load::<(User, UserPermission)>(&conn)?;
+
+            let groups = crate::groups::crate_member_grants_blocking(&conn, &self.crate_)?;
+
+            let mut members: BTreeMap<i32, CrateMember> = BTreeMap::new();
+
+            let now = chrono::Utc::now().naive_utc();
+
+            for (mut grant, user) in direct {
+                if grant.expires_at.map_or(false, |v| v <= now) {
+                    grant.permissions = UserPermission::default();
+                }
+
+                let member = members
+                    .entry(user.id)
+                    .or_insert_with(|| CrateMember::new(user));
+                member.permissions = grant.permissions;
+                member.restricted_permissions = grant.restricted_permissions;
+                member.expires_at = grant.expires_at;
+
+                if !grant.permissions.is_empty() {
+                    member
+                        .grants
+                        .push((PermissionSource::Crate, grant.permissions));
+                }
+            }
+
+            for (user, permissions) in inherited {
+                members
+                    .entry(user.id)
+                    .or_insert_with(|| CrateMember::new(user))
+                    .grants
+                    .push((PermissionSource::Organisation, permissions));
+            }
+
+            for (user, group, permissions) in groups {
+                members
+                    .entry(user.id)
+                    .or_insert_with(|| CrateMember::new(user))
+                    .grants
+                    .push((PermissionSource::Group(group), permissions));
+            }
+
+            Ok(members.into_values().collect())
         })
         .await?
     }
@@ -442,27 +732,59 @@ impl CrateWithPermissions {
     pub async fn update_permissions(
         self: Arc<Self>,
         conn: ConnectionPool,
+        actor: &Actor,
         given_user_id: i32,
         given_permissions: UserPermission,
+        given_restricted_permissions: Option<UserPermission>,
+        given_expires_at: Option<chrono::NaiveDateTime>,
     ) -> Result<usize> {
         if !self.permissions.contains(UserPermission::MANAGE_USERS) {
             return Err(Error::MissingCratePermission(UserPermission::MANAGE_USERS));
         }
 
+        ensure_grantable(self.permissions, given_permissions)?;
+
+        let actor = actor.clone();
+
         tokio::task::spawn_blocking(move || {
             use crate::schema::user_crate_permissions::dsl::{
-                crate_id, permissions, user_crate_permissions, user_id,
+                crate_id, expires_at, permissions, restricted_permissions, user_crate_permissions,
+                user_id,
             };
 
             let conn = conn.get()?;
 
-            Ok(diesel::update(
-                user_crate_permissions
-                    .filter(user_id.eq(given_user_id))
-                    .filter(crate_id.eq(self.crate_.id)),
-            )
-            .set(permissions.eq(given_permissions.bits()))
-            .execute(&conn)?)
+            conn.transaction::<_, Error, _>(|| {
+                self.ensure_can_manage_member(&conn, given_user_id)?;
+
+                // restrictions are left untouched if new ones weren't given
+                let affected_rows = diesel::update(
+                    user_crate_permissions
+                        .filter(user_id.eq(given_user_id))
+                        .filter(crate_id.eq(self.crate_.id)),
+                )
+                .set((
+                    permissions.eq(given_permissions.bits()),
+                    given_restricted_permissions.map(|v| restricted_permissions.eq(v.bits())),
+                    expires_at.eq(given_expires_at),
+                ))
+                .execute(&conn)?;
+
+                if affected_rows > 0 {
+                    self.enqueue_member_update(&conn, given_user_id, Some(given_permissions))?;
+
+                    record_blocking(
+                        &conn,
+                        &actor,
+                        NewAuditEvent::new(AuditAction::CrateMemberUpdate)
+                            .for_crate(&self.crate_)
+                            .target_user(given_user_id)
+                            .permissions(given_permissions),
+                    )?;
+                }
+
+                Ok(affected_rows)
+            })
         })
         .await?
     }
@@ -470,27 +792,78 @@ impl CrateWithPermissions {
     pub async fn insert_permissions(
         self: Arc<Self>,
         conn: ConnectionPool,
+        actor: &Actor,
         given_user_id: i32,
         given_permissions: UserPermission,
+        given_restricted_permissions: UserPermission,
+        given_expires_at: Option<chrono::NaiveDateTime>,
     ) -> Result<usize> {
         if !self.permissions.contains(UserPermission::MANAGE_USERS) {
             return Err(Error::MissingCratePermission(UserPermission::MANAGE_USERS));
         }
 
+        ensure_grantable(self.permissions, given_permissions)?;
+
+        let actor = actor.clone();
+
         tokio::task::spawn_blocking(move || {
             use crate::schema::user_crate_permissions::dsl::{
-                crate_id, permissions, user_crate_permissions, user_id,
+                crate_id, expires_at, permissions, restricted_permissions, user_crate_permissions,
+                user_id,
             };
 
             let conn = conn.get()?;
 
-            Ok(diesel::insert_into(user_crate_permissions)
-                .values((
-                    user_id.eq(given_user_id),
-                    crate_id.eq(self.crate_.id),
+            conn.transaction::<_, Error, _>(|| {
+                // restrictions can only be placed on users with no more permissions than us
+                self.ensure_can_manage_member(&conn, given_user_id)?;
+
+                // a grant that has lapsed, or was only kept around for its restrictions, is
+                // replaced rather than conflicting with the new one
+                let affected_rows = diesel::update(
+                    user_crate_permissions
+                        .filter(user_id.eq(given_user_id))
+                        .filter(crate_id.eq(self.crate_.id))
+                        .filter(
+                            expires_at
+                                .le(chrono::Utc::now().naive_utc())
+                                .or(permissions.eq(0)),
+                        ),
+                )
+                .set((
                     permissions.eq(given_permissions.bits()),
+                    restricted_permissions.eq(given_restricted_permissions.bits()),
+                    expires_at.eq(given_expires_at),
                 ))
-                .execute(&conn)?)
+                .execute(&conn)?;
+
+                let affected_rows = if affected_rows > 0 {
+                    affected_rows
+                } else {
+                    diesel::insert_into(user_crate_permissions)
+                        .values((
+                            user_id.eq(given_user_id),
+                            crate_id.eq(self.crate_.id),
+                            permissions.eq(given_permissions.bits()),
+                            restricted_permissions.eq(given_restricted_permissions.bits()),
+                            expires_at.eq(given_expires_at),
+                        ))
+                        .execute(&conn)?
+                };
+
+                self.enqueue_member_update(&conn, given_user_id, Some(given_permissions))?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::CrateMemberInsert)
+                        .for_crate(&self.crate_)
+                        .target_user(given_user_id)
+                        .permissions(given_permissions),
+                )?;
+
+                Ok(affected_rows)
+            })
         })
         .await?
     }
@@ -498,12 +871,15 @@ impl CrateWithPermissions {
     pub async fn delete_member(
         self: Arc<Self>,
         conn: ConnectionPool,
+        actor: &Actor,
         given_user_id: i32,
     ) -> Result<()> {
         if !self.permissions.contains(UserPermission::MANAGE_USERS) {
             return Err(Error::MissingCratePermission(UserPermission::MANAGE_USERS));
         }
 
+        let actor = actor.clone();
+
         tokio::task::spawn_blocking(move || {
             use crate::schema::user_crate_permissions::dsl::{
                 crate_id, user_crate_permissions, user_id,
@@ -511,14 +887,137 @@ impl CrateWithPermissions {
 
             let conn = conn.get()?;
 
-            diesel::delete(
-                user_crate_permissions
-                    .filter(user_id.eq(given_user_id))
-                    .filter(crate_id.eq(self.crate_.id)),
-            )
-            .execute(&conn)?;
+            conn.transaction::<_, Error, _>(|| {
+                self.ensure_can_manage_member(&conn, given_user_id)?;
 
-            Ok(())
+                let affected_rows = diesel::delete(
+                    user_crate_permissions
+                        .filter(user_id.eq(given_user_id))
+                        .filter(crate_id.eq(self.crate_.id)),
+                )
+                .execute(&conn)?;
+
+                if affected_rows > 0 {
+                    self.enqueue_member_update(&conn, given_user_id, None)?;
+
+                    record_blocking(
+                        &conn,
+                        &actor,
+                        NewAuditEvent::new(AuditAction::CrateMemberDelete)
+                            .for_crate(&self.crate_)
+                            .target_user(given_user_id),
+                    )?;
+                }
+
+                Ok(())
+            })
+        })
+        .await?
+    }
+
+    /// Moves the crate into another organisation, which the user needs `CREATE_CRATE` on. The
+    /// crate's group permissions are dropped as groups belong to the old organisation.
+    ///
+    /// If `redirect_expires_at` is given, the crate can still be fetched through the old
+    /// organisation until then so `Cargo.toml`s that haven't been updated keep resolving.
+    pub async fn move_to(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        actor: &Actor,
+        scope: Arc<TokenScope>,
+        target: Arc<OrganisationWithPermissions>,
+        redirect_expires_at: Option<chrono::NaiveDateTime>,
+    ) -> Result<()> {
+        use crate::schema::crate_redirects::dsl::{crate_id, expires_at, name, organisation_id};
+
+        if !self.permissions.contains(UserPermission::MANAGE_USERS) {
+            return Err(Error::MissingCratePermission(UserPermission::MANAGE_USERS));
+        }
+
+        let target_permissions = scope.restrict(
+            &target.organisation().name,
+            &self.crate_.name,
+            target.permissions(),
+        );
+        if !target_permissions.contains(UserPermission::CREATE_CRATE) {
+            return Err(Error::MissingOrganisationPermission(
+                UserPermission::CREATE_CRATE,
+            ));
+        }
+
+        if target.organisation().upstream().is_some() {
+            return Err(Error::MirrorOrganisation);
+        }
+
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let source_id = self.crate_.organisation_id;
+                let target_id = target.organisation().id;
+
+                let name_taken = crates::table
+                    .filter(crates::organisation_id.eq(target_id))
+                    .filter(crates::name.eq(&self.crate_.name))
+                    .count()
+                    .get_result::<i64>(&conn)?
+                    > 0;
+                if name_taken {
+                    return Err(Error::CrateNameTaken);
+                }
+
+                let source_name: String = organisations::table
+                    .find(source_id)
+                    .select(organisations::name)
+                    .get_result(&conn)?;
+
+                // any redirect in the target organisation would be shadowed by the crate itself,
+                // and any left in the source from a previous move is stale
+                diesel::delete(
+                    crate_redirects::table
+                        .filter(organisation_id.eq_any(vec![source_id, target_id]))
+                        .filter(name.eq(&self.crate_.name)),
+                )
+                .execute(&conn)?;
+
+                if let Some(redirect_expires_at) = redirect_expires_at {
+                    insert_into(crate_redirects::table)
+                        .values((
+                            organisation_id.eq(source_id),
+                            name.eq(&self.crate_.name),
+                            crate_id.eq(self.crate_.id),
+                            expires_at.eq(redirect_expires_at),
+                        ))
+                        .execute(&conn)?;
+                }
+
+                diesel::delete(
+                    group_crate_permissions::table
+                        .filter(group_crate_permissions::crate_id.eq(self.crate_.id)),
+                )
+                .execute(&conn)?;
+
+                diesel::update(crates::table.find(self.crate_.id))
+                    .set(crates::organisation_id.eq(target_id))
+                    .execute(&conn)?;
+
+                // recorded against both organisations so the move shows up in each of their
+                // audit logs
+                for audit_organisation_id in [source_id, target_id] {
+                    record_blocking(
+                        &conn,
+                        &actor,
+                        NewAuditEvent::new(AuditAction::CrateMove)
+                            .for_crate(&self.crate_)
+                            .for_organisation(audit_organisation_id)
+                            .previous_organisation(source_name.clone()),
+                    )?;
+                }
+
+                Ok(())
+            })
         })
         .await?
     }
@@ -527,6 +1026,7 @@ impl CrateWithPermissions {
     pub async fn publish_version(
         self: Arc<Self>,
         conn: ConnectionPool,
+        actor: &Actor,
         user: Arc<User>,
         file_identifier: chartered_fs::FileReference,
         file_checksum: String,
@@ -550,12 +1050,27 @@ impl CrateWithPermissions {
             ));
         }
 
+        let actor = actor.clone();
+
         tokio::task::spawn_blocking(move || {
             use diesel::result::{DatabaseErrorKind, Error as DieselError};
 
             let conn = conn.get()?;
 
             conn.transaction::<_, crate::Error, _>(|| {
+                let reserved_checksum = crate_version_reservations::table
+                    .filter(
+                        crate_version_reservations::organisation_id.eq(self.crate_.organisation_id),
+                    )
+                    .filter(crate_version_reservations::crate_name.eq(&self.crate_.name))
+                    .filter(crate_version_reservations::version.eq(&given.vers))
+                    .select(crate_version_reservations::checksum)
+                    .get_result::<String>(&conn)
+                    .optional()?;
+                if matches!(reserved_checksum, Some(v) if v != file_checksum) {
+                    return Err(Error::ReservedVersion(given.vers.into_owned()));
+                }
+
                 diesel::update(crates.filter(id.eq(self.crate_.id)))
                     .set((
                         name.eq(given.name),
@@ -582,12 +1097,29 @@ impl CrateWithPermissions {
                     .execute(&conn);
 
                 match res {
-                    Ok(_) => Ok(()),
+                    Ok(_) => {}
                     Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
-                        Err(Error::VersionConflict(given.vers.into_owned()))
+                        return Err(Error::VersionConflict(given.vers.into_owned()));
                     }
-                    Err(e) => Err(e.into()),
+                    Err(e) => return Err(e.into()),
                 }
+
+                enqueue_webhook(
+                    &conn,
+                    self.crate_.organisation_id,
+                    &WebhookPayload::PublishVersion {
+                        crate_name: self.crate_.name.clone(),
+                        version: given.vers.to_string(),
+                    },
+                )?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::PublishVersion)
+                        .for_crate(&self.crate_)
+                        .version(given.vers.into_owned()),
+                )
             })?;
 
             Ok(())
@@ -598,6 +1130,7 @@ impl CrateWithPermissions {
     pub async fn yank_version(
         self: Arc<Self>,
         conn: ConnectionPool,
+        actor: &Actor,
         given_version: String,
         yank: bool,
     ) -> Result<()> {
@@ -607,21 +1140,254 @@ impl CrateWithPermissions {
             return Err(Error::MissingCratePermission(UserPermission::YANK_VERSION));
         }
 
+        let actor = actor.clone();
+
         tokio::task::spawn_blocking(move || {
             let conn = conn.get()?;
 
-            diesel::update(
-                crate_versions
-                    .filter(crate_id.eq(self.crate_.id))
-                    .filter(version.eq(given_version)),
-            )
-            .set(yanked.eq(yank))
-            .execute(&conn)?;
+            conn.transaction::<_, Error, _>(|| {
+                let affected_rows = diesel::update(
+                    crate_versions
+                        .filter(crate_id.eq(self.crate_.id))
+                        .filter(version.eq(&given_version)),
+                )
+                .set(yanked.eq(yank))
+                .execute(&conn)?;
 
-            Ok(())
+                if affected_rows == 0 {
+                    return Ok(());
+                }
+
+                let crate_name = self.crate_.name.clone();
+                let (payload, action) = if yank {
+                    (
+                        WebhookPayload::YankVersion {
+                            crate_name,
+                            version: given_version.clone(),
+                        },
+                        AuditAction::YankVersion,
+                    )
+                } else {
+                    (
+                        WebhookPayload::UnyankVersion {
+                            crate_name,
+                            version: given_version.clone(),
+                        },
+                        AuditAction::UnyankVersion,
+                    )
+                };
+
+                enqueue_webhook(&conn, self.crate_.organisation_id, &payload)?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(action)
+                        .for_crate(&self.crate_)
+                        .version(given_version),
+                )
+            })
+        })
+        .await?
+    }
+
+    /// Deletes a single version of the crate, so long as it was published within `window`. The
+    /// version's crate file is scheduled for removal from the file system straight away.
+    ///
+    /// If `reserve` is set, the version can't be published again unless it has the same
+    /// checksum as the deleted one.
+    pub async fn delete_version(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        actor: &Actor,
+        given_version: String,
+        window: chrono::Duration,
+        reserve: bool,
+    ) -> Result<()> {
+        use crate::schema::crate_versions::dsl::version;
+
+        if !self.permissions.contains(UserPermission::DELETE_CRATE) {
+            return Err(Error::MissingCratePermission(UserPermission::DELETE_CRATE));
+        }
+
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let crate_version = CrateVersion::belonging_to(&self.crate_)
+                    .filter(version.eq(&given_version))
+                    .get_result::<CrateVersion<'_>>(&conn)
+                    .optional()?
+                    .ok_or(Error::MissingVersion)?;
+
+                if crate_version.created_at + window < chrono::Utc::now().naive_utc() {
+                    return Err(Error::DeletionWindowPassed(window.num_hours()));
+                }
+
+                delete_version_blocking(&conn, &self.crate_, crate_version, reserve)?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::VersionDelete)
+                        .for_crate(&self.crate_)
+                        .version(given_version),
+                )
+            })
+        })
+        .await?
+    }
+
+    /// Deletes the crate along with all of its versions, so long as every version was published
+    /// within `window`. See [`CrateWithPermissions::delete_version`] for `reserve`.
+    pub async fn delete(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        actor: &Actor,
+        window: chrono::Duration,
+        reserve: bool,
+    ) -> Result<()> {
+        if !self.permissions.contains(UserPermission::DELETE_CRATE) {
+            return Err(Error::MissingCratePermission(UserPermission::DELETE_CRATE));
+        }
+
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let versions =
+                    CrateVersion::belonging_to(&self.crate_).load::<CrateVersion<'_>>(&conn)?;
+
+                let now = chrono::Utc::now().naive_utc();
+                if versions.iter().any(|v| v.created_at + window < now) {
+                    return Err(Error::DeletionWindowPassed(window.num_hours()));
+                }
+
+                for crate_version in versions {
+                    delete_version_blocking(&conn, &self.crate_, crate_version, reserve)?;
+                }
+
+                diesel::delete(
+                    user_crate_permissions::table
+                        .filter(user_crate_permissions::crate_id.eq(self.crate_.id)),
+                )
+                .execute(&conn)?;
+
+                diesel::delete(
+                    group_crate_permissions::table
+                        .filter(group_crate_permissions::crate_id.eq(self.crate_.id)),
+                )
+                .execute(&conn)?;
+
+                diesel::delete(
+                    crate_trusted_publishers::table
+                        .filter(crate_trusted_publishers::crate_id.eq(self.crate_.id)),
+                )
+                .execute(&conn)?;
+
+                diesel::delete(
+                    crate_redirects::table.filter(crate_redirects::crate_id.eq(self.crate_.id)),
+                )
+                .execute(&conn)?;
+
+                diesel::delete(crates::table.find(self.crate_.id)).execute(&conn)?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::CrateDelete).for_crate(&self.crate_),
+                )
+            })
         })
         .await?
     }
+
+    fn enqueue_member_update(
+        &self,
+        conn: &crate::Connection,
+        given_user_id: i32,
+        given_permissions: Option<UserPermission>,
+    ) -> Result<()> {
+        let payload = WebhookPayload::member_update(
+            conn,
+            Some(self.crate_.name.clone()),
+            given_user_id,
+            given_permissions,
+        )?;
+
+        enqueue_webhook(conn, self.crate_.organisation_id, &payload)
+    }
+
+    /// Ensures the requesting user holds every permission the user currently has on the crate,
+    /// wherever they were granted from, so they can't demote, restrict or remove someone more
+    /// privileged than themselves.
+    fn ensure_can_manage_member(&self, conn: &crate::Connection, given_user_id: i32) -> Result<()> {
+        let current: Option<UserPermission> = crate_with_permissions!(given_user_id)
+            .inner_join(organisations::table)
+            .filter(crates::id.eq(self.crate_.id))
+            .select(select_permissions!(given_user_id))
+            .get_result(conn)
+            .optional()?;
+
+        ensure_grantable(self.permissions, current.unwrap_or_default())
+    }
+}
+
+/// Removes a version's rows and schedules its crate file for deletion, reserving the version
+/// against its checksum if requested.
+fn delete_version_blocking(
+    conn: &crate::Connection,
+    crate_: &Crate,
+    crate_version: CrateVersion<'_>,
+    reserve: bool,
+) -> Result<()> {
+    insert_into(storage_deletions::table)
+        .values((
+            storage_deletions::organisation_id.eq(crate_.organisation_id),
+            storage_deletions::filesystem_object.eq(&crate_version.filesystem_object),
+            storage_deletions::delete_after.eq(chrono::Utc::now().naive_utc()),
+            storage_deletions::reason.eq(StorageDeletionReason::VersionDelete),
+        ))
+        .execute(conn)?;
+
+    diesel::delete(
+        crate_version_downloads::table
+            .filter(crate_version_downloads::crate_version_id.eq(crate_version.id)),
+    )
+    .execute(conn)?;
+
+    diesel::delete(crate_versions::table.find(crate_version.id)).execute(conn)?;
+
+    if !reserve {
+        return Ok(());
+    }
+
+    // the version may have already been reserved by a previous deletion, in which case it could
+    // only have been republished with the same checksum
+    let already_reserved = crate_version_reservations::table
+        .filter(crate_version_reservations::organisation_id.eq(crate_.organisation_id))
+        .filter(crate_version_reservations::crate_name.eq(&crate_.name))
+        .filter(crate_version_reservations::version.eq(&crate_version.version))
+        .count()
+        .get_result::<i64>(conn)?
+        > 0;
+
+    if !already_reserved {
+        insert_into(crate_version_reservations::table)
+            .values((
+                crate_version_reservations::organisation_id.eq(crate_.organisation_id),
+                crate_version_reservations::crate_name.eq(&crate_.name),
+                crate_version_reservations::version.eq(&crate_version.version),
+                crate_version_reservations::checksum.eq(&crate_version.checksum),
+            ))
+            .execute(conn)?;
+    }
+
+    Ok(())
 }
 
 #[derive(Identifiable, Queryable, Associations, PartialEq, Eq, Debug)]
@@ -678,3 +1444,645 @@ impl From<chartered_types::cargo::CrateFeatures> for CrateFeatures {
         Self(o)
     }
 }
+
+#[cfg(all(test, feature = "sqlite"))]
+mod test {
+    use super::PermissionSource;
+    use crate::{permissions::UserPermission, scopes::TokenScope, testing, Error};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn organisation_permissions_are_inherited_less_restrictions() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        let member = testing::user(&conn, "member").await;
+        testing::organisation(&conn, "org", &owner).await;
+        let crate_ = testing::create_crate(&conn, "org", "my-crate", &owner).await;
+        let actor = testing::actor(&owner);
+
+        testing::add_member(
+            &conn,
+            "org",
+            &owner,
+            &member,
+            UserPermission::VISIBLE
+                | UserPermission::PUBLISH_VERSION
+                | UserPermission::YANK_VERSION,
+        )
+        .await;
+
+        let found = testing::find_crate(&conn, "org", "my-crate", &member)
+            .await
+            .unwrap();
+        assert_eq!(
+            found.permissions,
+            UserPermission::VISIBLE
+                | UserPermission::PUBLISH_VERSION
+                | UserPermission::YANK_VERSION
+        );
+
+        // a crate-level grant adds to the inherited permissions, while its restrictions take
+        // away from them
+        crate_
+            .clone()
+            .insert_permissions(
+                conn.clone(),
+                &actor,
+                member.id,
+                UserPermission::MANAGE_USERS,
+                UserPermission::YANK_VERSION,
+                None,
+            )
+            .await
+            .unwrap();
+
+        let found = testing::find_crate(&conn, "org", "my-crate", &member)
+            .await
+            .unwrap();
+        assert_eq!(
+            found.permissions,
+            UserPermission::VISIBLE
+                | UserPermission::PUBLISH_VERSION
+                | UserPermission::MANAGE_USERS
+        );
+
+        let members = crate_.clone().members(conn.clone()).await.unwrap();
+        let found_member = members.iter().find(|v| v.user.id == member.id).unwrap();
+        assert_eq!(
+            found_member.restricted_permissions,
+            UserPermission::YANK_VERSION
+        );
+        assert_eq!(
+            found_member.grants,
+            vec![
+                (PermissionSource::Crate, UserPermission::MANAGE_USERS),
+                (
+                    PermissionSource::Organisation,
+                    UserPermission::VISIBLE
+                        | UserPermission::PUBLISH_VERSION
+                        | UserPermission::YANK_VERSION
+                ),
+            ]
+        );
+        assert_eq!(found_member.effective_permissions(), found.permissions);
+
+        // restrictions are left alone when an update doesn't give new ones
+        crate_
+            .clone()
+            .update_permissions(
+                conn.clone(),
+                &actor,
+                member.id,
+                UserPermission::empty(),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+
+        let found = testing::find_crate(&conn, "org", "my-crate", &member)
+            .await
+            .unwrap();
+        assert_eq!(
+            found.permissions,
+            UserPermission::VISIBLE | UserPermission::PUBLISH_VERSION
+        );
+
+        // removing the crate-level grant removes its restrictions with it
+        crate_
+            .clone()
+            .delete_member(conn.clone(), &actor, member.id)
+            .await
+            .unwrap();
+
+        let found = testing::find_crate(&conn, "org", "my-crate", &member)
+            .await
+            .unwrap();
+        assert_eq!(
+            found.permissions,
+            UserPermission::VISIBLE
+                | UserPermission::PUBLISH_VERSION
+                | UserPermission::YANK_VERSION
+        );
+    }
+
+    #[tokio::test]
+    async fn restrictions_can_hide_a_crate() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        let member = testing::user(&conn, "member").await;
+        testing::organisation(&conn, "org", &owner).await;
+        let crate_ = testing::create_crate(&conn, "org", "my-crate", &owner).await;
+
+        testing::add_member(&conn, "org", &owner, &member, UserPermission::VISIBLE).await;
+
+        crate_
+            .insert_permissions(
+                conn.clone(),
+                &testing::actor(&owner),
+                member.id,
+                UserPermission::empty(),
+                UserPermission::VISIBLE,
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            testing::find_crate(&conn, "org", "my-crate", &member).await,
+            Err(Error::MissingCratePermission(UserPermission::VISIBLE))
+        ));
+    }
+
+    #[tokio::test]
+    async fn expired_grants_keep_their_restrictions() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        let member = testing::user(&conn, "member").await;
+        testing::organisation(&conn, "org", &owner).await;
+        let crate_ = testing::create_crate(&conn, "org", "my-crate", &owner).await;
+
+        testing::add_member(
+            &conn,
+            "org",
+            &owner,
+            &member,
+            UserPermission::VISIBLE | UserPermission::PUBLISH_VERSION,
+        )
+        .await;
+
+        crate_
+            .clone()
+            .insert_permissions(
+                conn.clone(),
+                &testing::actor(&owner),
+                member.id,
+                UserPermission::YANK_VERSION,
+                UserPermission::PUBLISH_VERSION,
+                Some(chrono::Utc::now().naive_utc() - chrono::Duration::days(1)),
+            )
+            .await
+            .unwrap();
+
+        let expected = UserPermission::VISIBLE;
+
+        let found = testing::find_crate(&conn, "org", "my-crate", &member)
+            .await
+            .unwrap();
+        assert_eq!(found.permissions, expected);
+
+        assert_eq!(
+            crate::grants::delete_expired(conn.clone()).await.unwrap(),
+            1
+        );
+
+        let found = testing::find_crate(&conn, "org", "my-crate", &member)
+            .await
+            .unwrap();
+        assert_eq!(found.permissions, expected);
+
+        let members = crate_.members(conn).await.unwrap();
+        let found_member = members.iter().find(|v| v.user.id == member.id).unwrap();
+        assert_eq!(found_member.permissions, UserPermission::empty());
+        assert_eq!(
+            found_member.restricted_permissions,
+            UserPermission::PUBLISH_VERSION
+        );
+        assert_eq!(found_member.expires_at, None);
+    }
+
+    #[tokio::test]
+    async fn lapsed_grants_can_be_granted_again() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        let member = testing::user(&conn, "member").await;
+        testing::organisation(&conn, "org", &owner).await;
+        let crate_ = testing::create_crate(&conn, "org", "my-crate", &owner).await;
+        let actor = testing::actor(&owner);
+
+        crate_
+            .clone()
+            .insert_permissions(
+                conn.clone(),
+                &actor,
+                member.id,
+                UserPermission::VISIBLE,
+                UserPermission::empty(),
+                Some(chrono::Utc::now().naive_utc() - chrono::Duration::days(1)),
+            )
+            .await
+            .unwrap();
+
+        assert!(testing::find_crate(&conn, "org", "my-crate", &member)
+            .await
+            .is_err());
+
+        crate_
+            .insert_permissions(
+                conn.clone(),
+                &actor,
+                member.id,
+                UserPermission::VISIBLE | UserPermission::PUBLISH_VERSION,
+                UserPermission::empty(),
+                None,
+            )
+            .await
+            .unwrap();
+
+        let found = testing::find_crate(&conn, "org", "my-crate", &member)
+            .await
+            .unwrap();
+        assert_eq!(
+            found.permissions,
+            UserPermission::VISIBLE | UserPermission::PUBLISH_VERSION
+        );
+    }
+
+    #[tokio::test]
+    async fn managing_members_requires_manage_users() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        let member = testing::user(&conn, "member").await;
+        testing::organisation(&conn, "org", &owner).await;
+        testing::create_crate(&conn, "org", "my-crate", &owner).await;
+
+        testing::add_member(&conn, "org", &owner, &member, UserPermission::VISIBLE).await;
+        let crate_ = testing::find_crate(&conn, "org", "my-crate", &member)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            crate_
+                .clone()
+                .insert_permissions(
+                    conn.clone(),
+                    &testing::actor(&member),
+                    member.id,
+                    UserPermission::PUBLISH_VERSION,
+                    UserPermission::empty(),
+                    None,
+                )
+                .await,
+            Err(Error::MissingCratePermission(UserPermission::MANAGE_USERS))
+        ));
+        assert!(matches!(
+            crate_.members(conn).await,
+            Err(Error::MissingCratePermission(UserPermission::MANAGE_USERS))
+        ));
+    }
+
+    #[tokio::test]
+    async fn moved_crates_redirect_from_their_old_organisation() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        testing::organisation(&conn, "old", &owner).await;
+        let target = testing::organisation(&conn, "new", &owner).await;
+        let crate_ = testing::create_crate(&conn, "old", "my-crate", &owner).await;
+        let actor = testing::actor(&owner);
+
+        let old = testing::find_organisation(&conn, "old", &owner).await;
+        old.create_group(
+            conn.clone(),
+            &actor,
+            "team".to_string(),
+            UserPermission::VISIBLE,
+        )
+        .await
+        .unwrap();
+        crate_
+            .clone()
+            .set_group_permissions(
+                conn.clone(),
+                &actor,
+                "team".to_string(),
+                UserPermission::PUBLISH_VERSION,
+            )
+            .await
+            .unwrap();
+
+        crate_
+            .clone()
+            .move_to(
+                conn.clone(),
+                &actor,
+                Arc::new(TokenScope::default()),
+                target,
+                Some((chrono::Utc::now() + chrono::Duration::days(1)).naive_utc()),
+            )
+            .await
+            .unwrap();
+
+        let moved = testing::find_crate(&conn, "new", "my-crate", &owner)
+            .await
+            .unwrap();
+        assert_eq!(moved.crate_.id, crate_.crate_.id);
+        assert_eq!(moved.permissions, UserPermission::all());
+
+        // group grants belong to the old organisation, so they don't follow the crate
+        assert!(moved.groups(conn.clone()).await.unwrap().is_empty());
+
+        // the old name still resolves, but only for reading
+        let redirected = testing::find_crate(&conn, "old", "my-crate", &owner)
+            .await
+            .unwrap();
+        assert_eq!(redirected.crate_.id, crate_.crate_.id);
+        assert_eq!(redirected.permissions, UserPermission::VISIBLE);
+    }
+
+    #[tokio::test]
+    async fn moving_without_a_redirect() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        testing::organisation(&conn, "old", &owner).await;
+        let target = testing::organisation(&conn, "new", &owner).await;
+        let crate_ = testing::create_crate(&conn, "old", "my-crate", &owner).await;
+
+        crate_
+            .move_to(
+                conn.clone(),
+                &testing::actor(&owner),
+                Arc::new(TokenScope::default()),
+                target,
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            testing::find_crate(&conn, "old", "my-crate", &owner).await,
+            Err(Error::MissingCrate)
+        ));
+        assert!(testing::find_crate(&conn, "new", "my-crate", &owner)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn moving_is_refused_if_the_target_cant_take_the_crate() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        let member = testing::user(&conn, "member").await;
+        testing::organisation(&conn, "old", &owner).await;
+        let target = testing::organisation(&conn, "new", &owner).await;
+        let crate_ = testing::create_crate(&conn, "old", "my-crate", &owner).await;
+        testing::create_crate(&conn, "new", "my-crate", &owner).await;
+
+        assert!(matches!(
+            crate_
+                .clone()
+                .move_to(
+                    conn.clone(),
+                    &testing::actor(&owner),
+                    Arc::new(TokenScope::default()),
+                    target,
+                    None,
+                )
+                .await,
+            Err(Error::CrateNameTaken)
+        ));
+
+        // the member can manage the crate, but can't create crates in the target
+        testing::add_member(&conn, "old", &owner, &member, UserPermission::all()).await;
+        testing::add_member(&conn, "new", &owner, &member, UserPermission::VISIBLE).await;
+        let crate_ = testing::find_crate(&conn, "old", "my-crate", &member)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            crate_
+                .move_to(
+                    conn.clone(),
+                    &testing::actor(&member),
+                    Arc::new(TokenScope::default()),
+                    testing::find_organisation(&conn, "new", &member).await,
+                    None,
+                )
+                .await,
+            Err(Error::MissingOrganisationPermission(
+                UserPermission::CREATE_CRATE
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn deleted_versions_can_be_reserved() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        testing::organisation(&conn, "org", &owner).await;
+        let crate_ = testing::create_crate(&conn, "org", "my-crate", &owner).await;
+        let actor = testing::actor(&owner);
+
+        testing::publish(&conn, &crate_, &owner, "1.0.0", "aaa")
+            .await
+            .unwrap();
+        testing::publish(&conn, &crate_, &owner, "1.1.0", "bbb")
+            .await
+            .unwrap();
+
+        crate_
+            .clone()
+            .delete_version(
+                conn.clone(),
+                &actor,
+                "1.0.0".to_string(),
+                chrono::Duration::hours(1),
+                true,
+            )
+            .await
+            .unwrap();
+        crate_
+            .clone()
+            .delete_version(
+                conn.clone(),
+                &actor,
+                "1.1.0".to_string(),
+                chrono::Duration::hours(1),
+                false,
+            )
+            .await
+            .unwrap();
+
+        assert!(crate_
+            .clone()
+            .version(conn.clone(), "1.0.0".to_string())
+            .await
+            .unwrap()
+            .is_none());
+        assert!(matches!(
+            crate_
+                .clone()
+                .delete_version(
+                    conn.clone(),
+                    &actor,
+                    "1.0.0".to_string(),
+                    chrono::Duration::hours(1),
+                    false,
+                )
+                .await,
+            Err(Error::MissingVersion)
+        ));
+
+        // a reserved version can only be republished with the same content
+        assert!(matches!(
+            testing::publish(&conn, &crate_, &owner, "1.0.0", "ccc").await,
+            Err(Error::ReservedVersion(_))
+        ));
+        testing::publish(&conn, &crate_, &owner, "1.0.0", "aaa")
+            .await
+            .unwrap();
+
+        // versions deleted without a reservation can be republished with anything
+        testing::publish(&conn, &crate_, &owner, "1.1.0", "ccc")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn deletion_is_limited_to_the_window() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        testing::organisation(&conn, "org", &owner).await;
+        let crate_ = testing::create_crate(&conn, "org", "my-crate", &owner).await;
+        let actor = testing::actor(&owner);
+
+        testing::publish(&conn, &crate_, &owner, "1.0.0", "aaa")
+            .await
+            .unwrap();
+
+        // a negative window means every version is already past it
+        let window = chrono::Duration::seconds(-1);
+
+        assert!(matches!(
+            crate_
+                .clone()
+                .delete_version(conn.clone(), &actor, "1.0.0".to_string(), window, false)
+                .await,
+            Err(Error::DeletionWindowPassed(_))
+        ));
+        assert!(matches!(
+            crate_
+                .clone()
+                .delete(conn.clone(), &actor, window, false)
+                .await,
+            Err(Error::DeletionWindowPassed(_))
+        ));
+
+        crate_
+            .delete(conn.clone(), &actor, chrono::Duration::hours(1), false)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            testing::find_crate(&conn, "org", "my-crate", &owner).await,
+            Err(Error::MissingCrate)
+        ));
+    }
+
+    #[tokio::test]
+    async fn deletion_requires_delete_crate() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        let member = testing::user(&conn, "member").await;
+        testing::organisation(&conn, "org", &owner).await;
+        let crate_ = testing::create_crate(&conn, "org", "my-crate", &owner).await;
+
+        testing::publish(&conn, &crate_, &owner, "1.0.0", "aaa")
+            .await
+            .unwrap();
+
+        let mut permissions = UserPermission::all();
+        permissions.remove(UserPermission::DELETE_CRATE);
+        testing::add_member(&conn, "org", &owner, &member, permissions).await;
+        let crate_ = testing::find_crate(&conn, "org", "my-crate", &member)
+            .await
+            .unwrap();
+        let actor = testing::actor(&member);
+
+        assert!(matches!(
+            crate_
+                .clone()
+                .delete_version(
+                    conn.clone(),
+                    &actor,
+                    "1.0.0".to_string(),
+                    chrono::Duration::hours(1),
+                    false,
+                )
+                .await,
+            Err(Error::MissingCratePermission(UserPermission::DELETE_CRATE))
+        ));
+        assert!(matches!(
+            crate_
+                .delete(conn, &actor, chrono::Duration::hours(1), false)
+                .await,
+            Err(Error::MissingCratePermission(UserPermission::DELETE_CRATE))
+        ));
+    }
+
+    #[tokio::test]
+    async fn crate_managers_cant_escalate() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        let manager = testing::user(&conn, "manager").await;
+        let target = testing::user(&conn, "target").await;
+        testing::organisation(&conn, "org", &owner).await;
+        testing::create_crate(&conn, "org", "my-crate", &owner).await;
+
+        testing::add_member(
+            &conn,
+            "org",
+            &owner,
+            &manager,
+            UserPermission::VISIBLE | UserPermission::MANAGE_USERS,
+        )
+        .await;
+        let crate_ = testing::find_crate(&conn, "org", "my-crate", &manager)
+            .await
+            .unwrap();
+        let actor = testing::actor(&manager);
+
+        assert!(matches!(
+            crate_
+                .clone()
+                .insert_permissions(
+                    conn.clone(),
+                    &actor,
+                    target.id,
+                    UserPermission::PUBLISH_VERSION,
+                    UserPermission::empty(),
+                    None,
+                )
+                .await,
+            Err(Error::PermissionEscalation(v)) if v == UserPermission::PUBLISH_VERSION
+        ));
+
+        // the owner's permissions are inherited from the organisation, but restricting them on
+        // the crate would still be managing someone more privileged
+        assert!(matches!(
+            crate_
+                .clone()
+                .insert_permissions(
+                    conn.clone(),
+                    &actor,
+                    owner.id,
+                    UserPermission::empty(),
+                    UserPermission::VISIBLE,
+                    None,
+                )
+                .await,
+            Err(Error::PermissionEscalation(_))
+        ));
+        assert!(matches!(
+            crate_.delete_member(conn.clone(), &actor, owner.id).await,
+            Err(Error::PermissionEscalation(_))
+        ));
+
+        assert_eq!(
+            testing::find_crate(&conn, "org", "my-crate", &owner)
+                .await
+                .unwrap()
+                .permissions,
+            UserPermission::all()
+        );
+    }
+}
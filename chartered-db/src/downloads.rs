@@ -0,0 +1,240 @@
+//! Per-version, per-day download statistics.
+//!
+//! Downloads happen far too often to issue an `UPDATE` for every single one, so counts are
+//! aggregated in memory by a [`DownloadTracker`] and periodically flushed out to the database
+//! in a single transaction.
+
+use super::{
+    schema::{crate_version_downloads, crate_versions, crates},
+    ConnectionPool, Error, Result,
+};
+use chrono::{NaiveDate, Utc};
+use diesel::{insert_into, prelude::*};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+#[derive(Hash, PartialEq, Eq, Clone, Copy, Debug)]
+struct PendingDownload {
+    crate_id: i32,
+    crate_version_id: i32,
+    date: NaiveDate,
+}
+
+/// Aggregates download counts in memory until they're flushed to the database.
+#[derive(Default, Debug)]
+pub struct DownloadTracker {
+    pending: Mutex<HashMap<PendingDownload, i32>>,
+}
+
+impl DownloadTracker {
+    /// Records a single download of the given crate version against today's date.
+    pub fn record(&self, crate_id: i32, crate_version_id: i32) {
+        let key = PendingDownload {
+            crate_id,
+            crate_version_id,
+            date: Utc::now().naive_utc().date(),
+        };
+
+        *self.pending.lock().unwrap().entry(key).or_default() += 1;
+    }
+
+    /// Writes all the pending download counts out to the database, bumping both the
+    /// per-version daily count and the crate's total download count. If the write fails the
+    /// counts are put back so they can be retried on the next flush. Counts for versions that
+    /// have since been deleted are discarded.
+    pub async fn flush(self: Arc<Self>, conn: ConnectionPool) -> Result<()> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let res = tokio::task::spawn_blocking({
+            let pending = pending.clone();
+
+            move || {
+                let conn = conn.get()?;
+
+                conn.transaction::<_, Error, _>(|| {
+                    use crate::schema::crate_version_downloads::dsl::{
+                        crate_version_id, date, downloads,
+                    };
+
+                    // versions can be deleted between being downloaded and the flush, there's
+                    // nowhere to record their downloads anymore so they're dropped
+                    let existing: HashSet<i32> = crate_versions::table
+                        .filter(
+                            crate_versions::id.eq_any(
+                                pending
+                                    .keys()
+                                    .map(|v| v.crate_version_id)
+                                    .collect::<Vec<_>>(),
+                            ),
+                        )
+                        .select(crate_versions::id)
+                        .load(&conn)?
+                        .into_iter()
+                        .collect();
+
+                    let mut crate_totals: HashMap<i32, i32> = HashMap::new();
+
+                    for (key, count) in pending
+                        .into_iter()
+                        .filter(|(key, _)| existing.contains(&key.crate_version_id))
+                    {
+                        *crate_totals.entry(key.crate_id).or_default() += count;
+
+                        let updated = diesel::update(
+                            crate_version_downloads::table
+                                .filter(crate_version_id.eq(key.crate_version_id))
+                                .filter(date.eq(key.date)),
+                        )
+                        .set(downloads.eq(downloads + count))
+                        .execute(&conn)?;
+
+                        // first download of this version today, so there's no row to bump yet
+                        if updated == 0 {
+                            insert_into(crate_version_downloads::table)
+                                .values((
+                                    crate_version_id.eq(key.crate_version_id),
+                                    date.eq(key.date),
+                                    downloads.eq(count),
+                                ))
+                                .execute(&conn)?;
+                        }
+                    }
+
+                    for (crate_id, count) in crate_totals {
+                        diesel::update(crates::table.filter(crates::id.eq(crate_id)))
+                            .set(crates::downloads.eq(crates::downloads + count))
+                            .execute(&conn)?;
+                    }
+
+                    Ok(())
+                })
+            }
+        })
+        .await
+        .map_err(Error::from)
+        .and_then(|v| v);
+
+        if res.is_err() {
+            let mut current = self.pending.lock().unwrap();
+
+            for (key, count) in pending {
+                *current.entry(key).or_default() += count;
+            }
+        }
+
+        res
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod test {
+    use super::DownloadTracker;
+    use crate::testing;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn flush_aggregates_downloads() {
+        let conn = testing::pool();
+        let user = testing::user(&conn, "user").await;
+        testing::organisation(&conn, "org", &user).await;
+        let crate_ = testing::create_crate(&conn, "org", "my-crate", &user).await;
+        testing::publish(&conn, &crate_, &user, "1.0.0", "abc")
+            .await
+            .unwrap();
+
+        let version = crate_
+            .clone()
+            .version(conn.clone(), "1.0.0".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        let today = chrono::Utc::now().naive_utc().date();
+
+        let tracker = Arc::new(DownloadTracker::default());
+        tracker.record(crate_.crate_.id, version.id);
+        tracker.record(crate_.crate_.id, version.id);
+        tracker.clone().flush(conn.clone()).await.unwrap();
+
+        // a second flush on the same day bumps the existing row rather than inserting another
+        tracker.record(crate_.crate_.id, version.id);
+        tracker.clone().flush(conn.clone()).await.unwrap();
+
+        assert!(tracker.pending.lock().unwrap().is_empty());
+        assert_eq!(
+            crate_
+                .clone()
+                .version_downloads(conn.clone(), today)
+                .await
+                .unwrap(),
+            vec![("1.0.0".to_string(), today, 3)]
+        );
+
+        let crate_ = testing::find_crate(&conn, "org", "my-crate", &user)
+            .await
+            .unwrap();
+        assert_eq!(crate_.crate_.downloads, 3);
+    }
+
+    #[tokio::test]
+    async fn flush_without_downloads_is_a_no_op() {
+        let tracker = Arc::new(DownloadTracker::default());
+        tracker.flush(testing::pool()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn flush_drops_downloads_of_deleted_versions() {
+        let conn = testing::pool();
+        let user = testing::user(&conn, "user").await;
+        testing::organisation(&conn, "org", &user).await;
+        let crate_ = testing::create_crate(&conn, "org", "my-crate", &user).await;
+        testing::publish(&conn, &crate_, &user, "1.0.0", "abc")
+            .await
+            .unwrap();
+        testing::publish(&conn, &crate_, &user, "1.1.0", "def")
+            .await
+            .unwrap();
+
+        let deleted = crate_
+            .clone()
+            .version(conn.clone(), "1.0.0".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        let kept = crate_
+            .clone()
+            .version(conn.clone(), "1.1.0".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let tracker = Arc::new(DownloadTracker::default());
+        tracker.record(crate_.crate_.id, deleted.id);
+        tracker.record(crate_.crate_.id, kept.id);
+
+        crate_
+            .clone()
+            .delete_version(
+                conn.clone(),
+                &testing::actor(&user),
+                "1.0.0".to_string(),
+                chrono::Duration::hours(1),
+                false,
+            )
+            .await
+            .unwrap();
+
+        tracker.clone().flush(conn.clone()).await.unwrap();
+        assert!(tracker.pending.lock().unwrap().is_empty());
+
+        let crate_ = testing::find_crate(&conn, "org", "my-crate", &user)
+            .await
+            .unwrap();
+        assert_eq!(crate_.crate_.downloads, 1);
+    }
+}
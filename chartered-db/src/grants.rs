@@ -0,0 +1,156 @@
+//! Permission grants given to users with an expiry, such as temporary publish rights for a
+//! contractor. Expired grants are ignored by permission checks as soon as they lapse, and are
+//! removed by a background task in `chartered-web` which records the lapse in the audit log.
+//! Crate grants that also restrict the user only lose their permissions, as the restrictions
+//! weren't granted with an expiry.
+
+use super::{
+    audit::{record_blocking, Actor, AuditAction, NewAuditEvent},
+    crates::Crate,
+    permissions::UserPermission,
+    schema::{crates, user_crate_permissions, user_organisation_permissions},
+    webhooks::{enqueue_blocking as enqueue_webhook, WebhookPayload},
+    ConnectionPool, Error, Result,
+};
+use chrono::Utc;
+use diesel::{prelude::*, sql_types::Text};
+use std::io::Write;
+
+/// Where an organisation grant came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromSqlRow, AsExpression)]
+#[sql_type = "Text"]
+pub enum GrantSource {
+    /// Granted by a member of the organisation, or by accepting an invite.
+    Manual,
+    /// Mapped from the user's groups in an external directory, such as LDAP, and replaced
+    /// each time they log in.
+    Directory,
+}
+
+impl GrantSource {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Manual => "manual",
+            Self::Directory => "directory",
+        }
+    }
+}
+
+impl std::str::FromStr for GrantSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "manual" => Self::Manual,
+            "directory" => Self::Directory,
+            _ => return Err(format!("unknown grant source `{}`", s)),
+        })
+    }
+}
+
+impl<B: diesel::backend::Backend> diesel::deserialize::FromSql<Text, B> for GrantSource
+where
+    String: diesel::deserialize::FromSql<Text, B>,
+{
+    fn from_sql(bytes: Option<&B::RawValue>) -> diesel::deserialize::Result<Self> {
+        String::from_sql(bytes)?.parse().map_err(Into::into)
+    }
+}
+
+impl<B: diesel::backend::Backend> diesel::serialize::ToSql<Text, B> for GrantSource
+where
+    str: diesel::serialize::ToSql<Text, B>,
+{
+    fn to_sql<W: Write>(
+        &self,
+        out: &mut diesel::serialize::Output<'_, W, B>,
+    ) -> diesel::serialize::Result {
+        self.as_str().to_sql(out)
+    }
+}
+
+/// Removes every organisation and crate grant that has expired, returning how many lapsed.
+pub async fn delete_expired(conn: ConnectionPool) -> Result<usize> {
+    tokio::task::spawn_blocking(move || {
+        let conn = conn.get()?;
+
+        conn.transaction::<_, Error, _>(|| {
+            let now = Utc::now().naive_utc();
+            let actor = Actor::system();
+
+            let organisation_grants: Vec<(i32, i32, i32, UserPermission)> =
+                user_organisation_permissions::table
+                    .filter(user_organisation_permissions::expires_at.le(now))
+                    .select((
+                        user_organisation_permissions::id,
+                        user_organisation_permissions::user_id,
+                        user_organisation_permissions::organisation_id,
+                        user_organisation_permissions::permissions,
+                    ))
+                    .load(&conn)?;
+
+            for (id, user_id, organisation_id, permissions) in &organisation_grants {
+                diesel::delete(user_organisation_permissions::table.find(*id)).execute(&conn)?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::OrganisationMemberExpire)
+                        .for_organisation(*organisation_id)
+                        .target_user(*user_id)
+                        .permissions(*permissions),
+                )?;
+
+                let payload = WebhookPayload::member_update(&conn, None, *user_id, None)?;
+                enqueue_webhook(&conn, *organisation_id, &payload)?;
+            }
+
+            let crate_grants: Vec<(i32, i32, UserPermission, UserPermission, Crate)> =
+                user_crate_permissions::table
+                    .inner_join(crates::table)
+                    .filter(user_crate_permissions::expires_at.le(now))
+                    .select((
+                        user_crate_permissions::id,
+                        user_crate_permissions::user_id,
+                        user_crate_permissions::permissions,
+                        user_crate_permissions::restricted_permissions,
+                        crates::all_columns,
+                    ))
+                    .load(&conn)?;
+
+            for (id, user_id, permissions, restricted_permissions, crate_) in &crate_grants {
+                if restricted_permissions.is_empty() {
+                    diesel::delete(user_crate_permissions::table.find(*id)).execute(&conn)?;
+                } else {
+                    diesel::update(user_crate_permissions::table.find(*id))
+                        .set((
+                            user_crate_permissions::permissions.eq(0),
+                            user_crate_permissions::expires_at.eq(None::<chrono::NaiveDateTime>),
+                        ))
+                        .execute(&conn)?;
+                }
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::CrateMemberExpire)
+                        .for_crate(crate_)
+                        .target_user(*user_id)
+                        .permissions(*permissions),
+                )?;
+
+                let payload = WebhookPayload::member_update(
+                    &conn,
+                    Some(crate_.name.clone()),
+                    *user_id,
+                    None,
+                )?;
+                enqueue_webhook(&conn, crate_.organisation_id, &payload)?;
+            }
+
+            Ok(organisation_grants.len() + crate_grants.len())
+        })
+    })
+    .await?
+}
@@ -0,0 +1,922 @@
+//! Named groups of users within an organisation, so permissions can be granted to a whole team
+//! at once rather than one user at a time. Groups can be granted permissions at the organisation
+//! level, which apply to every crate in the organisation, and at the crate level.
+//!
+//! The permissions a user has are the union of those granted to them directly and those
+//! granted to every group they're a member of.
+
+use super::{
+    audit::{record_blocking, Actor, AuditAction, NewAuditEvent},
+    crates::{Crate, CrateWithPermissions},
+    organisations::{Organisation, OrganisationWithPermissions},
+    permissions::{ensure_grantable, UserPermission},
+    schema::{group_crate_permissions, organisation_group_members, organisation_groups, users},
+    users::User,
+    uuid::SqlUuid,
+    ConnectionPool, Error, Result,
+};
+use diesel::{
+    insert_into,
+    prelude::*,
+    result::{DatabaseErrorKind, Error as DieselError},
+    Associations, Identifiable, Queryable,
+};
+use itertools::Itertools;
+use std::{collections::HashMap, sync::Arc};
+
+#[derive(Identifiable, Queryable, Associations, PartialEq, Eq, Hash, Debug)]
+#[belongs_to(Organisation)]
+pub struct OrganisationGroup {
+    pub id: i32,
+    pub uuid: SqlUuid,
+    pub organisation_id: i32,
+    pub name: String,
+    /// Permissions granted to members of the group on every crate in the organisation.
+    pub permissions: UserPermission,
+}
+
+#[derive(Identifiable, Queryable, Associations, PartialEq, Eq, Hash, Debug)]
+#[belongs_to(OrganisationGroup, foreign_key = "group_id")]
+#[belongs_to(User)]
+pub struct OrganisationGroupMember {
+    pub id: i32,
+    pub group_id: i32,
+    pub user_id: i32,
+}
+
+#[derive(Identifiable, Queryable, Associations, PartialEq, Eq, Hash, Debug)]
+#[belongs_to(OrganisationGroup, foreign_key = "group_id")]
+#[belongs_to(Crate)]
+pub struct GroupCratePermission {
+    pub id: i32,
+    pub group_id: i32,
+    pub crate_id: i32,
+    pub permissions: UserPermission,
+}
+
+/// Builds an aggregate expression ORing `column` together across every row. sqlite has no
+/// `BIT_OR` aggregate, so each permission bit is aggregated separately and then summed back
+/// together, which is equivalent as each term can only ever contain its own bit.
+fn bit_or_aggregate(column: &str) -> String {
+    let all = UserPermission::all().bits();
+
+    (0..i32::BITS)
+        .map(|i| 1_i32 << i)
+        .filter(|bit| all & bit != 0)
+        .map(|bit| format!("MAX({} & {})", column, bit))
+        .join(" + ")
+}
+
+/// SQL expression for the permissions `user_id` has been granted by the groups they're in for
+/// the organisation identified by `organisation_id_column` in the outer query.
+pub(crate) fn organisation_group_permissions_sql(
+    user_id: i32,
+    organisation_id_column: &str,
+) -> String {
+    // the user id is an integer, so it's safe to interpolate it into the query
+    format!(
+        "COALESCE((SELECT {} FROM organisation_groups \
+         INNER JOIN organisation_group_members \
+         ON organisation_group_members.group_id = organisation_groups.id \
+         WHERE organisation_group_members.user_id = {} \
+         AND organisation_groups.organisation_id = {}), 0)",
+        bit_or_aggregate("organisation_groups.permissions"),
+        user_id,
+        organisation_id_column,
+    )
+}
+
+/// SQL expression for the permissions `user_id` has been granted by the groups they're in for
+/// the crate identified by `crate_id_column` in the outer query. This doesn't include the
+/// permissions the groups have been granted on the organisation as a whole.
+pub(crate) fn crate_group_permissions_sql(user_id: i32, crate_id_column: &str) -> String {
+    // the user id is an integer, so it's safe to interpolate it into the query
+    format!(
+        "COALESCE((SELECT {} FROM group_crate_permissions \
+         INNER JOIN organisation_group_members \
+         ON organisation_group_members.group_id = group_crate_permissions.group_id \
+         WHERE organisation_group_members.user_id = {} \
+         AND group_crate_permissions.crate_id = {}), 0)",
+        bit_or_aggregate("group_crate_permissions.permissions"),
+        user_id,
+        crate_id_column,
+    )
+}
+
+fn find_group_blocking(
+    conn: &crate::Connection,
+    given_organisation_id: i32,
+    given_name: &str,
+) -> Result<OrganisationGroup> {
+    use crate::schema::organisation_groups::dsl::{name, organisation_id};
+
+    organisation_groups::table
+        .filter(organisation_id.eq(given_organisation_id))
+        .filter(name.eq(given_name))
+        .get_result(conn)
+        .optional()?
+        .ok_or(Error::MissingGroup)
+}
+
+/// Loads the permissions every group member has on the crate through their groups, along with
+/// the name of the group each grant came from. A group's grant on the crate is the union of the
+/// permissions it has across the organisation and on the crate itself.
+pub(crate) fn crate_member_grants_blocking(
+    conn: &crate::Connection,
+    crate_: &Crate,
+) -> Result<Vec<(User, String, UserPermission)>> {
+    use crate::schema::organisation_groups::dsl::organisation_id;
+
+    let groups: Vec<OrganisationGroup> = organisation_groups::table
+        .filter(organisation_id.eq(crate_.organisation_id))
+        .load(conn)?;
+
+    let crate_grants: HashMap<i32, UserPermission> = GroupCratePermission::belonging_to(crate_)
+        .load::<GroupCratePermission>(conn)?
+        .into_iter()
+        .map(|v| (v.group_id, v.permissions))
+        .collect();
+
+    let members = OrganisationGroupMember::belonging_to(&groups)
+        .inner_join(users::table)
+        .load::<(OrganisationGroupMember, User)>(conn)?
+        .grouped_by(&groups);
+
+    Ok(groups
+        .into_iter()
+        .zip(members)
+        .flat_map(|(group, members)| {
+            let permissions =
+                group.permissions | crate_grants.get(&group.id).copied().unwrap_or_default();
+
+            members
+                .into_iter()
+                .map(move |(_, user)| (user, group.name.clone(), permissions))
+        })
+        .filter(|(_, _, permissions)| !permissions.is_empty())
+        .collect())
+}
+
+impl OrganisationWithPermissions {
+    /// Lists all the groups in the organisation along with their members.
+    pub async fn groups(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+    ) -> Result<Vec<(OrganisationGroup, Vec<User>)>> {
+        if !self.permissions().contains(UserPermission::VISIBLE) {
+            return Err(Error::MissingOrganisationPermission(
+                UserPermission::VISIBLE,
+            ));
+        }
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            let groups: Vec<OrganisationGroup> =
+                OrganisationGroup::belonging_to(self.organisation())
+                    .order_by(organisation_groups::name.asc())
+                    .load(&conn)?;
+
+            let members = OrganisationGroupMember::belonging_to(&groups)
+                .inner_join(users::table)
+                .load::<(OrganisationGroupMember, User)>(&conn)?
+                .grouped_by(&groups);
+
+            Ok(groups
+                .into_iter()
+                .zip(members)
+                .map(|(group, members)| {
+                    (group, members.into_iter().map(|(_, user)| user).collect())
+                })
+                .collect())
+        })
+        .await?
+    }
+
+    pub async fn create_group(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        actor: &Actor,
+        given_name: String,
+        given_permissions: UserPermission,
+    ) -> Result<OrganisationGroup> {
+        use crate::schema::organisation_groups::dsl::{name, organisation_id, permissions, uuid};
+
+        if !self.permissions().contains(UserPermission::MANAGE_USERS) {
+            return Err(Error::MissingOrganisationPermission(
+                UserPermission::MANAGE_USERS,
+            ));
+        }
+
+        ensure_grantable(self.permissions(), given_permissions)?;
+
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let generated_uuid = SqlUuid::random();
+
+                let res = insert_into(organisation_groups::table)
+                    .values((
+                        uuid.eq(generated_uuid),
+                        organisation_id.eq(self.organisation().id),
+                        name.eq(&given_name),
+                        permissions.eq(given_permissions.bits()),
+                    ))
+                    .execute(&conn);
+
+                match res {
+                    Ok(_) => {}
+                    Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                        return Err(Error::DuplicateGroup);
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+
+                let inserted: OrganisationGroup = organisation_groups::table
+                    .filter(uuid.eq(generated_uuid))
+                    .get_result(&conn)?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::GroupInsert)
+                        .for_organisation(self.organisation().id)
+                        .group(given_name)
+                        .permissions(given_permissions),
+                )?;
+
+                Ok(inserted)
+            })
+        })
+        .await?
+    }
+
+    /// Updates the permissions the group has across the whole organisation.
+    pub async fn update_group(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        actor: &Actor,
+        given_name: String,
+        given_permissions: UserPermission,
+    ) -> Result<()> {
+        use crate::schema::organisation_groups::dsl::{id, permissions};
+
+        if !self.permissions().contains(UserPermission::MANAGE_USERS) {
+            return Err(Error::MissingOrganisationPermission(
+                UserPermission::MANAGE_USERS,
+            ));
+        }
+
+        ensure_grantable(self.permissions(), given_permissions)?;
+
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let group = find_group_blocking(&conn, self.organisation().id, &given_name)?;
+                ensure_grantable(self.permissions(), group.permissions)?;
+
+                diesel::update(organisation_groups::table.filter(id.eq(group.id)))
+                    .set(permissions.eq(given_permissions.bits()))
+                    .execute(&conn)?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::GroupUpdate)
+                        .for_organisation(self.organisation().id)
+                        .group(given_name)
+                        .permissions(given_permissions),
+                )
+            })
+        })
+        .await?
+    }
+
+    /// Deletes the group along with its memberships and any permissions it was granted on
+    /// crates.
+    pub async fn delete_group(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        actor: &Actor,
+        given_name: String,
+    ) -> Result<()> {
+        use crate::schema::{
+            group_crate_permissions::dsl::group_id as crate_group_id,
+            organisation_group_members::dsl::group_id as member_group_id,
+            organisation_groups::dsl::id,
+        };
+
+        if !self.permissions().contains(UserPermission::MANAGE_USERS) {
+            return Err(Error::MissingOrganisationPermission(
+                UserPermission::MANAGE_USERS,
+            ));
+        }
+
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let group = find_group_blocking(&conn, self.organisation().id, &given_name)?;
+                ensure_grantable(self.permissions(), group.permissions)?;
+
+                diesel::delete(
+                    organisation_group_members::table.filter(member_group_id.eq(group.id)),
+                )
+                .execute(&conn)?;
+                diesel::delete(group_crate_permissions::table.filter(crate_group_id.eq(group.id)))
+                    .execute(&conn)?;
+                diesel::delete(organisation_groups::table.filter(id.eq(group.id)))
+                    .execute(&conn)?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::GroupDelete)
+                        .for_organisation(self.organisation().id)
+                        .group(given_name),
+                )
+            })
+        })
+        .await?
+    }
+
+    /// Adds the user to the group, returning `false` if they were already a member.
+    pub async fn insert_group_member(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        actor: &Actor,
+        given_name: String,
+        given_user_id: i32,
+    ) -> Result<bool> {
+        use crate::schema::organisation_group_members::dsl::{group_id, user_id};
+
+        if !self.permissions().contains(UserPermission::MANAGE_USERS) {
+            return Err(Error::MissingOrganisationPermission(
+                UserPermission::MANAGE_USERS,
+            ));
+        }
+
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let group = find_group_blocking(&conn, self.organisation().id, &given_name)?;
+                ensure_grantable(self.permissions(), group.permissions)?;
+
+                let existing: Option<OrganisationGroupMember> = organisation_group_members::table
+                    .filter(group_id.eq(group.id))
+                    .filter(user_id.eq(given_user_id))
+                    .get_result(&conn)
+                    .optional()?;
+
+                if existing.is_some() {
+                    return Ok(false);
+                }
+
+                insert_into(organisation_group_members::table)
+                    .values((group_id.eq(group.id), user_id.eq(given_user_id)))
+                    .execute(&conn)?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::GroupMemberInsert)
+                        .for_organisation(self.organisation().id)
+                        .target_user(given_user_id)
+                        .group(given_name),
+                )?;
+
+                Ok(true)
+            })
+        })
+        .await?
+    }
+
+    /// Removes the user from the group, returning `false` if they weren't a member.
+    pub async fn delete_group_member(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        actor: &Actor,
+        given_name: String,
+        given_user_id: i32,
+    ) -> Result<bool> {
+        use crate::schema::organisation_group_members::dsl::{group_id, user_id};
+
+        if !self.permissions().contains(UserPermission::MANAGE_USERS) {
+            return Err(Error::MissingOrganisationPermission(
+                UserPermission::MANAGE_USERS,
+            ));
+        }
+
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let group = find_group_blocking(&conn, self.organisation().id, &given_name)?;
+                ensure_grantable(self.permissions(), group.permissions)?;
+
+                let deleted = diesel::delete(
+                    organisation_group_members::table
+                        .filter(group_id.eq(group.id))
+                        .filter(user_id.eq(given_user_id)),
+                )
+                .execute(&conn)?;
+
+                if deleted == 0 {
+                    return Ok(false);
+                }
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::GroupMemberDelete)
+                        .for_organisation(self.organisation().id)
+                        .target_user(given_user_id)
+                        .group(given_name),
+                )?;
+
+                Ok(true)
+            })
+        })
+        .await?
+    }
+}
+
+impl CrateWithPermissions {
+    /// Ensures the requesting user holds every permission the group currently has on the
+    /// crate, so they can't change the grant of a group more privileged than themselves.
+    fn ensure_can_manage_group(&self, conn: &crate::Connection, given_group_id: i32) -> Result<()> {
+        use crate::schema::group_crate_permissions::dsl::{crate_id, group_id, permissions};
+
+        let current: Option<UserPermission> = group_crate_permissions::table
+            .filter(group_id.eq(given_group_id))
+            .filter(crate_id.eq(self.crate_.id))
+            .select(permissions)
+            .get_result(conn)
+            .optional()?;
+
+        ensure_grantable(self.permissions, current.unwrap_or_default())
+    }
+
+    /// Lists the groups that have been granted permissions on this crate specifically, this
+    /// doesn't include the permissions groups have across the whole organisation.
+    pub async fn groups(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+    ) -> Result<Vec<(OrganisationGroup, UserPermission)>> {
+        if !self.permissions.contains(UserPermission::MANAGE_USERS) {
+            return Err(Error::MissingCratePermission(UserPermission::MANAGE_USERS));
+        }
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            Ok(GroupCratePermission::belonging_to(&self.crate_)
+                .inner_join(organisation_groups::table)
+                .select((
+                    organisation_groups::all_columns,
+                    group_crate_permissions::permissions,
+                ))
+                .order_by(organisation_groups::name.asc())
+                .load(&conn)?)
+        })
+        .await?
+    }
+
+    /// Grants the group the given permissions on this crate, replacing any permissions it
+    /// already had on the crate.
+    pub async fn set_group_permissions(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        actor: &Actor,
+        given_name: String,
+        given_permissions: UserPermission,
+    ) -> Result<()> {
+        use crate::schema::group_crate_permissions::dsl::{crate_id, group_id, permissions};
+
+        if !self.permissions.contains(UserPermission::MANAGE_USERS) {
+            return Err(Error::MissingCratePermission(UserPermission::MANAGE_USERS));
+        }
+
+        ensure_grantable(self.permissions, given_permissions)?;
+
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let group = find_group_blocking(&conn, self.crate_.organisation_id, &given_name)?;
+                self.ensure_can_manage_group(&conn, group.id)?;
+
+                let updated = diesel::update(
+                    group_crate_permissions::table
+                        .filter(group_id.eq(group.id))
+                        .filter(crate_id.eq(self.crate_.id)),
+                )
+                .set(permissions.eq(given_permissions.bits()))
+                .execute(&conn)?;
+
+                if updated == 0 {
+                    insert_into(group_crate_permissions::table)
+                        .values((
+                            group_id.eq(group.id),
+                            crate_id.eq(self.crate_.id),
+                            permissions.eq(given_permissions.bits()),
+                        ))
+                        .execute(&conn)?;
+                }
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::CrateGroupUpdate)
+                        .for_crate(&self.crate_)
+                        .group(given_name)
+                        .permissions(given_permissions),
+                )
+            })
+        })
+        .await?
+    }
+
+    /// Removes any permissions the group was granted on this crate, returning `false` if it
+    /// didn't have any.
+    pub async fn delete_group_permissions(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        actor: &Actor,
+        given_name: String,
+    ) -> Result<bool> {
+        use crate::schema::group_crate_permissions::dsl::{crate_id, group_id};
+
+        if !self.permissions.contains(UserPermission::MANAGE_USERS) {
+            return Err(Error::MissingCratePermission(UserPermission::MANAGE_USERS));
+        }
+
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let group = find_group_blocking(&conn, self.crate_.organisation_id, &given_name)?;
+                self.ensure_can_manage_group(&conn, group.id)?;
+
+                let deleted = diesel::delete(
+                    group_crate_permissions::table
+                        .filter(group_id.eq(group.id))
+                        .filter(crate_id.eq(self.crate_.id)),
+                )
+                .execute(&conn)?;
+
+                if deleted == 0 {
+                    return Ok(false);
+                }
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::CrateGroupDelete)
+                        .for_crate(&self.crate_)
+                        .group(given_name),
+                )?;
+
+                Ok(true)
+            })
+        })
+        .await?
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::bit_or_aggregate;
+    use crate::permissions::UserPermission;
+    #[cfg(feature = "sqlite")]
+    use crate::{crates::PermissionSource, testing, Error};
+
+    #[test]
+    fn bit_or_aggregate_covers_every_permission() {
+        let aggregate = bit_or_aggregate("p");
+
+        assert!(aggregate.starts_with("MAX(p & 1) + MAX(p & 2)"));
+        assert_eq!(
+            aggregate.matches("MAX(").count(),
+            UserPermission::all().bits().count_ones() as usize
+        );
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn group_permissions_apply_to_members() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        let member = testing::user(&conn, "member").await;
+        let organisation = testing::organisation(&conn, "org", &owner).await;
+        let crate_ = testing::create_crate(&conn, "org", "my-crate", &owner).await;
+        let actor = testing::actor(&owner);
+
+        organisation
+            .clone()
+            .create_group(
+                conn.clone(),
+                &actor,
+                "team".to_string(),
+                UserPermission::VISIBLE | UserPermission::PUBLISH_VERSION,
+            )
+            .await
+            .unwrap();
+
+        // the group's permissions don't apply to anyone until they're a member of it
+        assert!(testing::find_crate(&conn, "org", "my-crate", &member)
+            .await
+            .is_err());
+
+        assert!(organisation
+            .clone()
+            .insert_group_member(conn.clone(), &actor, "team".to_string(), member.id)
+            .await
+            .unwrap());
+        assert!(!organisation
+            .clone()
+            .insert_group_member(conn.clone(), &actor, "team".to_string(), member.id)
+            .await
+            .unwrap());
+
+        let found = testing::find_crate(&conn, "org", "my-crate", &member)
+            .await
+            .unwrap();
+        assert_eq!(
+            found.permissions,
+            UserPermission::VISIBLE | UserPermission::PUBLISH_VERSION
+        );
+
+        // crate-level grants add to those the group has across the organisation
+        crate_
+            .clone()
+            .set_group_permissions(
+                conn.clone(),
+                &actor,
+                "team".to_string(),
+                UserPermission::YANK_VERSION,
+            )
+            .await
+            .unwrap();
+
+        let found = testing::find_crate(&conn, "org", "my-crate", &member)
+            .await
+            .unwrap();
+        assert_eq!(
+            found.permissions,
+            UserPermission::VISIBLE
+                | UserPermission::PUBLISH_VERSION
+                | UserPermission::YANK_VERSION
+        );
+
+        let members = crate_.clone().members(conn.clone()).await.unwrap();
+        let grants = &members
+            .iter()
+            .find(|v| v.user.id == member.id)
+            .unwrap()
+            .grants;
+        assert_eq!(
+            grants,
+            &vec![(
+                PermissionSource::Group("team".to_string()),
+                UserPermission::VISIBLE
+                    | UserPermission::PUBLISH_VERSION
+                    | UserPermission::YANK_VERSION
+            )]
+        );
+
+        assert!(organisation
+            .clone()
+            .delete_group_member(conn.clone(), &actor, "team".to_string(), member.id)
+            .await
+            .unwrap());
+        assert!(!organisation
+            .delete_group_member(conn.clone(), &actor, "team".to_string(), member.id)
+            .await
+            .unwrap());
+
+        assert!(testing::find_crate(&conn, "org", "my-crate", &member)
+            .await
+            .is_err());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn deleting_a_group_removes_its_grants() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        let organisation = testing::organisation(&conn, "org", &owner).await;
+        let crate_ = testing::create_crate(&conn, "org", "my-crate", &owner).await;
+        let actor = testing::actor(&owner);
+
+        organisation
+            .clone()
+            .create_group(
+                conn.clone(),
+                &actor,
+                "team".to_string(),
+                UserPermission::VISIBLE,
+            )
+            .await
+            .unwrap();
+        crate_
+            .clone()
+            .set_group_permissions(
+                conn.clone(),
+                &actor,
+                "team".to_string(),
+                UserPermission::PUBLISH_VERSION,
+            )
+            .await
+            .unwrap();
+        assert_eq!(crate_.clone().groups(conn.clone()).await.unwrap().len(), 1);
+
+        organisation
+            .clone()
+            .delete_group(conn.clone(), &actor, "team".to_string())
+            .await
+            .unwrap();
+
+        assert!(crate_
+            .clone()
+            .groups(conn.clone())
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(organisation
+            .clone()
+            .groups(conn.clone())
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(matches!(
+            organisation
+                .delete_group(conn.clone(), &actor, "team".to_string())
+                .await,
+            Err(Error::MissingGroup)
+        ));
+        assert!(matches!(
+            crate_
+                .delete_group_permissions(conn, &actor, "team".to_string())
+                .await,
+            Err(Error::MissingGroup)
+        ));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn managing_groups_requires_manage_users() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        let member = testing::user(&conn, "member").await;
+        let organisation = testing::organisation(&conn, "org", &owner).await;
+
+        organisation
+            .clone()
+            .create_group(
+                conn.clone(),
+                &testing::actor(&owner),
+                "team".to_string(),
+                UserPermission::VISIBLE,
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            organisation
+                .create_group(
+                    conn.clone(),
+                    &testing::actor(&owner),
+                    "team".to_string(),
+                    UserPermission::VISIBLE,
+                )
+                .await,
+            Err(Error::DuplicateGroup)
+        ));
+
+        testing::add_member(&conn, "org", &owner, &member, UserPermission::VISIBLE).await;
+        let organisation = testing::find_organisation(&conn, "org", &member).await;
+
+        assert!(matches!(
+            organisation
+                .clone()
+                .create_group(
+                    conn.clone(),
+                    &testing::actor(&member),
+                    "other".to_string(),
+                    UserPermission::VISIBLE,
+                )
+                .await,
+            Err(Error::MissingOrganisationPermission(
+                UserPermission::MANAGE_USERS
+            ))
+        ));
+        assert!(matches!(
+            organisation
+                .insert_group_member(
+                    conn,
+                    &testing::actor(&member),
+                    "team".to_string(),
+                    member.id,
+                )
+                .await,
+            Err(Error::MissingOrganisationPermission(
+                UserPermission::MANAGE_USERS
+            ))
+        ));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn group_managers_cant_escalate() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        let manager = testing::user(&conn, "manager").await;
+        let organisation = testing::organisation(&conn, "org", &owner).await;
+        let crate_ = testing::create_crate(&conn, "org", "my-crate", &owner).await;
+
+        organisation
+            .create_group(
+                conn.clone(),
+                &testing::actor(&owner),
+                "admins".to_string(),
+                UserPermission::all(),
+            )
+            .await
+            .unwrap();
+        crate_
+            .set_group_permissions(
+                conn.clone(),
+                &testing::actor(&owner),
+                "admins".to_string(),
+                UserPermission::DELETE_CRATE,
+            )
+            .await
+            .unwrap();
+
+        testing::add_member(
+            &conn,
+            "org",
+            &owner,
+            &manager,
+            UserPermission::VISIBLE | UserPermission::MANAGE_USERS,
+        )
+        .await;
+        let organisation = testing::find_organisation(&conn, "org", &manager).await;
+        let crate_ = testing::find_crate(&conn, "org", "my-crate", &manager)
+            .await
+            .unwrap();
+        let actor = testing::actor(&manager);
+
+        assert!(matches!(
+            organisation
+                .clone()
+                .create_group(
+                    conn.clone(),
+                    &actor,
+                    "team".to_string(),
+                    UserPermission::DELETE_CRATE,
+                )
+                .await,
+            Err(Error::PermissionEscalation(_))
+        ));
+
+        // joining a more privileged group is just as much an escalation as being granted its
+        // permissions directly
+        assert!(matches!(
+            organisation
+                .clone()
+                .insert_group_member(conn.clone(), &actor, "admins".to_string(), manager.id)
+                .await,
+            Err(Error::PermissionEscalation(_))
+        ));
+        assert!(matches!(
+            organisation
+                .delete_group(conn.clone(), &actor, "admins".to_string())
+                .await,
+            Err(Error::PermissionEscalation(_))
+        ));
+        assert!(matches!(
+            crate_
+                .delete_group_permissions(conn.clone(), &actor, "admins".to_string())
+                .await,
+            Err(Error::PermissionEscalation(_))
+        ));
+    }
+}
@@ -0,0 +1,725 @@
+//! Invitations for users to join an organisation, so they get a say in whether they're added as
+//! a member. Invites are either addressed to an existing user, who'll see it listed against
+//! their account, or are a single-use link that can be handed to someone who hasn't signed up
+//! yet.
+//!
+//! Only a SHA-256 hash of a link's token is stored, so the link is only ever shown to the
+//! inviter once when it's created.
+
+use super::{
+    audit::{record_blocking, Actor, AuditAction, NewAuditEvent},
+    grants::GrantSource,
+    organisations::{Organisation, OrganisationWithPermissions},
+    permissions::{ensure_grantable, UserPermission},
+    schema::{organisation_invites, organisations, user_organisation_permissions, users},
+    users::User,
+    uuid::SqlUuid,
+    webhooks::{enqueue_blocking as enqueue_webhook, WebhookPayload},
+    ConnectionPool, Error, Result,
+};
+use chrono::{NaiveDateTime, Utc};
+use diesel::{insert_into, prelude::*, Associations, Identifiable, Queryable};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+/// Prefix given to every invite link's token.
+const TOKEN_PREFIX: &str = "chartered_invite_";
+
+/// Number of random characters following the prefix.
+const TOKEN_LENGTH: usize = 48;
+
+/// Hashes a token for storage or lookup.
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[derive(Identifiable, Queryable, Associations, PartialEq, Eq, Hash, Debug)]
+#[belongs_to(Organisation)]
+#[belongs_to(User)]
+pub struct OrganisationInvite {
+    pub id: i32,
+    pub uuid: SqlUuid,
+    pub organisation_id: i32,
+    /// The user the invite is addressed to, or `None` if the invite is a link.
+    pub user_id: Option<i32>,
+    pub token_hash: Option<String>,
+    /// Permissions the invitee will be given on the organisation when they accept.
+    pub permissions: UserPermission,
+    pub invited_by_user_id: i32,
+    pub expires_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+}
+
+impl OrganisationInvite {
+    /// Lists the unexpired invites addressed to the user, along with the organisation they're
+    /// being invited to.
+    pub async fn list_for_user(
+        conn: ConnectionPool,
+        given_user_id: i32,
+    ) -> Result<Vec<(OrganisationInvite, Organisation)>> {
+        use crate::schema::organisation_invites::dsl::{created_at, expires_at, user_id};
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            Ok(organisation_invites::table
+                .inner_join(organisations::table)
+                .filter(organisations::deleted_at.is_null())
+                .filter(user_id.eq(given_user_id))
+                .filter(expires_at.gt(Utc::now().naive_utc()))
+                .select((
+                    organisation_invites::all_columns,
+                    organisations::all_columns,
+                ))
+                .order_by(created_at.desc())
+                .load(&conn)?)
+        })
+        .await?
+    }
+
+    /// Accepts an invite addressed to the user, adding them to the organisation with the
+    /// permissions they were invited with.
+    pub async fn accept(
+        conn: ConnectionPool,
+        actor: &Actor,
+        given_user_id: i32,
+        given_uuid: uuid::Uuid,
+    ) -> Result<Organisation> {
+        use crate::schema::organisation_invites::dsl::{expires_at, user_id, uuid};
+
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let invite: OrganisationInvite = organisation_invites::table
+                    .filter(user_id.eq(given_user_id))
+                    .filter(uuid.eq(SqlUuid(given_uuid)))
+                    .filter(expires_at.gt(Utc::now().naive_utc()))
+                    .get_result(&conn)
+                    .optional()?
+                    .ok_or(Error::MissingInvite)?;
+
+                accept_blocking(&conn, &actor, &invite, given_user_id)
+            })
+        })
+        .await?
+    }
+
+    /// Redeems an invite link, adding the user to the organisation with the permissions the
+    /// link was created with. Links can only be redeemed once.
+    pub async fn accept_link(
+        conn: ConnectionPool,
+        actor: &Actor,
+        given_user_id: i32,
+        given_token: String,
+    ) -> Result<Organisation> {
+        use crate::schema::organisation_invites::dsl::{expires_at, token_hash};
+
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let invite: OrganisationInvite = organisation_invites::table
+                    .filter(token_hash.eq(hash_token(&given_token)))
+                    .filter(expires_at.gt(Utc::now().naive_utc()))
+                    .get_result(&conn)
+                    .optional()?
+                    .ok_or(Error::MissingInvite)?;
+
+                accept_blocking(&conn, &actor, &invite, given_user_id)
+            })
+        })
+        .await?
+    }
+
+    /// Declines an invite addressed to the user, removing it.
+    pub async fn decline(
+        conn: ConnectionPool,
+        actor: &Actor,
+        given_user_id: i32,
+        given_uuid: uuid::Uuid,
+    ) -> Result<()> {
+        use crate::schema::organisation_invites::dsl::{expires_at, id, user_id, uuid};
+
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let invite: OrganisationInvite = organisation_invites::table
+                    .filter(user_id.eq(given_user_id))
+                    .filter(uuid.eq(SqlUuid(given_uuid)))
+                    .filter(expires_at.gt(Utc::now().naive_utc()))
+                    .get_result(&conn)
+                    .optional()?
+                    .ok_or(Error::MissingInvite)?;
+
+                diesel::delete(organisation_invites::table.filter(id.eq(invite.id)))
+                    .execute(&conn)?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::InviteDecline)
+                        .for_organisation(invite.organisation_id)
+                        .target_user(given_user_id)
+                        .invite(invite.uuid.0),
+                )
+            })
+        })
+        .await?
+    }
+}
+
+/// Adds the user to the invite's organisation and removes the invite so it can't be used again.
+fn accept_blocking(
+    conn: &crate::Connection,
+    actor: &Actor,
+    invite: &OrganisationInvite,
+    given_user_id: i32,
+) -> Result<Organisation> {
+    use crate::schema::user_organisation_permissions::dsl::{
+        expires_at, organisation_id, permissions, source, user_id,
+    };
+
+    let organisation: Organisation = organisations::table
+        .find(invite.organisation_id)
+        .get_result(conn)?;
+
+    // invites to deleted organisations can't be accepted until it's restored
+    if organisation.deleted_at.is_some() {
+        return Err(Error::MissingInvite);
+    }
+
+    let existing: Option<(UserPermission, Option<NaiveDateTime>)> =
+        user_organisation_permissions::table
+            .filter(user_id.eq(given_user_id))
+            .filter(organisation_id.eq(invite.organisation_id))
+            .select((permissions, expires_at))
+            .get_result(conn)
+            .optional()?;
+
+    let new_permissions = if let Some((existing, existing_expires_at)) = existing {
+        // the user has been added to the organisation some other way since they were invited,
+        // so the permissions they were invited with are added on top of what they already have -
+        // unless those have since lapsed. either way, invites don't expire once accepted
+        let new_permissions = if existing_expires_at.map_or(true, |v| v > Utc::now().naive_utc()) {
+            existing | invite.permissions
+        } else {
+            invite.permissions
+        };
+
+        diesel::update(
+            user_organisation_permissions::table
+                .filter(user_id.eq(given_user_id))
+                .filter(organisation_id.eq(invite.organisation_id)),
+        )
+        .set((
+            permissions.eq(new_permissions.bits()),
+            expires_at.eq(None::<NaiveDateTime>),
+            // the access the invite gave isn't the directory's to take away
+            source.eq(GrantSource::Manual),
+        ))
+        .execute(conn)?;
+
+        new_permissions
+    } else {
+        insert_into(user_organisation_permissions::table)
+            .values((
+                user_id.eq(given_user_id),
+                organisation_id.eq(invite.organisation_id),
+                permissions.eq(invite.permissions.bits()),
+            ))
+            .execute(conn)?;
+
+        invite.permissions
+    };
+
+    // the invite might have been redeemed or revoked since we looked it up, in which case
+    // returning an error rolls back the membership we just granted
+    let deleted =
+        diesel::delete(organisation_invites::table.filter(organisation_invites::id.eq(invite.id)))
+            .execute(conn)?;
+    if deleted != 1 {
+        return Err(Error::MissingInvite);
+    }
+
+    let payload = WebhookPayload::member_update(conn, None, given_user_id, Some(new_permissions))?;
+    enqueue_webhook(conn, invite.organisation_id, &payload)?;
+
+    record_blocking(
+        conn,
+        actor,
+        NewAuditEvent::new(AuditAction::InviteAccept)
+            .for_organisation(invite.organisation_id)
+            .target_user(given_user_id)
+            .permissions(invite.permissions)
+            .invite(invite.uuid.0),
+    )?;
+
+    Ok(organisation)
+}
+
+impl OrganisationWithPermissions {
+    /// Lists the organisation's unexpired invites, along with the user each is addressed to if
+    /// it isn't a link.
+    pub async fn invites(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+    ) -> Result<Vec<(OrganisationInvite, Option<User>)>> {
+        use crate::schema::organisation_invites::dsl::{created_at, expires_at};
+
+        if !self.permissions().contains(UserPermission::MANAGE_USERS) {
+            return Err(Error::MissingOrganisationPermission(
+                UserPermission::MANAGE_USERS,
+            ));
+        }
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            Ok(OrganisationInvite::belonging_to(self.organisation())
+                .left_join(users::table)
+                .filter(expires_at.gt(Utc::now().naive_utc()))
+                .select((
+                    organisation_invites::all_columns,
+                    users::all_columns.nullable(),
+                ))
+                .order_by(created_at.desc())
+                .load(&conn)?)
+        })
+        .await?
+    }
+
+    /// Invites a user to the organisation, or creates an invite link if no user is given. The
+    /// link's token is returned alongside the invite and can't be retrieved again after this.
+    pub async fn create_invite(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        actor: &Actor,
+        given_user_id: Option<i32>,
+        given_permissions: UserPermission,
+        given_expires_at: NaiveDateTime,
+    ) -> Result<(OrganisationInvite, Option<String>)> {
+        use crate::schema::organisation_invites::dsl::{
+            expires_at, invited_by_user_id, organisation_id, permissions, token_hash, user_id, uuid,
+        };
+
+        if !self.permissions().contains(UserPermission::MANAGE_USERS) {
+            return Err(Error::MissingOrganisationPermission(
+                UserPermission::MANAGE_USERS,
+            ));
+        }
+
+        ensure_grantable(self.permissions(), given_permissions)?;
+
+        // invites are attributed to whoever created them
+        let inviter_id = actor.user_id.ok_or(Error::UserActorRequired)?;
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            // invites addressed to a user are listed against their account, so they don't need
+            // a link at all
+            let token: Option<String> = given_user_id.is_none().then(|| {
+                TOKEN_PREFIX
+                    .chars()
+                    .chain(
+                        thread_rng()
+                            .sample_iter(&Alphanumeric)
+                            .take(TOKEN_LENGTH)
+                            .map(char::from),
+                    )
+                    .collect()
+            });
+
+            let inserted = conn.transaction::<_, Error, _>(|| {
+                if let Some(given_user_id) = given_user_id {
+                    let is_member = user_organisation_permissions::table
+                        .filter(user_organisation_permissions::user_id.eq(given_user_id))
+                        .filter(
+                            user_organisation_permissions::organisation_id
+                                .eq(self.organisation().id),
+                        )
+                        .filter(unexpired!(user_organisation_permissions::expires_at))
+                        .count()
+                        .get_result::<i64>(&conn)?
+                        > 0;
+                    if is_member {
+                        return Err(Error::AlreadyMember);
+                    }
+
+                    let has_pending_invite = organisation_invites::table
+                        .filter(organisation_id.eq(self.organisation().id))
+                        .filter(user_id.eq(given_user_id))
+                        .filter(expires_at.gt(Utc::now().naive_utc()))
+                        .count()
+                        .get_result::<i64>(&conn)?
+                        > 0;
+                    if has_pending_invite {
+                        return Err(Error::DuplicateInvite);
+                    }
+                }
+
+                let generated_uuid = SqlUuid::random();
+
+                insert_into(organisation_invites::table)
+                    .values((
+                        uuid.eq(generated_uuid),
+                        organisation_id.eq(self.organisation().id),
+                        user_id.eq(given_user_id),
+                        token_hash.eq(token.as_deref().map(hash_token)),
+                        permissions.eq(given_permissions.bits()),
+                        invited_by_user_id.eq(inviter_id),
+                        expires_at.eq(given_expires_at),
+                    ))
+                    .execute(&conn)?;
+
+                let inserted: OrganisationInvite = organisation_invites::table
+                    .filter(uuid.eq(generated_uuid))
+                    .get_result(&conn)?;
+
+                let mut event = NewAuditEvent::new(AuditAction::InviteInsert)
+                    .for_organisation(self.organisation().id)
+                    .permissions(given_permissions)
+                    .invite(inserted.uuid.0);
+                if let Some(given_user_id) = given_user_id {
+                    event = event.target_user(given_user_id);
+                }
+
+                record_blocking(&conn, &actor, event)?;
+
+                Ok(inserted)
+            })?;
+
+            Ok((inserted, token))
+        })
+        .await?
+    }
+
+    /// Revokes one of the organisation's invites, returning `false` if there was no such
+    /// invite.
+    pub async fn delete_invite(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        actor: &Actor,
+        given_uuid: uuid::Uuid,
+    ) -> Result<bool> {
+        use crate::schema::organisation_invites::dsl::{id, organisation_id, uuid};
+
+        if !self.permissions().contains(UserPermission::MANAGE_USERS) {
+            return Err(Error::MissingOrganisationPermission(
+                UserPermission::MANAGE_USERS,
+            ));
+        }
+
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let invite: Option<OrganisationInvite> = organisation_invites::table
+                    .filter(organisation_id.eq(self.organisation().id))
+                    .filter(uuid.eq(SqlUuid(given_uuid)))
+                    .get_result(&conn)
+                    .optional()?;
+
+                let invite = match invite {
+                    Some(v) => v,
+                    None => return Ok(false),
+                };
+
+                ensure_grantable(self.permissions(), invite.permissions)?;
+
+                diesel::delete(organisation_invites::table.filter(id.eq(invite.id)))
+                    .execute(&conn)?;
+
+                let mut event = NewAuditEvent::new(AuditAction::InviteDelete)
+                    .for_organisation(self.organisation().id)
+                    .invite(invite.uuid.0);
+                if let Some(invitee) = invite.user_id {
+                    event = event.target_user(invitee);
+                }
+
+                record_blocking(&conn, &actor, event)?;
+
+                Ok(true)
+            })
+        })
+        .await?
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod test {
+    use super::{accept_blocking, OrganisationInvite};
+    use crate::{
+        audit::Actor, organisations::Organisation, permissions::UserPermission, testing, Error,
+    };
+    use chrono::{Duration, Utc};
+    use diesel::prelude::*;
+
+    #[tokio::test]
+    async fn invites_can_only_be_accepted_once() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        let invitee = testing::user(&conn, "invitee").await;
+        let organisation = testing::organisation(&conn, "org", &owner).await;
+        let expires_at = (Utc::now() + Duration::days(1)).naive_utc();
+
+        let (invite, token) = organisation
+            .clone()
+            .create_invite(
+                conn.clone(),
+                &testing::actor(&owner),
+                Some(invitee.id),
+                UserPermission::VISIBLE,
+                expires_at,
+            )
+            .await
+            .unwrap();
+        assert!(token.is_none());
+        assert_eq!(invite.invited_by_user_id, owner.id);
+
+        assert!(matches!(
+            organisation
+                .clone()
+                .create_invite(
+                    conn.clone(),
+                    &testing::actor(&owner),
+                    Some(invitee.id),
+                    UserPermission::VISIBLE,
+                    expires_at,
+                )
+                .await,
+            Err(Error::DuplicateInvite)
+        ));
+
+        OrganisationInvite::accept(
+            conn.clone(),
+            &testing::actor(&invitee),
+            invitee.id,
+            invite.uuid.0,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            testing::find_organisation(&conn, "org", &invitee)
+                .await
+                .permissions(),
+            UserPermission::VISIBLE
+        );
+
+        assert!(matches!(
+            OrganisationInvite::accept(
+                conn.clone(),
+                &testing::actor(&invitee),
+                invitee.id,
+                invite.uuid.0,
+            )
+            .await,
+            Err(Error::MissingInvite)
+        ));
+        assert!(matches!(
+            organisation
+                .create_invite(
+                    conn,
+                    &testing::actor(&owner),
+                    Some(invitee.id),
+                    UserPermission::VISIBLE,
+                    expires_at,
+                )
+                .await,
+            Err(Error::AlreadyMember)
+        ));
+    }
+
+    #[tokio::test]
+    async fn invite_links_can_only_be_redeemed_once() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        let first = testing::user(&conn, "first").await;
+        let second = testing::user(&conn, "second").await;
+        let organisation = testing::organisation(&conn, "org", &owner).await;
+
+        let (_, token) = organisation
+            .create_invite(
+                conn.clone(),
+                &testing::actor(&owner),
+                None,
+                UserPermission::VISIBLE,
+                (Utc::now() + Duration::days(1)).naive_utc(),
+            )
+            .await
+            .unwrap();
+        let token = token.unwrap();
+
+        OrganisationInvite::accept_link(
+            conn.clone(),
+            &testing::actor(&first),
+            first.id,
+            token.clone(),
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            OrganisationInvite::accept_link(
+                conn.clone(),
+                &testing::actor(&second),
+                second.id,
+                token,
+            )
+            .await,
+            Err(Error::MissingInvite)
+        ));
+        assert!(testing::find_organisation(&conn, "org", &second)
+            .await
+            .permissions()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn accepting_a_redeemed_invite_rolls_back() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        let invitee = testing::user(&conn, "invitee").await;
+        let organisation = testing::organisation(&conn, "org", &owner).await;
+
+        let (invite, _) = organisation
+            .create_invite(
+                conn.clone(),
+                &testing::actor(&owner),
+                None,
+                UserPermission::VISIBLE,
+                (Utc::now() + Duration::days(1)).naive_utc(),
+            )
+            .await
+            .unwrap();
+
+        // simulates a concurrent redemption that removed the invite after we looked it up
+        let db = conn.get().unwrap();
+        diesel::delete(&invite).execute(&db).unwrap();
+
+        let res = db.transaction::<_, Error, _>(|| {
+            accept_blocking(&db, &testing::actor(&invitee), &invite, invitee.id)
+        });
+        assert!(matches!(res, Err(Error::MissingInvite)));
+
+        assert!(testing::find_organisation(&conn, "org", &invitee)
+            .await
+            .permissions()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn invites_must_be_created_by_a_user() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        let organisation = testing::organisation(&conn, "org", &owner).await;
+
+        assert!(matches!(
+            organisation
+                .create_invite(
+                    conn,
+                    &Actor::system(),
+                    None,
+                    UserPermission::VISIBLE,
+                    (Utc::now() + Duration::days(1)).naive_utc(),
+                )
+                .await,
+            Err(Error::UserActorRequired)
+        ));
+    }
+
+    #[tokio::test]
+    async fn invites_cant_grant_permissions_the_inviter_doesnt_have() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        let manager = testing::user(&conn, "manager").await;
+        testing::organisation(&conn, "org", &owner).await;
+
+        testing::add_member(
+            &conn,
+            "org",
+            &owner,
+            &manager,
+            UserPermission::VISIBLE | UserPermission::MANAGE_USERS,
+        )
+        .await;
+
+        assert!(matches!(
+            testing::find_organisation(&conn, "org", &manager)
+                .await
+                .create_invite(
+                    conn,
+                    &testing::actor(&manager),
+                    None,
+                    UserPermission::all(),
+                    (Utc::now() + Duration::days(1)).naive_utc(),
+                )
+                .await,
+            Err(Error::PermissionEscalation(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn accepted_invites_survive_directory_reconciles() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        let invitee = testing::user(&conn, "invitee").await;
+        let organisation = testing::organisation(&conn, "org", &owner).await;
+
+        let (invite, _) = organisation
+            .create_invite(
+                conn.clone(),
+                &testing::actor(&owner),
+                Some(invitee.id),
+                UserPermission::VISIBLE | UserPermission::PUBLISH_VERSION,
+                (Utc::now() + Duration::days(1)).naive_utc(),
+            )
+            .await
+            .unwrap();
+
+        // the user is given a grant by the directory before accepting
+        Organisation::reconcile_memberships(
+            conn.clone(),
+            invitee.id,
+            vec![("org".to_string(), UserPermission::VISIBLE)],
+        )
+        .await
+        .unwrap();
+
+        OrganisationInvite::accept(
+            conn.clone(),
+            &testing::actor(&invitee),
+            invitee.id,
+            invite.uuid.0,
+        )
+        .await
+        .unwrap();
+
+        Organisation::reconcile_memberships(
+            conn.clone(),
+            invitee.id,
+            vec![("org".to_string(), UserPermission::empty())],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            testing::find_organisation(&conn, "org", &invitee)
+                .await
+                .permissions(),
+            UserPermission::VISIBLE | UserPermission::PUBLISH_VERSION
+        );
+    }
+}
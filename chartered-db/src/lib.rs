@@ -32,13 +32,59 @@ macro_rules! derive_diesel_json {
     };
 }
 
+/// Matches the organisation currently known by `$name`, or that was known by it before being
+/// renamed, so old index URLs keep working. Deleted organisations are never matched.
+macro_rules! organisation_by_name {
+    ($name:expr) => {
+        crate::schema::organisations::deleted_at.is_null().and(
+            crate::schema::organisations::name
+                .eq($name)
+                .or(crate::schema::organisations::id.eq_any(
+                    crate::schema::organisation_aliases::table
+                        .filter(crate::schema::organisation_aliases::name.eq($name))
+                        .select(crate::schema::organisation_aliases::organisation_id),
+                )),
+        )
+    };
+}
+
+/// Matches permission grants that haven't expired, given the grant's nullable `expires_at`
+/// column. Expired grants are cleaned up by a background task but are ignored as soon as they
+/// lapse.
+macro_rules! unexpired {
+    ($expires_at:path) => {
+        $expires_at
+            .is_null()
+            .or($expires_at.gt(chrono::Utc::now().naive_utc()))
+    };
+}
+
+pub mod api_tokens;
+pub mod audit;
+pub mod cargo_keys;
 pub mod crates;
+pub mod downloads;
+pub mod grants;
+pub mod groups;
+pub mod invites;
+pub mod mirrors;
 pub mod organisations;
+pub mod passkeys;
 pub mod permissions;
 pub mod schema;
+pub mod scopes;
 pub mod server_private_key;
+pub mod statistics;
+pub mod storage_deletions;
+pub mod trusted_publishers;
+pub mod two_factor;
+pub mod used_challenges;
 pub mod users;
 pub mod uuid;
+pub mod webhooks;
+
+#[cfg(all(test, feature = "sqlite"))]
+mod testing;
 
 #[macro_use]
 extern crate diesel;
@@ -137,13 +183,59 @@ pub enum Error {
     VersionConflict(String),
     /// Username is already taken
     UsernameTaken,
+    /// The requested webhook does not exist
+    MissingWebhook,
+    /// Crates can't be published to an organisation that mirrors another registry
+    MirrorOrganisation,
+    /// Failed to serialise webhook payload: {0}
+    WebhookPayload(#[from] serde_json::Error),
+    /// This key has already been registered
+    DuplicateCargoKey,
+    /// The requested group does not exist
+    MissingGroup,
+    /// A group with this name already exists in the organisation
+    DuplicateGroup,
+    /// The requested invite does not exist or has expired
+    MissingInvite,
+    /// The user is already a member of the organisation
+    AlreadyMember,
+    /// The user already has a pending invite to the organisation
+    DuplicateInvite,
+    /// An organisation with this name already exists
+    OrganisationNameTaken,
+    /// A crate with this name already exists in the organisation
+    CrateNameTaken,
+    /// The requested version does not exist
+    MissingVersion,
+    /// Crates and versions can only be deleted within {0} hours of being published
+    DeletionWindowPassed(i64),
+    /// Version {0} was deleted and reserved, it can only be republished with the same content
+    ReservedVersion(String),
+    /// This account has been disabled
+    UserDisabled,
+    /// Too many failed login attempts, try again later
+    UserLocked,
+    /// Two-factor authentication is already enabled for this account
+    TwoFactorAlreadyEnabled,
+    /// Two-factor authentication isn't enabled for this account
+    TwoFactorNotEnabled,
+    /// This action can only be performed by a user
+    UserActorRequired,
+    /// You can't grant, or manage someone with, the {0:?} permissions as you don't have them
+    PermissionEscalation(crate::permissions::UserPermission),
+    /// The instance must keep at least one enabled administrator
+    LastAdmin,
 }
 
 impl Error {
     #[must_use]
     pub fn status_code(&self) -> http::StatusCode {
         match self {
-            Self::MissingCrate => http::StatusCode::NOT_FOUND,
+            Self::MissingCrate
+            | Self::MissingWebhook
+            | Self::MissingGroup
+            | Self::MissingInvite
+            | Self::MissingVersion => http::StatusCode::NOT_FOUND,
             Self::MissingCratePermission(v) | Self::MissingOrganisationPermission(v)
                 if v.contains(crate::permissions::UserPermission::VISIBLE) =>
             {
@@ -152,7 +244,24 @@ impl Error {
             Self::MissingCratePermission(_) | Self::MissingOrganisationPermission(_) => {
                 http::StatusCode::FORBIDDEN
             }
-            Self::KeyParse(_) | Self::VersionConflict(_) => http::StatusCode::BAD_REQUEST,
+            Self::KeyParse(_)
+            | Self::VersionConflict(_)
+            | Self::MirrorOrganisation
+            | Self::DuplicateCargoKey
+            | Self::DuplicateGroup
+            | Self::AlreadyMember
+            | Self::DuplicateInvite
+            | Self::OrganisationNameTaken
+            | Self::CrateNameTaken
+            | Self::DeletionWindowPassed(_)
+            | Self::ReservedVersion(_)
+            | Self::TwoFactorAlreadyEnabled
+            | Self::TwoFactorNotEnabled
+            | Self::LastAdmin => http::StatusCode::BAD_REQUEST,
+            Self::UserDisabled
+            | Self::UserLocked
+            | Self::UserActorRequired
+            | Self::PermissionEscalation(_) => http::StatusCode::FORBIDDEN,
             _ => http::StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -0,0 +1,190 @@
+//! Caches for organisations that mirror an upstream registry. Index files are cached with the
+//! time they were fetched so they can be refreshed periodically, whereas `.crate` files are
+//! immutable so they're fetched once and kept forever.
+
+use super::{
+    organisations::{Organisation, OrganisationWithPermissions},
+    permissions::UserPermission,
+    schema::{mirror_crate_files, mirror_index_files},
+    ConnectionPool, Error, Result,
+};
+use chrono::{NaiveDateTime, Utc};
+use diesel::{
+    insert_into,
+    prelude::*,
+    result::{DatabaseErrorKind, Error as DieselError},
+    Associations, Identifiable, Queryable,
+};
+use std::sync::Arc;
+
+/// Ensures the user can see the organisation and that it is actually a mirror, returning the
+/// organisation's id.
+fn mirror_organisation_id(organisation: &OrganisationWithPermissions) -> Result<i32> {
+    if !organisation.permissions().contains(UserPermission::VISIBLE) {
+        return Err(Error::MissingOrganisationPermission(
+            UserPermission::VISIBLE,
+        ));
+    }
+
+    if organisation.organisation().upstream().is_none() {
+        return Err(Error::MissingOrganisation);
+    }
+
+    Ok(organisation.organisation().id)
+}
+
+/// Ignores unique violations, which happen when two requests race to cache the same file.
+fn ignore_conflict(res: std::result::Result<usize, DieselError>) -> Result<()> {
+    match res {
+        Ok(_) | Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[derive(Identifiable, Queryable, Associations, PartialEq, Eq, Debug)]
+#[belongs_to(Organisation)]
+pub struct MirrorIndexFile {
+    pub id: i32,
+    pub organisation_id: i32,
+    pub path: String,
+    pub content: Vec<u8>,
+    pub fetched_at: NaiveDateTime,
+}
+
+impl MirrorIndexFile {
+    pub async fn find(
+        conn: ConnectionPool,
+        organisation: Arc<OrganisationWithPermissions>,
+        given_path: String,
+    ) -> Result<Option<Self>> {
+        use crate::schema::mirror_index_files::dsl::{organisation_id, path};
+
+        let given_organisation_id = mirror_organisation_id(&organisation)?;
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            mirror_index_files::table
+                .filter(organisation_id.eq(given_organisation_id))
+                .filter(path.eq(given_path))
+                .get_result(&conn)
+                .optional()
+                .map_err(Into::into)
+        })
+        .await?
+    }
+
+    /// Caches a freshly fetched index file, replacing any previous version of it.
+    pub async fn store(
+        conn: ConnectionPool,
+        organisation: Arc<OrganisationWithPermissions>,
+        given_path: String,
+        given_content: Vec<u8>,
+    ) -> Result<()> {
+        use crate::schema::mirror_index_files::dsl::{content, fetched_at, organisation_id, path};
+
+        let given_organisation_id = mirror_organisation_id(&organisation)?;
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+            let now = Utc::now().naive_utc();
+
+            let updated = diesel::update(
+                mirror_index_files::table
+                    .filter(organisation_id.eq(given_organisation_id))
+                    .filter(path.eq(&given_path)),
+            )
+            .set((content.eq(&given_content), fetched_at.eq(now)))
+            .execute(&conn)?;
+
+            if updated > 0 {
+                return Ok(());
+            }
+
+            ignore_conflict(
+                insert_into(mirror_index_files::table)
+                    .values((
+                        organisation_id.eq(given_organisation_id),
+                        path.eq(&given_path),
+                        content.eq(&given_content),
+                        fetched_at.eq(now),
+                    ))
+                    .execute(&conn),
+            )
+        })
+        .await?
+    }
+}
+
+#[derive(Identifiable, Queryable, Associations, PartialEq, Eq, Debug)]
+#[belongs_to(Organisation)]
+pub struct MirrorCrateFile {
+    pub id: i32,
+    pub organisation_id: i32,
+    pub crate_name: String,
+    pub version: String,
+    pub checksum: String,
+    pub filesystem_object: String,
+    pub created_at: NaiveDateTime,
+}
+
+impl MirrorCrateFile {
+    pub async fn find(
+        conn: ConnectionPool,
+        organisation: Arc<OrganisationWithPermissions>,
+        given_crate_name: String,
+        given_version: String,
+    ) -> Result<Option<Self>> {
+        use crate::schema::mirror_crate_files::dsl::{crate_name, organisation_id, version};
+
+        let given_organisation_id = mirror_organisation_id(&organisation)?;
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            mirror_crate_files::table
+                .filter(organisation_id.eq(given_organisation_id))
+                .filter(crate_name.eq(given_crate_name))
+                .filter(version.eq(given_version))
+                .get_result(&conn)
+                .optional()
+                .map_err(Into::into)
+        })
+        .await?
+    }
+
+    /// Records a `.crate` file that has been fetched from upstream, verified and written out to
+    /// the file system.
+    pub async fn store(
+        conn: ConnectionPool,
+        organisation: Arc<OrganisationWithPermissions>,
+        given_crate_name: String,
+        given_version: String,
+        given_checksum: String,
+        file_identifier: &chartered_fs::FileReference,
+    ) -> Result<()> {
+        use crate::schema::mirror_crate_files::dsl::{
+            checksum, crate_name, filesystem_object, organisation_id, version,
+        };
+
+        let given_organisation_id = mirror_organisation_id(&organisation)?;
+        let given_filesystem_object = file_identifier.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            ignore_conflict(
+                insert_into(mirror_crate_files::table)
+                    .values((
+                        organisation_id.eq(given_organisation_id),
+                        crate_name.eq(given_crate_name),
+                        version.eq(given_version),
+                        checksum.eq(given_checksum),
+                        filesystem_object.eq(given_filesystem_object),
+                    ))
+                    .execute(&conn),
+            )
+        })
+        .await?
+    }
+}
@@ -1,24 +1,49 @@
 use crate::{
-    coalesce, crates::Crate, permissions::UserPermission, users::User, BitwiseExpressionMethods,
-    Error,
+    audit::{audit_log_blocking, record_blocking, Actor, AuditAction, AuditLog, NewAuditEvent},
+    coalesce,
+    crates::Crate,
+    grants::GrantSource,
+    permissions::{ensure_grantable, UserPermission},
+    storage_deletions::StorageDeletionReason,
+    users::User,
+    webhooks::{
+        enqueue_blocking as enqueue_webhook, OrganisationWebhook, WebhookDelivery,
+        WebhookDeliveryAttempt, WebhookEvent, WebhookPayload,
+    },
+    BitwiseExpressionMethods, Error,
 };
 
 use super::{
-    schema::{organisations, user_organisation_permissions, users},
+    schema::{
+        audit_events, crate_versions, crates, mirror_crate_files, organisation_aliases,
+        organisation_webhooks, organisations, storage_deletions, user_organisation_permissions,
+        users, webhook_deliveries,
+    },
     uuid::SqlUuid,
     ConnectionPool, Result,
 };
 
+use chrono::{Duration, NaiveDateTime, Utc};
 use diesel::{prelude::*, Associations, Identifiable, Queryable};
 
 use std::sync::Arc;
 
+/// Number of days a deleted organisation can be restored for, after which its crates' storage
+/// objects are removed for good.
+pub const DELETION_GRACE_PERIOD_DAYS: i64 = 7;
+
 macro_rules! select_permissions {
-    () => {
+    ($user_id:ident) => {
         coalesce(
             crate::schema::user_organisation_permissions::permissions.nullable(),
             0,
         )
+        .bitwise_or(diesel::dsl::sql::<diesel::sql_types::Integer>(
+            &crate::groups::organisation_group_permissions_sql($user_id, "organisations.id"),
+        ))
+        .bitwise_and(diesel::dsl::sql::<diesel::sql_types::Integer>(
+            &crate::two_factor::two_factor_mask_sql($user_id, "organisations"),
+        ))
         .bitwise_or(diesel::dsl::sql::<diesel::sql_types::Integer>(&format!(
             "COALESCE(CASE WHEN {} THEN {} ELSE 0 END, 0)",
             "public",
@@ -34,9 +59,39 @@ pub struct Organisation {
     pub name: String,
     pub description: String,
     pub public: bool,
+    pub upstream_index_uri: Option<String>,
+    pub upstream_download_uri: Option<String>,
+    /// When the organisation was deleted, deleted organisations can be restored until their
+    /// storage objects are cleaned up after `DELETION_GRACE_PERIOD_DAYS`.
+    pub deleted_at: Option<NaiveDateTime>,
+    /// Whether members must have two-factor authentication enabled to be given any of the
+    /// permissions they've been granted on the organisation.
+    pub require_two_factor: bool,
+}
+
+/// The registry an organisation is mirroring.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Upstream {
+    /// Base URL of the upstream's sparse index, ie. `https://index.crates.io/`
+    pub index_uri: String,
+    /// Where to download `.crate` files from, in the same format as `dl` in an index's
+    /// `config.json`
+    pub download_uri: String,
 }
 
 impl Organisation {
+    /// Returns the upstream registry this organisation mirrors, if it's a mirror.
+    #[must_use]
+    pub fn upstream(&self) -> Option<Upstream> {
+        match (&self.upstream_index_uri, &self.upstream_download_uri) {
+            (Some(index_uri), Some(download_uri)) => Some(Upstream {
+                index_uri: index_uri.clone(),
+                download_uri: download_uri.clone(),
+            }),
+            _ => None,
+        }
+    }
+
     pub async fn list(conn: ConnectionPool, requesting_user_id: i32) -> Result<Vec<Organisation>> {
         tokio::task::spawn_blocking(move || {
             let conn = conn.get()?;
@@ -48,10 +103,12 @@ impl Organisation {
                         .and(
                             user_organisation_permissions::organisation_id
                                 .eq(organisations::dsl::id),
-                        )),
+                        )
+                        .and(unexpired!(user_organisation_permissions::expires_at))),
                 )
+                .filter(organisations::deleted_at.is_null())
                 .filter(
-                    select_permissions!()
+                    select_permissions!(requesting_user_id)
                         .bitwise_and(UserPermission::VISIBLE.bits())
                         .eq(UserPermission::VISIBLE.bits()),
                 )
@@ -62,13 +119,197 @@ impl Organisation {
         .await?
     }
 
+    /// Lists every organisation on the instance, including deleted ones, for instance
+    /// administrators.
+    pub async fn list_all(conn: ConnectionPool) -> Result<Vec<Organisation>> {
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            organisations::table
+                .order_by(organisations::name.asc())
+                .load(&conn)
+                .map_err(Into::into)
+        })
+        .await?
+    }
+
+    /// Gives the user every permission on the organisation without it ever expiring, replacing
+    /// any permissions they already had. Used by instance administrators to recover
+    /// organisations that have lost all of their owners.
+    pub async fn grant_ownership(
+        conn: ConnectionPool,
+        actor: &Actor,
+        given_name: String,
+        given_user_id: i32,
+    ) -> Result<()> {
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let organisation_id = organisations::table
+                    .filter(organisation_by_name!(&given_name))
+                    .select(organisations::id)
+                    .get_result::<i32>(&conn)
+                    .optional()?
+                    .ok_or(Error::MissingOrganisation)?;
+
+                let permissions = UserPermission::all();
+
+                let updated = diesel::update(
+                    user_organisation_permissions::table
+                        .filter(user_organisation_permissions::user_id.eq(given_user_id))
+                        .filter(user_organisation_permissions::organisation_id.eq(organisation_id)),
+                )
+                .set((
+                    user_organisation_permissions::permissions.eq(permissions.bits()),
+                    user_organisation_permissions::expires_at.eq(None::<NaiveDateTime>),
+                    // ownership isn't something a directory should be able to take away
+                    user_organisation_permissions::source.eq(GrantSource::Manual),
+                ))
+                .execute(&conn)?;
+
+                if updated == 0 {
+                    diesel::insert_into(user_organisation_permissions::table)
+                        .values((
+                            user_organisation_permissions::user_id.eq(given_user_id),
+                            user_organisation_permissions::organisation_id.eq(organisation_id),
+                            user_organisation_permissions::permissions.eq(permissions.bits()),
+                        ))
+                        .execute(&conn)?;
+                }
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::OrganisationOwnerGrant)
+                        .for_organisation(organisation_id)
+                        .target_user(given_user_id)
+                        .permissions(permissions),
+                )?;
+
+                let payload =
+                    WebhookPayload::member_update(&conn, None, given_user_id, Some(permissions))?;
+                enqueue_webhook(&conn, organisation_id, &payload)
+            })
+        })
+        .await?
+    }
+
+    /// Brings the user's membership of each of the given organisations in line with the
+    /// permissions they've been given by an external directory, such as LDAP groups. Empty
+    /// permissions remove the user from the organisation. Organisations that aren't given are
+    /// left alone, as are grants that weren't given by a directory, and organisations that
+    /// don't exist are skipped.
+    pub async fn reconcile_memberships(
+        conn: ConnectionPool,
+        given_user_id: i32,
+        given_memberships: Vec<(String, UserPermission)>,
+    ) -> Result<()> {
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let actor = Actor::system();
+
+                for (name, permissions) in given_memberships {
+                    let organisation_id = match organisations::table
+                        .filter(organisation_by_name!(&name))
+                        .select(organisations::id)
+                        .get_result::<i32>(&conn)
+                        .optional()?
+                    {
+                        Some(v) => v,
+                        None => continue,
+                    };
+
+                    let membership = user_organisation_permissions::table
+                        .filter(user_organisation_permissions::user_id.eq(given_user_id))
+                        .filter(user_organisation_permissions::organisation_id.eq(organisation_id));
+
+                    let existing: Option<(UserPermission, Option<NaiveDateTime>, GrantSource)> =
+                        membership
+                            .clone()
+                            .select((
+                                user_organisation_permissions::permissions,
+                                user_organisation_permissions::expires_at,
+                                user_organisation_permissions::source,
+                            ))
+                            .get_result(&conn)
+                            .optional()?;
+
+                    let action = match existing {
+                        None if permissions.is_empty() => continue,
+                        // grants given by someone in the organisation are theirs to manage, so
+                        // the directory leaves them alone
+                        Some((_, _, GrantSource::Manual)) => continue,
+                        // grants from the directory never expire, they're removed when the
+                        // user leaves the group instead
+                        Some((current, None, _)) if current == permissions => continue,
+                        None => {
+                            diesel::insert_into(user_organisation_permissions::table)
+                                .values((
+                                    user_organisation_permissions::user_id.eq(given_user_id),
+                                    user_organisation_permissions::organisation_id
+                                        .eq(organisation_id),
+                                    user_organisation_permissions::permissions
+                                        .eq(permissions.bits()),
+                                    user_organisation_permissions::source
+                                        .eq(GrantSource::Directory),
+                                ))
+                                .execute(&conn)?;
+
+                            AuditAction::OrganisationMemberInsert
+                        }
+                        Some(_) if permissions.is_empty() => {
+                            diesel::delete(membership).execute(&conn)?;
+
+                            AuditAction::OrganisationMemberDelete
+                        }
+                        Some(_) => {
+                            diesel::update(membership)
+                                .set((
+                                    user_organisation_permissions::permissions
+                                        .eq(permissions.bits()),
+                                    user_organisation_permissions::expires_at
+                                        .eq(None::<NaiveDateTime>),
+                                ))
+                                .execute(&conn)?;
+
+                            AuditAction::OrganisationMemberUpdate
+                        }
+                    };
+
+                    record_blocking(
+                        &conn,
+                        &actor,
+                        NewAuditEvent::new(action)
+                            .for_organisation(organisation_id)
+                            .target_user(given_user_id)
+                            .permissions(permissions),
+                    )?;
+
+                    let payload = WebhookPayload::member_update(
+                        &conn,
+                        None,
+                        given_user_id,
+                        (!permissions.is_empty()).then_some(permissions),
+                    )?;
+                    enqueue_webhook(&conn, organisation_id, &payload)?;
+                }
+
+                Ok(())
+            })
+        })
+        .await?
+    }
+
     pub async fn find_by_name(
         conn: ConnectionPool,
         requesting_user_id: i32,
         given_name: String,
     ) -> Result<OrganisationWithPermissions> {
-        use organisations::dsl::name as organisation_name;
-
         tokio::task::spawn_blocking(move || {
             let conn = conn.get()?;
 
@@ -79,10 +320,14 @@ impl Organisation {
                         .and(
                             user_organisation_permissions::organisation_id
                                 .eq(organisations::dsl::id),
-                        )),
+                        )
+                        .and(unexpired!(user_organisation_permissions::expires_at))),
                 )
-                .filter(organisation_name.eq(given_name))
-                .select((select_permissions!(), organisations::all_columns))
+                .filter(organisation_by_name!(&given_name))
+                .select((
+                    select_permissions!(requesting_user_id),
+                    organisations::all_columns,
+                ))
                 .get_result(&conn)
                 .optional()?
                 .ok_or(Error::MissingOrganisation)?;
@@ -100,15 +345,22 @@ impl Organisation {
         given_name: String,
         given_description: String,
         given_public: bool,
+        given_upstream: Option<Upstream>,
         requesting_user_id: i32,
     ) -> Result<()> {
         tokio::task::spawn_blocking(move || {
             let conn = conn.get()?;
 
             conn.transaction::<_, crate::Error, _>(|| {
-                use organisations::dsl::{description, id, name, public, uuid};
+                use organisations::dsl::{
+                    description, id, name, public, upstream_download_uri, upstream_index_uri, uuid,
+                };
                 use user_organisation_permissions::dsl::{organisation_id, permissions, user_id};
 
+                if name_taken_blocking(&conn, &given_name, None)? {
+                    return Err(Error::OrganisationNameTaken);
+                }
+
                 let generated_uuid = SqlUuid::random();
 
                 diesel::insert_into(organisations::table)
@@ -117,6 +369,8 @@ impl Organisation {
                         name.eq(given_name),
                         description.eq(given_description),
                         public.eq(given_public),
+                        upstream_index_uri.eq(given_upstream.as_ref().map(|v| &v.index_uri)),
+                        upstream_download_uri.eq(given_upstream.as_ref().map(|v| &v.download_uri)),
                     ))
                     .execute(&conn)?;
 
@@ -140,6 +394,112 @@ impl Organisation {
         })
         .await?
     }
+
+    /// Restores an organisation that was deleted within the last `DELETION_GRACE_PERIOD_DAYS`,
+    /// cancelling the clean up of its crates' storage objects. Deleted organisations are only
+    /// ever known by their name at the time they were deleted.
+    pub async fn restore(
+        conn: ConnectionPool,
+        actor: &Actor,
+        requesting_user_id: i32,
+        given_name: String,
+    ) -> Result<()> {
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let (permissions, organisation): (UserPermission, Organisation) =
+                    organisations::table
+                        .left_join(
+                            user_organisation_permissions::table.on(
+                                user_organisation_permissions::user_id
+                                    .eq(requesting_user_id)
+                                    .and(
+                                        user_organisation_permissions::organisation_id
+                                            .eq(organisations::dsl::id),
+                                    )
+                                    .and(unexpired!(user_organisation_permissions::expires_at)),
+                            ),
+                        )
+                        .filter(organisations::name.eq(&given_name))
+                        .filter(organisations::deleted_at.is_not_null())
+                        .select((
+                            select_permissions!(requesting_user_id),
+                            organisations::all_columns,
+                        ))
+                        .get_result(&conn)
+                        .optional()?
+                        .filter(|(permissions, _)| permissions.contains(UserPermission::VISIBLE))
+                        .ok_or(Error::MissingOrganisation)?;
+
+                if !permissions.contains(UserPermission::MANAGE_ORGANISATION) {
+                    return Err(Error::MissingOrganisationPermission(
+                        UserPermission::MANAGE_ORGANISATION,
+                    ));
+                }
+
+                // once the grace period is up the storage objects may have already been
+                // removed, so the organisation is gone for good
+                let restorable_until = organisation
+                    .deleted_at
+                    .map(|v| v + Duration::days(DELETION_GRACE_PERIOD_DAYS));
+                if !matches!(restorable_until, Some(v) if v > Utc::now().naive_utc()) {
+                    return Err(Error::MissingOrganisation);
+                }
+
+                diesel::update(organisations::table.find(organisation.id))
+                    .set(organisations::deleted_at.eq(None::<NaiveDateTime>))
+                    .execute(&conn)?;
+
+                // only the objects scheduled by the organisation's deletion are kept, versions
+                // that were deleted individually stay deleted
+                diesel::delete(
+                    storage_deletions::table
+                        .filter(storage_deletions::organisation_id.eq(organisation.id))
+                        .filter(
+                            storage_deletions::reason.eq(StorageDeletionReason::OrganisationDelete),
+                        ),
+                )
+                .execute(&conn)?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::OrganisationRestore)
+                        .for_organisation(organisation.id),
+                )
+            })
+        })
+        .await?
+    }
+}
+
+/// Checks whether `given_name` is in use by any organisation other than `except_id`, either as
+/// its current name or a name it was previously known by. Deleted organisations hold on to
+/// their names so they can be restored.
+fn name_taken_blocking(
+    conn: &crate::Connection,
+    given_name: &str,
+    except_id: Option<i32>,
+) -> Result<bool> {
+    // ids start from 1, so this won't exclude anything if no id was given
+    let except_id = except_id.unwrap_or_default();
+
+    let organisations_with_name: i64 = organisations::table
+        .filter(organisations::name.eq(given_name))
+        .filter(organisations::id.ne(except_id))
+        .count()
+        .get_result(conn)?;
+
+    let aliases_with_name: i64 = organisation_aliases::table
+        .filter(organisation_aliases::name.eq(given_name))
+        .filter(organisation_aliases::organisation_id.ne(except_id))
+        .count()
+        .get_result(conn)?;
+
+    Ok(organisations_with_name + aliases_with_name > 0)
 }
 
 pub struct OrganisationWithPermissions {
@@ -158,6 +518,173 @@ impl OrganisationWithPermissions {
         &self.organisation
     }
 
+    /// Updates the organisation's details, any that are `None` are left as they are. When the
+    /// organisation is renamed its old name is kept as an alias so index URLs using it keep
+    /// working.
+    pub async fn update(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        actor: &Actor,
+        given_name: Option<String>,
+        given_description: Option<String>,
+        given_public: Option<bool>,
+        given_require_two_factor: Option<bool>,
+    ) -> Result<()> {
+        use crate::schema::organisations::dsl::{description, name, public, require_two_factor};
+
+        if !self
+            .permissions
+            .contains(UserPermission::MANAGE_ORGANISATION)
+        {
+            return Err(Error::MissingOrganisationPermission(
+                UserPermission::MANAGE_ORGANISATION,
+            ));
+        }
+
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let organisation_id = self.organisation.id;
+                let given_name = given_name.filter(|v| *v != self.organisation.name);
+
+                if let Some(given_name) = &given_name {
+                    if name_taken_blocking(&conn, given_name, Some(organisation_id))? {
+                        return Err(Error::OrganisationNameTaken);
+                    }
+
+                    // the organisation might be being renamed back to a name it was previously
+                    // known by, which doesn't need to be an alias anymore
+                    diesel::delete(
+                        organisation_aliases::table
+                            .filter(organisation_aliases::organisation_id.eq(organisation_id))
+                            .filter(organisation_aliases::name.eq(given_name)),
+                    )
+                    .execute(&conn)?;
+
+                    diesel::insert_into(organisation_aliases::table)
+                        .values((
+                            organisation_aliases::organisation_id.eq(organisation_id),
+                            organisation_aliases::name.eq(&self.organisation.name),
+                        ))
+                        .execute(&conn)?;
+
+                    diesel::update(organisations::table.find(organisation_id))
+                        .set(name.eq(given_name))
+                        .execute(&conn)?;
+
+                    record_blocking(
+                        &conn,
+                        &actor,
+                        NewAuditEvent::new(AuditAction::OrganisationRename)
+                            .for_organisation(organisation_id)
+                            .previous_name(self.organisation.name.clone()),
+                    )?;
+                }
+
+                if given_description.is_some()
+                    || given_public.is_some()
+                    || given_require_two_factor.is_some()
+                {
+                    diesel::update(organisations::table.find(organisation_id))
+                        .set((
+                            given_description.map(|v| description.eq(v)),
+                            given_public.map(|v| public.eq(v)),
+                            given_require_two_factor.map(|v| require_two_factor.eq(v)),
+                        ))
+                        .execute(&conn)?;
+
+                    record_blocking(
+                        &conn,
+                        &actor,
+                        NewAuditEvent::new(AuditAction::OrganisationUpdate)
+                            .for_organisation(organisation_id),
+                    )?;
+                }
+
+                Ok(())
+            })
+        })
+        .await?
+    }
+
+    /// Soft deletes the organisation, hiding it and all of its crates, and schedules the
+    /// storage objects for every version of its crates to be removed once the grace period is
+    /// up. Until then the organisation can be brought back using `Organisation::restore`.
+    ///
+    /// Returns the time after which the organisation can no longer be restored.
+    pub async fn delete(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        actor: &Actor,
+    ) -> Result<NaiveDateTime> {
+        use crate::schema::storage_deletions::dsl::{
+            delete_after, filesystem_object, organisation_id, reason,
+        };
+
+        if !self
+            .permissions
+            .contains(UserPermission::MANAGE_ORGANISATION)
+        {
+            return Err(Error::MissingOrganisationPermission(
+                UserPermission::MANAGE_ORGANISATION,
+            ));
+        }
+
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let now = Utc::now().naive_utc();
+                let given_delete_after = now + Duration::days(DELETION_GRACE_PERIOD_DAYS);
+
+                diesel::update(organisations::table.find(self.organisation.id))
+                    .set(organisations::deleted_at.eq(now))
+                    .execute(&conn)?;
+
+                let mut objects: Vec<String> = crate_versions::table
+                    .inner_join(crates::table)
+                    .filter(crates::organisation_id.eq(self.organisation.id))
+                    .select(crate_versions::filesystem_object)
+                    .load(&conn)?;
+
+                // mirrors don't have any crates of their own, but they do have a cache of the
+                // upstream's crate files
+                objects.extend(
+                    mirror_crate_files::table
+                        .filter(mirror_crate_files::organisation_id.eq(self.organisation.id))
+                        .select(mirror_crate_files::filesystem_object)
+                        .load::<String>(&conn)?,
+                );
+
+                for object in objects {
+                    diesel::insert_into(storage_deletions::table)
+                        .values((
+                            organisation_id.eq(self.organisation.id),
+                            filesystem_object.eq(object),
+                            delete_after.eq(given_delete_after),
+                            reason.eq(StorageDeletionReason::OrganisationDelete),
+                        ))
+                        .execute(&conn)?;
+                }
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::OrganisationDelete)
+                        .for_organisation(self.organisation.id),
+                )?;
+
+                Ok(given_delete_after)
+            })
+        })
+        .await?
+    }
+
     pub async fn crates(self: Arc<Self>, conn: ConnectionPool) -> Result<Vec<Crate>> {
         if !self.permissions.contains(UserPermission::VISIBLE) {
             return Err(Error::MissingOrganisationPermission(
@@ -174,10 +701,12 @@ impl OrganisationWithPermissions {
         .await?
     }
 
+    /// Lists the organisation's members along with their permissions and when those lapse, if
+    /// ever.
     pub async fn members(
         self: Arc<Self>,
         conn: ConnectionPool,
-    ) -> Result<Vec<(User, UserPermission)>> {
+    ) -> Result<Vec<(User, UserPermission, Option<NaiveDateTime>)>> {
         if !self.permissions.contains(UserPermission::VISIBLE) {
             return Err(Error::MissingOrganisationPermission(
                 UserPermission::VISIBLE,
@@ -190,10 +719,12 @@ impl OrganisationWithPermissions {
             let conn = conn.get()?;
             user_organisation_permissions::table
                 .filter(organisation_id.eq(self.organisation.id))
+                .filter(unexpired!(user_organisation_permissions::expires_at))
                 .inner_join(users::table)
                 .select((
                     users::all_columns,
                     user_organisation_permissions::columns::permissions,
+                    user_organisation_permissions::columns::expires_at,
                 ))
                 .load(&conn)
                 .map_err(Into::into)
@@ -204,27 +735,58 @@ impl OrganisationWithPermissions {
     pub async fn update_permissions(
         self: Arc<Self>,
         conn: ConnectionPool,
+        actor: &Actor,
         given_user_id: i32,
         given_permissions: UserPermission,
+        given_expires_at: Option<NaiveDateTime>,
     ) -> Result<usize> {
         if !self.permissions.contains(UserPermission::MANAGE_USERS) {
             return Err(Error::MissingCratePermission(UserPermission::MANAGE_USERS));
         }
 
+        ensure_grantable(self.permissions, given_permissions)?;
+
+        let actor = actor.clone();
+
         tokio::task::spawn_blocking(move || {
             use crate::schema::user_organisation_permissions::dsl::{
-                organisation_id, permissions, user_id, user_organisation_permissions,
+                expires_at, organisation_id, permissions, source, user_id,
+                user_organisation_permissions,
             };
 
             let conn = conn.get()?;
 
-            Ok(diesel::update(
-                user_organisation_permissions
-                    .filter(user_id.eq(given_user_id))
-                    .filter(organisation_id.eq(self.organisation.id)),
-            )
-            .set(permissions.eq(given_permissions.bits()))
-            .execute(&conn)?)
+            conn.transaction::<_, Error, _>(|| {
+                self.ensure_can_manage_member(&conn, given_user_id)?;
+
+                let affected_rows = diesel::update(
+                    user_organisation_permissions
+                        .filter(user_id.eq(given_user_id))
+                        .filter(organisation_id.eq(self.organisation.id)),
+                )
+                .set((
+                    permissions.eq(given_permissions.bits()),
+                    expires_at.eq(given_expires_at),
+                    // a grant edited by hand is no longer the directory's to manage
+                    source.eq(GrantSource::Manual),
+                ))
+                .execute(&conn)?;
+
+                if affected_rows > 0 {
+                    self.enqueue_member_update(&conn, given_user_id, Some(given_permissions))?;
+
+                    record_blocking(
+                        &conn,
+                        &actor,
+                        NewAuditEvent::new(AuditAction::OrganisationMemberUpdate)
+                            .for_organisation(self.organisation.id)
+                            .target_user(given_user_id)
+                            .permissions(given_permissions),
+                    )?;
+                }
+
+                Ok(affected_rows)
+            })
         })
         .await?
     }
@@ -232,27 +794,69 @@ impl OrganisationWithPermissions {
     pub async fn insert_permissions(
         self: Arc<Self>,
         conn: ConnectionPool,
+        actor: &Actor,
         given_user_id: i32,
         given_permissions: UserPermission,
+        given_expires_at: Option<NaiveDateTime>,
     ) -> Result<usize> {
         if !self.permissions.contains(UserPermission::MANAGE_USERS) {
             return Err(Error::MissingCratePermission(UserPermission::MANAGE_USERS));
         }
 
+        ensure_grantable(self.permissions, given_permissions)?;
+
+        let actor = actor.clone();
+
         tokio::task::spawn_blocking(move || {
             use crate::schema::user_organisation_permissions::dsl::{
-                organisation_id, permissions, user_id, user_organisation_permissions,
+                expires_at, organisation_id, permissions, source, user_id,
+                user_organisation_permissions,
             };
 
             let conn = conn.get()?;
 
-            Ok(diesel::insert_into(user_organisation_permissions)
-                .values((
-                    user_id.eq(given_user_id),
-                    organisation_id.eq(self.organisation.id),
+            conn.transaction::<_, Error, _>(|| {
+                // a grant that has lapsed but hasn't been cleaned up yet is replaced rather than
+                // conflicting with the new one
+                let affected_rows = diesel::update(
+                    user_organisation_permissions
+                        .filter(user_id.eq(given_user_id))
+                        .filter(organisation_id.eq(self.organisation.id))
+                        .filter(expires_at.le(Utc::now().naive_utc())),
+                )
+                .set((
                     permissions.eq(given_permissions.bits()),
+                    expires_at.eq(given_expires_at),
+                    source.eq(GrantSource::Manual),
                 ))
-                .execute(&conn)?)
+                .execute(&conn)?;
+
+                let affected_rows = if affected_rows > 0 {
+                    affected_rows
+                } else {
+                    diesel::insert_into(user_organisation_permissions)
+                        .values((
+                            user_id.eq(given_user_id),
+                            organisation_id.eq(self.organisation.id),
+                            permissions.eq(given_permissions.bits()),
+                            expires_at.eq(given_expires_at),
+                        ))
+                        .execute(&conn)?
+                };
+
+                self.enqueue_member_update(&conn, given_user_id, Some(given_permissions))?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::OrganisationMemberInsert)
+                        .for_organisation(self.organisation.id)
+                        .target_user(given_user_id)
+                        .permissions(given_permissions),
+                )?;
+
+                Ok(affected_rows)
+            })
         })
         .await?
     }
@@ -260,12 +864,15 @@ impl OrganisationWithPermissions {
     pub async fn delete_member(
         self: Arc<Self>,
         conn: ConnectionPool,
+        actor: &Actor,
         given_user_id: i32,
     ) -> Result<()> {
         if !self.permissions.contains(UserPermission::MANAGE_USERS) {
             return Err(Error::MissingCratePermission(UserPermission::MANAGE_USERS));
         }
 
+        let actor = actor.clone();
+
         tokio::task::spawn_blocking(move || {
             use crate::schema::user_organisation_permissions::dsl::{
                 organisation_id, user_id, user_organisation_permissions,
@@ -273,15 +880,592 @@ impl OrganisationWithPermissions {
 
             let conn = conn.get()?;
 
-            diesel::delete(
-                user_organisation_permissions
-                    .filter(user_id.eq(given_user_id))
-                    .filter(organisation_id.eq(self.organisation.id)),
-            )
-            .execute(&conn)?;
+            conn.transaction::<_, Error, _>(|| {
+                self.ensure_can_manage_member(&conn, given_user_id)?;
 
-            Ok(())
+                let affected_rows = diesel::delete(
+                    user_organisation_permissions
+                        .filter(user_id.eq(given_user_id))
+                        .filter(organisation_id.eq(self.organisation.id)),
+                )
+                .execute(&conn)?;
+
+                if affected_rows > 0 {
+                    self.enqueue_member_update(&conn, given_user_id, None)?;
+
+                    record_blocking(
+                        &conn,
+                        &actor,
+                        NewAuditEvent::new(AuditAction::OrganisationMemberDelete)
+                            .for_organisation(self.organisation.id)
+                            .target_user(given_user_id),
+                    )?;
+                }
+
+                Ok(())
+            })
+        })
+        .await?
+    }
+
+    /// Fetches a page of the organisation's audit log, newest events first, along with the
+    /// users referenced by the events on that page. Only users with `READ_AUDIT_LOG` can view
+    /// the log.
+    pub async fn audit_log(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        page: i64,
+        per_page: i64,
+    ) -> Result<AuditLog> {
+        if !self.permissions.contains(UserPermission::READ_AUDIT_LOG) {
+            return Err(Error::MissingOrganisationPermission(
+                UserPermission::READ_AUDIT_LOG,
+            ));
+        }
+
+        tokio::task::spawn_blocking(move || {
+            use crate::schema::audit_events::dsl::{created_at, id, organisation_id};
+
+            let conn = conn.get()?;
+
+            let total = audit_events::table
+                .filter(organisation_id.eq(self.organisation.id))
+                .count()
+                .get_result(&conn)?;
+
+            let events = audit_events::table
+                .filter(organisation_id.eq(self.organisation.id))
+                .order_by((created_at.desc(), id.desc()))
+                .limit(per_page)
+                .offset(page.saturating_sub(1) * per_page)
+                .load(&conn)?;
+
+            audit_log_blocking(&conn, events, total)
+        })
+        .await?
+    }
+
+    fn enqueue_member_update(
+        &self,
+        conn: &crate::Connection,
+        given_user_id: i32,
+        given_permissions: Option<UserPermission>,
+    ) -> Result<()> {
+        let payload = WebhookPayload::member_update(conn, None, given_user_id, given_permissions)?;
+
+        enqueue_webhook(conn, self.organisation.id, &payload)
+    }
+
+    /// Ensures the requesting user holds every permission the user's current grant on the
+    /// organisation gives them, so they can't demote or remove someone more privileged than
+    /// themselves.
+    fn ensure_can_manage_member(&self, conn: &crate::Connection, given_user_id: i32) -> Result<()> {
+        let current: Option<UserPermission> = user_organisation_permissions::table
+            .filter(user_organisation_permissions::user_id.eq(given_user_id))
+            .filter(user_organisation_permissions::organisation_id.eq(self.organisation.id))
+            .filter(unexpired!(user_organisation_permissions::expires_at))
+            .select(user_organisation_permissions::permissions)
+            .get_result(conn)
+            .optional()?;
+
+        ensure_grantable(self.permissions, current.unwrap_or_default())
+    }
+
+    pub async fn webhooks(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+    ) -> Result<Vec<OrganisationWebhook>> {
+        if !self.permissions.contains(UserPermission::MANAGE_WEBHOOKS) {
+            return Err(Error::MissingOrganisationPermission(
+                UserPermission::MANAGE_WEBHOOKS,
+            ));
+        }
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            OrganisationWebhook::belonging_to(&self.organisation)
+                .order_by(organisation_webhooks::id.asc())
+                .load(&conn)
+                .map_err(Into::into)
+        })
+        .await?
+    }
+
+    /// Subscribes `url` to the given events, signing deliveries using `given_secret` if one is
+    /// given.
+    pub async fn create_webhook(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        given_url: String,
+        given_secret: Option<String>,
+        given_events: WebhookEvent,
+    ) -> Result<OrganisationWebhook> {
+        if !self.permissions.contains(UserPermission::MANAGE_WEBHOOKS) {
+            return Err(Error::MissingOrganisationPermission(
+                UserPermission::MANAGE_WEBHOOKS,
+            ));
+        }
+
+        tokio::task::spawn_blocking(move || {
+            use crate::schema::organisation_webhooks::dsl::{
+                events, organisation_id, secret, url, uuid,
+            };
+
+            let conn = conn.get()?;
+
+            let webhook_uuid = SqlUuid::random();
+
+            diesel::insert_into(organisation_webhooks::table)
+                .values((
+                    uuid.eq(webhook_uuid),
+                    organisation_id.eq(self.organisation.id),
+                    url.eq(given_url),
+                    secret.eq(given_secret),
+                    events.eq(given_events.bits()),
+                ))
+                .execute(&conn)?;
+
+            organisation_webhooks::table
+                .filter(uuid.eq(webhook_uuid))
+                .get_result(&conn)
+                .map_err(Into::into)
         })
         .await?
     }
+
+    /// Removes a webhook along with its delivery history.
+    pub async fn delete_webhook(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        webhook_uuid: uuid::Uuid,
+    ) -> Result<()> {
+        use crate::schema::webhook_delivery_attempts;
+
+        let webhook = self
+            .clone()
+            .find_webhook(conn.clone(), webhook_uuid)
+            .await?;
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let deliveries =
+                    WebhookDelivery::belonging_to(&webhook).select(webhook_deliveries::id);
+
+                diesel::delete(
+                    webhook_delivery_attempts::table
+                        .filter(webhook_delivery_attempts::webhook_delivery_id.eq_any(deliveries)),
+                )
+                .execute(&conn)?;
+
+                diesel::delete(WebhookDelivery::belonging_to(&webhook)).execute(&conn)?;
+                diesel::delete(&webhook).execute(&conn)?;
+
+                Ok(())
+            })
+        })
+        .await?
+    }
+
+    /// Fetches the most recent deliveries for a webhook along with every attempt that was made
+    /// to send them.
+    pub async fn webhook_deliveries(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        webhook_uuid: uuid::Uuid,
+        limit: i64,
+    ) -> Result<Vec<(WebhookDelivery, Vec<WebhookDeliveryAttempt>)>> {
+        let webhook = self
+            .clone()
+            .find_webhook(conn.clone(), webhook_uuid)
+            .await?;
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            let deliveries: Vec<WebhookDelivery> = WebhookDelivery::belonging_to(&webhook)
+                .order_by(webhook_deliveries::id.desc())
+                .limit(limit)
+                .load(&conn)?;
+
+            let attempts = WebhookDeliveryAttempt::belonging_to(&deliveries)
+                .load::<WebhookDeliveryAttempt>(&conn)?
+                .grouped_by(&deliveries);
+
+            Ok(deliveries.into_iter().zip(attempts).collect())
+        })
+        .await?
+    }
+
+    async fn find_webhook(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        webhook_uuid: uuid::Uuid,
+    ) -> Result<OrganisationWebhook> {
+        if !self.permissions.contains(UserPermission::MANAGE_WEBHOOKS) {
+            return Err(Error::MissingOrganisationPermission(
+                UserPermission::MANAGE_WEBHOOKS,
+            ));
+        }
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            OrganisationWebhook::belonging_to(&self.organisation)
+                .filter(organisation_webhooks::uuid.eq(SqlUuid(webhook_uuid)))
+                .get_result(&conn)
+                .optional()?
+                .ok_or(Error::MissingWebhook)
+        })
+        .await?
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod test {
+    use super::Organisation;
+    use crate::{
+        permissions::UserPermission,
+        schema::storage_deletions,
+        storage_deletions::{StorageDeletion, StorageDeletionReason},
+        testing, Error,
+    };
+    use diesel::prelude::*;
+
+    #[tokio::test]
+    async fn restoring_keeps_deleted_versions_deleted() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        let organisation = testing::organisation(&conn, "org", &owner).await;
+        let crate_ = testing::create_crate(&conn, "org", "my-crate", &owner).await;
+        let actor = testing::actor(&owner);
+
+        testing::publish(&conn, &crate_, &owner, "1.0.0", "aaa")
+            .await
+            .unwrap();
+        testing::publish(&conn, &crate_, &owner, "1.1.0", "bbb")
+            .await
+            .unwrap();
+
+        crate_
+            .delete_version(
+                conn.clone(),
+                &actor,
+                "1.0.0".to_string(),
+                chrono::Duration::hours(1),
+                false,
+            )
+            .await
+            .unwrap();
+
+        organisation.delete(conn.clone(), &actor).await.unwrap();
+
+        let reasons = |conn: &crate::ConnectionPool| {
+            let mut reasons: Vec<_> = storage_deletions::table
+                .load::<StorageDeletion>(&conn.get().unwrap())
+                .unwrap()
+                .into_iter()
+                .map(|v| v.reason)
+                .collect();
+            reasons.sort_by_key(|v| v.as_str());
+            reasons
+        };
+
+        assert_eq!(
+            reasons(&conn),
+            vec![
+                StorageDeletionReason::OrganisationDelete,
+                StorageDeletionReason::VersionDelete
+            ]
+        );
+
+        Organisation::restore(conn.clone(), &actor, owner.id, "org".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(reasons(&conn), vec![StorageDeletionReason::VersionDelete]);
+    }
+
+    #[tokio::test]
+    async fn admins_can_grant_ownership() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        let admin = testing::user(&conn, "admin").await;
+        let member = testing::user(&conn, "member").await;
+        let outsider = testing::user(&conn, "outsider").await;
+        testing::organisation(&conn, "org", &owner).await;
+        let actor = testing::actor(&admin);
+
+        testing::add_member(&conn, "org", &owner, &member, UserPermission::VISIBLE).await;
+
+        for user in [&member, &outsider] {
+            Organisation::grant_ownership(conn.clone(), &actor, "org".to_string(), user.id)
+                .await
+                .unwrap();
+
+            let found = testing::find_organisation(&conn, "org", user).await;
+            assert_eq!(found.permissions, UserPermission::all());
+        }
+
+        assert!(matches!(
+            Organisation::grant_ownership(conn, &actor, "missing".to_string(), outsider.id).await,
+            Err(Error::MissingOrganisation)
+        ));
+    }
+
+    #[tokio::test]
+    async fn lapsed_grants_can_be_granted_again() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        let member = testing::user(&conn, "member").await;
+        let organisation = testing::organisation(&conn, "org", &owner).await;
+        let actor = testing::actor(&owner);
+
+        organisation
+            .clone()
+            .insert_permissions(
+                conn.clone(),
+                &actor,
+                member.id,
+                UserPermission::VISIBLE,
+                Some(chrono::Utc::now().naive_utc() - chrono::Duration::days(1)),
+            )
+            .await
+            .unwrap();
+
+        organisation
+            .insert_permissions(
+                conn.clone(),
+                &actor,
+                member.id,
+                UserPermission::VISIBLE | UserPermission::PUBLISH_VERSION,
+                None,
+            )
+            .await
+            .unwrap();
+
+        let found = testing::find_organisation(&conn, "org", &member).await;
+        assert_eq!(
+            found.permissions,
+            UserPermission::VISIBLE | UserPermission::PUBLISH_VERSION
+        );
+    }
+
+    #[tokio::test]
+    async fn members_cant_grant_permissions_they_dont_have() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        let manager = testing::user(&conn, "manager").await;
+        let target = testing::user(&conn, "target").await;
+        testing::organisation(&conn, "org", &owner).await;
+
+        testing::add_member(
+            &conn,
+            "org",
+            &owner,
+            &manager,
+            UserPermission::VISIBLE | UserPermission::MANAGE_USERS,
+        )
+        .await;
+        let organisation = testing::find_organisation(&conn, "org", &manager).await;
+        let actor = testing::actor(&manager);
+
+        assert!(matches!(
+            organisation
+                .clone()
+                .insert_permissions(
+                    conn.clone(),
+                    &actor,
+                    target.id,
+                    UserPermission::VISIBLE | UserPermission::DELETE_CRATE,
+                    None,
+                )
+                .await,
+            Err(Error::PermissionEscalation(v)) if v == UserPermission::DELETE_CRATE
+        ));
+
+        organisation
+            .clone()
+            .insert_permissions(
+                conn.clone(),
+                &actor,
+                target.id,
+                UserPermission::VISIBLE,
+                None,
+            )
+            .await
+            .unwrap();
+        organisation
+            .clone()
+            .update_permissions(
+                conn.clone(),
+                &actor,
+                target.id,
+                UserPermission::VISIBLE | UserPermission::MANAGE_USERS,
+                None,
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn members_cant_manage_more_privileged_members() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        let manager = testing::user(&conn, "manager").await;
+        testing::organisation(&conn, "org", &owner).await;
+
+        testing::add_member(
+            &conn,
+            "org",
+            &owner,
+            &manager,
+            UserPermission::VISIBLE | UserPermission::MANAGE_USERS,
+        )
+        .await;
+        let organisation = testing::find_organisation(&conn, "org", &manager).await;
+        let actor = testing::actor(&manager);
+
+        assert!(matches!(
+            organisation
+                .clone()
+                .update_permissions(
+                    conn.clone(),
+                    &actor,
+                    owner.id,
+                    UserPermission::VISIBLE,
+                    None
+                )
+                .await,
+            Err(Error::PermissionEscalation(_))
+        ));
+        assert!(matches!(
+            organisation
+                .delete_member(conn.clone(), &actor, owner.id)
+                .await,
+            Err(Error::PermissionEscalation(_))
+        ));
+
+        assert_eq!(
+            testing::find_organisation(&conn, "org", &owner)
+                .await
+                .permissions(),
+            UserPermission::all()
+        );
+    }
+
+    #[tokio::test]
+    async fn directories_only_reconcile_their_own_grants() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        let manual = testing::user(&conn, "manual").await;
+        let mapped = testing::user(&conn, "mapped").await;
+        testing::organisation(&conn, "org", &owner).await;
+
+        testing::add_member(&conn, "org", &owner, &manual, UserPermission::VISIBLE).await;
+
+        for user in [&owner, &manual, &mapped] {
+            Organisation::reconcile_memberships(
+                conn.clone(),
+                user.id,
+                vec![(
+                    "org".to_string(),
+                    UserPermission::VISIBLE | UserPermission::PUBLISH_VERSION,
+                )],
+            )
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(
+            testing::find_organisation(&conn, "org", &owner)
+                .await
+                .permissions,
+            UserPermission::all()
+        );
+        assert_eq!(
+            testing::find_organisation(&conn, "org", &manual)
+                .await
+                .permissions,
+            UserPermission::VISIBLE
+        );
+        assert_eq!(
+            testing::find_organisation(&conn, "org", &mapped)
+                .await
+                .permissions,
+            UserPermission::VISIBLE | UserPermission::PUBLISH_VERSION
+        );
+
+        // leaving the group removes the mapped grant, but not those given by the organisation
+        for user in [&owner, &manual, &mapped] {
+            Organisation::reconcile_memberships(
+                conn.clone(),
+                user.id,
+                vec![("org".to_string(), UserPermission::empty())],
+            )
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(
+            testing::find_organisation(&conn, "org", &owner)
+                .await
+                .permissions,
+            UserPermission::all()
+        );
+        assert_eq!(
+            testing::find_organisation(&conn, "org", &manual)
+                .await
+                .permissions,
+            UserPermission::VISIBLE
+        );
+        assert!(testing::find_organisation(&conn, "org", &mapped)
+            .await
+            .permissions
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn edited_directory_grants_are_left_alone() {
+        let conn = testing::pool();
+        let owner = testing::user(&conn, "owner").await;
+        let mapped = testing::user(&conn, "mapped").await;
+        let organisation = testing::organisation(&conn, "org", &owner).await;
+
+        Organisation::reconcile_memberships(
+            conn.clone(),
+            mapped.id,
+            vec![("org".to_string(), UserPermission::VISIBLE)],
+        )
+        .await
+        .unwrap();
+
+        organisation
+            .update_permissions(
+                conn.clone(),
+                &testing::actor(&owner),
+                mapped.id,
+                UserPermission::VISIBLE | UserPermission::PUBLISH_VERSION,
+                None,
+            )
+            .await
+            .unwrap();
+
+        Organisation::reconcile_memberships(
+            conn.clone(),
+            mapped.id,
+            vec![("org".to_string(), UserPermission::empty())],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            testing::find_organisation(&conn, "org", &mapped)
+                .await
+                .permissions,
+            UserPermission::VISIBLE | UserPermission::PUBLISH_VERSION
+        );
+    }
 }
@@ -0,0 +1,184 @@
+//! WebAuthn credentials ("passkeys") registered by users, which can be used to log in to the web
+//! UI without a password, or in place of a TOTP code for users that have enabled two-factor
+//! authentication.
+//!
+//! The registration and authentication ceremonies themselves are handled by `chartered-web`,
+//! which hands us the credential serialised as JSON to store alongside its id. We never need to
+//! look inside it ourselves.
+
+use super::{
+    audit::{record_blocking, Actor, AuditAction, NewAuditEvent},
+    schema::user_passkeys,
+    users::User,
+    uuid::SqlUuid,
+    ConnectionPool, Error, Result,
+};
+use chrono::{NaiveDateTime, Utc};
+use diesel::{insert_into, prelude::*, Associations, Identifiable, Queryable};
+use std::collections::HashSet;
+
+#[derive(Identifiable, Queryable, Associations, PartialEq, Eq, Hash, Debug)]
+#[belongs_to(User)]
+pub struct UserPasskey {
+    pub id: i32,
+    pub uuid: SqlUuid,
+    pub user_id: i32,
+    pub name: String,
+    pub credential_id: Vec<u8>,
+    /// The credential as serialised by `webauthn-rs`, including its public key and signature
+    /// counter.
+    pub passkey: String,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+impl UserPasskey {
+    /// Lists all the passkeys the user has registered.
+    pub async fn list(conn: ConnectionPool, given_user_id: i32) -> Result<Vec<UserPasskey>> {
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            Ok(user_passkeys::table
+                .filter(user_passkeys::user_id.eq(given_user_id))
+                .order_by(user_passkeys::created_at.desc())
+                .load(&conn)?)
+        })
+        .await?
+    }
+
+    /// Stores a passkey the user has just completed registration for.
+    pub async fn insert(
+        conn: ConnectionPool,
+        given_user_id: i32,
+        actor: &Actor,
+        given_name: String,
+        given_credential_id: Vec<u8>,
+        given_passkey: String,
+    ) -> Result<UserPasskey> {
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let generated_uuid = SqlUuid::random();
+
+                insert_into(user_passkeys::table)
+                    .values((
+                        user_passkeys::uuid.eq(generated_uuid),
+                        user_passkeys::user_id.eq(given_user_id),
+                        user_passkeys::name.eq(given_name),
+                        user_passkeys::credential_id.eq(given_credential_id),
+                        user_passkeys::passkey.eq(given_passkey),
+                    ))
+                    .execute(&conn)?;
+
+                let inserted: UserPasskey = user_passkeys::table
+                    .filter(user_passkeys::uuid.eq(generated_uuid))
+                    .get_result(&conn)?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::PasskeyInsert)
+                        .target_user(given_user_id)
+                        .passkey(inserted.uuid.0),
+                )?;
+
+                Ok(inserted)
+            })
+        })
+        .await?
+    }
+
+    /// Returns which of the given users have registered at least one passkey.
+    pub async fn users_with_passkeys(
+        conn: ConnectionPool,
+        given_user_ids: Vec<i32>,
+    ) -> Result<HashSet<i32>> {
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            Ok(user_passkeys::table
+                .filter(user_passkeys::user_id.eq_any(given_user_ids))
+                .select(user_passkeys::user_id)
+                .distinct()
+                .load::<i32>(&conn)?
+                .into_iter()
+                .collect())
+        })
+        .await?
+    }
+
+    /// Bumps `last_used_at` for the user's passkey after a successful authentication, replacing
+    /// the stored credential if its signature counter or backup state has changed.
+    pub async fn record_use(
+        conn: ConnectionPool,
+        given_user_id: i32,
+        given_credential_id: Vec<u8>,
+        given_passkey: Option<String>,
+    ) -> Result<()> {
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+            let now = Utc::now().naive_utc();
+
+            let passkey = user_passkeys::table
+                .filter(user_passkeys::user_id.eq(given_user_id))
+                .filter(user_passkeys::credential_id.eq(given_credential_id));
+
+            if let Some(given_passkey) = given_passkey {
+                diesel::update(passkey)
+                    .set((
+                        user_passkeys::last_used_at.eq(now),
+                        user_passkeys::passkey.eq(given_passkey),
+                    ))
+                    .execute(&conn)?;
+            } else {
+                diesel::update(passkey)
+                    .set(user_passkeys::last_used_at.eq(now))
+                    .execute(&conn)?;
+            }
+
+            Ok(())
+        })
+        .await?
+    }
+
+    /// Removes one of the user's passkeys, returning `false` if the user has no such passkey.
+    pub async fn delete_by_uuid(
+        conn: ConnectionPool,
+        given_user_id: i32,
+        actor: &Actor,
+        given_uuid: uuid::Uuid,
+    ) -> Result<bool> {
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let deleted = diesel::delete(
+                    user_passkeys::table
+                        .filter(user_passkeys::user_id.eq(given_user_id))
+                        .filter(user_passkeys::uuid.eq(SqlUuid(given_uuid))),
+                )
+                .execute(&conn)?;
+
+                if deleted == 0 {
+                    return Ok(false);
+                }
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::PasskeyDelete)
+                        .target_user(given_user_id)
+                        .passkey(given_uuid),
+                )?;
+
+                Ok(true)
+            })
+        })
+        .await?
+    }
+}
@@ -1,14 +1,20 @@
 use bitflags::bitflags;
 use option_set::{option_set, OptionSet};
+use serde::{Deserialize, Deserializer, Serialize};
 
 option_set! {
     #[derive(FromSqlRow, AsExpression)]
     pub struct UserPermission: Identity + i32 {
-        const VISIBLE         = 0b0000_0000_0000_0000_0000_0000_0000_0001;
-        const PUBLISH_VERSION = 0b0000_0000_0000_0000_0000_0000_0000_0010;
-        const YANK_VERSION    = 0b0000_0000_0000_0000_0000_0000_0000_0100;
-        const MANAGE_USERS    = 0b0000_0000_0000_0000_0000_0000_0000_1000;
-        const CREATE_CRATE    = 0b0000_0000_0000_0000_0000_0000_0001_0000;
+        const VISIBLE             = 0b0000_0000_0000_0000_0000_0000_0000_0001;
+        const PUBLISH_VERSION     = 0b0000_0000_0000_0000_0000_0000_0000_0010;
+        const YANK_VERSION        = 0b0000_0000_0000_0000_0000_0000_0000_0100;
+        const MANAGE_USERS        = 0b0000_0000_0000_0000_0000_0000_0000_1000;
+        const CREATE_CRATE        = 0b0000_0000_0000_0000_0000_0000_0001_0000;
+        const MANAGE_ORGANISATION = 0b0000_0000_0000_0000_0000_0000_0010_0000;
+        const DELETE_CRATE        = 0b0000_0000_0000_0000_0000_0000_0100_0000;
+        const MANAGE_WEBHOOKS     = 0b0000_0000_0000_0000_0000_0000_1000_0000;
+        const MANAGE_TOKENS       = 0b0000_0000_0000_0000_0000_0001_0000_0000;
+        const READ_AUDIT_LOG      = 0b0000_0000_0000_0000_0000_0010_0000_0000;
     }
 }
 
@@ -30,6 +36,88 @@ impl UserPermission {
     }
 }
 
+/// Ensures a user holding `actor` isn't granting, or changing the grant of someone holding,
+/// `permissions` they don't have themselves, so managing users can't be used to escalate
+/// privileges.
+pub(crate) fn ensure_grantable(
+    actor: UserPermission,
+    permissions: UserPermission,
+) -> crate::Result<()> {
+    let mut missing = permissions;
+    missing.remove(actor);
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(crate::Error::PermissionEscalation(missing))
+    }
+}
+
+/// Named sets of permissions that can be assigned to users and groups in place of picking out
+/// each permission individually. Each role includes every permission of the role before it.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Role {
+    Viewer,
+    Publisher,
+    Maintainer,
+    Admin,
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [Self::Viewer, Self::Publisher, Self::Maintainer, Self::Admin];
+
+    #[must_use]
+    pub fn permissions(self) -> UserPermission {
+        match self {
+            Self::Viewer => UserPermission::VISIBLE,
+            Self::Publisher => {
+                Self::Viewer.permissions()
+                    | UserPermission::PUBLISH_VERSION
+                    | UserPermission::YANK_VERSION
+                    | UserPermission::CREATE_CRATE
+            }
+            Self::Maintainer => {
+                Self::Publisher.permissions()
+                    | UserPermission::MANAGE_USERS
+                    | UserPermission::DELETE_CRATE
+                    | UserPermission::MANAGE_TOKENS
+                    | UserPermission::READ_AUDIT_LOG
+            }
+            Self::Admin => UserPermission::all(),
+        }
+    }
+
+    /// Returns every role along with the permissions it grants, for the frontend to offer as
+    /// presets.
+    #[must_use]
+    pub fn presets() -> Vec<(Role, UserPermission)> {
+        Self::ALL.iter().map(|v| (*v, v.permissions())).collect()
+    }
+}
+
+/// Deserialises permissions given either as a list of permission names or as the name of a
+/// [`Role`], for use with `#[serde(deserialize_with)]` on request bodies.
+///
+/// # Errors
+///
+/// Fails if the value is neither a known role nor a list of known permissions.
+pub fn deserialize_assignable<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<UserPermission, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Assignable {
+        Role(Role),
+        Permissions(UserPermission),
+    }
+
+    Ok(match Assignable::deserialize(deserializer)? {
+        Assignable::Role(role) => role.permissions(),
+        Assignable::Permissions(permissions) => permissions,
+    })
+}
+
 impl<B: diesel::backend::Backend> diesel::deserialize::FromSql<diesel::sql_types::Integer, B>
     for UserPermission
 where
@@ -42,3 +130,47 @@ where
         Ok(UserPermission::from_bits_truncate(val))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{deserialize_assignable, ensure_grantable, Role, UserPermission};
+
+    #[test]
+    fn roles_build_on_each_other() {
+        for [lower, higher] in [
+            [Role::Viewer, Role::Publisher],
+            [Role::Publisher, Role::Maintainer],
+            [Role::Maintainer, Role::Admin],
+        ] {
+            assert!(higher.permissions().contains(lower.permissions()));
+            assert_ne!(higher.permissions(), lower.permissions());
+        }
+    }
+
+    #[test]
+    fn assignable_accepts_roles_and_permissions() {
+        let deserialize =
+            |v: &str| deserialize_assignable(&mut serde_json::Deserializer::from_str(v));
+
+        assert_eq!(
+            deserialize(r#""PUBLISHER""#).unwrap(),
+            Role::Publisher.permissions()
+        );
+        assert_eq!(
+            deserialize(r#"["VISIBLE", "YANK_VERSION"]"#).unwrap(),
+            UserPermission::VISIBLE | UserPermission::YANK_VERSION
+        );
+    }
+
+    #[test]
+    fn only_held_permissions_are_grantable() {
+        let actor = UserPermission::VISIBLE | UserPermission::MANAGE_USERS;
+
+        assert!(ensure_grantable(actor, UserPermission::VISIBLE).is_ok());
+        assert!(ensure_grantable(actor, UserPermission::empty()).is_ok());
+        assert!(matches!(
+            ensure_grantable(actor, UserPermission::VISIBLE | UserPermission::DELETE_CRATE),
+            Err(crate::Error::PermissionEscalation(v)) if v == UserPermission::DELETE_CRATE
+        ));
+    }
+}
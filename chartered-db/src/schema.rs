@@ -1,3 +1,64 @@
+table! {
+    audit_events (id) {
+        id -> Integer,
+        uuid -> Binary,
+        action -> Text,
+        actor_user_id -> Nullable<Integer>,
+        target_user_id -> Nullable<Integer>,
+        organisation_id -> Nullable<Integer>,
+        crate_id -> Nullable<Integer>,
+        details -> Binary,
+        ip -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    crate_redirects (id) {
+        id -> Integer,
+        organisation_id -> Integer,
+        name -> Text,
+        crate_id -> Integer,
+        expires_at -> Timestamp,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    crate_trusted_publishers (id) {
+        id -> Integer,
+        uuid -> Binary,
+        crate_id -> Integer,
+        user_id -> Integer,
+        provider -> Text,
+        repository -> Text,
+        workflow -> Nullable<Text>,
+        branch -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    crate_version_downloads (id) {
+        id -> Integer,
+        crate_version_id -> Integer,
+        date -> Date,
+        downloads -> Integer,
+    }
+}
+
+table! {
+    crate_version_reservations (id) {
+        id -> Integer,
+        organisation_id -> Integer,
+        crate_name -> Text,
+        version -> Text,
+        checksum -> Text,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     crate_versions (id) {
         id -> Integer,
@@ -30,6 +91,15 @@ table! {
     }
 }
 
+table! {
+    group_crate_permissions (id) {
+        id -> Integer,
+        group_id -> Integer,
+        crate_id -> Integer,
+        permissions -> Integer,
+    }
+}
+
 table! {
     organisations (id) {
         id -> Integer,
@@ -37,6 +107,85 @@ table! {
         name -> Text,
         description -> Text,
         public -> Bool,
+        upstream_index_uri -> Nullable<Text>,
+        upstream_download_uri -> Nullable<Text>,
+        deleted_at -> Nullable<Timestamp>,
+        require_two_factor -> Bool,
+    }
+}
+
+table! {
+    mirror_crate_files (id) {
+        id -> Integer,
+        organisation_id -> Integer,
+        crate_name -> Text,
+        version -> Text,
+        checksum -> Text,
+        filesystem_object -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    mirror_index_files (id) {
+        id -> Integer,
+        organisation_id -> Integer,
+        path -> Text,
+        content -> Binary,
+        fetched_at -> Timestamp,
+    }
+}
+
+table! {
+    organisation_aliases (id) {
+        id -> Integer,
+        organisation_id -> Integer,
+        name -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    organisation_group_members (id) {
+        id -> Integer,
+        group_id -> Integer,
+        user_id -> Integer,
+    }
+}
+
+table! {
+    organisation_groups (id) {
+        id -> Integer,
+        uuid -> Binary,
+        organisation_id -> Integer,
+        name -> Text,
+        permissions -> Integer,
+    }
+}
+
+table! {
+    organisation_invites (id) {
+        id -> Integer,
+        uuid -> Binary,
+        organisation_id -> Integer,
+        user_id -> Nullable<Integer>,
+        token_hash -> Nullable<Text>,
+        permissions -> Integer,
+        invited_by_user_id -> Integer,
+        expires_at -> Timestamp,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    organisation_webhooks (id) {
+        id -> Integer,
+        uuid -> Binary,
+        organisation_id -> Integer,
+        url -> Text,
+        secret -> Nullable<Text>,
+        events -> Integer,
+        created_at -> Timestamp,
     }
 }
 
@@ -48,12 +197,68 @@ table! {
     }
 }
 
+table! {
+    storage_deletions (id) {
+        id -> Integer,
+        organisation_id -> Integer,
+        filesystem_object -> Text,
+        delete_after -> Timestamp,
+        created_at -> Timestamp,
+        reason -> Text,
+    }
+}
+
+table! {
+    used_cargo_tokens (id) {
+        id -> Integer,
+        token_hash -> Text,
+        expires_at -> Timestamp,
+    }
+}
+
+table! {
+    used_challenges (id) {
+        id -> Integer,
+        challenge_hash -> Text,
+        expires_at -> Timestamp,
+    }
+}
+
+table! {
+    user_api_tokens (id) {
+        id -> Integer,
+        uuid -> Binary,
+        user_id -> Integer,
+        name -> Text,
+        token_hash -> Text,
+        expires_at -> Nullable<Timestamp>,
+        last_used_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        scope -> Nullable<Binary>,
+    }
+}
+
+table! {
+    user_cargo_keys (id) {
+        id -> Integer,
+        uuid -> Binary,
+        user_id -> Integer,
+        name -> Text,
+        public_key -> Text,
+        key_id -> Text,
+        created_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
+    }
+}
+
 table! {
     user_crate_permissions (id) {
         id -> Integer,
         user_id -> Integer,
         crate_id -> Integer,
         permissions -> Integer,
+        restricted_permissions -> Integer,
+        expires_at -> Nullable<Timestamp>,
     }
 }
 
@@ -63,6 +268,29 @@ table! {
         user_id -> Integer,
         organisation_id -> Integer,
         permissions -> Integer,
+        expires_at -> Nullable<Timestamp>,
+        source -> Text,
+    }
+}
+
+table! {
+    user_passkeys (id) {
+        id -> Integer,
+        uuid -> Binary,
+        user_id -> Integer,
+        name -> Text,
+        credential_id -> Binary,
+        passkey -> Text,
+        created_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    user_recovery_codes (id) {
+        id -> Integer,
+        user_id -> Integer,
+        code_hash -> Text,
     }
 }
 
@@ -76,6 +304,7 @@ table! {
         user_agent -> Nullable<Text>,
         ip -> Nullable<Text>,
         uuid -> Binary,
+        scope -> Nullable<Binary>,
     }
 }
 
@@ -102,28 +331,106 @@ table! {
         email -> Nullable<Text>,
         external_profile_url -> Nullable<Text>,
         picture_url -> Nullable<Text>,
+        admin -> Bool,
+        disabled_at -> Nullable<Timestamp>,
+        failed_login_attempts -> Integer,
+        locked_until -> Nullable<Timestamp>,
+        totp_secret -> Nullable<Binary>,
+        totp_enabled_at -> Nullable<Timestamp>,
+        totp_last_step -> Nullable<BigInt>,
+    }
+}
+
+table! {
+    webhook_deliveries (id) {
+        id -> Integer,
+        uuid -> Binary,
+        webhook_id -> Integer,
+        event -> Text,
+        payload -> Binary,
+        attempts -> Integer,
+        next_attempt_at -> Nullable<Timestamp>,
+        delivered_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    webhook_delivery_attempts (id) {
+        id -> Integer,
+        webhook_delivery_id -> Integer,
+        response_status -> Nullable<Integer>,
+        error -> Nullable<Text>,
+        duration_ms -> Integer,
+        attempted_at -> Timestamp,
     }
 }
 
+joinable!(crate_redirects -> crates (crate_id));
+joinable!(crate_redirects -> organisations (organisation_id));
+joinable!(crate_trusted_publishers -> crates (crate_id));
+joinable!(crate_trusted_publishers -> users (user_id));
+joinable!(crate_version_downloads -> crate_versions (crate_version_id));
+joinable!(crate_version_reservations -> organisations (organisation_id));
 joinable!(crate_versions -> crates (crate_id));
 joinable!(crate_versions -> users (user_id));
 joinable!(crates -> organisations (organisation_id));
+joinable!(group_crate_permissions -> crates (crate_id));
+joinable!(group_crate_permissions -> organisation_groups (group_id));
+joinable!(mirror_crate_files -> organisations (organisation_id));
+joinable!(mirror_index_files -> organisations (organisation_id));
+joinable!(organisation_aliases -> organisations (organisation_id));
+joinable!(organisation_group_members -> organisation_groups (group_id));
+joinable!(organisation_group_members -> users (user_id));
+joinable!(organisation_groups -> organisations (organisation_id));
+joinable!(organisation_invites -> organisations (organisation_id));
+joinable!(organisation_invites -> users (user_id));
+joinable!(organisation_webhooks -> organisations (organisation_id));
+joinable!(storage_deletions -> organisations (organisation_id));
+joinable!(user_api_tokens -> users (user_id));
+joinable!(user_cargo_keys -> users (user_id));
 joinable!(user_crate_permissions -> crates (crate_id));
 joinable!(user_crate_permissions -> users (user_id));
 joinable!(user_organisation_permissions -> organisations (organisation_id));
 joinable!(user_organisation_permissions -> users (user_id));
+joinable!(user_passkeys -> users (user_id));
+joinable!(user_recovery_codes -> users (user_id));
 joinable!(user_sessions -> user_ssh_keys (user_ssh_key_id));
 joinable!(user_sessions -> users (user_id));
 joinable!(user_ssh_keys -> users (user_id));
+joinable!(webhook_deliveries -> organisation_webhooks (webhook_id));
+joinable!(webhook_delivery_attempts -> webhook_deliveries (webhook_delivery_id));
 
 allow_tables_to_appear_in_same_query!(
+    audit_events,
+    crate_redirects,
+    crate_trusted_publishers,
+    crate_version_downloads,
+    crate_version_reservations,
     crate_versions,
     crates,
+    group_crate_permissions,
+    mirror_crate_files,
+    mirror_index_files,
+    organisation_aliases,
+    organisation_group_members,
+    organisation_groups,
+    organisation_invites,
+    organisation_webhooks,
     organisations,
     server_private_keys,
+    storage_deletions,
+    used_cargo_tokens,
+    used_challenges,
+    user_api_tokens,
+    user_cargo_keys,
     user_crate_permissions,
     user_organisation_permissions,
+    user_passkeys,
+    user_recovery_codes,
     user_sessions,
     user_ssh_keys,
     users,
+    webhook_deliveries,
+    webhook_delivery_attempts,
 );
@@ -0,0 +1,162 @@
+//! Restrictions that can be placed on a session or API token so it can only ever do a subset
+//! of what the user that owns it can do, ie. a CI job that only needs to publish a single
+//! crate.
+//!
+//! A scope never grants permissions, it's applied on top of the permissions the user already
+//! has through their organisation and crate memberships.
+
+use super::permissions::UserPermission;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, FromSqlRow, AsExpression, Debug, Clone, PartialEq, Eq, Hash)]
+#[sql_type = "diesel::sql_types::Blob"]
+pub struct TokenScope {
+    /// Permissions the session is allowed to use, any permissions outside of this mask are
+    /// removed from the user's permissions.
+    #[serde(default = "UserPermission::all")]
+    pub permissions: UserPermission,
+    /// Globs matching the names of the crates the session can act on, ie. `my-crate-*`. If
+    /// empty, all crates can be acted on.
+    #[serde(default)]
+    pub crates: Vec<String>,
+    /// Names of the organisations the session can act on. If empty, all organisations can be
+    /// acted on.
+    #[serde(default)]
+    pub organisations: Vec<String>,
+}
+
+derive_diesel_json!(TokenScope);
+
+impl Default for TokenScope {
+    /// A scope with no restrictions at all.
+    fn default() -> Self {
+        Self {
+            permissions: UserPermission::all(),
+            crates: Vec::new(),
+            organisations: Vec::new(),
+        }
+    }
+}
+
+impl TokenScope {
+    /// The scope given to anonymous users, which can only ever see crates.
+    #[must_use]
+    pub fn anonymous() -> Self {
+        Self {
+            permissions: UserPermission::VISIBLE,
+            ..Self::default()
+        }
+    }
+
+    #[must_use]
+    pub fn allows_organisation(&self, organisation: &str) -> bool {
+        self.organisations.is_empty()
+            || self
+                .organisations
+                .iter()
+                .any(|v| v.eq_ignore_ascii_case(organisation))
+    }
+
+    #[must_use]
+    pub fn allows_crate(&self, organisation: &str, crate_name: &str) -> bool {
+        self.allows_organisation(organisation)
+            && (self.crates.is_empty() || self.crates.iter().any(|v| glob_match(v, crate_name)))
+    }
+
+    /// Restricts the given permissions for a crate to what's allowed by this scope, returning
+    /// no permissions at all if the crate is out of scope.
+    #[must_use]
+    pub fn restrict(
+        &self,
+        organisation: &str,
+        crate_name: &str,
+        permissions: UserPermission,
+    ) -> UserPermission {
+        if self.allows_crate(organisation, crate_name) {
+            permissions & self.permissions
+        } else {
+            UserPermission::empty()
+        }
+    }
+}
+
+/// Matches `name` against a glob where `*` matches any number of characters and `?` matches
+/// exactly one. Matching is case-insensitive, as crate names are.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase().into_bytes();
+    let name = name.to_ascii_lowercase().into_bytes();
+
+    let (mut p, mut n) = (0, 0);
+    // position of the last `*` seen in the pattern, and the position in the name it was
+    // tried against so we can backtrack to it on a mismatch
+    let mut backtrack = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == b'?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    backtrack = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+#[cfg(test)]
+mod test {
+    use super::{glob_match, TokenScope};
+    use crate::permissions::UserPermission;
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("serde", "serde"));
+        assert!(glob_match("Serde", "serde"));
+        assert!(!glob_match("serde", "serde_json"));
+        assert!(glob_match("serde*", "serde_json"));
+        assert!(glob_match("*json", "serde_json"));
+        assert!(glob_match("s*_*n", "serde_json"));
+        assert!(glob_match("serd?", "serde"));
+        assert!(!glob_match("serd?", "serd"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("a*b", "acbd"));
+    }
+
+    #[test]
+    fn restrict() {
+        let scope = TokenScope {
+            permissions: UserPermission::VISIBLE | UserPermission::PUBLISH_VERSION,
+            crates: vec!["my-crate-*".to_string()],
+            organisations: vec!["my-org".to_string()],
+        };
+
+        assert_eq!(
+            scope.restrict("my-org", "my-crate-core", UserPermission::all()),
+            UserPermission::VISIBLE | UserPermission::PUBLISH_VERSION
+        );
+        assert_eq!(
+            scope.restrict("my-org", "other-crate", UserPermission::all()),
+            UserPermission::empty()
+        );
+        assert_eq!(
+            scope.restrict("other-org", "my-crate-core", UserPermission::all()),
+            UserPermission::empty()
+        );
+        assert_eq!(
+            TokenScope::default().restrict("any", "thing", UserPermission::YANK_VERSION),
+            UserPermission::YANK_VERSION
+        );
+    }
+}
@@ -0,0 +1,56 @@
+//! Instance-wide statistics shown to instance administrators.
+
+use super::{
+    schema::{crate_versions, crates, organisations, users},
+    ConnectionPool, Result,
+};
+use diesel::{dsl::sum, prelude::*};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstanceStatistics {
+    pub users: i64,
+    pub admins: i64,
+    pub disabled_users: i64,
+    /// Organisations that haven't been deleted.
+    pub organisations: i64,
+    pub crates: i64,
+    pub versions: i64,
+    /// Total size of every `.crate` file stored, in bytes.
+    pub storage_bytes: i64,
+    pub downloads: i64,
+}
+
+impl InstanceStatistics {
+    pub async fn get(conn: ConnectionPool) -> Result<Self> {
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            Ok(Self {
+                users: users::table.count().get_result(&conn)?,
+                admins: users::table
+                    .filter(users::admin.eq(true))
+                    .count()
+                    .get_result(&conn)?,
+                disabled_users: users::table
+                    .filter(users::disabled_at.is_not_null())
+                    .count()
+                    .get_result(&conn)?,
+                organisations: organisations::table
+                    .filter(organisations::deleted_at.is_null())
+                    .count()
+                    .get_result(&conn)?,
+                crates: crates::table.count().get_result(&conn)?,
+                versions: crate_versions::table.count().get_result(&conn)?,
+                storage_bytes: crate_versions::table
+                    .select(sum(crate_versions::size))
+                    .get_result::<Option<i64>>(&conn)?
+                    .unwrap_or_default(),
+                downloads: crates::table
+                    .select(sum(crates::downloads))
+                    .get_result::<Option<i64>>(&conn)?
+                    .unwrap_or_default(),
+            })
+        })
+        .await?
+    }
+}
@@ -0,0 +1,104 @@
+//! Storage objects that are no longer referenced by anything in the registry, such as the crate
+//! files of a deleted organisation. They're picked up by a background task in `chartered-web`
+//! once `delete_after` has passed, which removes them from the file system.
+
+use super::{organisations::Organisation, schema::storage_deletions, ConnectionPool, Result};
+use chrono::{NaiveDateTime, Utc};
+use diesel::{prelude::*, sql_types::Text, Associations, Identifiable, Queryable};
+use std::{io::Write, sync::Arc};
+
+/// Why a storage object was scheduled for deletion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromSqlRow, AsExpression)]
+#[sql_type = "Text"]
+pub enum StorageDeletionReason {
+    /// A single version was deleted, which can't be undone.
+    VersionDelete,
+    /// The whole organisation was deleted, which is cancelled if the organisation is restored.
+    OrganisationDelete,
+}
+
+impl StorageDeletionReason {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::VersionDelete => "version_delete",
+            Self::OrganisationDelete => "organisation_delete",
+        }
+    }
+}
+
+impl std::str::FromStr for StorageDeletionReason {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "version_delete" => Self::VersionDelete,
+            "organisation_delete" => Self::OrganisationDelete,
+            _ => return Err(format!("unknown storage deletion reason `{}`", s)),
+        })
+    }
+}
+
+impl<B: diesel::backend::Backend> diesel::deserialize::FromSql<Text, B> for StorageDeletionReason
+where
+    String: diesel::deserialize::FromSql<Text, B>,
+{
+    fn from_sql(bytes: Option<&B::RawValue>) -> diesel::deserialize::Result<Self> {
+        String::from_sql(bytes)?.parse().map_err(Into::into)
+    }
+}
+
+impl<B: diesel::backend::Backend> diesel::serialize::ToSql<Text, B> for StorageDeletionReason
+where
+    str: diesel::serialize::ToSql<Text, B>,
+{
+    fn to_sql<W: Write>(
+        &self,
+        out: &mut diesel::serialize::Output<'_, W, B>,
+    ) -> diesel::serialize::Result {
+        self.as_str().to_sql(out)
+    }
+}
+
+#[derive(Identifiable, Queryable, Associations, PartialEq, Eq, Hash, Debug)]
+#[belongs_to(Organisation)]
+pub struct StorageDeletion {
+    pub id: i32,
+    pub organisation_id: i32,
+    /// The `FileReference` of the object to remove, in its string form.
+    pub filesystem_object: String,
+    pub delete_after: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    pub reason: StorageDeletionReason,
+}
+
+impl StorageDeletion {
+    /// Finds objects whose grace period is up and can be removed.
+    pub async fn find_due(conn: ConnectionPool, limit: i64) -> Result<Vec<StorageDeletion>> {
+        use crate::schema::storage_deletions::dsl::delete_after;
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            storage_deletions::table
+                .filter(delete_after.le(Utc::now().naive_utc()))
+                .order_by(delete_after.asc())
+                .limit(limit)
+                .load(&conn)
+                .map_err(Into::into)
+        })
+        .await?
+    }
+
+    /// Marks the object as removed from the file system.
+    pub async fn complete(self: Arc<Self>, conn: ConnectionPool) -> Result<()> {
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            diesel::delete(&*self).execute(&conn)?;
+
+            Ok(())
+        })
+        .await?
+    }
+}
@@ -0,0 +1,172 @@
+//! Helpers for tests that need a real database to run against, each test gets its own sqlite
+//! database in the temp directory with every migration applied.
+
+use super::{
+    audit::Actor,
+    crates::{Crate, CrateWithPermissions},
+    organisations::{Organisation, OrganisationWithPermissions},
+    permissions::UserPermission,
+    scopes::TokenScope,
+    users::User,
+    ConnectionPool,
+};
+use diesel::r2d2::{ConnectionManager, Pool};
+use std::sync::Arc;
+
+/// Creates an empty database with the schema fully migrated.
+pub fn pool() -> ConnectionPool {
+    let path = std::env::temp_dir().join(format!("chartered-test-{}.db", uuid::Uuid::new_v4()));
+
+    let pool = Pool::new(ConnectionManager::new(path.to_string_lossy())).unwrap();
+    super::embedded_migrations::run(&pool.get().unwrap()).unwrap();
+
+    Arc::new(pool)
+}
+
+pub async fn user(conn: &ConnectionPool, username: &str) -> User {
+    User::register(conn.clone(), username.to_string(), "password".to_string())
+        .await
+        .unwrap();
+
+    User::find_by_username(conn.clone(), username.to_string())
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+/// Creates an organisation owned by `owner`, returning it with the owner's permissions.
+pub async fn organisation(
+    conn: &ConnectionPool,
+    name: &str,
+    owner: &User,
+) -> Arc<OrganisationWithPermissions> {
+    Organisation::create(
+        conn.clone(),
+        name.to_string(),
+        String::new(),
+        false,
+        None,
+        owner.id,
+    )
+    .await
+    .unwrap();
+
+    Arc::new(
+        Organisation::find_by_name(conn.clone(), owner.id, name.to_string())
+            .await
+            .unwrap(),
+    )
+}
+
+/// Fetches the organisation again as seen by `user`.
+pub async fn find_organisation(
+    conn: &ConnectionPool,
+    name: &str,
+    user: &User,
+) -> Arc<OrganisationWithPermissions> {
+    Arc::new(
+        Organisation::find_by_name(conn.clone(), user.id, name.to_string())
+            .await
+            .unwrap(),
+    )
+}
+
+/// Makes `user` a member of the organisation with the given permissions, granted by its owner.
+pub async fn add_member(
+    conn: &ConnectionPool,
+    organisation: &str,
+    owner: &User,
+    user: &User,
+    permissions: UserPermission,
+) {
+    find_organisation(conn, organisation, owner)
+        .await
+        .insert_permissions(conn.clone(), &actor(owner), user.id, permissions, None)
+        .await
+        .unwrap();
+}
+
+/// Creates a crate within the organisation, which `user` needs `CREATE_CRATE` on.
+pub async fn create_crate(
+    conn: &ConnectionPool,
+    organisation: &str,
+    name: &str,
+    user: &User,
+) -> Arc<CrateWithPermissions> {
+    Arc::new(
+        Crate::create(
+            conn.clone(),
+            user.id,
+            Arc::new(TokenScope::default()),
+            organisation.to_string(),
+            name.to_string(),
+        )
+        .await
+        .unwrap(),
+    )
+}
+
+/// Publishes an empty version of the crate, with a crate file that has the given checksum.
+pub async fn publish(
+    conn: &ConnectionPool,
+    crate_: &Arc<CrateWithPermissions>,
+    user: &User,
+    version: &str,
+    checksum: &str,
+) -> crate::Result<()> {
+    let user = User::find_by_username(conn.clone(), user.username.clone())
+        .await?
+        .unwrap();
+
+    crate_
+        .clone()
+        .publish_version(
+            conn.clone(),
+            &actor(&user),
+            Arc::new(user),
+            format!("local:{}", uuid::Uuid::new_v4()).parse().unwrap(),
+            checksum.to_string(),
+            0,
+            chartered_types::cargo::CrateVersion {
+                name: crate_.crate_.name.clone().into(),
+                vers: version.to_string().into(),
+                deps: Vec::new(),
+                features: chartered_types::cargo::CrateFeatures(std::collections::BTreeMap::new()),
+                links: None,
+            },
+            chartered_types::cargo::CrateVersionMetadata {
+                description: None,
+                readme: None,
+                repository: None,
+                homepage: None,
+                documentation: None,
+            },
+        )
+        .await
+}
+
+/// An actor for actions taken by `user`.
+pub fn actor(user: &User) -> Actor {
+    Actor {
+        user_id: Some(user.id),
+        ..Actor::default()
+    }
+}
+
+/// Fetches the crate again as seen by `user`, picking up any changes to their permissions.
+pub async fn find_crate(
+    conn: &ConnectionPool,
+    organisation: &str,
+    name: &str,
+    user: &User,
+) -> crate::Result<Arc<CrateWithPermissions>> {
+    Crate::find_by_name(
+        conn.clone(),
+        user.id,
+        Arc::new(TokenScope::default()),
+        organisation.to_string(),
+        name.to_string(),
+    )
+    .await
+    .map(Arc::new)
+}
@@ -0,0 +1,196 @@
+//! Policies allowing CI jobs to publish a crate without a stored secret. The CI provider hands
+//! the job an OIDC ID token describing the repository and workflow it's running for, which can
+//! be exchanged for a short-lived token if it matches one of the crate's policies.
+//!
+//! Tokens handed out are owned by the user that created the policy, scoped down so they can
+//! only publish the crate the policy belongs to. Verifying the ID token and matching it against
+//! a policy is left to `chartered-web`.
+
+use super::{
+    audit::{record_blocking, Actor, AuditAction, NewAuditEvent},
+    crates::{Crate, CrateWithPermissions},
+    permissions::UserPermission,
+    schema::{crate_trusted_publishers, crates, organisations, users},
+    users::User,
+    uuid::SqlUuid,
+    ConnectionPool, Error, Result,
+};
+use chrono::NaiveDateTime;
+use diesel::{insert_into, prelude::*, Associations, Identifiable, Queryable};
+use std::sync::Arc;
+
+#[derive(Identifiable, Queryable, Associations, PartialEq, Eq, Hash, Debug)]
+#[belongs_to(Crate)]
+#[belongs_to(User)]
+pub struct CrateTrustedPublisher {
+    pub id: i32,
+    pub uuid: SqlUuid,
+    pub crate_id: i32,
+    pub user_id: i32,
+    /// Name of the provider in the `trusted_publishing` config the ID token must come from.
+    pub provider: String,
+    /// The repository the CI job must be running for, ie. `my-org/my-repo`.
+    pub repository: String,
+    /// The workflow file the CI job must be running, ie. `release.yml`. Any workflow in the
+    /// repository is allowed if empty.
+    pub workflow: Option<String>,
+    /// The branch the CI job must be running on. Any branch is allowed if empty.
+    pub branch: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+impl CrateTrustedPublisher {
+    /// Finds all the policies for the given crate that accept ID tokens from `provider`, along
+    /// with the user that created each policy.
+    pub async fn find_for_crate(
+        conn: ConnectionPool,
+        given_org_name: String,
+        given_crate_name: String,
+        given_provider: String,
+    ) -> Result<Vec<(CrateTrustedPublisher, User)>> {
+        use crate::schema::crate_trusted_publishers::dsl::provider;
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            Ok(crate_trusted_publishers::table
+                .inner_join(crates::table.inner_join(organisations::table))
+                .inner_join(users::table)
+                .filter(organisation_by_name!(&given_org_name))
+                .filter(crates::name.eq(given_crate_name))
+                .filter(provider.eq(given_provider))
+                // tokens are handed out on behalf of the policy's creator, so policies created
+                // by users that have since been disabled can't be used
+                .filter(users::disabled_at.is_null())
+                .select((crate_trusted_publishers::all_columns, users::all_columns))
+                .load(&conn)?)
+        })
+        .await?
+    }
+}
+
+impl CrateWithPermissions {
+    pub async fn trusted_publishers(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+    ) -> Result<Vec<CrateTrustedPublisher>> {
+        if !self.permissions.contains(UserPermission::MANAGE_TOKENS) {
+            return Err(Error::MissingCratePermission(UserPermission::MANAGE_TOKENS));
+        }
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            Ok(CrateTrustedPublisher::belonging_to(&self.crate_)
+                .order_by(crate_trusted_publishers::created_at.desc())
+                .load(&conn)?)
+        })
+        .await?
+    }
+
+    /// Creates a new policy for the crate, tokens handed out by the policy will be owned by
+    /// `given_user_id`.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn insert_trusted_publisher(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        actor: &Actor,
+        given_user_id: i32,
+        given_provider: String,
+        given_repository: String,
+        given_workflow: Option<String>,
+        given_branch: Option<String>,
+    ) -> Result<CrateTrustedPublisher> {
+        use crate::schema::crate_trusted_publishers::dsl::{
+            branch, crate_id, provider, repository, user_id, uuid, workflow,
+        };
+
+        if !self.permissions.contains(UserPermission::MANAGE_TOKENS) {
+            return Err(Error::MissingCratePermission(UserPermission::MANAGE_TOKENS));
+        }
+
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let generated_uuid = SqlUuid::random();
+
+                insert_into(crate_trusted_publishers::table)
+                    .values((
+                        uuid.eq(generated_uuid),
+                        crate_id.eq(self.crate_.id),
+                        user_id.eq(given_user_id),
+                        provider.eq(given_provider),
+                        repository.eq(given_repository),
+                        workflow.eq(given_workflow),
+                        branch.eq(given_branch),
+                    ))
+                    .execute(&conn)?;
+
+                let inserted: CrateTrustedPublisher = crate_trusted_publishers::table
+                    .filter(uuid.eq(generated_uuid))
+                    .get_result(&conn)?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::TrustedPublisherInsert)
+                        .for_crate(&self.crate_)
+                        .trusted_publisher(&inserted.provider, &inserted.repository),
+                )?;
+
+                Ok(inserted)
+            })
+        })
+        .await?
+    }
+
+    /// Removes one of the crate's policies, returning `false` if the crate has no such policy.
+    pub async fn delete_trusted_publisher(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        actor: &Actor,
+        given_uuid: uuid::Uuid,
+    ) -> Result<bool> {
+        use crate::schema::crate_trusted_publishers::dsl::{crate_id, id, uuid};
+
+        if !self.permissions.contains(UserPermission::MANAGE_TOKENS) {
+            return Err(Error::MissingCratePermission(UserPermission::MANAGE_TOKENS));
+        }
+
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let policy: Option<CrateTrustedPublisher> = crate_trusted_publishers::table
+                    .filter(crate_id.eq(self.crate_.id))
+                    .filter(uuid.eq(SqlUuid(given_uuid)))
+                    .get_result(&conn)
+                    .optional()?;
+
+                let policy = match policy {
+                    Some(v) => v,
+                    None => return Ok(false),
+                };
+
+                diesel::delete(crate_trusted_publishers::table.filter(id.eq(policy.id)))
+                    .execute(&conn)?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::TrustedPublisherDelete)
+                        .for_crate(&self.crate_)
+                        .trusted_publisher(&policy.provider, &policy.repository),
+                )?;
+
+                Ok(true)
+            })
+        })
+        .await?
+    }
+}
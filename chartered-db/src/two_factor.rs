@@ -0,0 +1,412 @@
+//! Storage for users' TOTP two-factor authentication, and the recovery codes that can be used
+//! in place of a code if they lose their device. Generating and checking the codes themselves
+//! is handled by `chartered-web`.
+//!
+//! Enrolment happens in two steps, the secret is stored when the user starts enrolling but
+//! isn't enforced until they've proven they can generate codes from it.
+
+use super::{
+    audit::{record_blocking, Actor, AuditAction, NewAuditEvent},
+    schema::{user_passkeys, user_recovery_codes, users},
+    users::User,
+    ConnectionPool, Error, Result,
+};
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sha2::{Digest, Sha256};
+
+/// Number of recovery codes handed out to the user each time they're generated.
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Number of random characters in each half of a recovery code.
+const RECOVERY_CODE_HALF_LENGTH: usize = 5;
+
+/// Hashes a recovery code for storage or lookup. Codes are compared case-insensitively and
+/// without surrounding whitespace, since they're typed in by hand.
+fn hash_recovery_code(code: &str) -> String {
+    hex::encode(Sha256::digest(code.trim().to_ascii_lowercase().as_bytes()))
+}
+
+/// Generates a fresh set of recovery codes in the format `xxxxx-xxxxx`.
+fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = thread_rng();
+    let mut half = || -> String {
+        (&mut rng)
+            .sample_iter(&Alphanumeric)
+            .take(RECOVERY_CODE_HALF_LENGTH)
+            .map(|c| char::from(c).to_ascii_lowercase())
+            .collect()
+    };
+
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| format!("{}-{}", half(), half()))
+        .collect()
+}
+
+/// Replaces all of the user's recovery codes with a fresh set, returning the new codes so they
+/// can be shown to the user. Only hashes of the codes are stored.
+fn replace_recovery_codes_blocking(
+    conn: &crate::Connection,
+    given_user_id: i32,
+) -> Result<Vec<String>> {
+    diesel::delete(
+        user_recovery_codes::table.filter(user_recovery_codes::user_id.eq(given_user_id)),
+    )
+    .execute(conn)?;
+
+    let codes = generate_recovery_codes();
+
+    let rows: Vec<_> = codes
+        .iter()
+        .map(|code| {
+            (
+                user_recovery_codes::user_id.eq(given_user_id),
+                user_recovery_codes::code_hash.eq(hash_recovery_code(code)),
+            )
+        })
+        .collect();
+
+    diesel::insert_into(user_recovery_codes::table)
+        .values(&rows)
+        .execute(conn)?;
+
+    Ok(codes)
+}
+
+impl User {
+    /// Whether the user has completed TOTP enrolment.
+    #[must_use]
+    pub fn has_totp(&self) -> bool {
+        self.totp_enabled_at.is_some()
+    }
+
+    /// Whether the user has a second factor, either by completing TOTP enrolment or registering
+    /// a passkey, and must use it to complete a password login.
+    pub async fn has_two_factor(&self, conn: ConnectionPool) -> Result<bool> {
+        if self.has_totp() {
+            return Ok(true);
+        }
+
+        let given_user_id = self.id;
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            Ok(diesel::select(diesel::dsl::exists(
+                user_passkeys::table.filter(user_passkeys::user_id.eq(given_user_id)),
+            ))
+            .get_result(&conn)?)
+        })
+        .await?
+    }
+
+    /// Starts TOTP enrolment by storing the given secret, replacing any secret from a previous
+    /// enrolment that was never completed.
+    pub async fn begin_totp_enrolment(
+        &self,
+        conn: ConnectionPool,
+        given_secret: Vec<u8>,
+    ) -> Result<()> {
+        if self.has_totp() {
+            return Err(Error::TwoFactorAlreadyEnabled);
+        }
+
+        let given_user_id = self.id;
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            diesel::update(users::table.find(given_user_id))
+                .set((
+                    users::totp_secret.eq(given_secret),
+                    users::totp_last_step.eq(None::<i64>),
+                ))
+                .execute(&conn)?;
+
+            Ok(())
+        })
+        .await?
+    }
+
+    /// Completes TOTP enrolment once the user has given a valid code for `step`, returning the
+    /// user's recovery codes.
+    pub async fn enable_totp(
+        &self,
+        conn: ConnectionPool,
+        actor: &Actor,
+        step: i64,
+    ) -> Result<Vec<String>> {
+        if self.has_totp() {
+            return Err(Error::TwoFactorAlreadyEnabled);
+        }
+
+        let actor = actor.clone();
+        let given_user_id = self.id;
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                diesel::update(users::table.find(given_user_id))
+                    .set((
+                        users::totp_enabled_at.eq(Utc::now().naive_utc()),
+                        users::totp_last_step.eq(step),
+                    ))
+                    .execute(&conn)?;
+
+                let codes = replace_recovery_codes_blocking(&conn, given_user_id)?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::TwoFactorEnable).target_user(given_user_id),
+                )?;
+
+                Ok(codes)
+            })
+        })
+        .await?
+    }
+
+    /// Turns off two-factor authentication for the user, removing their secret and recovery
+    /// codes.
+    pub async fn disable_totp(&self, conn: ConnectionPool, actor: &Actor) -> Result<()> {
+        let actor = actor.clone();
+        let given_user_id = self.id;
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                diesel::update(users::table.find(given_user_id))
+                    .set((
+                        users::totp_secret.eq(None::<Vec<u8>>),
+                        users::totp_enabled_at.eq(None::<NaiveDateTime>),
+                        users::totp_last_step.eq(None::<i64>),
+                    ))
+                    .execute(&conn)?;
+
+                diesel::delete(
+                    user_recovery_codes::table
+                        .filter(user_recovery_codes::user_id.eq(given_user_id)),
+                )
+                .execute(&conn)?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::TwoFactorDisable).target_user(given_user_id),
+                )
+            })
+        })
+        .await?
+    }
+
+    /// Marks the TOTP time step as used, returning `false` if a code for this step (or a later
+    /// one) has already been accepted and the code is being replayed.
+    pub async fn use_totp_step(&self, conn: ConnectionPool, step: i64) -> Result<bool> {
+        let given_user_id = self.id;
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            let updated = diesel::update(
+                users::table.find(given_user_id).filter(
+                    users::totp_last_step
+                        .is_null()
+                        .or(users::totp_last_step.lt(step)),
+                ),
+            )
+            .set(users::totp_last_step.eq(step))
+            .execute(&conn)?;
+
+            Ok(updated == 1)
+        })
+        .await?
+    }
+
+    /// Uses up one of the user's recovery codes, returning `false` if the code isn't one of
+    /// theirs or has already been used.
+    pub async fn use_recovery_code(
+        &self,
+        conn: ConnectionPool,
+        actor: &Actor,
+        given_code: String,
+    ) -> Result<bool> {
+        let actor = actor.clone();
+        let given_user_id = self.id;
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let deleted = diesel::delete(
+                    user_recovery_codes::table
+                        .filter(user_recovery_codes::user_id.eq(given_user_id))
+                        .filter(user_recovery_codes::code_hash.eq(hash_recovery_code(&given_code))),
+                )
+                .execute(&conn)?;
+
+                if deleted == 0 {
+                    return Ok(false);
+                }
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::RecoveryCodeUse).target_user(given_user_id),
+                )?;
+
+                Ok(true)
+            })
+        })
+        .await?
+    }
+
+    /// Replaces the user's recovery codes with a fresh set, returning the new codes.
+    pub async fn regenerate_recovery_codes(
+        &self,
+        conn: ConnectionPool,
+        actor: &Actor,
+    ) -> Result<Vec<String>> {
+        if !self.has_totp() {
+            return Err(Error::TwoFactorNotEnabled);
+        }
+
+        let actor = actor.clone();
+        let given_user_id = self.id;
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let codes = replace_recovery_codes_blocking(&conn, given_user_id)?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::RecoveryCodesRegenerate)
+                        .target_user(given_user_id),
+                )?;
+
+                Ok(codes)
+            })
+        })
+        .await?
+    }
+
+    /// Number of unused recovery codes the user has left.
+    pub async fn recovery_codes_remaining(&self, conn: ConnectionPool) -> Result<i64> {
+        let given_user_id = self.id;
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            Ok(user_recovery_codes::table
+                .filter(user_recovery_codes::user_id.eq(given_user_id))
+                .count()
+                .get_result(&conn)?)
+        })
+        .await?
+    }
+}
+
+/// SQL evaluating to a mask that removes every permission a member has been granted on the
+/// organisation in `organisation_table` if it requires two-factor authentication and the user
+/// has neither enabled TOTP nor registered a passkey, or a mask that keeps every permission
+/// otherwise.
+pub(crate) fn two_factor_mask_sql(user_id: i32, organisation_table: &str) -> String {
+    format!(
+        "(CASE WHEN {table}.require_two_factor AND NOT EXISTS (SELECT 1 FROM users WHERE users.id = {user} AND users.totp_enabled_at IS NOT NULL) AND NOT EXISTS (SELECT 1 FROM user_passkeys WHERE user_passkeys.user_id = {user}) THEN 0 ELSE -1 END)",
+        table = organisation_table,
+        user = user_id,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{generate_recovery_codes, hash_recovery_code, RECOVERY_CODE_COUNT};
+    #[cfg(feature = "sqlite")]
+    use crate::{passkeys::UserPasskey, permissions::UserPermission, testing};
+
+    #[test]
+    fn recovery_codes_are_unique_and_well_formed() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+
+        for code in &codes {
+            let (left, right) = code.split_once('-').unwrap();
+            assert_eq!(left.len(), 5);
+            assert_eq!(right.len(), 5);
+            assert!(code
+                .chars()
+                .all(|c| c == '-' || c.is_ascii_lowercase() || c.is_ascii_digit()));
+        }
+
+        let mut deduped = codes.clone();
+        deduped.sort();
+        deduped.dedup();
+        assert_eq!(deduped.len(), codes.len());
+    }
+
+    #[test]
+    fn recovery_code_hash_ignores_case_and_whitespace() {
+        assert_eq!(
+            hash_recovery_code("abcde-12345"),
+            hash_recovery_code(" ABCDE-12345\n")
+        );
+        assert_ne!(
+            hash_recovery_code("abcde-12345"),
+            hash_recovery_code("abcde-12346")
+        );
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn passkeys_count_as_a_second_factor() {
+        let db = testing::pool();
+        let owner = testing::user(&db, "owner").await;
+        let member = testing::user(&db, "member").await;
+
+        testing::organisation(&db, "org", &owner)
+            .await
+            .update(
+                db.clone(),
+                &testing::actor(&owner),
+                None,
+                None,
+                None,
+                Some(true),
+            )
+            .await
+            .unwrap();
+        testing::add_member(&db, "org", &owner, &member, UserPermission::VISIBLE).await;
+
+        assert!(!member.has_two_factor(db.clone()).await.unwrap());
+        assert!(testing::find_organisation(&db, "org", &member)
+            .await
+            .permissions()
+            .is_empty());
+
+        UserPasskey::insert(
+            db.clone(),
+            member.id,
+            &testing::actor(&member),
+            "laptop".to_string(),
+            vec![1, 2, 3],
+            "{}".to_string(),
+        )
+        .await
+        .unwrap();
+
+        assert!(member.has_two_factor(db.clone()).await.unwrap());
+        assert!(!member.has_totp());
+        assert_eq!(
+            testing::find_organisation(&db, "org", &member)
+                .await
+                .permissions(),
+            UserPermission::VISIBLE
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//! Login challenges that have already been completed, such as the encrypted state handed to the
+//! client for a passkey ceremony. Challenges are otherwise stateless, so without this anyone who
+//! observed one alongside its response could repeat the login for as long as the challenge is
+//! valid.
+//!
+//! Only a hash of the challenge is stored, hashing is left to `chartered-web`.
+
+use super::{schema::used_challenges, ConnectionPool, Result};
+use chrono::{NaiveDateTime, Utc};
+use diesel::{
+    insert_into,
+    prelude::*,
+    result::{DatabaseErrorKind, Error as DieselError},
+};
+
+/// A challenge that's been completed, kept until it would've expired anyway.
+pub struct UsedChallenge;
+
+impl UsedChallenge {
+    /// Marks the challenge as used, returning `false` if it's already been used before.
+    pub async fn claim(
+        conn: ConnectionPool,
+        given_challenge_hash: String,
+        given_expires_at: NaiveDateTime,
+    ) -> Result<bool> {
+        use crate::schema::used_challenges::dsl::{challenge_hash, expires_at};
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            // challenges that have expired are rejected anyway, so there's no point keeping
+            // them around
+            diesel::delete(used_challenges::table.filter(expires_at.lt(Utc::now().naive_utc())))
+                .execute(&conn)?;
+
+            let res = insert_into(used_challenges::table)
+                .values((
+                    challenge_hash.eq(given_challenge_hash),
+                    expires_at.eq(given_expires_at),
+                ))
+                .execute(&conn);
+
+            match res {
+                Ok(_) => Ok(true),
+                Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Ok(false),
+                Err(e) => Err(e.into()),
+            }
+        })
+        .await?
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod test {
+    use super::UsedChallenge;
+    use crate::testing;
+    use chrono::{Duration, Utc};
+
+    #[tokio::test]
+    async fn challenges_can_only_be_claimed_once() {
+        let db = testing::pool();
+        let expires_at = Utc::now().naive_utc() + Duration::minutes(5);
+
+        assert!(
+            UsedChallenge::claim(db.clone(), "a".to_string(), expires_at)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !UsedChallenge::claim(db.clone(), "a".to_string(), expires_at)
+                .await
+                .unwrap()
+        );
+        assert!(UsedChallenge::claim(db, "b".to_string(), expires_at)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn expired_challenges_are_purged() {
+        let db = testing::pool();
+
+        assert!(UsedChallenge::claim(
+            db.clone(),
+            "a".to_string(),
+            Utc::now().naive_utc() - Duration::minutes(1),
+        )
+        .await
+        .unwrap());
+
+        // the expired row is removed before the insert, so it doesn't conflict
+        assert!(UsedChallenge::claim(
+            db,
+            "a".to_string(),
+            Utc::now().naive_utc() + Duration::minutes(5),
+        )
+        .await
+        .unwrap());
+    }
+}
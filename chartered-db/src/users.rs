@@ -1,7 +1,9 @@
 use super::{
+    audit::{record_blocking, Actor, AuditAction, NewAuditEvent},
     crates::UserCratePermission,
     permissions::UserPermission,
-    schema::{user_crate_permissions, user_sessions, user_ssh_keys, users},
+    schema::{user_api_tokens, user_crate_permissions, user_sessions, user_ssh_keys, users},
+    scopes::TokenScope,
     uuid::SqlUuid,
     ConnectionPool, Error, Result,
 };
@@ -17,6 +19,17 @@ use rand::{thread_rng, Rng};
 use std::sync::Arc;
 use thrussh_keys::PublicKeyBase64;
 
+/// Id given to anonymous users. Ids are allocated from 1, so this never matches a real user
+/// and anonymous users end up with only the `VISIBLE` permission public organisations grant to
+/// everyone.
+pub const ANONYMOUS_USER_ID: i32 = 0;
+
+/// Number of consecutive failed password logins after which the user is locked out.
+pub const MAX_FAILED_LOGIN_ATTEMPTS: i32 = 5;
+
+/// How long a user is locked out of password logins for after too many failed attempts.
+pub const LOCKOUT_DURATION_MINUTES: i64 = 15;
+
 #[derive(Identifiable, Queryable, Associations, PartialEq, Eq, Hash, Debug)]
 pub struct User {
     pub id: i32,
@@ -28,9 +41,73 @@ pub struct User {
     pub email: Option<String>,
     pub external_profile_url: Option<String>,
     pub picture_url: Option<String>,
+    /// Whether the user is an instance administrator, able to manage every user and
+    /// organisation on the server.
+    pub admin: bool,
+    /// When the user was disabled, disabled users can't authenticate in any way.
+    pub disabled_at: Option<NaiveDateTime>,
+    pub failed_login_attempts: i32,
+    /// Password logins are refused until this time, after too many failed attempts.
+    pub locked_until: Option<NaiveDateTime>,
+    /// The user's TOTP secret, which is only enforced once `totp_enabled_at` is set.
+    pub totp_secret: Option<Vec<u8>>,
+    pub totp_enabled_at: Option<NaiveDateTime>,
+    /// The last TOTP time step a code was accepted for, codes from this step or before are
+    /// rejected so they can't be replayed.
+    pub totp_last_step: Option<i64>,
 }
 
 impl User {
+    /// A user that hasn't authenticated at all, for servers that allow anonymous access to
+    /// public organisations.
+    #[must_use]
+    pub fn anonymous() -> Self {
+        Self {
+            id: ANONYMOUS_USER_ID,
+            uuid: SqlUuid(uuid::Uuid::nil()),
+            username: "anonymous".to_string(),
+            password: None,
+            name: None,
+            nick: None,
+            email: None,
+            external_profile_url: None,
+            picture_url: None,
+            admin: false,
+            disabled_at: None,
+            failed_login_attempts: 0,
+            locked_until: None,
+            totp_secret: None,
+            totp_enabled_at: None,
+            totp_last_step: None,
+        }
+    }
+
+    #[must_use]
+    pub fn is_anonymous(&self) -> bool {
+        self.id == ANONYMOUS_USER_ID
+    }
+
+    #[must_use]
+    pub fn is_disabled(&self) -> bool {
+        self.disabled_at.is_some()
+    }
+
+    /// Whether the user is currently locked out of password logins.
+    #[must_use]
+    pub fn is_locked(&self) -> bool {
+        matches!(self.locked_until, Some(v) if v > Utc::now().naive_utc())
+    }
+
+    /// Returns an error if the user isn't allowed to authenticate, must be checked by every
+    /// way a user can authenticate or log in.
+    pub fn ensure_enabled(&self) -> Result<()> {
+        if self.is_disabled() {
+            Err(Error::UserDisabled)
+        } else {
+            Ok(())
+        }
+    }
+
     pub async fn search(
         conn: ConnectionPool,
         given_query: String,
@@ -180,6 +257,28 @@ impl User {
         .await?
     }
 
+    /// Replaces the user's name and email with the ones held by the external directory they
+    /// log in through, which is authoritative for them.
+    pub async fn sync_directory_profile(
+        &self,
+        conn: ConnectionPool,
+        given_name: Option<String>,
+        given_email: Option<String>,
+    ) -> Result<()> {
+        let given_user_id = self.id;
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            diesel::update(users::table.find(given_user_id))
+                .set((users::name.eq(given_name), users::email.eq(given_email)))
+                .execute(&conn)?;
+
+            Ok(())
+        })
+        .await?
+    }
+
     pub async fn register(
         conn: ConnectionPool,
         username: String,
@@ -212,6 +311,7 @@ impl User {
     pub async fn insert_ssh_key(
         self: Arc<Self>,
         conn: ConnectionPool,
+        actor: &Actor,
         ssh_key: &str,
     ) -> Result<()> {
         let mut split = ssh_key.split_whitespace();
@@ -223,22 +323,37 @@ impl User {
 
         let parsed_key = thrussh_keys::parse_public_key_base64(key)?;
         let parsed_name = split.next().unwrap_or("(none)").to_string();
+        let actor = actor.clone();
 
         tokio::task::spawn_blocking(move || {
             use crate::schema::user_ssh_keys::dsl::{name, ssh_key, user_id, uuid};
 
             let conn = conn.get()?;
 
-            insert_into(crate::schema::user_ssh_keys::dsl::user_ssh_keys)
-                .values((
-                    uuid.eq(SqlUuid::random()),
-                    name.eq(parsed_name),
-                    ssh_key.eq(parsed_key.public_key_bytes()),
-                    user_id.eq(self.id),
-                ))
-                .execute(&conn)?;
-
-            Ok(())
+            conn.transaction::<_, Error, _>(|| {
+                let key_uuid = SqlUuid::random();
+
+                insert_into(crate::schema::user_ssh_keys::dsl::user_ssh_keys)
+                    .values((
+                        uuid.eq(key_uuid),
+                        name.eq(parsed_name),
+                        ssh_key.eq(parsed_key.public_key_bytes()),
+                        user_id.eq(self.id),
+                    ))
+                    .execute(&conn)?;
+
+                let inserted: UserSshKey = user_ssh_keys::table
+                    .filter(uuid.eq(key_uuid))
+                    .get_result(&conn)?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::SshKeyInsert)
+                        .target_user(self.id)
+                        .ssh_key_fingerprint(inserted.fingerprint()?),
+                )
+            })
         })
         .await?
     }
@@ -246,21 +361,43 @@ impl User {
     pub async fn delete_user_ssh_key_by_uuid(
         self: Arc<Self>,
         conn: ConnectionPool,
+        actor: &Actor,
         ssh_key_id: uuid::Uuid,
     ) -> Result<bool> {
         use crate::schema::user_ssh_keys::dsl::{user_id, user_ssh_keys, uuid};
 
+        let actor = actor.clone();
+
         tokio::task::spawn_blocking(move || {
             let conn = conn.get()?;
 
-            let rows = diesel::delete(
-                user_ssh_keys
+            conn.transaction::<_, Error, _>(|| {
+                let key: Option<UserSshKey> = user_ssh_keys
                     .filter(user_id.eq(self.id))
-                    .filter(uuid.eq(SqlUuid(ssh_key_id))),
-            )
-            .execute(&conn)?;
+                    .filter(uuid.eq(SqlUuid(ssh_key_id)))
+                    .get_result(&conn)
+                    .optional()?;
 
-            Ok(rows > 0)
+                let key = match key {
+                    Some(key) => key,
+                    None => return Ok(false),
+                };
+
+                diesel::delete(user_ssh_keys.filter(crate::schema::user_ssh_keys::id.eq(key.id)))
+                    .execute(&conn)?;
+
+                let mut event = NewAuditEvent::new(AuditAction::SshKeyDelete).target_user(self.id);
+
+                // we still want to allow unparseable keys to be removed, even if we can't log
+                // exactly which key it was
+                if let Ok(fingerprint) = key.fingerprint() {
+                    event = event.ssh_key_fingerprint(fingerprint);
+                }
+
+                record_blocking(&conn, &actor, event)?;
+
+                Ok(true)
+            })
         })
         .await?
     }
@@ -293,6 +430,7 @@ impl User {
             let conn = conn.get()?;
 
             Ok(UserCratePermission::belonging_to(&*self)
+                .filter(unexpired!(user_crate_permissions::expires_at))
                 .inner_join(crate::schema::crates::table)
                 .select((user_crate_permissions::permissions, crates::all_columns))
                 .load(&conn)?)
@@ -311,6 +449,295 @@ impl User {
             .permissions)
     }
 
+    /// Lists a page of every user on the instance, ordered by username, along with the total
+    /// number of users.
+    pub async fn list(conn: ConnectionPool, page: i64, per_page: i64) -> Result<(Vec<User>, i64)> {
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            let total = users::table.count().get_result(&conn)?;
+
+            let users = users::table
+                .order_by(users::username.asc())
+                .limit(per_page)
+                .offset(page.saturating_sub(1) * per_page)
+                .load(&conn)?;
+
+            Ok((users, total))
+        })
+        .await?
+    }
+
+    /// Makes each of the given users an instance administrator, used to bootstrap admins from
+    /// the server's config. Usernames that don't exist yet are ignored.
+    pub async fn promote_admins(conn: ConnectionPool, usernames: Vec<String>) -> Result<()> {
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            diesel::update(users::table.filter(users::username.eq_any(usernames)))
+                .set(users::admin.eq(true))
+                .execute(&conn)?;
+
+            Ok(())
+        })
+        .await?
+    }
+
+    /// Grants or revokes the user's instance administrator rights. The last enabled
+    /// administrator can't be demoted.
+    pub async fn set_admin(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        actor: &Actor,
+        given_admin: bool,
+    ) -> Result<()> {
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                if !given_admin {
+                    ensure_admin_remains_blocking(&conn, self.id)?;
+                }
+
+                diesel::update(users::table.find(self.id))
+                    .set(users::admin.eq(given_admin))
+                    .execute(&conn)?;
+
+                let action = if given_admin {
+                    AuditAction::AdminGrant
+                } else {
+                    AuditAction::AdminRevoke
+                };
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(action).target_user(self.id),
+                )
+            })
+        })
+        .await?
+    }
+
+    /// Disables or re-enables the user. Disabling a user also revokes all of their sessions and
+    /// API tokens so they're logged out everywhere straight away. The last enabled
+    /// administrator can't be disabled.
+    pub async fn set_disabled(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        actor: &Actor,
+        disabled: bool,
+    ) -> Result<()> {
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                if disabled {
+                    ensure_admin_remains_blocking(&conn, self.id)?;
+                }
+
+                let given_disabled_at = disabled.then(|| Utc::now().naive_utc());
+
+                diesel::update(users::table.find(self.id))
+                    .set(users::disabled_at.eq(given_disabled_at))
+                    .execute(&conn)?;
+
+                let action = if disabled {
+                    delete_credentials_blocking(&conn, self.id)?;
+
+                    AuditAction::UserDisable
+                } else {
+                    AuditAction::UserEnable
+                };
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(action).target_user(self.id),
+                )
+            })
+        })
+        .await?
+    }
+
+    /// Revokes every session and API token the user has, logging them out everywhere. Returns
+    /// the number of sessions and tokens revoked.
+    pub async fn delete_sessions(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        actor: &Actor,
+    ) -> Result<usize> {
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let deleted = delete_credentials_blocking(&conn, self.id)?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::SessionRevokeAll).target_user(self.id),
+                )?;
+
+                Ok(deleted)
+            })
+        })
+        .await?
+    }
+
+    /// Removes every SSH key the user has, along with the sessions created for them. Returns
+    /// the number of keys removed.
+    pub async fn delete_ssh_keys(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        actor: &Actor,
+    ) -> Result<usize> {
+        let actor = actor.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                diesel::delete(
+                    user_sessions::table
+                        .filter(user_sessions::user_id.eq(self.id))
+                        .filter(user_sessions::user_ssh_key_id.is_not_null()),
+                )
+                .execute(&conn)?;
+
+                let deleted =
+                    diesel::delete(user_ssh_keys::table.filter(user_ssh_keys::user_id.eq(self.id)))
+                        .execute(&conn)?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::SshKeyRevokeAll).target_user(self.id),
+                )?;
+
+                Ok(deleted)
+            })
+        })
+        .await?
+    }
+
+    /// Claims one of the user's login attempts before their password or code is checked, so
+    /// concurrent guesses can't all get past the lockout before any of them are recorded. The
+    /// attempt counts as a failure until `clear_failed_logins` is called, returning
+    /// `Error::UserLocked` if the user is locked out or has no attempts left.
+    pub async fn claim_login_attempt(&self, conn: ConnectionPool, actor: &Actor) -> Result<()> {
+        let actor = actor.clone();
+        let given_user_id = self.id;
+
+        let claimed = tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                let now = Utc::now().naive_utc();
+
+                let claimed = diesel::update(
+                    users::table
+                        .find(given_user_id)
+                        .filter(
+                            users::locked_until
+                                .is_null()
+                                .or(users::locked_until.le(now)),
+                        )
+                        .filter(users::failed_login_attempts.lt(MAX_FAILED_LOGIN_ATTEMPTS)),
+                )
+                .set(users::failed_login_attempts.eq(users::failed_login_attempts + 1))
+                .execute(&conn)?;
+
+                // attempts can run out without any of them being recorded as failures, such as
+                // when they're abandoned part way through, in which case the user is locked
+                // out now rather than being left without any attempts forever
+                if claimed == 0 {
+                    lock_if_exhausted_blocking(&conn, &actor, given_user_id)?;
+                }
+
+                Ok(claimed > 0)
+            })
+        })
+        .await??;
+
+        if claimed {
+            Ok(())
+        } else {
+            Err(Error::UserLocked)
+        }
+    }
+
+    /// Records that a claimed login attempt failed, locking the user out for
+    /// `LOCKOUT_DURATION_MINUTES` once they've used up `MAX_FAILED_LOGIN_ATTEMPTS` in a row.
+    pub async fn record_failed_login(&self, conn: ConnectionPool, actor: &Actor) -> Result<()> {
+        let actor = actor.clone();
+        let given_user_id = self.id;
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                lock_if_exhausted_blocking(&conn, &actor, given_user_id)
+            })
+        })
+        .await?
+    }
+
+    /// Gives back an attempt claimed for a password that turned out to be correct when the
+    /// login still needs a second factor. Any failures before it keep counting towards the
+    /// lockout until the login is completed, so wrong codes can't be reset by giving the
+    /// password again.
+    pub async fn release_login_attempt(&self, conn: ConnectionPool) -> Result<()> {
+        let given_user_id = self.id;
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            diesel::update(
+                users::table
+                    .find(given_user_id)
+                    .filter(users::failed_login_attempts.gt(0)),
+            )
+            .set(users::failed_login_attempts.eq(users::failed_login_attempts - 1))
+            .execute(&conn)?;
+
+            Ok(())
+        })
+        .await?
+    }
+
+    /// Resets the user's failed login count after a successful login, giving back the attempt
+    /// claimed for it.
+    pub async fn clear_failed_logins(&self, conn: ConnectionPool) -> Result<()> {
+        let given_user_id = self.id;
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            diesel::update(
+                users::table.find(given_user_id).filter(
+                    users::failed_login_attempts
+                        .ne(0)
+                        .or(users::locked_until.is_not_null()),
+                ),
+            )
+            .set((
+                users::failed_login_attempts.eq(0),
+                users::locked_until.eq(None::<NaiveDateTime>),
+            ))
+            .execute(&conn)?;
+
+            Ok(())
+        })
+        .await?
+    }
+
     #[must_use]
     pub fn display_name(&self) -> &str {
         self.nick
@@ -355,6 +782,84 @@ impl User {
     }
 }
 
+/// Locks the user out if they've used up all of their login attempts and aren't already locked
+/// out. Only one of the attempts that fail concurrently gets to lock the user, so the lockout
+/// is only recorded once.
+fn lock_if_exhausted_blocking(
+    conn: &crate::Connection,
+    actor: &Actor,
+    given_user_id: i32,
+) -> Result<()> {
+    let now = Utc::now().naive_utc();
+
+    let locked = diesel::update(
+        users::table
+            .find(given_user_id)
+            .filter(
+                users::locked_until
+                    .is_null()
+                    .or(users::locked_until.le(now)),
+            )
+            .filter(users::failed_login_attempts.ge(MAX_FAILED_LOGIN_ATTEMPTS)),
+    )
+    .set((
+        users::failed_login_attempts.eq(0),
+        users::locked_until.eq(now + Duration::minutes(LOCKOUT_DURATION_MINUTES)),
+    ))
+    .execute(conn)?;
+
+    if locked == 0 {
+        return Ok(());
+    }
+
+    record_blocking(
+        conn,
+        actor,
+        NewAuditEvent::new(AuditAction::UserLock).target_user(given_user_id),
+    )
+}
+
+/// Ensures the instance still has an enabled administrator other than the given user, if the
+/// user is one, so the last administrator can't be demoted or disabled.
+fn ensure_admin_remains_blocking(conn: &crate::Connection, given_user_id: i32) -> Result<()> {
+    let is_admin = users::table
+        .find(given_user_id)
+        .filter(users::disabled_at.is_null())
+        .select(users::admin)
+        .get_result::<bool>(conn)
+        .optional()?
+        .unwrap_or(false);
+
+    if !is_admin {
+        return Ok(());
+    }
+
+    let remaining: i64 = users::table
+        .filter(users::id.ne(given_user_id))
+        .filter(users::admin.eq(true))
+        .filter(users::disabled_at.is_null())
+        .count()
+        .get_result(conn)?;
+
+    if remaining == 0 {
+        Err(Error::LastAdmin)
+    } else {
+        Ok(())
+    }
+}
+
+/// Deletes every session and API token belonging to the user, returning how many were deleted.
+fn delete_credentials_blocking(conn: &crate::Connection, given_user_id: i32) -> Result<usize> {
+    let sessions =
+        diesel::delete(user_sessions::table.filter(user_sessions::user_id.eq(given_user_id)))
+            .execute(conn)?;
+    let tokens =
+        diesel::delete(user_api_tokens::table.filter(user_api_tokens::user_id.eq(given_user_id)))
+            .execute(conn)?;
+
+    Ok(sessions + tokens)
+}
+
 #[derive(Identifiable, Queryable, Associations, PartialEq, Eq, Hash, Debug)]
 #[belongs_to(User)]
 #[belongs_to(UserSshKey)]
@@ -367,21 +872,37 @@ pub struct UserSession {
     pub user_agent: Option<String>,
     pub ip: Option<String>,
     pub uuid: SqlUuid,
+    pub scope: Option<TokenScope>,
 }
 
 impl UserSession {
+    /// The restrictions placed on what this session can do, sessions without a scope can do
+    /// anything the user can.
+    #[must_use]
+    pub fn scope(&self) -> TokenScope {
+        self.scope.clone().unwrap_or_default()
+    }
+
+    /// Creates a new session for the user, which is recorded to the audit log as a login by
+    /// the `actor`.
+    #[allow(clippy::too_many_arguments)]
     pub async fn generate(
         conn: ConnectionPool,
+        actor: &Actor,
         given_user_id: i32,
         given_user_ssh_key_id: Option<i32>,
         given_expires_at: Option<chrono::NaiveDateTime>,
         given_user_agent: Option<String>,
         given_ip: Option<String>,
+        given_scope: Option<TokenScope>,
     ) -> Result<Self> {
         use crate::schema::user_sessions::dsl::{
-            expires_at, ip, session_key, user_agent, user_id, user_sessions, user_ssh_key_id, uuid,
+            expires_at, ip, scope, session_key, user_agent, user_id, user_sessions,
+            user_ssh_key_id, uuid,
         };
 
+        let actor = actor.clone();
+
         tokio::task::spawn_blocking(move || {
             let conn = conn.get()?;
 
@@ -391,21 +912,34 @@ impl UserSession {
                 .map(char::from)
                 .collect();
 
-            insert_into(user_sessions)
-                .values((
-                    user_id.eq(given_user_id),
-                    session_key.eq(&generated_session_key),
-                    user_ssh_key_id.eq(given_user_ssh_key_id),
-                    expires_at.eq(given_expires_at),
-                    user_agent.eq(given_user_agent),
-                    ip.eq(given_ip),
-                    uuid.eq(SqlUuid::random()),
-                ))
-                .execute(&conn)?;
-
-            Ok(crate::schema::user_sessions::table
-                .filter(session_key.eq(generated_session_key))
-                .get_result(&conn)?)
+            conn.transaction::<_, Error, _>(|| {
+                let generated_uuid = SqlUuid::random();
+
+                insert_into(user_sessions)
+                    .values((
+                        user_id.eq(given_user_id),
+                        session_key.eq(&generated_session_key),
+                        user_ssh_key_id.eq(given_user_ssh_key_id),
+                        expires_at.eq(given_expires_at),
+                        user_agent.eq(given_user_agent),
+                        ip.eq(given_ip),
+                        uuid.eq(generated_uuid),
+                        scope.eq(given_scope),
+                    ))
+                    .execute(&conn)?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::Login)
+                        .target_user(given_user_id)
+                        .session(generated_uuid.0),
+                )?;
+
+                Ok(crate::schema::user_sessions::table
+                    .filter(session_key.eq(generated_session_key))
+                    .get_result(&conn)?)
+            })
         })
         .await?
     }
@@ -432,28 +966,47 @@ impl UserSession {
         .await?
     }
 
-    pub async fn delete(self: Arc<Self>, conn: ConnectionPool) -> Result<bool> {
-        tokio::task::spawn_blocking(move || {
-            let conn = conn.get()?;
-
-            let res = diesel::delete(user_sessions::table)
-                .filter(user_sessions::id.eq(self.id))
-                .execute(&conn)?;
-
-            Ok(res > 0)
-        })
-        .await?
+    pub async fn delete(self: Arc<Self>, conn: ConnectionPool, actor: &Actor) -> Result<bool> {
+        Self::delete_by_uuid(conn, actor, self.uuid.0).await
     }
 
-    pub async fn delete_by_uuid(conn: ConnectionPool, uuid: uuid::Uuid) -> Result<bool> {
+    pub async fn delete_by_uuid(
+        conn: ConnectionPool,
+        actor: &Actor,
+        uuid: uuid::Uuid,
+    ) -> Result<bool> {
+        let actor = actor.clone();
+
         tokio::task::spawn_blocking(move || {
             let conn = conn.get()?;
 
-            let res = diesel::delete(user_sessions::table)
-                .filter(user_sessions::uuid.eq(SqlUuid(uuid)))
-                .execute(&conn)?;
-
-            Ok(res > 0)
+            conn.transaction::<_, Error, _>(|| {
+                let session_user_id = user_sessions::table
+                    .filter(user_sessions::uuid.eq(SqlUuid(uuid)))
+                    .select(user_sessions::user_id)
+                    .get_result::<i32>(&conn)
+                    .optional()?;
+
+                let session_user_id = if let Some(v) = session_user_id {
+                    v
+                } else {
+                    return Ok(false);
+                };
+
+                diesel::delete(user_sessions::table)
+                    .filter(user_sessions::uuid.eq(SqlUuid(uuid)))
+                    .execute(&conn)?;
+
+                record_blocking(
+                    &conn,
+                    &actor,
+                    NewAuditEvent::new(AuditAction::SessionDelete)
+                        .target_user(session_user_id)
+                        .session(uuid),
+                )?;
+
+                Ok(true)
+            })
         })
         .await?
     }
@@ -526,7 +1079,23 @@ impl UserSshKey {
         if let Some(res) = res {
             Ok(res)
         } else {
-            UserSession::generate(conn, self.user_id, Some(self.id), None, None, ip).await
+            let actor = Actor {
+                user_id: Some(self.user_id),
+                ip: ip.clone(),
+                user_agent: None,
+            };
+
+            UserSession::generate(
+                conn,
+                &actor,
+                self.user_id,
+                Some(self.id),
+                None,
+                None,
+                ip,
+                None,
+            )
+            .await
         }
     }
 
@@ -568,3 +1137,225 @@ impl UserSshKey {
         Ok(hex)
     }
 }
+
+#[cfg(all(test, feature = "sqlite"))]
+mod test {
+    use super::{User, UserSession, MAX_FAILED_LOGIN_ATTEMPTS};
+    use crate::{
+        audit::{Actor, AuditAction, AuditEvent},
+        schema::users,
+        testing, Error,
+    };
+    use diesel::prelude::*;
+    use std::sync::Arc;
+
+    async fn reload(conn: &crate::ConnectionPool, user: &User) -> Arc<User> {
+        Arc::new(
+            User::find_by_uuid(conn.clone(), user.uuid.0)
+                .await
+                .unwrap()
+                .unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn admins_can_be_granted_and_revoked() {
+        let conn = testing::pool();
+        let admin = testing::user(&conn, "admin").await;
+        let user = Arc::new(testing::user(&conn, "user").await);
+        let actor = testing::actor(&admin);
+
+        User::promote_admins(conn.clone(), vec!["admin".to_string()])
+            .await
+            .unwrap();
+        assert!(reload(&conn, &admin).await.admin);
+
+        user.clone()
+            .set_admin(conn.clone(), &actor, true)
+            .await
+            .unwrap();
+        assert!(reload(&conn, &user).await.admin);
+
+        user.clone()
+            .set_admin(conn.clone(), &actor, false)
+            .await
+            .unwrap();
+        assert!(!reload(&conn, &user).await.admin);
+    }
+
+    #[tokio::test]
+    async fn the_last_admin_cant_be_demoted_or_disabled() {
+        let conn = testing::pool();
+        let admin = testing::user(&conn, "admin").await;
+        let other = testing::user(&conn, "other").await;
+        let actor = testing::actor(&admin);
+
+        User::promote_admins(conn.clone(), vec!["admin".to_string()])
+            .await
+            .unwrap();
+        let admin = reload(&conn, &admin).await;
+
+        assert!(matches!(
+            admin.clone().set_admin(conn.clone(), &actor, false).await,
+            Err(Error::LastAdmin)
+        ));
+        assert!(matches!(
+            admin.clone().set_disabled(conn.clone(), &actor, true).await,
+            Err(Error::LastAdmin)
+        ));
+        assert!(reload(&conn, &admin).await.admin);
+        assert!(!reload(&conn, &admin).await.is_disabled());
+
+        // a disabled admin doesn't count towards keeping the instance manageable
+        let other = reload(&conn, &other).await;
+        other
+            .clone()
+            .set_admin(conn.clone(), &actor, true)
+            .await
+            .unwrap();
+        other
+            .clone()
+            .set_disabled(conn.clone(), &actor, true)
+            .await
+            .unwrap();
+        assert!(matches!(
+            admin.clone().set_admin(conn.clone(), &actor, false).await,
+            Err(Error::LastAdmin)
+        ));
+
+        other
+            .set_disabled(conn.clone(), &actor, false)
+            .await
+            .unwrap();
+        admin.set_admin(conn.clone(), &actor, false).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn disabling_a_user_logs_them_out() {
+        let conn = testing::pool();
+        let admin = testing::user(&conn, "admin").await;
+        let user = Arc::new(testing::user(&conn, "user").await);
+        let actor = testing::actor(&admin);
+
+        let session = UserSession::generate(
+            conn.clone(),
+            &testing::actor(&user),
+            user.id,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+        user.clone()
+            .set_disabled(conn.clone(), &actor, true)
+            .await
+            .unwrap();
+        assert!(reload(&conn, &user).await.is_disabled());
+        assert!(matches!(
+            reload(&conn, &user).await.ensure_enabled(),
+            Err(Error::UserDisabled)
+        ));
+        assert!(User::find_by_session_key(conn.clone(), session.session_key)
+            .await
+            .unwrap()
+            .is_none());
+
+        user.clone()
+            .set_disabled(conn.clone(), &actor, false)
+            .await
+            .unwrap();
+        assert!(reload(&conn, &user).await.ensure_enabled().is_ok());
+    }
+
+    async fn fail_login(conn: &crate::ConnectionPool, user: &User) -> Result<(), Error> {
+        user.claim_login_attempt(conn.clone(), &Actor::default())
+            .await?;
+        user.record_failed_login(conn.clone(), &Actor::default())
+            .await
+    }
+
+    #[tokio::test]
+    async fn too_many_failed_logins_lock_the_user_out() {
+        let conn = testing::pool();
+        let user = testing::user(&conn, "user").await;
+
+        for _ in 0..MAX_FAILED_LOGIN_ATTEMPTS {
+            fail_login(&conn, &user).await.unwrap();
+        }
+
+        assert!(reload(&conn, &user).await.is_locked());
+        assert!(matches!(
+            user.claim_login_attempt(conn.clone(), &Actor::default())
+                .await,
+            Err(Error::UserLocked)
+        ));
+
+        let log = AuditEvent::list_for_user(conn.clone(), user.id, 1, 10)
+            .await
+            .unwrap();
+        assert_eq!(log.total, 1);
+        assert_eq!(log.events[0].action, AuditAction::UserLock);
+    }
+
+    #[tokio::test]
+    async fn claimed_attempts_count_until_cleared() {
+        let conn = testing::pool();
+        let user = testing::user(&conn, "user").await;
+
+        // attempts still being checked count towards the lockout, so concurrent guesses can't
+        // all get past it
+        for _ in 0..MAX_FAILED_LOGIN_ATTEMPTS {
+            user.claim_login_attempt(conn.clone(), &Actor::default())
+                .await
+                .unwrap();
+        }
+        assert!(matches!(
+            user.claim_login_attempt(conn.clone(), &Actor::default())
+                .await,
+            Err(Error::UserLocked)
+        ));
+
+        // none of the attempts were recorded as failures, but the user still ends up locked
+        // out rather than without any attempts left forever
+        assert!(reload(&conn, &user).await.is_locked());
+
+        user.clear_failed_logins(conn.clone()).await.unwrap();
+        user.claim_login_attempt(conn.clone(), &Actor::default())
+            .await
+            .unwrap();
+        assert_eq!(reload(&conn, &user).await.failed_login_attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn lockouts_expire() {
+        let conn = testing::pool();
+        let user = testing::user(&conn, "user").await;
+
+        for _ in 0..MAX_FAILED_LOGIN_ATTEMPTS {
+            fail_login(&conn, &user).await.unwrap();
+        }
+
+        diesel::update(users::table.find(user.id))
+            .set(
+                users::locked_until
+                    .eq(chrono::Utc::now().naive_utc() - chrono::Duration::minutes(1)),
+            )
+            .execute(&conn.get().unwrap())
+            .unwrap();
+
+        let user = reload(&conn, &user).await;
+        assert!(!user.is_locked());
+        assert_eq!(user.failed_login_attempts, 0);
+
+        // the user gets a full set of attempts back rather than being locked out again by the
+        // next failure
+        for _ in 0..MAX_FAILED_LOGIN_ATTEMPTS - 1 {
+            fail_login(&conn, &user).await.unwrap();
+        }
+        assert!(!reload(&conn, &user).await.is_locked());
+    }
+}
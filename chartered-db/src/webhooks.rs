@@ -0,0 +1,336 @@
+//! Outgoing webhooks that organisations can subscribe to so they're notified of events
+//! happening within the registry, such as a new version being published.
+//!
+//! Deliveries are written to the database within the same transaction as the event that caused
+//! them and are then picked up by a background task in `chartered-web` which sends them out,
+//! retrying with an exponential backoff if the receiving end fails.
+
+use super::{
+    organisations::Organisation,
+    permissions::UserPermission,
+    schema::{
+        organisation_webhooks, organisations, users, webhook_deliveries, webhook_delivery_attempts,
+    },
+    uuid::SqlUuid,
+    BitwiseExpressionMethods, ConnectionPool, Error, Result,
+};
+use bitflags::bitflags;
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{insert_into, prelude::*, Associations, Identifiable, Queryable};
+use option_set::{option_set, OptionSet};
+use serde::Serialize;
+use std::sync::Arc;
+
+/// Number of times a delivery is attempted before we give up on it.
+pub const MAX_DELIVERY_ATTEMPTS: i32 = 8;
+
+option_set! {
+    #[derive(FromSqlRow, AsExpression)]
+    pub struct WebhookEvent: Identity + i32 {
+        const PUBLISH_VERSION = 0b0000_0000_0000_0000_0000_0000_0000_0001;
+        const YANK_VERSION    = 0b0000_0000_0000_0000_0000_0000_0000_0010;
+        const UNYANK_VERSION  = 0b0000_0000_0000_0000_0000_0000_0000_0100;
+        const MEMBER_UPDATE   = 0b0000_0000_0000_0000_0000_0000_0000_1000;
+    }
+}
+
+impl WebhookEvent {
+    #[must_use]
+    pub fn names() -> &'static [&'static str] {
+        Self::NAMES
+    }
+}
+
+impl<B: diesel::backend::Backend> diesel::deserialize::FromSql<diesel::sql_types::Integer, B>
+    for WebhookEvent
+where
+    i32: diesel::deserialize::FromSql<diesel::sql_types::Integer, B>,
+{
+    fn from_sql(
+        bytes: Option<&B::RawValue>,
+    ) -> std::result::Result<WebhookEvent, Box<dyn std::error::Error + Send + Sync>> {
+        let val = i32::from_sql(bytes)?;
+        Ok(WebhookEvent::from_bits_truncate(val))
+    }
+}
+
+/// The body of a webhook, serialised as JSON and sent to every subscribed webhook in the
+/// organisation the event happened in.
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WebhookPayload {
+    PublishVersion {
+        crate_name: String,
+        version: String,
+    },
+    YankVersion {
+        crate_name: String,
+        version: String,
+    },
+    UnyankVersion {
+        crate_name: String,
+        version: String,
+    },
+    /// A user's permissions were changed on either the organisation or, if `crate_name` is
+    /// set, a crate within the organisation. `permissions` is `None` if the user was removed.
+    MemberUpdate {
+        crate_name: Option<String>,
+        user_uuid: uuid::Uuid,
+        permissions: Option<UserPermission>,
+    },
+}
+
+impl WebhookPayload {
+    #[must_use]
+    pub fn event(&self) -> WebhookEvent {
+        match self {
+            Self::PublishVersion { .. } => WebhookEvent::PUBLISH_VERSION,
+            Self::YankVersion { .. } => WebhookEvent::YANK_VERSION,
+            Self::UnyankVersion { .. } => WebhookEvent::UNYANK_VERSION,
+            Self::MemberUpdate { .. } => WebhookEvent::MEMBER_UPDATE,
+        }
+    }
+
+    #[must_use]
+    pub fn event_name(&self) -> &'static str {
+        match self {
+            Self::PublishVersion { .. } => "publish_version",
+            Self::YankVersion { .. } => "yank_version",
+            Self::UnyankVersion { .. } => "unyank_version",
+            Self::MemberUpdate { .. } => "member_update",
+        }
+    }
+
+    /// Builds a `MemberUpdate` payload, looking up the uuid of the affected user.
+    pub(crate) fn member_update(
+        conn: &crate::Connection,
+        crate_name: Option<String>,
+        user_id: i32,
+        permissions: Option<UserPermission>,
+    ) -> Result<Self> {
+        let user_uuid: SqlUuid = users::table
+            .find(user_id)
+            .select(users::uuid)
+            .get_result(conn)?;
+
+        Ok(Self::MemberUpdate {
+            crate_name,
+            user_uuid: user_uuid.0,
+            permissions,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct WebhookEnvelope<'a> {
+    organisation: &'a str,
+    timestamp: chrono::DateTime<Utc>,
+    #[serde(flatten)]
+    payload: &'a WebhookPayload,
+}
+
+/// Queues up a delivery of `payload` to every webhook in the organisation that's subscribed to
+/// the event. This should be called within the transaction that caused the event so deliveries
+/// are only sent out for changes that actually made it to the database.
+pub(crate) fn enqueue_blocking(
+    conn: &crate::Connection,
+    given_organisation_id: i32,
+    given_payload: &WebhookPayload,
+) -> Result<()> {
+    let webhook_ids: Vec<i32> = organisation_webhooks::table
+        .filter(organisation_webhooks::organisation_id.eq(given_organisation_id))
+        .filter(
+            organisation_webhooks::events
+                .bitwise_and(given_payload.event().bits())
+                .ne(0),
+        )
+        .select(organisation_webhooks::id)
+        .load(conn)?;
+
+    if webhook_ids.is_empty() {
+        return Ok(());
+    }
+
+    let organisation: String = organisations::table
+        .find(given_organisation_id)
+        .select(organisations::name)
+        .get_result(conn)?;
+
+    let body = serde_json::to_vec(&WebhookEnvelope {
+        organisation: &organisation,
+        timestamp: Utc::now(),
+        payload: given_payload,
+    })?;
+
+    let now = Utc::now().naive_utc();
+
+    for given_webhook_id in webhook_ids {
+        use crate::schema::webhook_deliveries::dsl::{
+            event, next_attempt_at, payload, uuid, webhook_id,
+        };
+
+        insert_into(webhook_deliveries::table)
+            .values((
+                uuid.eq(SqlUuid::random()),
+                webhook_id.eq(given_webhook_id),
+                event.eq(given_payload.event_name()),
+                payload.eq(&body),
+                next_attempt_at.eq(now),
+            ))
+            .execute(conn)?;
+    }
+
+    Ok(())
+}
+
+/// How long to wait before retrying a delivery that has failed `attempts` times, or `None` if
+/// we've hit [`MAX_DELIVERY_ATTEMPTS`] and should give up.
+#[must_use]
+pub fn retry_delay(attempts: i32) -> Option<Duration> {
+    if attempts >= MAX_DELIVERY_ATTEMPTS {
+        return None;
+    }
+
+    // 30s, 1m, 2m, 4m, ...
+    let exponent = u32::try_from(attempts.max(1) - 1).unwrap_or_default();
+    Some(Duration::seconds(30 * 2_i64.pow(exponent)))
+}
+
+#[derive(Identifiable, Queryable, Associations, PartialEq, Eq, Debug)]
+#[belongs_to(Organisation)]
+pub struct OrganisationWebhook {
+    pub id: i32,
+    pub uuid: SqlUuid,
+    pub organisation_id: i32,
+    pub url: String,
+    /// Key deliveries are signed with, deliveries are unsigned if there isn't one.
+    pub secret: Option<String>,
+    pub events: WebhookEvent,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Identifiable, Queryable, Associations, PartialEq, Eq, Debug)]
+#[belongs_to(OrganisationWebhook, foreign_key = "webhook_id")]
+#[table_name = "webhook_deliveries"]
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub uuid: SqlUuid,
+    pub webhook_id: i32,
+    pub event: String,
+    pub payload: Vec<u8>,
+    pub attempts: i32,
+    pub next_attempt_at: Option<NaiveDateTime>,
+    pub delivered_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+impl WebhookDelivery {
+    /// Fetches deliveries that are due to be sent, along with the webhook they're to be sent
+    /// to.
+    pub async fn find_due(
+        conn: ConnectionPool,
+        limit: i64,
+    ) -> Result<Vec<(WebhookDelivery, OrganisationWebhook)>> {
+        use crate::schema::webhook_deliveries::dsl::next_attempt_at;
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.get()?;
+
+            webhook_deliveries::table
+                .inner_join(organisation_webhooks::table)
+                .filter(next_attempt_at.le(Utc::now().naive_utc()))
+                .order_by(next_attempt_at.asc())
+                .limit(limit)
+                .load(&conn)
+                .map_err(Into::into)
+        })
+        .await?
+    }
+
+    /// Logs an attempt at sending this delivery and schedules the next attempt if it failed.
+    pub async fn record_attempt(
+        self: Arc<Self>,
+        conn: ConnectionPool,
+        given_response_status: Option<u16>,
+        given_error: Option<String>,
+        duration: std::time::Duration,
+    ) -> Result<()> {
+        let success = matches!(given_response_status, Some(200..=299));
+
+        tokio::task::spawn_blocking(move || {
+            use crate::schema::{
+                webhook_deliveries::dsl::{attempts, delivered_at, id, next_attempt_at},
+                webhook_delivery_attempts::dsl::{
+                    duration_ms, error, response_status, webhook_delivery_id,
+                },
+            };
+
+            let conn = conn.get()?;
+
+            conn.transaction::<_, Error, _>(|| {
+                insert_into(webhook_delivery_attempts::table)
+                    .values((
+                        webhook_delivery_id.eq(self.id),
+                        response_status.eq(given_response_status.map(i32::from)),
+                        error.eq(given_error),
+                        duration_ms.eq(i32::try_from(duration.as_millis()).unwrap_or(i32::MAX)),
+                    ))
+                    .execute(&conn)?;
+
+                let now = Utc::now().naive_utc();
+                let new_attempts = self.attempts + 1;
+
+                let (new_delivered_at, new_next_attempt_at) = if success {
+                    (Some(now), None)
+                } else {
+                    (None, retry_delay(new_attempts).map(|delay| now + delay))
+                };
+
+                diesel::update(webhook_deliveries::table.filter(id.eq(self.id)))
+                    .set((
+                        attempts.eq(new_attempts),
+                        delivered_at.eq(new_delivered_at),
+                        next_attempt_at.eq(new_next_attempt_at),
+                    ))
+                    .execute(&conn)?;
+
+                Ok(())
+            })
+        })
+        .await?
+    }
+}
+
+#[derive(Identifiable, Queryable, Associations, PartialEq, Eq, Debug)]
+#[belongs_to(WebhookDelivery)]
+pub struct WebhookDeliveryAttempt {
+    pub id: i32,
+    pub webhook_delivery_id: i32,
+    pub response_status: Option<i32>,
+    pub error: Option<String>,
+    pub duration_ms: i32,
+    pub attempted_at: NaiveDateTime,
+}
+
+#[cfg(test)]
+mod test {
+    use super::{retry_delay, MAX_DELIVERY_ATTEMPTS};
+    use chrono::Duration;
+
+    #[test]
+    fn retry_delay_backs_off_exponentially() {
+        assert_eq!(retry_delay(1), Some(Duration::seconds(30)));
+        assert_eq!(retry_delay(2), Some(Duration::seconds(60)));
+        assert_eq!(retry_delay(3), Some(Duration::seconds(120)));
+        assert_eq!(
+            retry_delay(MAX_DELIVERY_ATTEMPTS - 1),
+            Some(Duration::seconds(30 * 64))
+        );
+    }
+
+    #[test]
+    fn retry_delay_gives_up() {
+        assert_eq!(retry_delay(MAX_DELIVERY_ATTEMPTS), None);
+        assert_eq!(retry_delay(MAX_DELIVERY_ATTEMPTS + 1), None);
+    }
+}
@@ -1,5 +1,6 @@
 import { get, writable } from 'svelte/store';
 import { goto } from '$app/navigation';
+import { getPasskeyAssertion, type PasskeyRequestOptions } from '../webauthn';
 
 /**
  * The base URL of the chartered-web instance
@@ -106,21 +107,46 @@ interface LoginResponse {
 }
 type LoginResult = LoginResponse & Error;
 
+/**
+ * Response type of /web/v1/auth/login/password for users with two-factor authentication
+ * enabled, the challenge needs to be sent back along with a code to complete the login.
+ */
+interface TwoFactorRequiredResponse {
+    two_factor_required: true;
+    challenge: string;
+    passkey: PasskeyRequestOptions | null;
+}
+type PasswordLoginResult = (LoginResponse | TwoFactorRequiredResponse) & Error;
+
+/**
+ * A password login that still needs a second factor, `passkey` is set if the user has passkeys
+ * they can use in place of a code.
+ */
+export type TwoFactorChallenge = Omit<TwoFactorRequiredResponse, 'two_factor_required'>;
+
 /**
  * Attempt to log the user in using password-based auth with the given credentials,
  * throwing an error if the credentials are invalid or another error occurred.
  *
+ * If the user has two-factor authentication enabled, the challenge to pass to
+ * `loginTwoFactor` or `loginTwoFactorPasskey` is returned instead.
+ *
  * @param username username to attempt to log in with
  * @param password password to attempt to log in with
+ * @param method whether to check the credentials against chartered's own users or the LDAP directory
  */
-export async function login(username: string, password: string) {
+export async function login(
+    username: string,
+    password: string,
+    method: 'password' | 'ldap' = 'password',
+): Promise<TwoFactorChallenge | null> {
     // call the backend and attempt the authentication
-    const result = await fetch(`${BASE_URL}/web/v1/public/auth/login/password`, {
+    const result = await fetch(`${BASE_URL}/web/v1/public/auth/login/${method}`, {
         method: 'POST',
         headers: { 'Content-Type': 'application/json' },
         body: JSON.stringify({ username, password }),
     });
-    const json: LoginResult = await result.json();
+    const json: PasswordLoginResult = await result.json();
 
     // server returned an error, forward it on - there's nothing else we
     // can do here
@@ -128,6 +154,11 @@ export async function login(username: string, password: string) {
         throw new Error(json.error);
     }
 
+    // the user needs to give us a code before they're logged in
+    if ('two_factor_required' in json) {
+        return { challenge: json.challenge, passkey: json.passkey };
+    }
+
     // we got a successful response back from the server, get in there son
     auth.set({
         auth_key: json.key,
@@ -135,6 +166,99 @@ export async function login(username: string, password: string) {
         picture_url: json.picture_url,
         uuid: json.user_uuid,
     });
+
+    return null;
+}
+
+/**
+ * Complete a password login for a user with two-factor authentication enabled, throwing an
+ * error if the code is invalid or another error occurred.
+ *
+ * @param challenge challenge returned by `login`
+ * @param code code from the user's authenticator app, or one of their recovery codes
+ */
+export async function loginTwoFactor(challenge: string, code: string) {
+    await completeLogin('/web/v1/public/auth/login/two-factor', { challenge, code });
+}
+
+/**
+ * Complete a password login for a user with two-factor authentication enabled using one of
+ * their passkeys in place of a code.
+ *
+ * @param challenge challenge returned by `login`, which must have passkey options
+ */
+export async function loginTwoFactorPasskey(challenge: TwoFactorChallenge) {
+    if (!challenge.passkey) {
+        throw new Error('No passkeys are registered for this account');
+    }
+
+    const passkey = await getPasskeyAssertion(challenge.passkey);
+
+    await completeLogin('/web/v1/public/auth/login/two-factor', {
+        challenge: challenge.challenge,
+        passkey,
+    });
+}
+
+/**
+ * Successful response type of /web/v1/auth/login/passkey/begin, contains the options to pass
+ * to the browser and the challenge to send back along with its response.
+ */
+interface PasskeyLoginBeginResponse {
+    challenge: string;
+    options: PasskeyRequestOptions;
+}
+type PasskeyLoginBeginResult = PasskeyLoginBeginResponse & Error;
+
+/**
+ * Attempt to log the user in using one of their passkeys, without a password.
+ *
+ * @param username username of the account to log in to
+ */
+export async function loginPasskey(username: string) {
+    const result = await fetch(`${BASE_URL}/web/v1/public/auth/login/passkey/begin`, {
+        method: 'POST',
+        headers: { 'Content-Type': 'application/json' },
+        body: JSON.stringify({ username }),
+    });
+    const json: PasskeyLoginBeginResult = await result.json();
+
+    if (json.error) {
+        throw new Error(json.error);
+    }
+
+    const credential = await getPasskeyAssertion(json.options);
+
+    await completeLogin('/web/v1/public/auth/login/passkey', {
+        challenge: json.challenge,
+        credential,
+    });
+}
+
+/**
+ * Sends the final step of a multistep login to the backend and stores the resulting session.
+ *
+ * @param url url (without base) to send the request to
+ * @param body request body
+ */
+async function completeLogin(url: string, body: object) {
+    const result = await fetch(`${BASE_URL}${url}`, {
+        method: 'POST',
+        headers: { 'Content-Type': 'application/json' },
+        body: JSON.stringify(body),
+    });
+    const json: LoginResult = await result.json();
+
+    if (json.error) {
+        throw new Error(json.error);
+    }
+
+    auth.set({
+        auth_key: json.key,
+        expires: Date.parse(json.expires),
+        picture_url: json.picture_url,
+        uuid: json.user_uuid,
+    });
 }
 
 /**
@@ -162,6 +286,28 @@ export async function handleOAuthCallback(params: string) {
     });
 }
 
+/**
+ * Attempt to log the user in using the ticket handed to us by the backend once it's accepted
+ * the response from a SAML IdP, throwing an error if an error occurred.
+ *
+ * @param params URL search parameters
+ */
+export async function handleSamlCallback(params: string) {
+    const result = await fetch(`${BASE_URL}/web/v1/public/auth/login/saml/complete${params}`);
+    const json: LoginResult = await result.json();
+
+    if (json.error) {
+        throw new Error(json.error);
+    }
+
+    auth.set({
+        auth_key: json.key,
+        expires: Date.parse(json.expires),
+        picture_url: json.picture_url,
+        uuid: json.user_uuid,
+    });
+}
+
 /**
  * Successful response type of /web/v1/auth/login/oauth/[provider]/begin, contains the URL
  * the user needs to visit to complete the OAuth flow.
@@ -218,6 +364,27 @@ export async function loginOAuth(provider: string) {
     await goto(json.redirect_url);
 }
 
+/**
+ * Grab a URL to send the user to the SAML IdP with and redirect the user to it, the IdP will
+ * send the user back to the backend once they've authenticated.
+ *
+ * @param provider SAML provider as configured on the backend to grab an auth link for
+ */
+export async function loginSaml(provider: string) {
+    // the backend binds the login to this browser with a cookie, which has to be kept until
+    // the IdP sends the user back
+    const result = await fetch(`${BASE_URL}/web/v1/public/auth/login/saml/${provider}/begin`, {
+        credentials: 'include',
+    });
+    const json: LoginOAuthResult = await result.json();
+
+    if (json.error) {
+        throw new Error(json.error);
+    }
+
+    await goto(json.redirect_url);
+}
+
 /**
  * Send a request to the backend to clear this session, ignoring the response and clearing
  * our local store regardless of the result.
@@ -245,7 +412,9 @@ export async function logout() {
  */
 interface OAuthProviders {
     password: boolean;
+    ldap: boolean;
     providers: string[];
+    saml: string[];
 }
 
 /**
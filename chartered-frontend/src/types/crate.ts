@@ -48,6 +48,7 @@ export interface SearchCrate {
 export interface CrateMembers {
     possible_permissions: string[];
     implied_permissions: [string[], string[]][];
+    roles: [string, string[]][];
     members: CrateMember[];
 }
 
@@ -56,4 +57,14 @@ export interface CrateMember {
     display_name: string;
     picture_url?: string;
     permissions: string[];
+    restricted_permissions?: string[];
+    expires_at?: string;
+    effective_permissions?: string[];
+    grants?: CrateMemberGrant[];
+}
+
+export interface CrateMemberGrant {
+    source: 'crate' | 'organisation' | 'group';
+    group?: string;
+    permissions: string[];
 }
@@ -0,0 +1,70 @@
+/**
+ * Helpers for calling into the browser's WebAuthn API with the options given to us by
+ * chartered-web, which encodes every binary field as unpadded base64url.
+ */
+
+function fromBase64Url(value: string): ArrayBuffer {
+    const base64 = value.replace(/-/g, '+').replace(/_/g, '/');
+    const padded = base64 + '='.repeat((4 - (base64.length % 4)) % 4);
+
+    return Uint8Array.from(atob(padded), (c) => c.charCodeAt(0)).buffer;
+}
+
+function toBase64Url(value: ArrayBuffer | null): string | null {
+    if (!value) {
+        return null;
+    }
+
+    return btoa(String.fromCharCode(...new Uint8Array(value)))
+        .replace(/\+/g, '-')
+        .replace(/\//g, '_')
+        .replace(/=+$/, '');
+}
+
+/**
+ * Options for an authentication ceremony, as returned by chartered-web.
+ */
+export interface PasskeyRequestOptions {
+    publicKey: {
+        challenge: string;
+        allowCredentials?: { id: string; type: 'public-key' }[];
+    } & Omit<PublicKeyCredentialRequestOptions, 'challenge' | 'allowCredentials'>;
+}
+
+/**
+ * Asks the browser to sign the challenge using one of the user's passkeys, returning the
+ * response encoded for sending back to chartered-web.
+ *
+ * @param options options returned by chartered-web when starting the ceremony
+ */
+export async function getPasskeyAssertion(options: PasskeyRequestOptions): Promise<object> {
+    const credential = (await navigator.credentials.get({
+        publicKey: {
+            ...options.publicKey,
+            challenge: fromBase64Url(options.publicKey.challenge),
+            allowCredentials: options.publicKey.allowCredentials?.map((v) => ({
+                ...v,
+                id: fromBase64Url(v.id),
+            })),
+        },
+    })) as PublicKeyCredential | null;
+
+    if (!credential) {
+        throw new Error('No passkey was selected');
+    }
+
+    const response = credential.response as AuthenticatorAssertionResponse;
+
+    return {
+        id: credential.id,
+        rawId: toBase64Url(credential.rawId),
+        type: credential.type,
+        extensions: credential.getClientExtensionResults(),
+        response: {
+            authenticatorData: toBase64Url(response.authenticatorData),
+            clientDataJSON: toBase64Url(response.clientDataJSON),
+            signature: toBase64Url(response.signature),
+            userHandle: toBase64Url(response.userHandle),
+        },
+    };
+}
@@ -5,7 +5,7 @@
 use std::{path::PathBuf, time::Duration};
 
 use async_trait::async_trait;
-use aws_sdk_s3::error::{GetObjectError, PutObjectError};
+use aws_sdk_s3::error::{DeleteObjectError, GetObjectError, PutObjectError};
 use aws_sdk_s3::{
     model::ObjectCannedAcl,
     presigning::config::PresigningConfig,
@@ -31,6 +31,8 @@ pub enum Error {
     S3Put(#[from] SdkError<PutObjectError>),
     #[error("failed to get object from s3: {0}")]
     S3Get(#[from] SdkError<GetObjectError>),
+    #[error("failed to delete object from s3: {0}")]
+    S3Delete(#[from] SdkError<DeleteObjectError>),
     #[error("i/o failure: {0}")]
     Io(#[from] std::io::Error),
     #[error("failed to parse uuid: {0}")]
@@ -99,6 +101,13 @@ impl FileSystem {
             Self::Local(v) => v.write(data).await,
         }
     }
+
+    pub async fn delete(&self, file_ref: FileReference) -> Result<(), Error> {
+        match self {
+            Self::S3(v) => v.delete(file_ref).await,
+            Self::Local(v) => v.delete(file_ref).await,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -167,6 +176,9 @@ pub trait FileSystemIo {
     async fn read(&self, file_ref: FileReference) -> Result<FilePointer, Error>;
     async fn write(&self, data: Bytes) -> Result<FileReference, Error>;
 
+    /// Removes the object, succeeding if the object has already been removed.
+    async fn delete(&self, file_ref: FileReference) -> Result<(), Error>;
+
     #[must_use]
     fn create_ref() -> FileReference {
         FileReference {
@@ -204,6 +216,15 @@ impl FileSystemIo for Local {
 
         Ok(file_ref)
     }
+
+    async fn delete(&self, file_ref: FileReference) -> Result<(), Error> {
+        let path = self.path.join(file_ref.reference.to_string());
+
+        match tokio::fs::remove_file(path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -246,6 +267,18 @@ impl FileSystemIo for S3 {
 
         Ok(file_ref)
     }
+
+    async fn delete(&self, file_ref: FileReference) -> Result<(), Error> {
+        // s3 doesn't complain about deleting objects that don't exist
+        self.client
+            .delete_object()
+            .key(format!("{}/{}", self.path, file_ref.reference))
+            .bucket(&self.bucket)
+            .send()
+            .await?;
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -278,4 +311,17 @@ mod tests {
             FilePointer::Content(Vec::from(b"abcdef".as_ref()))
         );
     }
+
+    #[tokio::test]
+    #[allow(clippy::pedantic)]
+    async fn local_delete() {
+        let fs = super::Local {
+            path: "/tmp".into(),
+        };
+        let file_ref = fs.write(Bytes::from_static(b"abcdef")).await.unwrap();
+        let path = fs.path.join(file_ref.reference.to_string());
+
+        fs.delete(file_ref).await.unwrap();
+        assert!(!path.exists());
+    }
 }
@@ -8,6 +8,14 @@ pub struct Config {
     pub bind_address: SocketAddr,
     pub database_uri: String,
     pub web_base_uri: Url,
+    /// Whether cargo should send the API key in the `Authorization` header rather than us
+    /// embedding it in the URLs written to `config.json`.
+    #[serde(default)]
+    pub auth_required: bool,
+    /// Whether the index of public organisations can be cloned without a registered SSH key
+    /// by connecting as the `anonymous` user.
+    #[serde(default)]
+    pub allow_anonymous: bool,
     #[serde(default)]
     pub committer: GitCommitter,
 }
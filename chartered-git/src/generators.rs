@@ -5,15 +5,42 @@ use serde::Serialize;
 pub struct CargoConfig {
     pub dl: String,
     pub api: String,
+    #[serde(rename = "auth-required", skip_serializing_if = "std::ops::Not::not")]
+    pub auth_required: bool,
 }
 
 impl CargoConfig {
+    /// Builds a config with the API key embedded in the URLs cargo will call.
     pub fn new(base: &url::Url, api_key: &str, organisation: &str) -> Self {
         let base = format!("{}a/{}/o/{}", base, api_key, organisation);
 
         Self {
             dl: format!("{}/api/v1/crates", base),
             api: base,
+            auth_required: false,
+        }
+    }
+
+    /// Builds a config for anonymous users, who have no key to give cargo at all.
+    pub fn anonymous(base: &url::Url, organisation: &str) -> Self {
+        let base = format!("{}o/{}", base, organisation);
+
+        Self {
+            dl: format!("{}/api/v1/crates", base),
+            api: base,
+            auth_required: false,
+        }
+    }
+
+    /// Builds a config that has cargo send its token in the `Authorization` header instead,
+    /// keeping it out of any logs along the way.
+    pub fn auth_required(base: &url::Url, organisation: &str) -> Self {
+        let base = format!("{}o/{}", base, organisation);
+
+        Self {
+            dl: format!("{}/api/v1/crates", base),
+            api: base,
+            auth_required: true,
         }
     }
 }
@@ -38,4 +65,32 @@ mod test {
             "https://127.0.0.1:1234/a/my-api-key/o/my-organisation"
         );
     }
+
+    #[test]
+    fn test_cargo_config_auth_required() {
+        let conf = CargoConfig::auth_required(
+            &url::Url::parse("https://127.0.0.1:1234").unwrap(),
+            "my-organisation",
+        );
+        assert_eq!(
+            conf.dl,
+            "https://127.0.0.1:1234/o/my-organisation/api/v1/crates"
+        );
+        assert_eq!(conf.api, "https://127.0.0.1:1234/o/my-organisation");
+        assert!(conf.auth_required);
+    }
+
+    #[test]
+    fn test_cargo_config_anonymous() {
+        let conf = CargoConfig::anonymous(
+            &url::Url::parse("https://127.0.0.1:1234").unwrap(),
+            "my-organisation",
+        );
+        assert_eq!(
+            conf.dl,
+            "https://127.0.0.1:1234/o/my-organisation/api/v1/crates"
+        );
+        assert_eq!(conf.api, "https://127.0.0.1:1234/o/my-organisation");
+        assert!(!conf.auth_required);
+    }
 }
@@ -65,8 +65,15 @@ async fn main() -> anyhow::Result<()> {
     ServerPrivateKey::create_if_not_exists(db.clone()).await?;
     let keys = ServerPrivateKey::fetch_all(db.clone()).await?;
 
+    // anonymous users don't have a key to authenticate with at all
+    let methods = if config.allow_anonymous {
+        thrussh::MethodSet::PUBLICKEY | thrussh::MethodSet::NONE
+    } else {
+        thrussh::MethodSet::PUBLICKEY
+    };
+
     let trussh_config = Arc::new(thrussh::server::Config {
-        methods: thrussh::MethodSet::PUBLICKEY,
+        methods,
         keys: keys
             .into_iter()
             .map(ServerPrivateKey::into_private_key)
@@ -134,6 +141,7 @@ struct Handler {
 struct Authed {
     user: chartered_db::users::User,
     auth_key: String,
+    scope: Arc<chartered_db::scopes::TokenScope>,
 }
 
 impl Handler {
@@ -184,8 +192,23 @@ impl server::Handler for Handler {
         Box::pin(futures::future::ready(Ok((self, s))))
     }
 
-    fn auth_none(self, _user: &str) -> Self::FutureAuth {
-        self.finished_auth(server::Auth::UnsupportedMethod)
+    /// Lets users connecting as `anonymous` in without a key if the server allows it, they
+    /// can only see crates in public organisations.
+    fn auth_none(mut self, user: &str) -> Self::FutureAuth {
+        if !self.config.allow_anonymous || user != "anonymous" {
+            return self.finished_auth(server::Auth::UnsupportedMethod);
+        }
+
+        self.span
+            .in_scope(|| info!("Accepted anonymous connection"));
+
+        self.authed = Some(Authed {
+            user: chartered_db::users::User::anonymous(),
+            scope: Arc::new(chartered_db::scopes::TokenScope::anonymous()),
+            auth_key: String::new(),
+        });
+
+        self.finished_auth(server::Auth::Accept)
     }
 
     fn auth_password(self, _user: &str, _password: &str) -> Self::FutureAuth {
@@ -201,26 +224,27 @@ impl server::Handler for Handler {
 
         Box::pin(
             async move {
-                let (ssh_key, user) =
-                    match chartered_db::users::User::find_by_ssh_key(self.db.clone(), public_key)
-                        .await?
-                    {
-                        Some(user) => user,
-                        None => return self.finished_auth(server::Auth::Reject).await,
-                    };
+                let (ssh_key, user) = match find_user_by_key(self.db.clone(), public_key).await? {
+                    Some(user) => user,
+                    None => return self.finished_auth(server::Auth::Reject).await,
+                };
+
                 let ssh_key = Arc::new(ssh_key);
 
                 if let Err(e) = ssh_key.clone().update_last_used(self.db.clone()).await {
                     warn!("Failed to update last used key: {:?}", e);
                 }
 
-                let auth_key = ssh_key
+                let session = ssh_key
                     .clone()
                     .get_or_insert_session(self.db.clone(), self.ip.map(|v| v.to_string()))
-                    .await?
-                    .session_key;
+                    .await?;
 
-                self.authed = Some(Authed { user, auth_key });
+                self.authed = Some(Authed {
+                    user,
+                    scope: Arc::new(session.scope()),
+                    auth_key: session.session_key,
+                });
 
                 self.finished_auth(server::Auth::Accept).await
             }
@@ -265,8 +289,13 @@ impl server::Handler for Handler {
                     let mut packfile = GitRepository::default();
 
                     // write the config.json to the root of the repository
-                    let config =
-                        CargoConfig::new(&self.config.web_base_uri, &authed.auth_key, org_name);
+                    let config = if authed.user.is_anonymous() {
+                        CargoConfig::anonymous(&self.config.web_base_uri, org_name)
+                    } else if self.config.auth_required {
+                        CargoConfig::auth_required(&self.config.web_base_uri, org_name)
+                    } else {
+                        CargoConfig::new(&self.config.web_base_uri, &authed.auth_key, org_name)
+                    };
                     let config = serde_json::to_vec(&config)?;
                     packfile.insert(&[], "config.json", config.into())?;
 
@@ -274,8 +303,13 @@ impl server::Handler for Handler {
                     // to the in-memory repository.
                     // todo: the whole tree needs caching and then we can filter in code rather than at
                     //  the database
-                    let tree =
-                        Tree::build(self.db.clone(), authed.user.id, org_name.to_string()).await;
+                    let tree = Tree::build(
+                        self.db.clone(),
+                        authed.user.id,
+                        authed.scope.clone(),
+                        org_name.to_string(),
+                    )
+                    .await;
                     tree.write_to_packfile(&mut packfile)?;
 
                     // finalises the git repository, creating a commit and fetching the finalised
@@ -431,3 +465,74 @@ impl server::Handler for Handler {
         Box::pin(futures::future::ready(Ok((self, session))))
     }
 }
+
+/// Finds the user the public key belongs to, ignoring keys belonging to disabled users so they
+/// can't authenticate.
+async fn find_user_by_key(
+    db: chartered_db::ConnectionPool,
+    public_key: Vec<u8>,
+) -> Result<Option<(chartered_db::users::UserSshKey, chartered_db::users::User)>, chartered_db::Error>
+{
+    let found = chartered_db::users::User::find_by_ssh_key(db, public_key).await?;
+
+    Ok(found.filter(|(_, user)| {
+        if user.is_disabled() {
+            info!("Rejected key belonging to disabled user {}", user.username);
+        }
+
+        !user.is_disabled()
+    }))
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod test {
+    use chartered_db::{audit::Actor, users::User};
+    use std::sync::Arc;
+
+    const PUBLIC_KEY: &str = "ssh-ed25519 \
+        AAAAC3NzaC1lZDI1NTE5AAAAIHyZs4K+Ie4hz21YTAUJIS/AOjWhAnlerrVIqKPi2fph test";
+
+    #[tokio::test]
+    async fn keys_of_disabled_users_are_rejected() {
+        let path = std::env::temp_dir().join(format!(
+            "chartered-git-test-{}.db",
+            chartered_db::uuid::Uuid::new_v4()
+        ));
+        let db = chartered_db::init(&format!("sqlite://{}", path.display())).unwrap();
+
+        User::register(db.clone(), "user".to_string(), "password".to_string())
+            .await
+            .unwrap();
+        let user = Arc::new(
+            User::find_by_username(db.clone(), "user".to_string())
+                .await
+                .unwrap()
+                .unwrap(),
+        );
+
+        user.clone()
+            .insert_ssh_key(db.clone(), &Actor::default(), PUBLIC_KEY)
+            .await
+            .unwrap();
+        let key = user
+            .clone()
+            .list_ssh_keys(db.clone())
+            .await
+            .unwrap()
+            .remove(0);
+
+        assert!(super::find_user_by_key(db.clone(), key.ssh_key.clone())
+            .await
+            .unwrap()
+            .is_some());
+
+        user.set_disabled(db.clone(), &Actor::default(), true)
+            .await
+            .unwrap();
+
+        assert!(super::find_user_by_key(db, key.ssh_key)
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
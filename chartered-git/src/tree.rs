@@ -6,7 +6,7 @@ use std::{collections::BTreeMap, sync::Arc};
 
 use arrayvec::ArrayVec;
 use bytes::Bytes;
-use chartered_db::crates::Crate;
+use chartered_db::{crates::Crate, scopes::TokenScope};
 use packfile::high_level::GitRepository;
 use ustr::ustr;
 
@@ -25,10 +25,15 @@ pub struct Tree {
 impl Tree {
     /// Grabs all the crates that the user has access to and writes out the manifests to
     /// `self.crates`.
-    pub async fn build(db: chartered_db::ConnectionPool, user_id: i32, org_name: String) -> Self {
+    pub async fn build(
+        db: chartered_db::ConnectionPool,
+        user_id: i32,
+        scope: Arc<TokenScope>,
+        org_name: String,
+    ) -> Self {
         let mut crates = BTreeMap::new();
 
-        for (crate_def, versions) in Crate::list_with_versions(db, user_id, org_name)
+        for (crate_def, versions) in Crate::list_with_versions(db, user_id, scope, org_name)
             .await
             .unwrap()
         {
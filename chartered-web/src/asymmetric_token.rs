@@ -0,0 +1,367 @@
+//! Verifies cargo's [asymmetric tokens][rfc], short-lived PASETOs signed by a private key that
+//! never leaves the user's machine. We only ever see the public half of the key, which users
+//! register with us through the web API.
+//!
+//! Tokens are `v3.public` PASETOs with a footer identifying the registry and key used, and a
+//! payload containing the time the token was issued and, for mutations, the crate and version
+//! being acted on. Tokens for mutations can only be used once.
+//!
+//! [rfc]: https://rust-lang.github.io/rfcs/3231-cargo-asymmetric-tokens.html
+
+use chartered_db::{
+    cargo_keys::{UsedCargoToken, UserCargoKey},
+    users::User,
+    ConnectionPool,
+};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use pasetors::{
+    keys::AsymmetricPublicKey,
+    paserk::{FormatAsPaserk, Id},
+    token::UntrustedToken,
+    version3::{PublicToken, V3},
+    Public,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use url::Url;
+
+use crate::config::Config;
+
+/// Prefix of every token cargo signs using an asymmetric key.
+const TOKEN_PREFIX: &str = "v3.public.";
+
+/// How old a token can be before we'll no longer accept it.
+const MAX_TOKEN_AGE_MINUTES: i64 = 5;
+
+/// How far in the future a token's issue time can be, to allow for clock skew.
+const MAX_CLOCK_SKEW_MINUTES: i64 = 1;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Database(#[from] chartered_db::Error),
+    #[error("Malformed asymmetric token")]
+    Malformed,
+    #[error("Invalid public key, expected a PASERK of the form k3.public.…")]
+    InvalidPublicKey,
+    #[error("The key used to sign this token isn't registered")]
+    UnknownKey,
+    #[error("Asymmetric token signature is invalid")]
+    InvalidSignature,
+    #[error("Asymmetric token was signed for a different registry")]
+    WrongRegistry,
+    #[error("Asymmetric token has expired, check your clock is correct")]
+    Expired,
+    #[error("Asymmetric token has already been used")]
+    Replayed,
+    #[error("Asymmetric token wasn't signed for this operation")]
+    WrongMutation,
+}
+
+impl Error {
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        use axum::http::StatusCode;
+
+        match self {
+            Self::Database(e) => e.status_code(),
+            Self::InvalidPublicKey => StatusCode::BAD_REQUEST,
+            Self::Malformed
+            | Self::UnknownKey
+            | Self::InvalidSignature
+            | Self::WrongRegistry
+            | Self::Expired
+            | Self::Replayed
+            | Self::WrongMutation => StatusCode::UNAUTHORIZED,
+        }
+    }
+}
+
+/// Footer of the token, identifying which registry and key it was signed for.
+#[derive(Deserialize, Debug)]
+pub struct TokenFooter {
+    pub url: String,
+    pub kip: String,
+}
+
+/// Claims made by the token about the request it's authorising.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TokenClaims {
+    pub iat: String,
+    pub sub: Option<String>,
+    pub mutation: Option<String>,
+    pub name: Option<String>,
+    pub vers: Option<String>,
+    pub cksum: Option<String>,
+    pub v: Option<u8>,
+}
+
+impl TokenClaims {
+    /// Ensures the token was signed specifically for this mutation, so it can't be used to
+    /// perform a different one.
+    pub fn check_mutation(
+        &self,
+        mutation: &str,
+        name: &str,
+        vers: &str,
+        cksum: Option<&str>,
+    ) -> Result<(), Error> {
+        let matches = self.mutation.as_deref() == Some(mutation)
+            && self.name.as_deref() == Some(name)
+            && self.vers.as_deref() == Some(vers)
+            && (cksum.is_none() || self.cksum.as_deref() == cksum);
+
+        if matches {
+            Ok(())
+        } else {
+            Err(Error::WrongMutation)
+        }
+    }
+}
+
+#[must_use]
+pub fn is_asymmetric_token(token: &str) -> bool {
+    token.starts_with(TOKEN_PREFIX)
+}
+
+/// Parses a PASERK public key, returning its PASERK id which cargo uses to identify the key
+/// in each token.
+pub fn key_id(public_key: &str) -> Result<String, Error> {
+    let public_key = AsymmetricPublicKey::<V3>::try_from(public_key.trim())
+        .map_err(|_| Error::InvalidPublicKey)?;
+
+    let mut key_id = String::new();
+    Id::from(&public_key)
+        .fmt(&mut key_id)
+        .map_err(|_| Error::InvalidPublicKey)?;
+
+    Ok(key_id)
+}
+
+/// Authenticates a request using an asymmetric token, returning the user that owns the key the
+/// token was signed with along with the claims the token makes.
+pub async fn authenticate(
+    db: ConnectionPool,
+    config: &Config,
+    token: &str,
+    organisation: &str,
+) -> Result<(User, TokenClaims), Error> {
+    let untrusted = UntrustedToken::<Public, V3>::try_from(token).map_err(|_| Error::Malformed)?;
+    let footer: TokenFooter =
+        serde_json::from_slice(untrusted.untrusted_footer()).map_err(|_| Error::Malformed)?;
+
+    let (key, user) = UserCargoKey::find_by_key_id(db.clone(), footer.kip.clone())
+        .await?
+        .ok_or(Error::UnknownKey)?;
+
+    let index_urls = index_urls(
+        config.web_base_uri.as_ref(),
+        config.ssh_base_uri.as_ref(),
+        organisation,
+    );
+
+    let claims = verify(
+        &key.public_key,
+        &untrusted,
+        &footer,
+        &index_urls,
+        Utc::now(),
+    )?;
+
+    // mutations can only be performed once per token, otherwise anyone who observed the token
+    // could repeat the request for as long as the token is valid
+    if claims.mutation.is_some() {
+        let token_hash = hex::encode(Sha256::digest(token.as_bytes()));
+        let expires_at = Utc::now().naive_utc()
+            + Duration::minutes(MAX_TOKEN_AGE_MINUTES + MAX_CLOCK_SKEW_MINUTES);
+
+        if !UsedCargoToken::claim(db, token_hash, expires_at).await? {
+            return Err(Error::Replayed);
+        }
+    }
+
+    Ok((user, claims))
+}
+
+/// Verifies the token's signature against the registered key and checks it was issued
+/// recently for one of the organisation's index URLs.
+fn verify(
+    public_key: &str,
+    untrusted: &UntrustedToken<Public, V3>,
+    footer: &TokenFooter,
+    index_urls: &[String],
+    now: DateTime<Utc>,
+) -> Result<TokenClaims, Error> {
+    let public_key =
+        AsymmetricPublicKey::<V3>::try_from(public_key).map_err(|_| Error::InvalidPublicKey)?;
+
+    let trusted = PublicToken::verify(&public_key, untrusted, None, None)
+        .map_err(|_| Error::InvalidSignature)?;
+    let claims: TokenClaims =
+        serde_json::from_str(trusted.payload()).map_err(|_| Error::Malformed)?;
+
+    if claims.v.map_or(false, |v| v != 1) {
+        return Err(Error::Malformed);
+    }
+
+    if !url_matches(&footer.url, index_urls) {
+        return Err(Error::WrongRegistry);
+    }
+
+    let issued_at: NaiveDateTime = DateTime::parse_from_rfc3339(&claims.iat)
+        .map_err(|_| Error::Malformed)?
+        .naive_utc();
+    let now = now.naive_utc();
+
+    if issued_at < now - Duration::minutes(MAX_TOKEN_AGE_MINUTES)
+        || issued_at > now + Duration::minutes(MAX_CLOCK_SKEW_MINUTES)
+    {
+        return Err(Error::Expired);
+    }
+
+    Ok(claims)
+}
+
+/// Every URL the organisation's index can be reached at, so a token signed for one organisation,
+/// or for another registry entirely, can't be used against this one. This is either the git
+/// index, ie. `ssh://ssh.chart.rs/my-organisation`, or the sparse index, ie.
+/// `sparse+https://api.chart.rs/o/my-organisation/index/`.
+fn index_urls(
+    web_base_uri: Option<&Url>,
+    ssh_base_uri: Option<&Url>,
+    organisation: &str,
+) -> Vec<String> {
+    let sparse = web_base_uri
+        .and_then(|v| v.join(&format!("o/{}/index/", organisation)).ok())
+        .map(|v| format!("sparse+{}", v));
+    let git = ssh_base_uri
+        .and_then(|v| v.join(organisation).ok())
+        .map(|v| v.to_string());
+
+    sparse.into_iter().chain(git).collect()
+}
+
+/// Checks the index URL the token was signed for is one of the organisation's, ignoring any
+/// trailing slash as cargo will happily use the URL either way.
+fn url_matches(url: &str, index_urls: &[String]) -> bool {
+    let url = url.trim_end_matches('/');
+
+    index_urls
+        .iter()
+        .any(|expected| expected.trim_end_matches('/') == url)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{TokenFooter, V3};
+    use chrono::{Duration, Utc};
+    use pasetors::{
+        keys::{AsymmetricKeyPair, Generate},
+        paserk::FormatAsPaserk,
+        token::UntrustedToken,
+        version3::PublicToken,
+        Public,
+    };
+    use url::Url;
+
+    #[test]
+    fn index_urls() {
+        let web_base_uri = Url::parse("https://api.chart.rs/").unwrap();
+        let ssh_base_uri = Url::parse("ssh://ssh.chart.rs/").unwrap();
+
+        let urls = super::index_urls(Some(&web_base_uri), Some(&ssh_base_uri), "my-org");
+        assert_eq!(
+            urls,
+            vec![
+                "sparse+https://api.chart.rs/o/my-org/index/".to_string(),
+                "ssh://ssh.chart.rs/my-org".to_string(),
+            ]
+        );
+
+        assert!(super::url_matches("ssh://ssh.chart.rs/my-org", &urls));
+        assert!(super::url_matches("ssh://ssh.chart.rs/my-org/", &urls));
+        assert!(super::url_matches(
+            "sparse+https://api.chart.rs/o/my-org/index/",
+            &urls
+        ));
+        assert!(!super::url_matches("ssh://ssh.chart.rs/other-org", &urls));
+        // the organisation name alone isn't enough, the registry has to match too
+        assert!(!super::url_matches("ssh://evil.example.com/my-org", &urls));
+        assert!(!super::url_matches(
+            "sparse+https://evil.example.com/o/my-org/index/",
+            &urls
+        ));
+        assert!(!super::url_matches("not a url", &urls));
+
+        assert!(super::index_urls(None, None, "my-org").is_empty());
+    }
+
+    #[test]
+    fn verify() {
+        let kp = AsymmetricKeyPair::<V3>::generate().unwrap();
+
+        let mut public_key = String::new();
+        kp.public.fmt(&mut public_key).unwrap();
+        let kip = super::key_id(&public_key).unwrap();
+
+        let sign = |iat: chrono::DateTime<Utc>, url: &str| {
+            let footer = serde_json::json!({ "url": url, "kip": kip }).to_string();
+            let message = serde_json::json!({
+                "iat": iat.to_rfc3339(),
+                "mutation": "publish",
+                "name": "my-crate",
+                "vers": "1.0.0",
+                "cksum": "abc",
+            })
+            .to_string();
+
+            let token = PublicToken::sign(
+                &kp.secret,
+                message.as_bytes(),
+                Some(footer.as_bytes()),
+                None,
+            )
+            .unwrap();
+            let untrusted = UntrustedToken::<Public, V3>::try_from(token.as_str()).unwrap();
+            let footer: TokenFooter = serde_json::from_slice(untrusted.untrusted_footer()).unwrap();
+
+            (untrusted, footer)
+        };
+
+        let now = Utc::now();
+        let index_urls = vec!["ssh://ssh.chart.rs/my-org".to_string()];
+        let other_index_urls = vec!["ssh://ssh.chart.rs/other-org".to_string()];
+
+        let (token, footer) = sign(now, "ssh://ssh.chart.rs/my-org");
+        let claims = super::verify(&public_key, &token, &footer, &index_urls, now).unwrap();
+        assert!(claims
+            .check_mutation("publish", "my-crate", "1.0.0", Some("abc"))
+            .is_ok());
+        assert!(claims
+            .check_mutation("yank", "my-crate", "1.0.0", None)
+            .is_err());
+        assert!(claims
+            .check_mutation("publish", "other-crate", "1.0.0", Some("abc"))
+            .is_err());
+
+        assert!(matches!(
+            super::verify(&public_key, &token, &footer, &other_index_urls, now),
+            Err(super::Error::WrongRegistry)
+        ));
+
+        let (token, footer) = sign(now - Duration::hours(1), "ssh://ssh.chart.rs/my-org");
+        assert!(matches!(
+            super::verify(&public_key, &token, &footer, &index_urls, now),
+            Err(super::Error::Expired)
+        ));
+
+        let other = AsymmetricKeyPair::<V3>::generate().unwrap();
+        let mut other_public_key = String::new();
+        other.public.fmt(&mut other_public_key).unwrap();
+        let (token, footer) = sign(now, "ssh://ssh.chart.rs/my-org");
+        assert!(matches!(
+            super::verify(&other_public_key, &token, &footer, &index_urls, now),
+            Err(super::Error::InvalidSignature)
+        ));
+    }
+}
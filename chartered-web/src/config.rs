@@ -1,7 +1,10 @@
+use crate::{group_mappings::GroupMapping, saml};
+
 use chacha20poly1305::Key as ChaCha20Poly1305Key;
 use chartered_fs::FileSystem;
 use oauth2::{AuthUrl, ClientId, ClientSecret, RedirectUrl, TokenUrl};
 use openid::DiscoveredClient;
+use samael::service_provider::ServiceProvider;
 use serde::{de::Error as SerdeDeError, Deserialize};
 use std::collections::HashMap;
 use std::net::SocketAddr;
@@ -16,6 +19,16 @@ pub enum Error {
     Fs(#[from] Box<chartered_fs::Error>),
     #[error("Failed to build URL: {0}")]
     Parse(#[from] url::ParseError),
+    #[error("Failed to fetch SAML IdP metadata: {0}")]
+    FetchSamlMetadata(#[from] reqwest::Error),
+    #[error("Failed to read SAML IdP metadata: {0}")]
+    ReadSamlMetadata(#[from] std::io::Error),
+    #[error("Failed to configure SAML provider: {0}")]
+    Saml(#[from] saml::Error),
+    #[error("web_base_uri must be configured to use SAML")]
+    MissingWebBaseUri,
+    #[error("SAML provider `{0}` has the same name as another provider")]
+    DuplicateProvider(String),
 }
 
 #[derive(Deserialize, Debug)]
@@ -25,13 +38,53 @@ pub struct Config {
     pub database_uri: String,
     pub storage_uri: String,
     pub frontend_base_uri: Url,
+    /// The URL this server is reachable at, used to build the `config.json` served to cargo by
+    /// the sparse index of mirror organisations.
+    pub web_base_uri: Option<Url>,
+    /// The URL the git index served by `chartered-git` is reachable at, ie.
+    /// `ssh://ssh.chart.rs/`, used to check which registry asymmetric tokens were signed for.
+    pub ssh_base_uri: Option<Url>,
     pub trusted_ip_header: Option<String>,
+    /// Hosts that webhooks can be delivered to, and mirrors fetched from, even though they
+    /// resolve to a loopback, private or link-local address, requests to any other such address
+    /// are refused.
+    #[serde(default)]
+    pub allowed_internal_hosts: Vec<String>,
+    /// Hosts that any user can create a mirror organisation of, mirrors of any other host can
+    /// only be created by instance administrators.
+    #[serde(default)]
+    pub allowed_upstream_hosts: Vec<String>,
+    /// Whether users that haven't authenticated can download crates from, and view crates in,
+    /// public organisations.
+    #[serde(default)]
+    pub allow_anonymous: bool,
+    /// How long after being published a crate version can still be deleted by users with the
+    /// `DELETE_CRATE` permission, in hours.
+    #[serde(default = "default_crate_deletion_window_hours")]
+    pub crate_deletion_window_hours: i64,
+    /// Usernames of users to make instance administrators at startup, further administrators
+    /// can then be managed through the admin API.
+    #[serde(default)]
+    pub admins: Vec<String>,
     pub auth: AuthConfig,
+    /// OIDC issuers whose ID tokens CI jobs can exchange for a short-lived publish token,
+    /// keyed by the name trust policies refer to them by.
+    #[serde(default)]
+    pub trusted_publishing: HashMap<String, TrustedPublishingConfig>,
     #[serde(deserialize_with = "deserialize_encryption_key")]
     pub encryption_key: ChaCha20Poly1305Key,
 }
 
+fn default_crate_deletion_window_hours() -> i64 {
+    72
+}
+
 impl Config {
+    #[must_use]
+    pub fn crate_deletion_window(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.crate_deletion_window_hours)
+    }
+
     pub async fn get_file_system(&self) -> Result<FileSystem, Error> {
         Ok(FileSystem::from_str(&self.storage_uri)
             .await
@@ -84,12 +137,88 @@ impl Config {
 
         Ok(clients)
     }
+
+    pub async fn create_saml_providers(
+        &self,
+        http_client: &reqwest::Client,
+    ) -> Result<SamlProviders, Error> {
+        futures::future::try_join_all(
+            self.auth
+                .saml
+                .iter()
+                .filter(|(_, config)| config.enabled)
+                .map(|(name, config)| async move {
+                    // SAML users are given the same `provider:id` usernames as OIDC users, so
+                    // the providers can't share a name
+                    if name == "github" || self.auth.oauth.contains_key(name) {
+                        return Err(Error::DuplicateProvider(name.to_string()));
+                    }
+
+                    // the IdP needs to be able to post responses straight back to us
+                    let web_base_uri =
+                        self.web_base_uri.as_ref().ok_or(Error::MissingWebBaseUri)?;
+
+                    let idp_metadata = if config.idp_metadata_uri.scheme() == "file" {
+                        let path = config
+                            .idp_metadata_uri
+                            .to_file_path()
+                            .map_err(|()| std::io::Error::from(std::io::ErrorKind::InvalidInput))?;
+
+                        tokio::fs::read_to_string(path).await?
+                    } else {
+                        http_client
+                            .get(config.idp_metadata_uri.clone())
+                            .send()
+                            .await?
+                            .error_for_status()?
+                            .text()
+                            .await?
+                    };
+
+                    let sp = saml::build(
+                        config,
+                        &saml::metadata_url(web_base_uri, name)?,
+                        &saml::acs_url(web_base_uri, name)?,
+                        &idp_metadata,
+                    )?;
+
+                    Ok::<_, Error>((name.to_string(), Box::new(sp)))
+                }),
+        )
+        .await
+        .map(|v| v.into_iter().collect())
+    }
+
+    pub async fn create_trusted_publishers(&self) -> Result<TrustedPublishers, Error> {
+        futures::future::try_join_all(self.trusted_publishing.iter().map(
+            |(name, config)| async move {
+                // ID tokens are issued to us with our audience as the client, there's no
+                // secret or redirect involved as we never request tokens ourselves
+                let client = Box::new(
+                    DiscoveredClient::discover(
+                        config.audience.to_string(),
+                        String::new(),
+                        None::<String>,
+                        config.discovery_uri.clone(),
+                    )
+                    .await?,
+                );
+
+                Ok::<_, Error>((name.to_string(), client))
+            },
+        ))
+        .await
+        .map(|v| v.into_iter().collect())
+    }
 }
 
 #[derive(Deserialize, Default, Debug)]
 pub struct AuthConfig {
     pub password: PasswordAuthConfig,
     pub github: Option<GitHubConfig>,
+    pub ldap: Option<LdapConfig>,
+    #[serde(default)]
+    pub saml: HashMap<String, SamlConfig>,
     #[serde(flatten)]
     pub oauth: HashMap<String, OAuthConfig>,
 }
@@ -108,16 +237,126 @@ pub struct GitHubConfig {
     pub client_secret: ClientSecret,
 }
 
+/// A directory users can log in to with their LDAP username and password. Users are looked up
+/// under `user_base_dn` using `user_filter`, then authenticated by binding as them.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct LdapConfig {
+    pub enabled: bool,
+    /// URL of the server, ie. `ldaps://ldap.example.com`
+    pub url: String,
+    /// Upgrade `ldap://` connections to TLS before sending any credentials.
+    #[serde(default)]
+    pub starttls: bool,
+    /// DN of the account to search for users with, searches are done anonymously if this
+    /// isn't set.
+    pub bind_dn: Option<String>,
+    pub bind_password: Option<String>,
+    pub user_base_dn: String,
+    /// Filter matching a single user, with `{username}` replaced by the escaped username given
+    /// by the user.
+    #[serde(default = "default_ldap_user_filter")]
+    pub user_filter: String,
+    #[serde(default = "default_ldap_username_attribute")]
+    pub username_attribute: String,
+    #[serde(default = "default_ldap_name_attribute")]
+    pub name_attribute: String,
+    #[serde(default = "default_ldap_email_attribute")]
+    pub email_attribute: String,
+    /// Attribute on the user's entry listing the DNs of the groups they belong to.
+    #[serde(default = "default_ldap_group_attribute")]
+    pub group_attribute: String,
+    /// Organisation memberships given to members of each group, reconciled on every login.
+    #[serde(default)]
+    pub groups: Vec<GroupMapping>,
+}
+
+fn default_ldap_user_filter() -> String {
+    "(uid={username})".to_string()
+}
+
+fn default_ldap_username_attribute() -> String {
+    "uid".to_string()
+}
+
+fn default_ldap_name_attribute() -> String {
+    "cn".to_string()
+}
+
+fn default_ldap_email_attribute() -> String {
+    "mail".to_string()
+}
+
+fn default_ldap_group_attribute() -> String {
+    "memberOf".to_string()
+}
+
 #[derive(Deserialize, Debug)]
 pub struct OAuthConfig {
     pub enabled: bool,
     pub discovery_uri: Url,
     pub client_id: String,
     pub client_secret: String,
+    /// Claim in the ID token listing the groups the user belongs to.
+    #[serde(default = "default_oauth_groups_claim")]
+    pub groups_claim: String,
+    /// Organisation memberships given to members of each group, reconciled on every login.
+    #[serde(default)]
+    pub groups: Vec<GroupMapping>,
+}
+
+fn default_oauth_groups_claim() -> String {
+    "groups".to_string()
+}
+
+/// An IdP users can sign in with using SAML 2.0, with chartered acting as the service provider.
+/// Our metadata and assertion consumer service are served under `web_base_uri`.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SamlConfig {
+    pub enabled: bool,
+    /// Where to load the IdP's metadata from at startup, either over HTTP or a `file://` path.
+    pub idp_metadata_uri: Url,
+    /// Entity ID to identify ourselves to the IdP with, defaults to the URL of our metadata.
+    pub entity_id: Option<String>,
+    /// Attributes to take the user's name, email and groups from, matched against either the
+    /// `Name` or `FriendlyName` of the attribute.
+    #[serde(default = "default_saml_name_attribute")]
+    pub name_attribute: String,
+    #[serde(default = "default_saml_email_attribute")]
+    pub email_attribute: String,
+    #[serde(default = "default_saml_groups_attribute")]
+    pub groups_attribute: String,
+    /// Organisation memberships given to members of each group, reconciled on every login.
+    #[serde(default)]
+    pub groups: Vec<GroupMapping>,
+}
+
+fn default_saml_name_attribute() -> String {
+    "displayName".to_string()
+}
+
+fn default_saml_email_attribute() -> String {
+    "mail".to_string()
+}
+
+fn default_saml_groups_attribute() -> String {
+    "groups".to_string()
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct TrustedPublishingConfig {
+    pub discovery_uri: Url,
+    pub audience: String,
 }
 
 pub type OidcClients = HashMap<String, OidcClient>;
 
+pub type TrustedPublishers = HashMap<String, Box<DiscoveredClient>>;
+
+pub type SamlProviders = HashMap<String, Box<ServiceProvider>>;
+
 pub enum OidcClient {
     Discovered(Box<DiscoveredClient>),
     GitHub(Box<oauth2::basic::BasicClient>),
@@ -1,37 +1,68 @@
 //! Called by cargo to download a crate, depending on how we're configured we'll either serve
 //! the crate directly from the disk - or we'll redirect cargo elsewhere to download the
 //! crate. It all really depends on the `FileSystem` in use in `chartered-fs`.
+//!
+//! For organisations mirroring an upstream registry, crates are fetched from the upstream the
+//! first time they're requested and served from our own storage from then on.
 
 use axum::{
     extract,
     response::{IntoResponse, Redirect, Response},
 };
-use chartered_db::{crates::Crate, users::User, ConnectionPool};
+use chartered_db::{
+    crates::Crate, downloads::DownloadTracker, organisations::Organisation,
+    permissions::UserPermission, scopes::TokenScope, users::User, ConnectionPool,
+};
 use chartered_fs::{FilePointer, FileReference, FileSystem};
 use std::{str::FromStr, sync::Arc};
 use thiserror::Error;
 
+use super::CrateVersionPath;
+use crate::{mirror, outbound::Outbound};
+
 pub async fn handle(
-    extract::Path((_session_key, organisation, name, version)): extract::Path<(
-        String,
-        String,
-        String,
-        String,
-    )>,
+    extract::Path(CrateVersionPath {
+        organisation,
+        name,
+        version,
+    }): extract::Path<CrateVersionPath>,
     extract::Extension(db): extract::Extension<ConnectionPool>,
     extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
     extract::Extension(fs): extract::Extension<Arc<FileSystem>>,
+    extract::Extension(download_tracker): extract::Extension<Arc<DownloadTracker>>,
+    extract::Extension(outbound): extract::Extension<Arc<Outbound>>,
 ) -> Result<ResponseOrRedirect, Error> {
-    let crate_with_permissions =
-        Arc::new(Crate::find_by_name(db.clone(), user.id, organisation, name).await?);
+    let crate_with_permissions = match Crate::find_by_name(
+        db.clone(),
+        user.id,
+        scope.clone(),
+        organisation.clone(),
+        name.clone(),
+    )
+    .await
+    {
+        Ok(v) => Arc::new(v),
+        Err(chartered_db::Error::MissingCrate) => {
+            // the crate might not exist locally because we're a mirror that hasn't
+            // fetched it yet, mirrored crates are still subject to the session's scope
+            if !scope
+                .restrict(&organisation, &name, UserPermission::VISIBLE)
+                .contains(UserPermission::VISIBLE)
+            {
+                return Err(
+                    chartered_db::Error::MissingCratePermission(UserPermission::VISIBLE).into(),
+                );
+            }
 
-    // we shouldn't really hold back this request from progressing whilst waiting
-    // on the downloads increment to complete so we'll just tokio::spawn it
-    tokio::spawn(
-        crate_with_permissions
-            .clone()
-            .increment_download_count(db.clone()),
-    );
+            let file_ref =
+                mirror_crate_file(db, &outbound, &fs, user.id, organisation, &name, &version)
+                    .await?;
+
+            return read_file(&fs, file_ref).await;
+        }
+        Err(e) => return Err(e.into()),
+    };
 
     // grab the requested version of the crate
     let version = crate_with_permissions
@@ -39,10 +70,41 @@ pub async fn handle(
         .await?
         .ok_or(Error::NoVersion)?;
 
+    // downloads are aggregated in memory and flushed to the database in batches so we
+    // don't hold this request up waiting on a write
+    download_tracker.record(version.crate_id, version.id);
+
     // parses the filesystem_object returned by the database to a `FileReference` that
     // we can use to get a `FilePointer` that is either on the disk and already available
     // to us or is stored elsewhere but we have a link to that we can redirect to.
     let file_ref = FileReference::from_str(&version.filesystem_object).map_err(Box::new)?;
+
+    read_file(&fs, file_ref).await
+}
+
+/// Fetches the crate from the organisation's upstream registry if it's a mirror.
+async fn mirror_crate_file(
+    db: ConnectionPool,
+    outbound: &Outbound,
+    fs: &FileSystem,
+    user_id: i32,
+    organisation: String,
+    name: &str,
+    version: &str,
+) -> Result<FileReference, Error> {
+    let organisation =
+        Arc::new(Organisation::find_by_name(db.clone(), user_id, organisation).await?);
+    let upstream = organisation
+        .organisation()
+        .upstream()
+        .ok_or(chartered_db::Error::MissingCrate)?;
+
+    mirror::crate_file(db, outbound, fs, organisation, &upstream, name, version)
+        .await?
+        .ok_or(Error::NoVersion)
+}
+
+async fn read_file(fs: &FileSystem, file_ref: FileReference) -> Result<ResponseOrRedirect, Error> {
     let res = fs.read(file_ref).await.map_err(Box::new)?;
 
     match res {
@@ -74,6 +136,8 @@ pub enum Error {
     Database(#[from] chartered_db::Error),
     #[error("Failed to fetch crate file: {0}")]
     File(#[from] Box<chartered_fs::Error>),
+    #[error("{0}")]
+    Mirror(#[from] mirror::Error),
     #[error("The requested version does not exist for the crate")]
     NoVersion,
 }
@@ -85,6 +149,7 @@ impl Error {
         match self {
             Self::Database(e) => e.status_code(),
             Self::File(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Mirror(e) => e.status_code(),
             Self::NoVersion => StatusCode::NOT_FOUND,
         }
     }
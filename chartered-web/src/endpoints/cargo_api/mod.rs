@@ -1,5 +1,7 @@
 //! This module is used for anything called directly by cargo. The base URL for all the routes
-//! listed in this module is `/a/:key/o/:organisation/api/v1`.
+//! listed in this module is either `/o/:organisation/api/v1` for registries that have
+//! `auth-required` set, where cargo sends the token in the `Authorization` header, or
+//! `/a/:key/o/:organisation/api/v1` for registries that embed the key in the path.
 //!
 //! Generally only endpoints listed in the [Web API section of the Cargo book][cargo-book] should
 //! be implemented here.
@@ -17,6 +19,7 @@ use axum::{
     routing::{delete, get, put},
     Router,
 };
+use serde::Deserialize;
 
 // requests are already authenticated before this router
 pub fn routes(rate_limit: &RateLimit) -> Router {
@@ -45,3 +48,27 @@ pub fn routes(rate_limit: &RateLimit) -> Router {
             get(download::handle.layer(rate_limit.with_cost(1))),
         )
 }
+
+/// Path parameters for routes acting on an organisation. The key is deliberately left out, it
+/// isn't always present and is handled by `CargoAuthMiddleware`.
+#[derive(Deserialize)]
+pub struct OrganisationPath {
+    organisation: String,
+}
+
+/// Path parameters for routes acting on a crate.
+#[derive(Deserialize)]
+pub struct CratePath {
+    organisation: String,
+    #[serde(rename = "crate")]
+    name: String,
+}
+
+/// Path parameters for routes acting on a specific version of a crate.
+#[derive(Deserialize)]
+pub struct CrateVersionPath {
+    organisation: String,
+    #[serde(rename = "crate")]
+    name: String,
+    version: String,
+}
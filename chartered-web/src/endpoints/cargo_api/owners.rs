@@ -4,18 +4,21 @@
 //! anyone with the `MANAGE_USERS` permission.
 
 use axum::{extract, Json};
-use chartered_db::{crates::Crate, users::User, ConnectionPool};
+use chartered_db::{crates::Crate, scopes::TokenScope, users::User, ConnectionPool};
 use serde::Serialize;
 use std::sync::Arc;
 use thiserror::Error;
 
+use super::CratePath;
+
 pub async fn handle_get(
-    extract::Path((_session_key, organisation, name)): extract::Path<(String, String, String)>,
+    extract::Path(CratePath { organisation, name }): extract::Path<CratePath>,
     extract::Extension(db): extract::Extension<ConnectionPool>,
     extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
 ) -> Result<Json<GetResponse>, Error> {
     let crate_with_permissions =
-        Arc::new(Crate::find_by_name(db.clone(), user.id, organisation, name).await?);
+        Arc::new(Crate::find_by_name(db.clone(), user.id, scope, organisation, name).await?);
 
     // grab all users with the `MANAGE_USERS` permission for the crate
     let users = crate_with_permissions
@@ -5,7 +5,7 @@
 
 use axum::extract;
 use bytes::Bytes;
-use chartered_db::{crates::Crate, users::User, ConnectionPool};
+use chartered_db::{audit::Actor, crates::Crate, scopes::TokenScope, users::User, ConnectionPool};
 use chartered_fs::FileSystem;
 use chartered_types::cargo::{CrateDependency, CrateFeatures, CrateVersion};
 use nom_bytes::BytesWrapper;
@@ -14,11 +14,17 @@ use sha2::{Digest, Sha256};
 use std::{borrow::Cow, convert::TryInto, sync::Arc};
 use thiserror::Error;
 
+use super::OrganisationPath;
+use crate::asymmetric_token::{self, TokenClaims};
+
 pub async fn handle(
-    extract::Path((_session_key, organisation)): extract::Path<(String, String)>,
+    extract::Path(OrganisationPath { organisation }): extract::Path<OrganisationPath>,
     extract::Extension(db): extract::Extension<ConnectionPool>,
     extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
     extract::Extension(fs): extract::Extension<Arc<FileSystem>>,
+    claims: Option<extract::Extension<Arc<TokenClaims>>>,
     body: Bytes,
 ) -> Result<axum::response::Json<PublishCrateResponse>, Error> {
     // cargo sends the crate metadata and the crate itself packed together, we'll parse these
@@ -32,10 +38,25 @@ pub async fn handle(
         return Err(Error::InvalidCrateName);
     }
 
+    // take a checksum of the crate to write to the database to ensure integrity
+    let checksum = hex::encode(Sha256::digest(&crate_bytes));
+
+    // asymmetric tokens are signed for a specific crate file, so make sure this is the one
+    // before we go creating anything
+    if let Some(extract::Extension(claims)) = claims {
+        claims.check_mutation(
+            "publish",
+            &metadata.inner.name,
+            &metadata.inner.vers,
+            Some(&checksum),
+        )?;
+    }
+
     // looks up the crate, though we won't error on it just yet
     let crate_with_permissions = Crate::find_by_name(
         db.clone(),
         user.id,
+        scope.clone(),
         organisation.clone(),
         metadata.inner.name.to_string(),
     )
@@ -49,6 +70,7 @@ pub async fn handle(
             let new_crate = Crate::create(
                 db.clone(),
                 user.id,
+                scope,
                 organisation,
                 metadata.inner.name.to_string(),
             )
@@ -58,9 +80,6 @@ pub async fn handle(
         Err(e) => return Err(e.into()),
     };
 
-    // take a checksum of the crate to write to the database to ensure integrity
-    let checksum = hex::encode(Sha256::digest(&crate_bytes));
-
     // writes the file to the filesystem and takes a `FileReference` we can store in the
     // db to.. reference this file when it's needed (ie. on download)
     let file_ref = fs.write(crate_bytes).await.map_err(Box::new)?;
@@ -69,6 +88,7 @@ pub async fn handle(
     crate_with_permissions
         .publish_version(
             db,
+            &actor,
             user,
             file_ref,
             checksum,
@@ -238,6 +258,8 @@ pub enum Error {
     InvalidCrateName,
     #[error("Failed to push crate file to storage: {0}")]
     File(#[from] Box<chartered_fs::Error>),
+    #[error("{0}")]
+    Token(#[from] asymmetric_token::Error),
 }
 
 impl Error {
@@ -251,6 +273,7 @@ impl Error {
             | Self::Database(chartered_db::Error::MissingOrganisation) => StatusCode::BAD_REQUEST,
             Self::Database(e) => e.status_code(),
             Self::File(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Token(e) => e.status_code(),
         }
     }
 }
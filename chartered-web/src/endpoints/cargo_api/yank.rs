@@ -4,46 +4,61 @@
 //! If a crate is yanked, cargo will refuse to download it.
 
 use axum::{extract, Json};
-use chartered_db::{crates::Crate, users::User, ConnectionPool};
+use chartered_db::{audit::Actor, crates::Crate, scopes::TokenScope, users::User, ConnectionPool};
 use serde::Serialize;
 use std::sync::Arc;
 use thiserror::Error;
 
+use super::CrateVersionPath;
+use crate::asymmetric_token::{self, TokenClaims};
+
 pub async fn handle_yank(
-    extract::Path((_session_key, organisation, name, version)): extract::Path<(
-        String,
-        String,
-        String,
-        String,
-    )>,
+    extract::Path(CrateVersionPath {
+        organisation,
+        name,
+        version,
+    }): extract::Path<CrateVersionPath>,
     extract::Extension(db): extract::Extension<ConnectionPool>,
     extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    claims: Option<extract::Extension<Arc<TokenClaims>>>,
 ) -> Result<Json<Response>, Error> {
+    if let Some(extract::Extension(claims)) = claims {
+        claims.check_mutation("yank", &name, &version, None)?;
+    }
+
     let crate_with_permissions =
-        Arc::new(Crate::find_by_name(db.clone(), user.id, organisation, name).await?);
+        Arc::new(Crate::find_by_name(db.clone(), user.id, scope, organisation, name).await?);
 
     crate_with_permissions
-        .yank_version(db, version, true)
+        .yank_version(db, &actor, version, true)
         .await?;
 
     Ok(Json(Response { ok: true }))
 }
 
 pub async fn handle_unyank(
-    extract::Path((_session_key, organisation, name, version)): extract::Path<(
-        String,
-        String,
-        String,
-        String,
-    )>,
+    extract::Path(CrateVersionPath {
+        organisation,
+        name,
+        version,
+    }): extract::Path<CrateVersionPath>,
     extract::Extension(db): extract::Extension<ConnectionPool>,
     extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    claims: Option<extract::Extension<Arc<TokenClaims>>>,
 ) -> Result<Json<Response>, Error> {
+    if let Some(extract::Extension(claims)) = claims {
+        claims.check_mutation("unyank", &name, &version, None)?;
+    }
+
     let crate_with_permissions =
-        Arc::new(Crate::find_by_name(db.clone(), user.id, organisation, name).await?);
+        Arc::new(Crate::find_by_name(db.clone(), user.id, scope, organisation, name).await?);
 
     crate_with_permissions
-        .yank_version(db, version, false)
+        .yank_version(db, &actor, version, false)
         .await?;
 
     Ok(Json(Response { ok: true }))
@@ -58,12 +73,15 @@ pub struct Response {
 pub enum Error {
     #[error("{0}")]
     Database(#[from] chartered_db::Error),
+    #[error("{0}")]
+    Token(#[from] asymmetric_token::Error),
 }
 
 impl Error {
     pub fn status_code(&self) -> axum::http::StatusCode {
         match self {
             Self::Database(e) => e.status_code(),
+            Self::Token(e) => e.status_code(),
         }
     }
 }
@@ -0,0 +1,146 @@
+//! Serves a [sparse index][sparse] for organisations that mirror an upstream registry. The base
+//! URL for all the routes listed in this module is either `/o/:organisation/index`, in which
+//! case the index is served with `auth-required` set, or `/a/:key/o/:organisation/index`.
+//!
+//! Regular organisations serve their index over git via `chartered-git` instead.
+//!
+//! [sparse]: https://doc.rust-lang.org/cargo/reference/registry-index.html#sparse-protocol
+
+use axum::{extract, handler::Handler, routing::get, Json, Router};
+use chartered_db::{
+    organisations::Organisation, permissions::UserPermission, scopes::TokenScope, users::User,
+    ConnectionPool,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::{config::Config, mirror, outbound::Outbound, RateLimit};
+
+// requests are already authenticated before this router
+pub fn routes(rate_limit: &RateLimit) -> Router {
+    Router::new().route("/*path", get(handle.layer(rate_limit.with_cost(1))))
+}
+
+pub async fn handle(
+    extract::Path(IndexPath {
+        key,
+        organisation,
+        path,
+    }): extract::Path<IndexPath>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
+    extract::Extension(config): extract::Extension<Arc<Config>>,
+    extract::Extension(outbound): extract::Extension<Arc<Outbound>>,
+) -> Result<axum::response::Response, Error> {
+    use axum::response::IntoResponse;
+
+    let organisation =
+        Arc::new(Organisation::find_by_name(db.clone(), user.id, organisation).await?);
+    if !organisation.permissions().contains(UserPermission::VISIBLE) {
+        return Err(Error::NotFound);
+    }
+
+    let upstream = organisation
+        .organisation()
+        .upstream()
+        .ok_or(Error::NotFound)?;
+
+    let path = path.trim_start_matches('/');
+
+    if path == "config.json" {
+        let web_base_uri = config
+            .web_base_uri
+            .as_ref()
+            .ok_or(Error::MissingWebBaseUri)?;
+        let organisation_name = &organisation.organisation().name;
+
+        // if the key wasn't given in the path we'll have cargo send it to us in the
+        // `Authorization` header instead, so we don't leak it into any logs. anonymous users
+        // don't have a key to send at all
+        let (base, auth_required) = match key {
+            _ if user.is_anonymous() => (format!("{}o/{}", web_base_uri, organisation_name), false),
+            Some(key) => (
+                format!("{}a/{}/o/{}", web_base_uri, key, organisation_name),
+                false,
+            ),
+            None => (format!("{}o/{}", web_base_uri, organisation_name), true),
+        };
+
+        return Ok(Json(CargoConfig {
+            dl: format!("{}/api/v1/crates", base),
+            api: base,
+            auth_required,
+        })
+        .into_response());
+    }
+
+    // only allow fetching paths that could actually be crates so we're not an open proxy to
+    // whatever else the upstream happens to serve
+    let crate_name = path.rsplit('/').next().unwrap_or_default();
+    if mirror::index_path(crate_name).as_deref() != Some(path) {
+        return Err(Error::NotFound);
+    }
+
+    // crates outside of the session's scope are hidden from the index entirely
+    if !scope
+        .restrict(
+            &organisation.organisation().name,
+            crate_name,
+            UserPermission::VISIBLE,
+        )
+        .contains(UserPermission::VISIBLE)
+    {
+        return Err(Error::NotFound);
+    }
+
+    let content = mirror::index_file(db, &outbound, organisation, &upstream, path)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    Ok(content.into_response())
+}
+
+#[derive(Deserialize)]
+pub struct IndexPath {
+    key: Option<String>,
+    organisation: String,
+    path: String,
+}
+
+/// The `config.json` file at the root of the index.
+#[derive(Serialize)]
+pub struct CargoConfig {
+    dl: String,
+    api: String,
+    #[serde(rename = "auth-required", skip_serializing_if = "std::ops::Not::not")]
+    auth_required: bool,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Database(#[from] chartered_db::Error),
+    #[error("{0}")]
+    Mirror(#[from] mirror::Error),
+    #[error("The requested file does not exist in the index")]
+    NotFound,
+    #[error("web_base_uri must be configured to serve a sparse index")]
+    MissingWebBaseUri,
+}
+
+impl Error {
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        use axum::http::StatusCode;
+
+        match self {
+            Self::Database(e) => e.status_code(),
+            Self::Mirror(e) => e.status_code(),
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::MissingWebBaseUri => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+define_error_response!(Error);
@@ -26,4 +26,5 @@ macro_rules! define_error_response {
 }
 
 pub mod cargo_api;
+pub mod cargo_index;
 pub mod web_api;
@@ -0,0 +1,34 @@
+//! Paginated view of every event in the instance's audit log, for instance administrators.
+
+use crate::endpoints::web_api::audit_log::{Query, Response};
+
+use axum::{extract, Json};
+use chartered_db::{audit::AuditEvent, ConnectionPool};
+use thiserror::Error;
+
+pub async fn handle_get(
+    extract::Query(query): extract::Query<Query>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+) -> Result<Json<Response>, Error> {
+    let (page, per_page) = query.pagination();
+
+    let log = AuditEvent::list_all(db, page, per_page).await?;
+
+    Ok(Json(Response::new(&log, page, per_page)))
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Database(#[from] chartered_db::Error),
+}
+
+impl Error {
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        match self {
+            Self::Database(e) => e.status_code(),
+        }
+    }
+}
+
+define_error_response!(Error);
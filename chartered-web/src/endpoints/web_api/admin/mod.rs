@@ -0,0 +1,64 @@
+//! Instance administration, only available to users with the `admin` flag set through an
+//! unscoped session. See `AdminMiddleware`.
+
+mod audit_log;
+mod organisations;
+mod statistics;
+mod users;
+
+use crate::RateLimit;
+use axum::{
+    handler::Handler,
+    routing::{delete, get, patch, post, put},
+    Router,
+};
+use tower::ServiceBuilder;
+
+pub fn routes(rate_limit: &RateLimit) -> Router {
+    Router::new()
+        .route(
+            "/users",
+            get(users::handle_list.layer(rate_limit.with_cost(1))),
+        )
+        .route(
+            "/users/:uuid",
+            patch(users::handle_patch.layer(rate_limit.with_cost(5))),
+        )
+        .route(
+            "/users/:uuid/disable",
+            post(users::handle_disable.layer(rate_limit.with_cost(5))),
+        )
+        .route(
+            "/users/:uuid/enable",
+            post(users::handle_enable.layer(rate_limit.with_cost(5))),
+        )
+        .route(
+            "/users/:uuid/sessions",
+            delete(users::handle_delete_sessions.layer(rate_limit.with_cost(5))),
+        )
+        .route(
+            "/users/:uuid/ssh-keys",
+            delete(users::handle_delete_ssh_keys.layer(rate_limit.with_cost(5))),
+        )
+        .route(
+            "/organisations",
+            get(organisations::handle_list.layer(rate_limit.with_cost(1))),
+        )
+        .route(
+            "/organisations/:org/owners",
+            put(organisations::handle_put_owner.layer(rate_limit.with_cost(5))),
+        )
+        .route(
+            "/audit-log",
+            get(audit_log::handle_get.layer(rate_limit.with_cost(5))),
+        )
+        .route(
+            "/statistics",
+            get(statistics::handle_get.layer(rate_limit.with_cost(5))),
+        )
+        .layer(
+            ServiceBuilder::new()
+                .layer_fn(crate::middleware::admin::AdminMiddleware)
+                .into_inner(),
+        )
+}
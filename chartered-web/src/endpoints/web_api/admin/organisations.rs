@@ -0,0 +1,85 @@
+//! Lets instance administrators see every organisation on the instance, and take ownership of
+//! organisations that have been left without anyone able to manage them.
+
+use axum::{extract, Json};
+use chartered_db::{audit::Actor, organisations::Organisation, users::User, ConnectionPool};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::endpoints::ErrorResponse;
+
+/// Lists every organisation, including deleted ones that can still be restored.
+pub async fn handle_list(
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+) -> Result<Json<ListResponse>, Error> {
+    let organisations = Organisation::list_all(db).await?;
+
+    Ok(Json(ListResponse {
+        organisations: organisations
+            .into_iter()
+            .map(|v| ListResponseOrganisation {
+                uuid: v.uuid.0,
+                name: v.name,
+                description: v.description,
+                public: v.public,
+                deleted_at: v.deleted_at.map(|v| Utc.from_utc_datetime(&v)),
+            })
+            .collect(),
+    }))
+}
+
+/// Gives a user every permission on the organisation.
+pub async fn handle_put_owner(
+    extract::Path(organisation): extract::Path<String>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    extract::Json(req): extract::Json<PutOwnerRequest>,
+) -> Result<Json<ErrorResponse>, Error> {
+    let owner = User::find_by_uuid(db.clone(), req.user_uuid)
+        .await?
+        .ok_or(Error::InvalidUserId)?;
+
+    Organisation::grant_ownership(db, &actor, organisation, owner.id).await?;
+
+    Ok(Json(ErrorResponse { error: None }))
+}
+
+#[derive(Serialize)]
+pub struct ListResponse {
+    organisations: Vec<ListResponseOrganisation>,
+}
+
+#[derive(Serialize)]
+pub struct ListResponseOrganisation {
+    uuid: chartered_db::uuid::Uuid,
+    name: String,
+    description: String,
+    public: bool,
+    deleted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub struct PutOwnerRequest {
+    user_uuid: chartered_db::uuid::Uuid,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Database(#[from] chartered_db::Error),
+    #[error("An invalid user id was given")]
+    InvalidUserId,
+}
+
+impl Error {
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        match self {
+            Self::Database(e) => e.status_code(),
+            Self::InvalidUserId => axum::http::StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+define_error_response!(Error);
@@ -0,0 +1,51 @@
+//! Instance-wide counts for administrators to keep an eye on how the instance is being used.
+
+use axum::{extract, Json};
+use chartered_db::{statistics::InstanceStatistics, ConnectionPool};
+use serde::Serialize;
+use thiserror::Error;
+
+pub async fn handle_get(
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+) -> Result<Json<Response>, Error> {
+    let statistics = InstanceStatistics::get(db).await?;
+
+    Ok(Json(Response {
+        users: statistics.users,
+        admins: statistics.admins,
+        disabled_users: statistics.disabled_users,
+        organisations: statistics.organisations,
+        crates: statistics.crates,
+        versions: statistics.versions,
+        storage_bytes: statistics.storage_bytes,
+        downloads: statistics.downloads,
+    }))
+}
+
+#[derive(Serialize)]
+pub struct Response {
+    users: i64,
+    admins: i64,
+    disabled_users: i64,
+    organisations: i64,
+    crates: i64,
+    versions: i64,
+    storage_bytes: i64,
+    downloads: i64,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Database(#[from] chartered_db::Error),
+}
+
+impl Error {
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        match self {
+            Self::Database(e) => e.status_code(),
+        }
+    }
+}
+
+define_error_response!(Error);
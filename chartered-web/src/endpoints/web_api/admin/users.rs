@@ -0,0 +1,201 @@
+//! Lets instance administrators list every user on the instance, disable them, revoke their
+//! credentials and manage who else is an administrator.
+
+use axum::{extract, Json};
+use chartered_db::{audit::Actor, passkeys::UserPasskey, users::User, ConnectionPool};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::endpoints::ErrorResponse;
+
+const DEFAULT_PER_PAGE: i64 = 50;
+const MAX_PER_PAGE: i64 = 100;
+
+pub async fn handle_list(
+    extract::Query(query): extract::Query<Query>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+) -> Result<Json<ListResponse>, Error> {
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query
+        .per_page
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .clamp(1, MAX_PER_PAGE);
+
+    let (users, total) = User::list(db.clone(), page, per_page).await?;
+    let with_passkeys =
+        UserPasskey::users_with_passkeys(db, users.iter().map(|v| v.id).collect()).await?;
+
+    Ok(Json(ListResponse {
+        users: users
+            .into_iter()
+            .map(|user| ListResponseUser {
+                uuid: user.uuid.0,
+                display_name: user.display_name().to_string(),
+                two_factor_enabled: user.has_totp() || with_passkeys.contains(&user.id),
+                username: user.username,
+                email: user.email,
+                picture_url: user.picture_url,
+                admin: user.admin,
+                disabled_at: user.disabled_at.map(|v| Utc.from_utc_datetime(&v)),
+                locked_until: user.locked_until.map(|v| Utc.from_utc_datetime(&v)),
+            })
+            .collect(),
+        page,
+        per_page,
+        total,
+    }))
+}
+
+/// Grants or revokes a user's instance administrator rights.
+pub async fn handle_patch(
+    extract::Path(uuid): extract::Path<chartered_db::uuid::Uuid>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    extract::Json(req): extract::Json<PatchRequest>,
+) -> Result<Json<ErrorResponse>, Error> {
+    let target = find_other_user(db.clone(), &user, uuid).await?;
+
+    target.set_admin(db, &actor, req.admin).await?;
+
+    Ok(Json(ErrorResponse { error: None }))
+}
+
+/// Disables a user, logging them out everywhere and stopping them from authenticating again
+/// until they're enabled.
+pub async fn handle_disable(
+    extract::Path(uuid): extract::Path<chartered_db::uuid::Uuid>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+) -> Result<Json<ErrorResponse>, Error> {
+    let target = find_other_user(db.clone(), &user, uuid).await?;
+
+    target.set_disabled(db, &actor, true).await?;
+
+    Ok(Json(ErrorResponse { error: None }))
+}
+
+pub async fn handle_enable(
+    extract::Path(uuid): extract::Path<chartered_db::uuid::Uuid>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+) -> Result<Json<ErrorResponse>, Error> {
+    let target = find_other_user(db.clone(), &user, uuid).await?;
+
+    target.set_disabled(db, &actor, false).await?;
+
+    Ok(Json(ErrorResponse { error: None }))
+}
+
+/// Revokes every session the user has, including API tokens.
+pub async fn handle_delete_sessions(
+    extract::Path(uuid): extract::Path<chartered_db::uuid::Uuid>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+) -> Result<Json<RevokeResponse>, Error> {
+    let target = find_user(db.clone(), uuid).await?;
+
+    let revoked = target.delete_sessions(db, &actor).await?;
+
+    Ok(Json(RevokeResponse { revoked }))
+}
+
+/// Removes every SSH key the user has registered.
+pub async fn handle_delete_ssh_keys(
+    extract::Path(uuid): extract::Path<chartered_db::uuid::Uuid>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+) -> Result<Json<RevokeResponse>, Error> {
+    let target = find_user(db.clone(), uuid).await?;
+
+    let revoked = target.delete_ssh_keys(db, &actor).await?;
+
+    Ok(Json(RevokeResponse { revoked }))
+}
+
+async fn find_user(db: ConnectionPool, uuid: chartered_db::uuid::Uuid) -> Result<Arc<User>, Error> {
+    User::find_by_uuid(db, uuid)
+        .await?
+        .map(Arc::new)
+        .ok_or(Error::NotFound)
+}
+
+/// Looks up a user other than the requesting administrator, so admins can't lock themselves
+/// out of the instance.
+async fn find_other_user(
+    db: ConnectionPool,
+    user: &User,
+    uuid: chartered_db::uuid::Uuid,
+) -> Result<Arc<User>, Error> {
+    if user.uuid.0 == uuid {
+        return Err(Error::CannotModifySelf);
+    }
+
+    find_user(db, uuid).await
+}
+
+#[derive(Deserialize)]
+pub struct Query {
+    page: Option<i64>,
+    per_page: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct ListResponse {
+    users: Vec<ListResponseUser>,
+    page: i64,
+    per_page: i64,
+    total: i64,
+}
+
+#[derive(Serialize)]
+pub struct ListResponseUser {
+    uuid: chartered_db::uuid::Uuid,
+    username: String,
+    display_name: String,
+    email: Option<String>,
+    picture_url: Option<String>,
+    admin: bool,
+    two_factor_enabled: bool,
+    disabled_at: Option<DateTime<Utc>>,
+    /// When the user's lockout from password logins ends, if they've been locked out.
+    locked_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub struct PatchRequest {
+    admin: bool,
+}
+
+#[derive(Serialize)]
+pub struct RevokeResponse {
+    revoked: usize,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Database(#[from] chartered_db::Error),
+    #[error("User not found")]
+    NotFound,
+    #[error("Administrators can't disable or demote themselves")]
+    CannotModifySelf,
+}
+
+impl Error {
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        use axum::http::StatusCode;
+
+        match self {
+            Self::Database(e) => e.status_code(),
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::CannotModifySelf => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+define_error_response!(Error);
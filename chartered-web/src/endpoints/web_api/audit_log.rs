@@ -0,0 +1,139 @@
+//! Paginated view of the audit log events the requesting user either performed or was the
+//! target of, including those that don't belong to any organisation such as logins.
+//!
+//! The response format is shared by every view of the audit log.
+
+use axum::{extract, Json};
+use chartered_db::{
+    audit::{AuditAction, AuditDetails, AuditEvent, AuditLog},
+    users::User,
+    ConnectionPool,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use thiserror::Error;
+
+const DEFAULT_PER_PAGE: i64 = 50;
+const MAX_PER_PAGE: i64 = 100;
+
+pub async fn handle_get(
+    extract::Query(query): extract::Query<Query>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+) -> Result<Json<Response>, Error> {
+    let (page, per_page) = query.pagination();
+
+    let log = AuditEvent::list_for_user(db, user.id, page, per_page).await?;
+
+    let mut response = Response::new(&log, page, per_page);
+
+    // the user can see that someone else acted on their account, but not where from
+    for (event, response_event) in log.events.iter().zip(&mut response.events) {
+        if event.actor_user_id != Some(user.id) {
+            response_event.ip = None;
+            response_event.user_agent = None;
+        }
+    }
+
+    Ok(Json(response))
+}
+
+#[derive(Deserialize)]
+pub struct Query {
+    page: Option<i64>,
+    per_page: Option<i64>,
+}
+
+impl Query {
+    /// The requested page and page size, clamped to sensible bounds.
+    #[must_use]
+    pub fn pagination(&self) -> (i64, i64) {
+        let page = self.page.unwrap_or(1).max(1);
+        let per_page = self
+            .per_page
+            .unwrap_or(DEFAULT_PER_PAGE)
+            .clamp(1, MAX_PER_PAGE);
+
+        (page, per_page)
+    }
+}
+
+#[derive(Serialize)]
+pub struct Response {
+    events: Vec<ResponseEvent>,
+    page: i64,
+    per_page: i64,
+    total: i64,
+}
+
+impl Response {
+    #[must_use]
+    pub fn new(log: &AuditLog, page: i64, per_page: i64) -> Self {
+        let to_response_user = |id: Option<i32>| {
+            id.and_then(|id| log.users.get(&id))
+                .map(|user| ResponseUser {
+                    uuid: user.uuid.0,
+                    display_name: user.display_name().to_string(),
+                    picture_url: user.picture_url.clone(),
+                })
+        };
+
+        let events = log
+            .events
+            .iter()
+            .map(|event| ResponseEvent {
+                uuid: event.uuid.0,
+                action: event.action,
+                actor: to_response_user(event.actor_user_id),
+                target_user: to_response_user(event.target_user_id),
+                details: event.details.clone(),
+                ip: event.ip.clone(),
+                user_agent: event.user_agent.clone(),
+                created_at: Utc.from_utc_datetime(&event.created_at),
+            })
+            .collect();
+
+        Self {
+            events,
+            page,
+            per_page,
+            total: log.total,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ResponseEvent {
+    uuid: chartered_db::uuid::Uuid,
+    action: AuditAction,
+    actor: Option<ResponseUser>,
+    target_user: Option<ResponseUser>,
+    details: AuditDetails,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct ResponseUser {
+    uuid: chartered_db::uuid::Uuid,
+    display_name: String,
+    picture_url: Option<String>,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Database(#[from] chartered_db::Error),
+}
+
+impl Error {
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        match self {
+            Self::Database(e) => e.status_code(),
+        }
+    }
+}
+
+define_error_response!(Error);
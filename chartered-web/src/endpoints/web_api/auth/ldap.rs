@@ -0,0 +1,140 @@
+//! Logins for users in an LDAP directory, using their directory username and password.
+//!
+//! Users are created on their first login and linked by the `ldap:username` username, the same
+//! way as OpenID Connect logins. Their name and email, along with any organisation memberships
+//! mapped from their groups, are refreshed from the directory every time they log in.
+
+use super::password::{login_or_challenge, LoginError, LoginResponse};
+use crate::{config::Config, group_mappings, ldap};
+
+use axum::{extract, Json};
+use chartered_db::{audit::Actor, organisations::Organisation, users::User, ConnectionPool};
+use serde::Deserialize;
+use thiserror::Error;
+use webauthn_rs::prelude::Webauthn;
+
+use std::{net::IpAddr, sync::Arc};
+
+/// Prefix given to the usernames of users created through LDAP logins, which can't clash with
+/// password users since they aren't allowed `:` in their usernames.
+const USERNAME_PREFIX: &str = "ldap";
+
+pub async fn handle_login(
+    extract::Extension(config): extract::Extension<Arc<Config>>,
+    extract::Extension(webauthn): extract::Extension<Arc<Webauthn>>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Json(req): extract::Json<LoginRequest>,
+    user_agent: Option<extract::TypedHeader<headers::UserAgent>>,
+    addr: extract::Extension<IpAddr>,
+) -> Result<Json<LoginResponse>, Error> {
+    let ldap_config = config
+        .auth
+        .ldap
+        .as_ref()
+        .filter(|v| v.enabled)
+        .ok_or(Error::LdapDisabled)?;
+
+    let entry = ldap::find(ldap_config, &req.username)
+        .await?
+        .ok_or(LoginError::InvalidPassword)?;
+
+    // the lockout is keyed on the username the directory has for the user, rather than what
+    // was typed in, so it can't be sidestepped by a directory that matches usernames loosely
+    let existing = User::find_by_username(
+        db.clone(),
+        format!("{}:{}", USERNAME_PREFIX, entry.username()),
+    )
+    .await?;
+
+    let actor = Actor {
+        user_id: None,
+        ip: Some(addr.0.to_string()),
+        user_agent: user_agent
+            .as_ref()
+            .map(|extract::TypedHeader(v)| v.as_str().to_string()),
+    };
+
+    // the directory will have its own lockout policy, but there's no reason to keep asking it
+    // about a user we've already locked out
+    if let Some(existing) = &existing {
+        match existing.claim_login_attempt(db.clone(), &actor).await {
+            Err(chartered_db::Error::UserLocked) => return Err(LoginError::Locked.into()),
+            v => v?,
+        }
+    }
+
+    let directory_user = match entry.authenticate(&req.password).await? {
+        Some(v) => v,
+        None => {
+            if let Some(existing) = existing {
+                existing.record_failed_login(db, &actor).await?;
+            }
+
+            return Err(LoginError::InvalidPassword.into());
+        }
+    };
+
+    let user = User::find_or_create(
+        db.clone(),
+        format!("{}:{}", USERNAME_PREFIX, directory_user.username),
+        directory_user.name.clone(),
+        None,
+        directory_user.email.clone(),
+        None,
+        None,
+    )
+    .await?;
+
+    if user.is_disabled() {
+        return Err(LoginError::Disabled.into());
+    }
+
+    user.sync_directory_profile(db.clone(), directory_user.name, directory_user.email)
+        .await?;
+
+    if !ldap_config.groups.is_empty() {
+        Organisation::reconcile_memberships(
+            db.clone(),
+            user.id,
+            group_mappings::memberships(&ldap_config.groups, &directory_user.groups),
+        )
+        .await?;
+    }
+
+    Ok(Json(
+        login_or_challenge(&config, &webauthn, db, user, None, user_agent, addr).await?,
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct LoginRequest {
+    username: String,
+    password: String,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Database(#[from] chartered_db::Error),
+    #[error("{0}")]
+    Login(#[from] LoginError),
+    #[error("Failed to query LDAP server")]
+    Ldap(#[from] ldap3::LdapError),
+    #[error("LDAP authentication is disabled")]
+    LdapDisabled,
+}
+
+impl Error {
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        use axum::http::StatusCode;
+
+        match self {
+            Self::Database(e) => e.status_code(),
+            Self::Login(e) => e.status_code(),
+            Self::Ldap(_) => StatusCode::BAD_GATEWAY,
+            Self::LdapDisabled => StatusCode::FORBIDDEN,
+        }
+    }
+}
+
+define_error_response!(Error);
@@ -3,10 +3,12 @@ use crate::middleware::rate_limit::RateLimit;
 use axum::{
     extract,
     handler::Handler,
-    routing::{get, post},
+    routing::{delete, get, post},
     Extension, Router,
 };
 use chartered_db::{
+    audit::Actor,
+    scopes::TokenScope,
     users::{User, UserSession},
     uuid::Uuid,
     ConnectionPool,
@@ -16,9 +18,13 @@ use serde::Serialize;
 use std::net::IpAddr;
 
 pub mod extend;
+pub mod ldap;
 pub mod logout;
 pub mod openid;
+pub mod passkeys;
 pub mod password;
+pub mod saml;
+pub mod two_factor;
 
 pub fn authenticated_routes(rate_limit: &RateLimit) -> Router {
     Router::new()
@@ -30,6 +36,36 @@ pub fn authenticated_routes(rate_limit: &RateLimit) -> Router {
             "/extend",
             get(extend::handle.layer(rate_limit.with_cost(1))),
         )
+        .route(
+            "/two-factor",
+            get(two_factor::handle_get.layer(rate_limit.with_cost(1)))
+                .delete(two_factor::handle_delete.layer(rate_limit.with_cost(50))),
+        )
+        .route(
+            "/two-factor/enrol",
+            post(two_factor::handle_enrol.layer(rate_limit.with_cost(10))),
+        )
+        .route(
+            "/two-factor/verify",
+            post(two_factor::handle_verify.layer(rate_limit.with_cost(50))),
+        )
+        .route(
+            "/two-factor/recovery-codes",
+            post(two_factor::handle_regenerate_recovery_codes.layer(rate_limit.with_cost(50))),
+        )
+        .route(
+            "/passkeys",
+            get(passkeys::handle_list.layer(rate_limit.with_cost(1)))
+                .post(passkeys::handle_register.layer(rate_limit.with_cost(50))),
+        )
+        .route(
+            "/passkeys/register",
+            post(passkeys::handle_register_begin.layer(rate_limit.with_cost(10))),
+        )
+        .route(
+            "/passkeys/:uuid",
+            delete(passkeys::handle_delete.layer(rate_limit.with_cost(50))),
+        )
 }
 
 pub fn unauthenticated_routes(rate_limit: &RateLimit) -> Router {
@@ -42,6 +78,22 @@ pub fn unauthenticated_routes(rate_limit: &RateLimit) -> Router {
             "/login/password",
             post(password::handle_login.layer(rate_limit.with_cost(100))),
         )
+        .route(
+            "/login/ldap",
+            post(ldap::handle_login.layer(rate_limit.with_cost(100))),
+        )
+        .route(
+            "/login/two-factor",
+            post(two_factor::handle_login.layer(rate_limit.with_cost(100))),
+        )
+        .route(
+            "/login/passkey/begin",
+            post(passkeys::handle_login_begin.layer(rate_limit.with_cost(10))),
+        )
+        .route(
+            "/login/passkey",
+            post(passkeys::handle_login.layer(rate_limit.with_cost(100))),
+        )
         .route(
             "/login/oauth/:provider/begin",
             get(openid::begin_oidc.layer(rate_limit.with_cost(1))),
@@ -50,6 +102,22 @@ pub fn unauthenticated_routes(rate_limit: &RateLimit) -> Router {
             "/login/oauth/complete",
             get(openid::complete_oidc.layer(rate_limit.with_cost(75))),
         )
+        .route(
+            "/login/saml/:provider/metadata",
+            get(saml::handle_metadata.layer(rate_limit.with_cost(1))),
+        )
+        .route(
+            "/login/saml/:provider/begin",
+            get(saml::handle_begin.layer(rate_limit.with_cost(1))),
+        )
+        .route(
+            "/login/saml/:provider/acs",
+            post(saml::handle_acs.layer(rate_limit.with_cost(75))),
+        )
+        .route(
+            "/login/saml/complete",
+            get(saml::handle_complete.layer(rate_limit.with_cost(75))),
+        )
         .route(
             "/login/oauth/providers",
             get(openid::list_providers.layer(rate_limit.with_cost(1))),
@@ -65,10 +133,12 @@ pub struct LoginResponse {
 }
 
 /// Takes the given `User` and generates a session for it and returns a response containing an API
-/// key to the frontend that it can save for further request
+/// key to the frontend that it can save for further request. The session is restricted to
+/// `scope` if one is given.
 pub async fn login(
     db: ConnectionPool,
     user: User,
+    scope: Option<TokenScope>,
     user_agent: Option<extract::TypedHeader<headers::UserAgent>>,
     Extension(addr): Extension<IpAddr>,
 ) -> Result<LoginResponse, chartered_db::Error> {
@@ -78,14 +148,22 @@ pub async fn login(
         None
     };
 
+    let actor = Actor {
+        user_id: Some(user.id),
+        ip: Some(addr.to_string()),
+        user_agent,
+    };
+
     let expires = chrono::Utc::now() + chrono::Duration::hours(1);
     let key = UserSession::generate(
         db,
+        &actor,
         user.id,
         None,
         Some(expires.naive_utc()),
-        user_agent,
-        Some(addr.to_string()),
+        actor.user_agent.clone(),
+        actor.ip.clone(),
+        scope,
     )
     .await?;
 
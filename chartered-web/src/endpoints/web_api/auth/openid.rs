@@ -2,11 +2,14 @@
 //! enabled providers so they can show them to the frontend and provide methods for actually doing
 //! the authentication.
 
-use crate::config::{Config, OidcClient, OidcClients};
+use crate::{
+    config::{Config, OidcClient, OidcClients, SamlProviders},
+    group_mappings,
+};
 
 use axum::{extract, Json};
 use chacha20poly1305::{aead::Aead, ChaCha20Poly1305, KeyInit, Nonce as ChaCha20Poly1305Nonce};
-use chartered_db::{users::User, ConnectionPool};
+use chartered_db::{organisations::Organisation, users::User, ConnectionPool};
 use oauth2::{
     basic::BasicErrorResponseType, AuthorizationCode, CsrfToken, RequestTokenError, Scope,
     StandardErrorResponse, TokenResponse,
@@ -25,14 +28,20 @@ pub type Nonce = [u8; 16];
 pub async fn list_providers(
     extract::Extension(config): extract::Extension<Arc<Config>>,
     extract::Extension(oidc_clients): extract::Extension<Arc<OidcClients>>,
+    extract::Extension(saml_providers): extract::Extension<Arc<SamlProviders>>,
 ) -> Json<ListProvidersResponse> {
     Json(ListProvidersResponse {
         password: config.auth.password.enabled,
+        ldap: config.auth.ldap.as_ref().map_or(false, |v| v.enabled),
         providers: oidc_clients
             .keys()
             .into_iter()
             .map(std::string::ToString::to_string)
             .collect(),
+        saml: saml_providers
+            .keys()
+            .map(std::string::ToString::to_string)
+            .collect(),
     })
 }
 
@@ -95,9 +104,15 @@ pub async fn complete_oidc(
         .get(&state.provider)
         .ok_or(Error::UnknownOauthProvider)?;
 
+    // groups the provider says the user is in, only read when the provider has mappings
+    // configured
+    let mut groups = None;
+
     let user = match client {
         OidcClient::Discovered(client) => {
-            let mut token: Token = client.request_token(&params.code).await?.into();
+            let bearer = client.request_token(&params.code).await?;
+            let raw_id_token = bearer.id_token.clone();
+            let mut token: Token = bearer.into();
 
             if let Some(id_token) = token.id_token.as_mut() {
                 // ensure the id_token is valid, checking `exp`, etc.
@@ -107,6 +122,21 @@ pub async fn complete_oidc(
                 // with the original request
                 let nonce = base64::encode_config(state.nonce, base64::URL_SAFE_NO_PAD);
                 client.validate_token(id_token, Some(nonce.as_str()), None)?;
+
+                if let (Some(provider), Some(raw_id_token)) = (
+                    config
+                        .auth
+                        .oauth
+                        .get(&state.provider)
+                        .filter(|v| !v.groups.is_empty()),
+                    raw_id_token,
+                ) {
+                    // providers can leave the claim out, such as when the user is in too many
+                    // groups to fit in the token, which tells us nothing about their groups
+                    // rather than that they're in none
+                    groups = id_token_groups(&raw_id_token, &provider.groups_claim)?
+                        .map(|groups| (provider, groups));
+                }
             } else {
                 // the provider didn't send us back a id_token
                 return Err(Error::MissingToken);
@@ -150,8 +180,45 @@ pub async fn complete_oidc(
     )
     .await?;
 
+    // the provider knowing about the user doesn't mean they're allowed in
+    user.ensure_enabled()?;
+
+    if let Some((provider, groups)) = groups {
+        Organisation::reconcile_memberships(
+            db.clone(),
+            user.id,
+            group_mappings::memberships(&provider.groups, &groups),
+        )
+        .await?;
+    }
+
     // request looks good, log the user in!
-    Ok(Json(super::login(db, user, user_agent, addr).await?))
+    Ok(Json(super::login(db, user, None, user_agent, addr).await?))
+}
+
+/// Reads the groups the user belongs to out of the given claim of an ID token we've already
+/// verified. The claim can either be a list of groups or a single group, and `None` is
+/// returned if it's missing.
+fn id_token_groups(id_token: &str, claim: &str) -> Result<Option<Vec<String>>, Error> {
+    // the standard claims don't know anything about groups, so read them straight out of the
+    // payload
+    let payload = id_token.split('.').nth(1).ok_or(Error::MalformedToken)?;
+    let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD)?;
+    let mut claims: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(&payload)?;
+
+    Ok(match claims.remove(claim) {
+        Some(serde_json::Value::Array(groups)) => Some(
+            groups
+                .into_iter()
+                .filter_map(|v| match v {
+                    serde_json::Value::String(v) => Some(v),
+                    _ => None,
+                })
+                .collect(),
+        ),
+        Some(serde_json::Value::String(group)) => Some(vec![group]),
+        _ => None,
+    })
 }
 
 pub struct UserIr {
@@ -203,7 +270,7 @@ const NONCE_LEN: usize = 12;
 
 // Encrypts the given string using ChaCha20Poly1305 and returns a url safe base64 encoded
 // version of it
-fn encrypt_url_safe(input: &[u8], config: &Config) -> Result<String, Error> {
+pub(super) fn encrypt_url_safe(input: &[u8], config: &Config) -> Result<String, Error> {
     let cipher = ChaCha20Poly1305::new(&config.encryption_key);
 
     let nonce = rand::random::<[u8; NONCE_LEN]>();
@@ -216,7 +283,7 @@ fn encrypt_url_safe(input: &[u8], config: &Config) -> Result<String, Error> {
 }
 
 // Decrypts the given string assuming it's a url safe base64 encoded ChaCha20Poly1305 cipher.
-fn decrypt_url_safe(input: &str, config: &Config) -> Result<Vec<u8>, Error> {
+pub(super) fn decrypt_url_safe(input: &str, config: &Config) -> Result<Vec<u8>, Error> {
     let cipher = ChaCha20Poly1305::new(&config.encryption_key);
 
     let mut ciphertext = base64::decode_config(input, base64::URL_SAFE_NO_PAD)?;
@@ -231,7 +298,9 @@ fn decrypt_url_safe(input: &str, config: &Config) -> Result<Vec<u8>, Error> {
 #[derive(Serialize)]
 pub struct ListProvidersResponse {
     password: bool,
+    ldap: bool,
     providers: Vec<String>,
+    saml: Vec<String>,
 }
 
 #[derive(Serialize)]
@@ -272,6 +341,8 @@ pub enum Error {
     Base64(#[from] base64::DecodeError),
     #[error("Missing id_token")]
     MissingToken,
+    #[error("Malformed id_token")]
+    MalformedToken,
     #[error("Failed to request profile from OAuth provider")]
     FetchProfile(#[from] reqwest::Error),
     #[error("Failed to request token from OAuth provider")]
@@ -297,3 +368,40 @@ impl Error {
 }
 
 define_error_response!(Error);
+
+#[cfg(test)]
+mod test {
+    fn id_token(claims: &serde_json::Value) -> String {
+        format!(
+            "e30.{}.c2lnbmF0dXJl",
+            base64::encode_config(claims.to_string(), base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    #[test]
+    fn reads_groups_claim() {
+        let token = id_token(&serde_json::json!({
+            "sub": "1234",
+            "groups": ["developers", 1, "release"],
+            "role": "admin",
+        }));
+
+        assert_eq!(
+            super::id_token_groups(&token, "groups").unwrap(),
+            Some(vec!["developers".to_string(), "release".to_string()])
+        );
+        assert_eq!(
+            super::id_token_groups(&token, "role").unwrap(),
+            Some(vec!["admin".to_string()])
+        );
+        assert_eq!(super::id_token_groups(&token, "missing").unwrap(), None);
+
+        // an empty list means the user has left every group, unlike a missing claim
+        let token = id_token(&serde_json::json!({ "sub": "1234", "groups": [] }));
+        assert_eq!(
+            super::id_token_groups(&token, "groups").unwrap(),
+            Some(Vec::new())
+        );
+        assert!(super::id_token_groups("not-a-token", "groups").is_err());
+    }
+}
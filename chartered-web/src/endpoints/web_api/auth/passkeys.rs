@@ -0,0 +1,463 @@
+//! WebAuthn passkeys, including registration and passwordless login.
+//!
+//! Both ceremonies happen in two steps, the first hands the browser the options to call into
+//! the authenticator with, alongside an encrypted challenge holding our side of the ceremony
+//! which has to be sent back with the authenticator's response within
+//! `CHALLENGE_EXPIRY_MINUTES`. Each challenge can only be used once, so a captured response
+//! can't be replayed.
+//!
+//! Passkeys prove both possession of the device and, through the authenticator's own PIN or
+//! biometrics, the user's presence, so a passkey login doesn't go on to ask for a TOTP code.
+//! Users that log in with a password can give a passkey in place of a TOTP code, see
+//! `two_factor::handle_login`.
+
+use crate::{config::Config, endpoints::ErrorResponse, passkeys};
+
+use axum::{extract, Json};
+use chartered_db::{
+    audit::Actor, passkeys::UserPasskey, scopes::TokenScope, used_challenges::UsedChallenge,
+    users::User, uuid::Uuid, ConnectionPool,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use webauthn_rs::prelude::{
+    CreationChallengeResponse, PasskeyAuthentication, PasskeyRegistration, PublicKeyCredential,
+    RegisterPublicKeyCredential, RequestChallengeResponse, Webauthn, WebauthnError,
+};
+
+use std::{net::IpAddr, sync::Arc};
+
+/// How long the user has to respond to a ceremony once it's started.
+const CHALLENGE_EXPIRY_MINUTES: i64 = 5;
+
+/// Contents of the encrypted challenge handed out when a ceremony is started.
+#[derive(Serialize, Deserialize)]
+struct Ceremony<T> {
+    user_uuid: Uuid,
+    expires: i64,
+    state: T,
+}
+
+/// Encrypts our side of a ceremony for the given user, to be sent back to us to complete it.
+fn seal<T: Serialize>(user: &User, state: T, config: &Config) -> Result<String, Error> {
+    let ceremony = serde_json::to_vec(&Ceremony {
+        user_uuid: user.uuid.0,
+        expires: (Utc::now() + chrono::Duration::minutes(CHALLENGE_EXPIRY_MINUTES)).timestamp(),
+        state,
+    })
+    .map_err(|_| Error::InvalidChallenge)?;
+
+    super::openid::encrypt_url_safe(&ceremony, config).map_err(|_| Error::InvalidChallenge)
+}
+
+/// Decrypts a challenge created by `seal`, returning the user it was created for and our side
+/// of the ceremony. The challenge is used up in the process, so it can't be opened again.
+async fn open<T: DeserializeOwned>(
+    db: ConnectionPool,
+    challenge: &str,
+    config: &Config,
+) -> Result<(Uuid, T), Error> {
+    let ceremony =
+        super::openid::decrypt_url_safe(challenge, config).map_err(|_| Error::InvalidChallenge)?;
+    let ceremony: Ceremony<T> =
+        serde_json::from_slice(&ceremony).map_err(|_| Error::InvalidChallenge)?;
+
+    if ceremony.expires < Utc::now().timestamp() {
+        return Err(Error::ExpiredChallenge);
+    }
+
+    if !consume(db, challenge).await? {
+        return Err(Error::UsedChallenge);
+    }
+
+    Ok((ceremony.user_uuid, ceremony.state))
+}
+
+/// Marks the challenge as used, returning `false` if it's been used before.
+pub(super) async fn consume(
+    db: ConnectionPool,
+    challenge: &str,
+) -> Result<bool, chartered_db::Error> {
+    let challenge_hash = hex::encode(Sha256::digest(challenge.as_bytes()));
+    let expires_at = Utc::now().naive_utc() + chrono::Duration::minutes(CHALLENGE_EXPIRY_MINUTES);
+
+    UsedChallenge::claim(db, challenge_hash, expires_at).await
+}
+
+/// Checks the authenticator's response against the user's passkeys, recording the use of the
+/// passkey if it's valid.
+pub(super) async fn verify(
+    db: ConnectionPool,
+    webauthn: &Webauthn,
+    user: &User,
+    credential: &PublicKeyCredential,
+    state: &PasskeyAuthentication,
+) -> Result<bool, chartered_db::Error> {
+    let passkeys = passkeys::decode(&UserPasskey::list(db.clone(), user.id).await?);
+
+    let (result, updated) =
+        match passkeys::finish_authentication(webauthn, &passkeys, credential, state) {
+            Ok(v) => v,
+            Err(_) => return Ok(false),
+        };
+
+    // the signature counter is stored so cloned authenticators can be detected
+    UserPasskey::record_use(
+        db,
+        user.id,
+        result.cred_id().0.clone(),
+        updated.and_then(|v| serde_json::to_string(&v).ok()),
+    )
+    .await?;
+
+    Ok(true)
+}
+
+/// Starts authenticating the user with their passkeys, returning `None` if they don't have any.
+pub(super) async fn start_authentication(
+    db: ConnectionPool,
+    webauthn: &Webauthn,
+    user: &User,
+) -> Result<Option<(RequestChallengeResponse, PasskeyAuthentication)>, Error> {
+    let passkeys = passkeys::decode(&UserPasskey::list(db, user.id).await?);
+
+    if passkeys.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(passkeys::start_authentication(webauthn, &passkeys)?))
+}
+
+/// Registering and removing passkeys can't be done by scoped sessions, such as API tokens with
+/// limited permissions.
+fn ensure_unscoped(scope: &TokenScope) -> Result<(), Error> {
+    if *scope == TokenScope::default() {
+        Ok(())
+    } else {
+        Err(Error::ScopedSession)
+    }
+}
+
+/// Starts a passwordless login for the given user.
+pub async fn handle_login_begin(
+    extract::Extension(config): extract::Extension<Arc<Config>>,
+    extract::Extension(webauthn): extract::Extension<Arc<Webauthn>>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Json(req): extract::Json<LoginBeginRequest>,
+) -> Result<Json<ChallengeResponse<RequestChallengeResponse>>, Error> {
+    let user = User::find_by_username(db.clone(), req.username)
+        .await?
+        .ok_or(Error::NoPasskeys)?;
+
+    let (options, state) = start_authentication(db, &webauthn, &user)
+        .await?
+        .ok_or(Error::NoPasskeys)?;
+
+    Ok(Json(ChallengeResponse {
+        challenge: seal(&user, state, &config)?,
+        options,
+    }))
+}
+
+/// Completes a passwordless login given the authenticator's response.
+pub async fn handle_login(
+    extract::Extension(config): extract::Extension<Arc<Config>>,
+    extract::Extension(webauthn): extract::Extension<Arc<Webauthn>>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Json(req): extract::Json<LoginRequest>,
+    user_agent: Option<extract::TypedHeader<headers::UserAgent>>,
+    addr: extract::Extension<IpAddr>,
+) -> Result<Json<super::LoginResponse>, Error> {
+    let (user_uuid, state): (_, PasskeyAuthentication) =
+        open(db.clone(), &req.challenge, &config).await?;
+
+    let user = User::find_by_uuid(db.clone(), user_uuid)
+        .await?
+        .ok_or(Error::InvalidChallenge)?;
+
+    if user.is_locked() {
+        return Err(Error::Locked);
+    }
+
+    user.ensure_enabled()?;
+
+    if !verify(db.clone(), &webauthn, &user, &req.credential, &state).await? {
+        return Err(Error::InvalidCredential);
+    }
+
+    user.clear_failed_logins(db.clone()).await?;
+
+    Ok(Json(super::login(db, user, None, user_agent, addr).await?))
+}
+
+/// Lists the passkeys the user has registered.
+pub async fn handle_list(
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+) -> Result<Json<ListResponse>, Error> {
+    let passkeys = UserPasskey::list(db, user.id)
+        .await?
+        .into_iter()
+        .map(ResponsePasskey::from)
+        .collect();
+
+    Ok(Json(ListResponse { passkeys }))
+}
+
+/// Starts registering a new passkey for the user.
+pub async fn handle_register_begin(
+    extract::Extension(config): extract::Extension<Arc<Config>>,
+    extract::Extension(webauthn): extract::Extension<Arc<Webauthn>>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
+) -> Result<Json<ChallengeResponse<CreationChallengeResponse>>, Error> {
+    ensure_unscoped(&scope)?;
+
+    let existing = passkeys::decode(&UserPasskey::list(db, user.id).await?);
+    let (options, state) = passkeys::start_registration(&webauthn, &user, &existing)?;
+
+    Ok(Json(ChallengeResponse {
+        challenge: seal(&user, state, &config)?,
+        options,
+    }))
+}
+
+/// Completes registration given the authenticator's response, storing the new passkey.
+pub async fn handle_register(
+    extract::Extension(config): extract::Extension<Arc<Config>>,
+    extract::Extension(webauthn): extract::Extension<Arc<Webauthn>>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
+    extract::Json(req): extract::Json<RegisterRequest>,
+) -> Result<Json<ResponsePasskey>, Error> {
+    ensure_unscoped(&scope)?;
+
+    let name = req.name.trim().to_string();
+    if name.is_empty() {
+        return Err(Error::MissingName);
+    }
+
+    let (user_uuid, state): (_, PasskeyRegistration) =
+        open(db.clone(), &req.challenge, &config).await?;
+
+    // challenges are only valid for the user that started the ceremony
+    if user_uuid != user.uuid.0 {
+        return Err(Error::InvalidChallenge);
+    }
+
+    let passkey = passkeys::finish_registration(&webauthn, &req.credential, &state)
+        .map_err(|_| Error::InvalidCredential)?;
+
+    let inserted = UserPasskey::insert(
+        db,
+        user.id,
+        &actor,
+        name,
+        passkey.cred_id().0.clone(),
+        serde_json::to_string(&passkey).map_err(|_| Error::InvalidCredential)?,
+    )
+    .await?;
+
+    Ok(Json(inserted.into()))
+}
+
+/// Removes one of the user's passkeys.
+pub async fn handle_delete(
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
+    extract::Path(passkey_id): extract::Path<Uuid>,
+) -> Result<Json<ErrorResponse>, Error> {
+    ensure_unscoped(&scope)?;
+
+    if UserPasskey::delete_by_uuid(db, user.id, &actor, passkey_id).await? {
+        Ok(Json(ErrorResponse { error: None }))
+    } else {
+        Err(Error::NonExistentPasskey)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LoginBeginRequest {
+    username: String,
+}
+
+#[derive(Deserialize)]
+pub struct LoginRequest {
+    challenge: String,
+    credential: PublicKeyCredential,
+}
+
+#[derive(Deserialize)]
+pub struct RegisterRequest {
+    challenge: String,
+    name: String,
+    credential: RegisterPublicKeyCredential,
+}
+
+#[derive(Serialize)]
+pub struct ChallengeResponse<T> {
+    /// Sent back along with the authenticator's response to complete the ceremony.
+    challenge: String,
+    /// Options to pass to `navigator.credentials` in the browser.
+    options: T,
+}
+
+#[derive(Serialize)]
+pub struct ListResponse {
+    passkeys: Vec<ResponsePasskey>,
+}
+
+#[derive(Serialize)]
+pub struct ResponsePasskey {
+    uuid: Uuid,
+    name: String,
+    last_used_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+}
+
+impl From<UserPasskey> for ResponsePasskey {
+    fn from(passkey: UserPasskey) -> Self {
+        Self {
+            uuid: passkey.uuid.0,
+            name: passkey.name,
+            last_used_at: passkey.last_used_at.map(|v| Utc.from_utc_datetime(&v)),
+            created_at: Utc.from_utc_datetime(&passkey.created_at),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Database(#[from] chartered_db::Error),
+    #[error("Failed to start WebAuthn ceremony: {0}")]
+    WebAuthn(#[from] WebauthnError),
+    #[error("Invalid passkey challenge")]
+    InvalidChallenge,
+    #[error("Passkey challenge expired, please try again")]
+    ExpiredChallenge,
+    #[error("Passkey challenge has already been used, please try again")]
+    UsedChallenge,
+    #[error("Passkey could not be verified")]
+    InvalidCredential,
+    #[error("No passkeys are registered for this account")]
+    NoPasskeys,
+    #[error("Too many failed login attempts, try again later")]
+    Locked,
+    #[error("A name must be given for the passkey")]
+    MissingName,
+    #[error("The passkey given does not exist")]
+    NonExistentPasskey,
+    #[error("Passkeys can't be managed using a scoped session")]
+    ScopedSession,
+}
+
+impl Error {
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        use axum::http::StatusCode;
+
+        match self {
+            Self::Database(e) => e.status_code(),
+            Self::WebAuthn(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::InvalidChallenge
+            | Self::NoPasskeys
+            | Self::MissingName
+            | Self::NonExistentPasskey => StatusCode::BAD_REQUEST,
+            Self::ExpiredChallenge
+            | Self::UsedChallenge
+            | Self::InvalidCredential
+            | Self::Locked
+            | Self::ScopedSession => StatusCode::FORBIDDEN,
+        }
+    }
+}
+
+define_error_response!(Error);
+
+#[cfg(all(test, feature = "sqlite"))]
+mod test {
+    use super::{
+        handle_login, handle_login_begin, handle_register, handle_register_begin, Error,
+        LoginBeginRequest, LoginRequest, RegisterRequest,
+    };
+    use crate::testing;
+    use axum::extract::{Extension, Json};
+    use chartered_db::{audit::Actor, scopes::TokenScope};
+    use std::{net::IpAddr, sync::Arc};
+    use webauthn_authenticator_rs::{softpasskey::SoftPasskey, WebauthnAuthenticator};
+
+    #[tokio::test]
+    async fn login_challenges_can_only_be_used_once() {
+        let config = testing::config();
+        let webauthn = testing::webauthn(&config);
+        let db = testing::pool();
+        let user = testing::user(&db, "jordan").await;
+        let mut authenticator = WebauthnAuthenticator::new(SoftPasskey::new());
+
+        let Json(begin) = handle_register_begin(
+            Extension(config.clone()),
+            Extension(webauthn.clone()),
+            Extension(db.clone()),
+            Extension(user.clone()),
+            Extension(Arc::new(TokenScope::default())),
+        )
+        .await
+        .unwrap();
+        let credential = authenticator
+            .do_registration(config.frontend_base_uri.clone(), begin.options)
+            .unwrap();
+        handle_register(
+            Extension(config.clone()),
+            Extension(webauthn.clone()),
+            Extension(db.clone()),
+            Extension(user.clone()),
+            Extension(Arc::new(Actor::default())),
+            Extension(Arc::new(TokenScope::default())),
+            Json(RegisterRequest {
+                challenge: begin.challenge,
+                name: "laptop".to_string(),
+                credential,
+            }),
+        )
+        .await
+        .unwrap();
+
+        let Json(begin) = handle_login_begin(
+            Extension(config.clone()),
+            Extension(webauthn.clone()),
+            Extension(db.clone()),
+            Json(LoginBeginRequest {
+                username: "jordan".to_string(),
+            }),
+        )
+        .await
+        .unwrap();
+        let credential = authenticator
+            .do_authentication(config.frontend_base_uri.clone(), begin.options)
+            .unwrap();
+
+        let login = || {
+            handle_login(
+                Extension(config.clone()),
+                Extension(webauthn.clone()),
+                Extension(db.clone()),
+                Json(LoginRequest {
+                    challenge: begin.challenge.clone(),
+                    credential: credential.clone(),
+                }),
+                None,
+                Extension(IpAddr::from([127, 0, 0, 1])),
+            )
+        };
+
+        assert!(login().await.is_ok());
+        assert!(matches!(login().await, Err(Error::UsedChallenge)));
+    }
+}
@@ -3,9 +3,10 @@
 use crate::config::Config;
 
 use axum::{extract, Json};
-use chartered_db::{users::User, ConnectionPool};
+use chartered_db::{audit::Actor, scopes::TokenScope, users::User, ConnectionPool};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use webauthn_rs::prelude::{RequestChallengeResponse, Webauthn};
 
 use std::{net::IpAddr, sync::Arc};
 
@@ -34,11 +35,12 @@ pub async fn handle_register(
 
 pub async fn handle_login(
     extract::Extension(config): extract::Extension<Arc<Config>>,
+    extract::Extension(webauthn): extract::Extension<Arc<Webauthn>>,
     extract::Extension(db): extract::Extension<ConnectionPool>,
     extract::Json(req): extract::Json<LoginRequest>,
     user_agent: Option<extract::TypedHeader<headers::UserAgent>>,
     addr: extract::Extension<IpAddr>,
-) -> Result<Json<super::LoginResponse>, LoginError> {
+) -> Result<Json<LoginResponse>, LoginError> {
     // some basic validation before we attempt a login
     if !config.auth.password.enabled {
         return Err(LoginError::PasswordAuthDisabled);
@@ -58,11 +60,79 @@ pub async fn handle_login(
         // password is nullable for openid logins
         .ok_or(LoginError::UnknownUser)?;
 
-    if bcrypt::verify(&req.password, password_hash)? {
-        Ok(Json(super::login(db, user, user_agent, addr).await?))
-    } else {
-        Err(LoginError::InvalidPassword)
+    let actor = Actor {
+        user_id: None,
+        ip: Some(addr.0.to_string()),
+        user_agent: user_agent
+            .as_ref()
+            .map(|extract::TypedHeader(v)| v.as_str().to_string()),
+    };
+
+    // don't even attempt to verify the password whilst the user is locked out, otherwise the
+    // lockout does nothing to slow down brute forcing
+    match user.claim_login_attempt(db.clone(), &actor).await {
+        Err(chartered_db::Error::UserLocked) => return Err(LoginError::Locked),
+        v => v?,
+    }
+
+    if !bcrypt::verify(&req.password, password_hash)? {
+        user.record_failed_login(db, &actor).await?;
+
+        return Err(LoginError::InvalidPassword);
     }
+
+    if user.is_disabled() {
+        // the password was correct, so the attempt mustn't count towards the lockout
+        user.release_login_attempt(db).await?;
+
+        return Err(LoginError::Disabled);
+    }
+
+    Ok(Json(
+        login_or_challenge(&config, &webauthn, db, user, req.scope, user_agent, addr).await?,
+    ))
+}
+
+/// Logs in a user that's given a valid password, or hands them a challenge to complete with
+/// their second factor if they've enabled two-factor authentication. Their failed logins are
+/// only reset once the login is complete, so wrong codes still count towards the lockout.
+/// The session is restricted to `scope` if one is given, which is carried through the challenge.
+pub(super) async fn login_or_challenge(
+    config: &Config,
+    webauthn: &Webauthn,
+    db: ConnectionPool,
+    user: User,
+    scope: Option<TokenScope>,
+    user_agent: Option<extract::TypedHeader<headers::UserAgent>>,
+    addr: extract::Extension<IpAddr>,
+) -> Result<LoginResponse, LoginError> {
+    // users with two-factor authentication enabled need to complete the login by giving a
+    // code, or using one of their passkeys, along with the challenge
+    if user.has_two_factor(db.clone()).await? {
+        user.release_login_attempt(db.clone()).await?;
+
+        let (passkey, passkey_state) =
+            match super::passkeys::start_authentication(db.clone(), webauthn, &user)
+                .await
+                .map_err(|_| LoginError::Challenge)?
+            {
+                Some((options, state)) => (Some(options), Some(state)),
+                None => (None, None),
+            };
+
+        return Ok(LoginResponse::TwoFactorRequired {
+            two_factor_required: true,
+            challenge: super::two_factor::create_challenge(&user, config, passkey_state, scope)
+                .map_err(|_| LoginError::Challenge)?,
+            passkey,
+        });
+    }
+
+    user.clear_failed_logins(db.clone()).await?;
+
+    Ok(LoginResponse::Login(
+        super::login(db, user, scope, user_agent, addr).await?,
+    ))
 }
 
 pub fn validate_username(username: &str) -> bool {
@@ -82,6 +152,24 @@ pub struct RegisterRequest {
 pub struct LoginRequest {
     username: String,
     password: String,
+    /// Restricts the session to a subset of what the user can do, ie. for scripts that only
+    /// need to read a single crate.
+    #[serde(default)]
+    scope: Option<TokenScope>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Login(super::LoginResponse),
+    TwoFactorRequired {
+        two_factor_required: bool,
+        /// Sent back to `/login/two-factor` along with the user's code to complete the login.
+        challenge: String,
+        /// Options to pass to `navigator.credentials` if the user would rather use one of their
+        /// passkeys than give a code.
+        passkey: Option<RequestChallengeResponse>,
+    },
 }
 
 #[derive(Serialize)]
@@ -133,6 +221,12 @@ pub enum LoginError {
     InvalidPassword,
     #[error("Password authentication is disabled")]
     PasswordAuthDisabled,
+    #[error("This account has been disabled")]
+    Disabled,
+    #[error("Too many failed login attempts, try again later")]
+    Locked,
+    #[error("Failed to create two-factor authentication challenge")]
+    Challenge,
 }
 
 impl LoginError {
@@ -140,12 +234,282 @@ impl LoginError {
         use axum::http::StatusCode;
 
         match self {
-            Self::Database(_) | Self::Bcrypt(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            Self::UnknownUser | Self::InvalidPassword | Self::PasswordAuthDisabled => {
-                StatusCode::FORBIDDEN
+            Self::Database(_) | Self::Bcrypt(_) | Self::Challenge => {
+                StatusCode::INTERNAL_SERVER_ERROR
             }
+            Self::UnknownUser
+            | Self::InvalidPassword
+            | Self::PasswordAuthDisabled
+            | Self::Disabled
+            | Self::Locked => StatusCode::FORBIDDEN,
         }
     }
 }
 
 define_error_response!(LoginError);
+
+#[cfg(all(test, feature = "sqlite"))]
+mod test {
+    use super::{handle_login, LoginError, LoginRequest, LoginResponse};
+    use crate::{endpoints::web_api::auth::two_factor, testing, totp};
+    use axum::extract::{Extension, Json};
+    use chartered_db::{
+        audit::Actor,
+        passkeys::UserPasskey,
+        permissions::UserPermission,
+        scopes::TokenScope,
+        users::{User, MAX_FAILED_LOGIN_ATTEMPTS},
+        ConnectionPool,
+    };
+    use std::{net::IpAddr, sync::Arc};
+
+    struct Server {
+        config: Arc<crate::config::Config>,
+        webauthn: Arc<webauthn_rs::prelude::Webauthn>,
+        db: ConnectionPool,
+    }
+
+    impl Server {
+        fn new() -> Self {
+            let config = testing::config();
+
+            Self {
+                webauthn: testing::webauthn(&config),
+                config,
+                db: testing::pool(),
+            }
+        }
+
+        /// Logs in with the correct password, returning the two-factor challenge.
+        async fn password(&self, username: &str) -> Result<String, LoginError> {
+            let res = handle_login(
+                Extension(self.config.clone()),
+                Extension(self.webauthn.clone()),
+                Extension(self.db.clone()),
+                Json(LoginRequest {
+                    username: username.to_string(),
+                    password: "password".to_string(),
+                    scope: None,
+                }),
+                None,
+                Extension(IpAddr::from([127, 0, 0, 1])),
+            )
+            .await?;
+
+            match res.0 {
+                LoginResponse::TwoFactorRequired { challenge, .. } => Ok(challenge),
+                LoginResponse::Login(_) => panic!("two-factor authentication wasn't required"),
+            }
+        }
+
+        async fn code(&self, challenge: String, code: &str) -> Result<(), two_factor::Error> {
+            let req = serde_json::from_value(serde_json::json!({
+                "challenge": challenge,
+                "code": code,
+            }))
+            .unwrap();
+
+            two_factor::handle_login(
+                Extension(self.config.clone()),
+                Extension(self.webauthn.clone()),
+                Extension(self.db.clone()),
+                Json(req),
+                None,
+                Extension(IpAddr::from([127, 0, 0, 1])),
+            )
+            .await
+            .map(|_| ())
+        }
+
+        async fn failed_login_attempts(&self, user: &User) -> i32 {
+            User::find_by_uuid(self.db.clone(), user.uuid.0)
+                .await
+                .unwrap()
+                .unwrap()
+                .failed_login_attempts
+        }
+    }
+
+    #[tokio::test]
+    async fn wrong_codes_count_towards_the_lockout() {
+        let server = Server::new();
+        let user = testing::user(&server.db, "user").await;
+
+        // enrol as of the previous step, so the current step's code hasn't been used yet
+        let secret = totp::generate_secret();
+        let step = totp::step_at(chrono::Utc::now().timestamp());
+        user.begin_totp_enrolment(server.db.clone(), secret.clone())
+            .await
+            .unwrap();
+        user.enable_totp(server.db.clone(), &Actor::default(), step - 1)
+            .await
+            .unwrap();
+
+        // a correct code completes the login and resets any failures
+        let challenge = server.password("user").await.unwrap();
+        assert!(server.code(challenge, "wrong").await.is_err());
+        assert_eq!(server.failed_login_attempts(&user).await, 1);
+
+        let challenge = server.password("user").await.unwrap();
+        server
+            .code(challenge, &totp::code_for_step(&secret, step))
+            .await
+            .unwrap();
+        assert_eq!(server.failed_login_attempts(&user).await, 0);
+
+        // giving the password again doesn't reset the wrong codes given before it
+        for attempt in 1..MAX_FAILED_LOGIN_ATTEMPTS {
+            let challenge = server.password("user").await.unwrap();
+            assert!(server.code(challenge, "wrong").await.is_err());
+            assert_eq!(server.failed_login_attempts(&user).await, attempt);
+        }
+
+        let challenge = server.password("user").await.unwrap();
+        assert!(matches!(
+            server.code(challenge, "wrong").await,
+            Err(two_factor::Error::InvalidCode)
+        ));
+
+        assert!(matches!(
+            server.password("user").await,
+            Err(LoginError::Locked)
+        ));
+    }
+
+    #[tokio::test]
+    async fn wrong_codes_when_disabling_count_towards_the_lockout() {
+        let server = Server::new();
+        let user = testing::user(&server.db, "user").await;
+
+        let secret = totp::generate_secret();
+        let step = totp::step_at(chrono::Utc::now().timestamp());
+        user.begin_totp_enrolment(server.db.clone(), secret.clone())
+            .await
+            .unwrap();
+        user.enable_totp(server.db.clone(), &Actor::default(), step - 1)
+            .await
+            .unwrap();
+
+        let user = Arc::new(
+            User::find_by_uuid(server.db.clone(), user.uuid.0)
+                .await
+                .unwrap()
+                .unwrap(),
+        );
+
+        let disable = |code: &str| {
+            let req = serde_json::from_value(serde_json::json!({ "code": code })).unwrap();
+
+            two_factor::handle_delete(
+                Extension(server.db.clone()),
+                Extension(user.clone()),
+                Extension(Arc::new(Actor::default())),
+                Extension(Arc::new(Default::default())),
+                Json(req),
+            )
+        };
+
+        for attempt in 1..=MAX_FAILED_LOGIN_ATTEMPTS {
+            assert!(matches!(
+                disable("wrong").await,
+                Err(two_factor::Error::InvalidCode)
+            ));
+            assert_eq!(server.failed_login_attempts(&user).await, attempt);
+        }
+
+        // even the correct code is refused once the user is locked out
+        assert!(matches!(
+            disable(&totp::code_for_step(&secret, step)).await,
+            Err(two_factor::Error::Locked)
+        ));
+    }
+
+    #[tokio::test]
+    async fn disabled_users_with_the_right_password_arent_locked_out() {
+        let server = Server::new();
+        let user = testing::disabled_user(&server.db, "user").await;
+
+        for _ in 0..=MAX_FAILED_LOGIN_ATTEMPTS {
+            assert!(matches!(
+                server.password("user").await,
+                Err(LoginError::Disabled)
+            ));
+            assert_eq!(server.failed_login_attempts(&user).await, 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn scoped_logins_create_scoped_sessions() {
+        let server = Server::new();
+        testing::user(&server.db, "user").await;
+
+        let scope = TokenScope {
+            permissions: UserPermission::VISIBLE,
+            crates: vec!["my-crate".to_string()],
+            ..TokenScope::default()
+        };
+
+        let res = handle_login(
+            Extension(server.config.clone()),
+            Extension(server.webauthn.clone()),
+            Extension(server.db.clone()),
+            Json(LoginRequest {
+                username: "user".to_string(),
+                password: "password".to_string(),
+                scope: Some(scope.clone()),
+            }),
+            None,
+            Extension(IpAddr::from([127, 0, 0, 1])),
+        )
+        .await
+        .unwrap();
+
+        let key = match res.0 {
+            LoginResponse::Login(v) => v.key,
+            LoginResponse::TwoFactorRequired { .. } => {
+                panic!("two-factor authentication was required")
+            }
+        };
+
+        let (session, user) = User::find_by_session_key(server.db.clone(), key)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.scope(), scope);
+
+        // the scope is enforced, so the session can't be used to manage the user's account
+        assert!(matches!(
+            two_factor::handle_enrol(
+                Extension(server.db.clone()),
+                Extension(Arc::new(user)),
+                Extension(Arc::new(session.scope())),
+            )
+            .await,
+            Err(two_factor::Error::ScopedSession)
+        ));
+    }
+
+    #[tokio::test]
+    async fn passkeys_require_a_second_factor() {
+        let server = Server::new();
+        let user = testing::user(&server.db, "user").await;
+
+        UserPasskey::insert(
+            server.db.clone(),
+            user.id,
+            &Actor::default(),
+            "laptop".to_string(),
+            vec![1, 2, 3],
+            "{}".to_string(),
+        )
+        .await
+        .unwrap();
+
+        // the user hasn't enrolled in TOTP, but a password alone isn't enough to log in
+        let challenge = server.password("user").await.unwrap();
+        assert!(matches!(
+            server.code(challenge, "000000").await,
+            Err(two_factor::Error::InvalidCode)
+        ));
+    }
+}
@@ -0,0 +1,430 @@
+//! SAML 2.0 single sign-on. The frontend grabs a URL to send the user to the IdP with from
+//! `begin`, the IdP then posts its response straight back to our assertion consumer service,
+//! which creates or links the user and hands the frontend a short-lived ticket it can exchange
+//! for a session with `complete`.
+//!
+//! Request IDs, assertion IDs and tickets can each only be used once, and a response is only
+//! accepted from the browser that started the login, so responses can't be replayed or
+//! planted in someone else's browser.
+//!
+//! Users are created on their first login with the username `provider:NameID`, the same way as
+//! `OpenID` Connect logins.
+
+use super::openid::{decrypt_url_safe, encrypt_url_safe};
+use crate::{
+    config::{Config, SamlProviders},
+    group_mappings, saml,
+};
+
+use axum::{
+    extract,
+    http::header,
+    response::{IntoResponse, Redirect},
+    Json,
+};
+use chartered_db::{
+    organisations::Organisation, used_challenges::UsedChallenge, users::User, ConnectionPool,
+};
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use std::{net::IpAddr, sync::Arc};
+
+/// How long the user has to complete their login at the IdP, in seconds.
+const REQUEST_EXPIRY: i64 = 600;
+
+/// How long the frontend has to exchange a ticket for a session, in seconds.
+const TICKET_EXPIRY: i64 = 60;
+
+/// Prefixed to the data we encrypt so a `RelayState` can't be passed off as a ticket, or the
+/// other way around.
+const RELAY_STATE_KIND: u8 = 0;
+const TICKET_KIND: u8 = 1;
+
+/// Cookie holding the secret the login's `RelayState` is bound to, scoped to the provider's
+/// assertion consumer service.
+const BINDING_COOKIE: &str = "chartered_saml_binding";
+
+/// Serves our metadata, which the IdP can be configured with instead of being given our entity
+/// ID and assertion consumer service by hand.
+pub async fn handle_metadata(
+    extract::Path(provider): extract::Path<String>,
+    extract::Extension(saml_providers): extract::Extension<Arc<SamlProviders>>,
+) -> Result<impl IntoResponse, Error> {
+    let sp = saml_providers
+        .get(&provider)
+        .ok_or(Error::UnknownProvider)?;
+
+    let metadata = sp
+        .metadata()
+        .map_err(|e| Error::Metadata(e.to_string()))?
+        .to_xml()
+        .map_err(|e| Error::Metadata(e.to_string()))?;
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/samlmetadata+xml")],
+        metadata,
+    ))
+}
+
+/// Starts the login, returning the URL of the IdP the frontend should redirect the user to.
+///
+/// Nothing is stored server-side, instead the `RelayState` we hand the IdP is an encrypted
+/// nonce, which the ID of our `AuthnRequest` is derived from so the response can be tied back to
+/// it. The nonce is itself a hash of a secret handed to the browser in a cookie, so only the
+/// browser that started the login can complete it. `RelayState` is limited to 80 bytes, so it's
+/// kept in a fixed binary format rather than JSON.
+#[allow(clippy::unused_async)]
+pub async fn handle_begin(
+    extract::Path(provider): extract::Path<String>,
+    extract::Extension(config): extract::Extension<Arc<Config>>,
+    extract::Extension(saml_providers): extract::Extension<Arc<SamlProviders>>,
+) -> Result<impl IntoResponse, Error> {
+    let sp = saml_providers
+        .get(&provider)
+        .ok_or(Error::UnknownProvider)?;
+
+    let binding = rand::random::<[u8; 16]>();
+    let nonce = binding_nonce(&binding);
+    let expires = Utc::now().timestamp() + REQUEST_EXPIRY;
+    let relay_state = seal(RELAY_STATE_KIND, &nonce, expires, &config)?;
+
+    Ok((
+        [(
+            header::SET_COOKIE,
+            binding_cookie(&config, &provider, &hex::encode(binding), REQUEST_EXPIRY)?,
+        )],
+        Json(BeginResponse {
+            redirect_url: saml::authn_request_url(sp, &request_id(&nonce), &relay_state)?,
+        }),
+    ))
+}
+
+/// Assertion consumer service the IdP posts its response to, validates the response and then
+/// sends the user back to the frontend with a ticket to complete their login with.
+pub async fn handle_acs(
+    extract::Path(provider): extract::Path<String>,
+    extract::Extension(config): extract::Extension<Arc<Config>>,
+    extract::Extension(saml_providers): extract::Extension<Arc<SamlProviders>>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    cookies: Option<extract::TypedHeader<headers::Cookie>>,
+    extract::Form(req): extract::Form<AcsRequest>,
+) -> Result<impl IntoResponse, Error> {
+    let (sp, provider_config) = saml_providers
+        .get(&provider)
+        .zip(config.auth.saml.get(&provider))
+        .ok_or(Error::UnknownProvider)?;
+
+    // we never start IdP-initiated logins, so a response without our state is unsolicited
+    let (nonce, expires) = open(
+        RELAY_STATE_KIND,
+        req.relay_state.as_deref().ok_or(Error::InvalidState)?,
+        &config,
+    )?;
+
+    // a response for a login started in another browser, such as one an attacker has posted
+    // here to log the user in to the attacker's account
+    let binding = cookies
+        .as_ref()
+        .and_then(|extract::TypedHeader(v)| v.get(BINDING_COOKIE))
+        .and_then(|v| hex::decode(v).ok())
+        .ok_or(Error::InvalidState)?;
+    if binding_nonce(&binding) != nonce {
+        return Err(Error::InvalidState);
+    }
+
+    let request_id = request_id(&nonce);
+    let saml_user = saml::verify(sp, provider_config, &req.saml_response, &request_id)?;
+
+    // each request can only be answered once, and each assertion only used once, until they'd
+    // have expired anyway
+    let request_expires_at = NaiveDateTime::from_timestamp(expires, 0);
+    if !claim(
+        db.clone(),
+        &format!("saml-request:{}", request_id),
+        request_expires_at,
+    )
+    .await?
+    {
+        return Err(Error::InvalidState);
+    }
+
+    let assertion_expires_at = saml_user
+        .assertion_expires_at
+        .map_or(request_expires_at, |v| v.naive_utc());
+    if !claim(
+        db.clone(),
+        &format!("saml-assertion:{}:{}", provider, saml_user.assertion_id),
+        assertion_expires_at,
+    )
+    .await?
+    {
+        return Err(Error::InvalidState);
+    }
+
+    let user = User::find_or_create(
+        db.clone(),
+        // we're using `provider:uid` as the format for SAML logins, the same as OIDC logins
+        format!("{}:{}", provider, saml_user.id),
+        saml_user.name,
+        None,
+        saml_user.email,
+        None,
+        None,
+    )
+    .await?;
+
+    // the IdP knowing about the user doesn't mean they're allowed in
+    user.ensure_enabled()?;
+
+    // an IdP that leaves the attribute out tells us nothing about the user's groups, rather
+    // than that they're in none
+    if let Some(groups) = saml_user
+        .groups
+        .filter(|_| !provider_config.groups.is_empty())
+    {
+        Organisation::reconcile_memberships(
+            db,
+            user.id,
+            group_mappings::memberships(&provider_config.groups, &groups),
+        )
+        .await?;
+    }
+
+    let ticket = seal(
+        TICKET_KIND,
+        user.uuid.0.as_bytes(),
+        Utc::now().timestamp() + TICKET_EXPIRY,
+        &config,
+    )?;
+
+    let mut redirect = config.frontend_base_uri.join("login/saml")?;
+    redirect.query_pairs_mut().append_pair("ticket", &ticket);
+
+    Ok((
+        [(
+            header::SET_COOKIE,
+            binding_cookie(&config, &provider, "", 0)?,
+        )],
+        Redirect::to(redirect.as_str()),
+    ))
+}
+
+/// Exchanges the ticket handed to the frontend by the assertion consumer service for a session.
+pub async fn handle_complete(
+    extract::Query(params): extract::Query<CompleteParams>,
+    extract::Extension(config): extract::Extension<Arc<Config>>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    user_agent: Option<extract::TypedHeader<headers::UserAgent>>,
+    addr: extract::Extension<IpAddr>,
+) -> Result<Json<super::LoginResponse>, Error> {
+    let (user_uuid, expires) = open(TICKET_KIND, &params.ticket, &config)?;
+    let user_uuid = chartered_db::uuid::Uuid::from_bytes(user_uuid);
+
+    // tickets end up in the frontend's URL, so could be picked up from the browser's history
+    if !claim(
+        db.clone(),
+        &format!("saml-ticket:{}", params.ticket),
+        NaiveDateTime::from_timestamp(expires, 0),
+    )
+    .await?
+    {
+        return Err(Error::InvalidState);
+    }
+
+    let user = User::find_by_uuid(db.clone(), user_uuid)
+        .await?
+        .ok_or(Error::InvalidState)?;
+
+    // the user might have been disabled since their ticket was issued
+    user.ensure_enabled()?;
+
+    Ok(Json(super::login(db, user, None, user_agent, addr).await?))
+}
+
+/// The ID we give our `AuthnRequest`, which needs to start with a letter or underscore.
+fn request_id(nonce: &[u8; 16]) -> String {
+    format!("_{}", hex::encode(nonce))
+}
+
+/// Derives the nonce for a login from the secret handed to the browser that started it.
+fn binding_nonce(binding: &[u8]) -> [u8; 16] {
+    let mut nonce = [0_u8; 16];
+    nonce.copy_from_slice(&Sha256::digest(binding)[..16]);
+    nonce
+}
+
+/// Builds the `Set-Cookie` header for the binding cookie. The IdP posts its response to us
+/// cross-site, so the cookie has to be `SameSite=None` to be sent along with it.
+fn binding_cookie(
+    config: &Config,
+    provider: &str,
+    value: &str,
+    max_age: i64,
+) -> Result<String, Error> {
+    let web_base_uri = config.web_base_uri.as_ref().ok_or(Error::UnknownProvider)?;
+
+    Ok(format!(
+        "{}={}; Path={}; Max-Age={}; HttpOnly; Secure; SameSite=None",
+        BINDING_COOKIE,
+        value,
+        saml::acs_url(web_base_uri, provider)?.path(),
+        max_age,
+    ))
+}
+
+/// Marks `id` as used until `expires_at`, returning `false` if it's been used before.
+async fn claim(
+    db: ConnectionPool,
+    id: &str,
+    expires_at: NaiveDateTime,
+) -> Result<bool, chartered_db::Error> {
+    UsedChallenge::claim(db, hex::encode(Sha256::digest(id.as_bytes())), expires_at).await
+}
+
+/// Encrypts the 16 byte `data` along with what it is and when it expires.
+fn seal(kind: u8, data: &[u8; 16], expires: i64, config: &Config) -> Result<String, Error> {
+    let mut plaintext = vec![kind];
+    plaintext.extend_from_slice(data);
+    plaintext.extend_from_slice(&expires.to_be_bytes());
+
+    Ok(encrypt_url_safe(&plaintext, config)?)
+}
+
+/// Decrypts data created by `seal`, checking it's of the expected kind and hasn't expired,
+/// returning the data along with when it expires.
+fn open(kind: u8, input: &str, config: &Config) -> Result<([u8; 16], i64), Error> {
+    let plaintext = decrypt_url_safe(input, config).map_err(|_| Error::InvalidState)?;
+    let plaintext = <[u8; 25]>::try_from(plaintext).map_err(|_| Error::InvalidState)?;
+
+    let mut data = [0_u8; 16];
+    data.copy_from_slice(&plaintext[1..17]);
+
+    let mut expires = [0_u8; 8];
+    expires.copy_from_slice(&plaintext[17..]);
+    let expires = i64::from_be_bytes(expires);
+
+    if plaintext[0] != kind || expires < Utc::now().timestamp() {
+        return Err(Error::InvalidState);
+    }
+
+    Ok((data, expires))
+}
+
+#[derive(Serialize)]
+pub struct BeginResponse {
+    redirect_url: String,
+}
+
+#[derive(Deserialize)]
+pub struct AcsRequest {
+    #[serde(rename = "SAMLResponse")]
+    saml_response: String,
+    #[serde(rename = "RelayState")]
+    relay_state: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CompleteParams {
+    ticket: String,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Database(#[from] chartered_db::Error),
+    #[error("Unknown SAML provider given")]
+    UnknownProvider,
+    #[error("Login request was invalid or has expired, please try again")]
+    InvalidState,
+    #[error("{0}")]
+    Saml(#[from] saml::Error),
+    #[error("Failed to build metadata: {0}")]
+    Metadata(String),
+    #[error("{0}")]
+    Encryption(#[from] super::openid::Error),
+    #[error("Failed to build URL: {0}")]
+    Parse(#[from] url::ParseError),
+}
+
+impl Error {
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        use axum::http::StatusCode;
+
+        match self {
+            Self::Database(e) => e.status_code(),
+            Self::UnknownProvider => StatusCode::NOT_FOUND,
+            Self::InvalidState
+            | Self::Saml(saml::Error::InvalidResponse(_) | saml::Error::MissingNameId) => {
+                StatusCode::BAD_REQUEST
+            }
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+define_error_response!(Error);
+
+#[cfg(all(test, feature = "sqlite"))]
+mod test {
+    use super::{handle_complete, seal, CompleteParams, Error, TICKET_EXPIRY, TICKET_KIND};
+    use crate::testing;
+    use axum::extract::{Extension, Query};
+    use std::net::IpAddr;
+
+    #[tokio::test]
+    async fn tickets_can_only_be_used_once() {
+        let config = testing::config();
+        let db = testing::pool();
+        let user = testing::user(&db, "jordan").await;
+
+        let ticket = seal(
+            TICKET_KIND,
+            user.uuid.0.as_bytes(),
+            chrono::Utc::now().timestamp() + TICKET_EXPIRY,
+            &config,
+        )
+        .unwrap();
+
+        let complete = || {
+            handle_complete(
+                Query(CompleteParams {
+                    ticket: ticket.clone(),
+                }),
+                Extension(config.clone()),
+                Extension(db.clone()),
+                None,
+                Extension(IpAddr::from([127, 0, 0, 1])),
+            )
+        };
+
+        assert!(complete().await.is_ok());
+        assert!(matches!(complete().await, Err(Error::InvalidState)));
+    }
+
+    #[test]
+    fn binding_cookie_is_sent_cross_site_to_the_acs_only() {
+        let config: crate::config::Config = toml::from_str(
+            r#"
+            bind_address = "127.0.0.1:8888"
+            database_uri = "sqlite://:memory:"
+            storage_uri = "file:///tmp/chartered"
+            frontend_base_uri = "http://localhost:5173/"
+            web_base_uri = "https://api.example.com/"
+            encryption_key = "thisisatestkeythatsonlyfortests!"
+
+            [auth.password]
+            enabled = false
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            super::binding_cookie(&config, "example", "abc", 600).unwrap(),
+            "chartered_saml_binding=abc; Path=/web/v1/public/auth/login/saml/example/acs; \
+             Max-Age=600; HttpOnly; Secure; SameSite=None"
+        );
+    }
+}
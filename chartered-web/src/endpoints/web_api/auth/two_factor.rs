@@ -0,0 +1,339 @@
+//! TOTP two-factor authentication, including enrolment and the second step of password logins
+//! for users that have enrolled.
+//!
+//! Once a user has given their password, they're handed an encrypted challenge which has to be
+//! sent back along with a code from their authenticator app, one of their recovery codes, or
+//! the response from one of their passkeys, within `CHALLENGE_EXPIRY_MINUTES` to complete the
+//! login.
+
+use crate::{config::Config, endpoints::ErrorResponse, totp};
+
+use axum::{extract, Json};
+use chartered_db::{audit::Actor, scopes::TokenScope, users::User, uuid::Uuid, ConnectionPool};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use webauthn_rs::prelude::{PasskeyAuthentication, PublicKeyCredential, Webauthn};
+
+use std::{net::IpAddr, sync::Arc};
+
+/// How long the user has to give their code after giving their password.
+const CHALLENGE_EXPIRY_MINUTES: i64 = 5;
+
+/// Contents of the encrypted challenge handed out after a successful password check.
+#[derive(Serialize, Deserialize)]
+struct Challenge {
+    user_uuid: Uuid,
+    expires: i64,
+    /// Our side of the passkey ceremony, if the user has any passkeys they can use in place of
+    /// a code.
+    #[serde(default)]
+    passkey: Option<PasskeyAuthentication>,
+    /// Scope requested along with the password, which the session is restricted to once the
+    /// login is complete.
+    #[serde(default)]
+    scope: Option<TokenScope>,
+}
+
+/// Creates the challenge for the user to complete with a code, or a passkey if `passkey` is
+/// given, once they've given their password.
+pub(super) fn create_challenge(
+    user: &User,
+    config: &Config,
+    passkey: Option<PasskeyAuthentication>,
+    scope: Option<TokenScope>,
+) -> Result<String, Error> {
+    let challenge = serde_json::to_vec(&Challenge {
+        user_uuid: user.uuid.0,
+        expires: (Utc::now() + chrono::Duration::minutes(CHALLENGE_EXPIRY_MINUTES)).timestamp(),
+        passkey,
+        scope,
+    })
+    .map_err(|_| Error::InvalidChallenge)?;
+
+    super::openid::encrypt_url_safe(&challenge, config).map_err(|_| Error::InvalidChallenge)
+}
+
+/// Checks the code against the user's TOTP secret, falling back to their recovery codes.
+async fn verify_code(
+    db: ConnectionPool,
+    user: &User,
+    actor: &Actor,
+    code: &str,
+) -> Result<bool, Error> {
+    let secret = match (&user.totp_secret, user.has_totp()) {
+        (Some(secret), true) => secret,
+        _ => return Ok(false),
+    };
+
+    if let Some(step) = totp::verify(secret, code, Utc::now().timestamp()) {
+        // a valid code that has already been used is rejected, rather than checked against
+        // the recovery codes
+        return Ok(user.use_totp_step(db, step).await?);
+    }
+
+    Ok(user.use_recovery_code(db, actor, code.to_string()).await?)
+}
+
+/// Checks a code given by a logged in user to manage their two-factor authentication. These
+/// count towards the same lockout as logins, so a stolen session can't be used to brute force
+/// the user's codes.
+async fn verify_code_with_lockout(
+    db: ConnectionPool,
+    user: &User,
+    actor: &Actor,
+    code: &str,
+) -> Result<(), Error> {
+    match user.claim_login_attempt(db.clone(), actor).await {
+        Err(chartered_db::Error::UserLocked) => return Err(Error::Locked),
+        v => v?,
+    }
+
+    if !verify_code(db.clone(), user, actor, code).await? {
+        user.record_failed_login(db, actor).await?;
+
+        return Err(Error::InvalidCode);
+    }
+
+    user.clear_failed_logins(db).await?;
+
+    Ok(())
+}
+
+/// Enrolment and removal of two-factor authentication can't be done by scoped sessions, such
+/// as API tokens with limited permissions.
+fn ensure_unscoped(scope: &TokenScope) -> Result<(), Error> {
+    if *scope == TokenScope::default() {
+        Ok(())
+    } else {
+        Err(Error::ScopedSession)
+    }
+}
+
+/// Completes a password login for a user that has two-factor authentication enabled.
+pub async fn handle_login(
+    extract::Extension(config): extract::Extension<Arc<Config>>,
+    extract::Extension(webauthn): extract::Extension<Arc<Webauthn>>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Json(req): extract::Json<LoginRequest>,
+    user_agent: Option<extract::TypedHeader<headers::UserAgent>>,
+    addr: extract::Extension<IpAddr>,
+) -> Result<Json<super::LoginResponse>, Error> {
+    let challenge = super::openid::decrypt_url_safe(&req.challenge, &config)
+        .map_err(|_| Error::InvalidChallenge)?;
+    let challenge: Challenge =
+        serde_json::from_slice(&challenge).map_err(|_| Error::InvalidChallenge)?;
+
+    if challenge.expires < Utc::now().timestamp() {
+        return Err(Error::ExpiredChallenge);
+    }
+
+    let user = User::find_by_uuid(db.clone(), challenge.user_uuid)
+        .await?
+        .ok_or(Error::InvalidChallenge)?;
+
+    user.ensure_enabled()?;
+
+    let actor = Actor {
+        user_id: None,
+        ip: Some(addr.0.to_string()),
+        user_agent: user_agent
+            .as_ref()
+            .map(|extract::TypedHeader(v)| v.as_str().to_string()),
+    };
+
+    match user.claim_login_attempt(db.clone(), &actor).await {
+        Err(chartered_db::Error::UserLocked) => return Err(Error::Locked),
+        v => v?,
+    }
+
+    let verified = match (&req.passkey, &challenge.passkey) {
+        (Some(credential), Some(state)) => {
+            // the passkey's response is only valid for this challenge, so using it up stops
+            // the pair from being replayed while the challenge is still valid
+            if !super::passkeys::consume(db.clone(), &req.challenge).await? {
+                return Err(Error::UsedChallenge);
+            }
+
+            super::passkeys::verify(db.clone(), &webauthn, &user, credential, state).await?
+        }
+        (None, _) => verify_code(db.clone(), &user, &actor, &req.code).await?,
+        // a passkey was given but none were offered when the challenge was created
+        (Some(_), None) => false,
+    };
+
+    if !verified {
+        // failed codes count towards the same lockout as failed passwords, so codes can't be
+        // brute forced by anyone that knows the password
+        user.record_failed_login(db, &actor).await?;
+
+        return Err(Error::InvalidCode);
+    }
+
+    user.clear_failed_logins(db.clone()).await?;
+
+    Ok(Json(
+        super::login(db, user, challenge.scope, user_agent, addr).await?,
+    ))
+}
+
+/// Returns whether the user has enabled two-factor authentication.
+pub async fn handle_get(
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+) -> Result<Json<StatusResponse>, Error> {
+    let recovery_codes_remaining = if user.has_totp() {
+        Some(user.recovery_codes_remaining(db).await?)
+    } else {
+        None
+    };
+
+    Ok(Json(StatusResponse {
+        enabled: user.has_totp(),
+        recovery_codes_remaining,
+    }))
+}
+
+/// Starts enrolment, returning a new secret for the user to add to their authenticator app.
+/// Enrolment isn't complete until a code generated from it is given to `handle_verify`.
+pub async fn handle_enrol(
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
+) -> Result<Json<EnrolResponse>, Error> {
+    ensure_unscoped(&scope)?;
+
+    let secret = totp::generate_secret();
+    user.begin_totp_enrolment(db, secret.clone()).await?;
+
+    Ok(Json(EnrolResponse {
+        secret: totp::encode_secret(&secret),
+        uri: totp::otpauth_uri(&secret, &user.username),
+    }))
+}
+
+/// Completes enrolment given a code generated from the secret returned by `handle_enrol`,
+/// returning the user's recovery codes. These are only ever shown once.
+pub async fn handle_verify(
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
+    extract::Json(req): extract::Json<CodeRequest>,
+) -> Result<Json<RecoveryCodesResponse>, Error> {
+    ensure_unscoped(&scope)?;
+
+    let secret = user.totp_secret.as_deref().ok_or(Error::NotEnrolling)?;
+
+    let step = totp::verify(secret, &req.code, Utc::now().timestamp()).ok_or(Error::InvalidCode)?;
+
+    let recovery_codes = user.enable_totp(db, &actor, step).await?;
+
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+/// Replaces the user's recovery codes, given a valid code.
+pub async fn handle_regenerate_recovery_codes(
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
+    extract::Json(req): extract::Json<CodeRequest>,
+) -> Result<Json<RecoveryCodesResponse>, Error> {
+    ensure_unscoped(&scope)?;
+
+    verify_code_with_lockout(db.clone(), &user, &actor, &req.code).await?;
+
+    let recovery_codes = user.regenerate_recovery_codes(db, &actor).await?;
+
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+/// Turns off two-factor authentication for the user, given a valid code.
+pub async fn handle_delete(
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
+    extract::Json(req): extract::Json<CodeRequest>,
+) -> Result<Json<ErrorResponse>, Error> {
+    ensure_unscoped(&scope)?;
+
+    verify_code_with_lockout(db.clone(), &user, &actor, &req.code).await?;
+
+    user.disable_totp(db, &actor).await?;
+
+    Ok(Json(ErrorResponse { error: None }))
+}
+
+#[derive(Deserialize)]
+pub struct LoginRequest {
+    challenge: String,
+    /// Either a code from the user's authenticator app, or one of their recovery codes.
+    #[serde(default)]
+    code: String,
+    /// The response from one of the user's passkeys, given in place of a code.
+    passkey: Option<PublicKeyCredential>,
+}
+
+#[derive(Deserialize)]
+pub struct CodeRequest {
+    code: String,
+}
+
+#[derive(Serialize)]
+pub struct StatusResponse {
+    enabled: bool,
+    recovery_codes_remaining: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct EnrolResponse {
+    /// The secret, base32 encoded for manual entry into an authenticator app.
+    secret: String,
+    /// `otpauth://` URI for the secret, to be shown as a QR code.
+    uri: String,
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodesResponse {
+    recovery_codes: Vec<String>,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Database(#[from] chartered_db::Error),
+    #[error("Invalid login challenge")]
+    InvalidChallenge,
+    #[error("Login challenge expired, please log in again")]
+    ExpiredChallenge,
+    #[error("Login challenge has already been used, please log in again")]
+    UsedChallenge,
+    #[error("Invalid two-factor authentication code")]
+    InvalidCode,
+    #[error("Too many failed login attempts, try again later")]
+    Locked,
+    #[error("Two-factor authentication enrolment hasn't been started")]
+    NotEnrolling,
+    #[error("Two-factor authentication can't be managed using a scoped session")]
+    ScopedSession,
+}
+
+impl Error {
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        use axum::http::StatusCode;
+
+        match self {
+            Self::Database(e) => e.status_code(),
+            Self::InvalidChallenge | Self::NotEnrolling => StatusCode::BAD_REQUEST,
+            Self::ExpiredChallenge
+            | Self::UsedChallenge
+            | Self::InvalidCode
+            | Self::Locked
+            | Self::ScopedSession => StatusCode::FORBIDDEN,
+        }
+    }
+}
+
+define_error_response!(Error);
@@ -0,0 +1,118 @@
+//! Handles CRD of the public keys cargo uses to sign asymmetric tokens for the requesting user.
+//! Like SSH keys these aren't updatable, a new key should be registered and the old one removed.
+
+use chartered_db::{audit::Actor, cargo_keys::UserCargoKey, users::User, ConnectionPool};
+
+use axum::{extract, Json};
+use chartered_db::uuid::Uuid;
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::{asymmetric_token, endpoints::ErrorResponse};
+
+pub async fn handle_get(
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+) -> Result<Json<GetResponse>, Error> {
+    let keys = UserCargoKey::list(db, user)
+        .await?
+        .into_iter()
+        .map(ResponseKey::from)
+        .collect();
+
+    Ok(Json(GetResponse { keys }))
+}
+
+pub async fn handle_put(
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    extract::Json(req): extract::Json<PutRequest>,
+) -> Result<Json<ResponseKey>, Error> {
+    let name = req.name.trim().to_string();
+    if name.is_empty() {
+        return Err(Error::MissingName);
+    }
+
+    let public_key = req.public_key.trim().to_string();
+    let key_id = asymmetric_token::key_id(&public_key)?;
+
+    let inserted = UserCargoKey::insert(db, user, &actor, name, public_key, key_id).await?;
+
+    Ok(Json(inserted.into()))
+}
+
+pub async fn handle_delete(
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    extract::Path(key_id): extract::Path<Uuid>,
+) -> Result<Json<ErrorResponse>, Error> {
+    if UserCargoKey::delete_by_uuid(db, user, &actor, key_id).await? {
+        Ok(Json(ErrorResponse { error: None }))
+    } else {
+        Err(Error::NonExistentKey)
+    }
+}
+
+#[derive(Serialize)]
+pub struct GetResponse {
+    keys: Vec<ResponseKey>,
+}
+
+#[derive(Serialize)]
+pub struct ResponseKey {
+    uuid: Uuid,
+    name: String,
+    public_key: String,
+    key_id: String,
+    created_at: DateTime<Utc>,
+    last_used_at: Option<DateTime<Utc>>,
+}
+
+impl From<UserCargoKey> for ResponseKey {
+    fn from(key: UserCargoKey) -> Self {
+        Self {
+            uuid: key.uuid.0,
+            name: key.name,
+            public_key: key.public_key,
+            key_id: key.key_id,
+            created_at: Utc.from_utc_datetime(&key.created_at),
+            last_used_at: key.last_used_at.map(|v| Utc.from_utc_datetime(&v)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PutRequest {
+    name: String,
+    public_key: String,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Database(#[from] chartered_db::Error),
+    #[error("{0}")]
+    Token(#[from] asymmetric_token::Error),
+    #[error("A name must be given for the key")]
+    MissingName,
+    #[error("The key given does not exist")]
+    NonExistentKey,
+}
+
+impl Error {
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        use axum::http::StatusCode;
+
+        match self {
+            Self::Database(e) => e.status_code(),
+            Self::Token(e) => e.status_code(),
+            Self::MissingName | Self::NonExistentKey => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+define_error_response!(Error);
@@ -0,0 +1,82 @@
+//! Deletes a crate, or a single version of it, that was published by mistake. Only versions
+//! published within the server's configured deletion window can be deleted, after that they
+//! should be yanked instead.
+//!
+//! Deleted versions can optionally be reserved so they can't be published again with different
+//! content.
+
+use axum::{extract, Json};
+use chartered_db::{audit::Actor, crates::Crate, scopes::TokenScope, users::User, ConnectionPool};
+use serde::Deserialize;
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::{config::Config, endpoints::ErrorResponse};
+
+pub async fn handle_crate(
+    extract::Path((organisation, name)): extract::Path<(String, String)>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(config): extract::Extension<Arc<Config>>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    extract::Json(req): extract::Json<Request>,
+) -> Result<Json<ErrorResponse>, Error> {
+    let crate_with_permissions =
+        Arc::new(Crate::find_by_name(db.clone(), user.id, scope, organisation, name).await?);
+
+    crate_with_permissions
+        .delete(db, &actor, config.crate_deletion_window(), req.reserve)
+        .await?;
+
+    Ok(Json(ErrorResponse { error: None }))
+}
+
+pub async fn handle_version(
+    extract::Path((organisation, name, version)): extract::Path<(String, String, String)>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(config): extract::Extension<Arc<Config>>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    extract::Json(req): extract::Json<Request>,
+) -> Result<Json<ErrorResponse>, Error> {
+    let crate_with_permissions =
+        Arc::new(Crate::find_by_name(db.clone(), user.id, scope, organisation, name).await?);
+
+    crate_with_permissions
+        .delete_version(
+            db,
+            &actor,
+            version,
+            config.crate_deletion_window(),
+            req.reserve,
+        )
+        .await?;
+
+    Ok(Json(ErrorResponse { error: None }))
+}
+
+#[derive(Deserialize)]
+pub struct Request {
+    /// Whether the deleted versions should be reserved so they can only be republished with
+    /// the same checksum.
+    #[serde(default)]
+    reserve: bool,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Database(#[from] chartered_db::Error),
+}
+
+impl Error {
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        match self {
+            Self::Database(e) => e.status_code(),
+        }
+    }
+}
+
+define_error_response!(Error);
@@ -0,0 +1,72 @@
+//! Returns the daily download counts for each version of a crate over a given period, which
+//! can be used by the web UI to draw download graphs in the same vein as crates.io.
+
+use axum::{extract, Json};
+use chartered_db::{crates::Crate, scopes::TokenScope, users::User, ConnectionPool};
+use chrono::{Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use thiserror::Error;
+
+/// Maximum amount of days that can be requested at once.
+const MAX_DAYS: i64 = 365;
+
+pub async fn handle(
+    extract::Path((organisation, name)): extract::Path<(String, String)>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
+    extract::Query(req): extract::Query<RequestParams>,
+) -> Result<Json<Response>, Error> {
+    let crate_with_permissions =
+        Arc::new(Crate::find_by_name(db.clone(), user.id, scope, organisation, name).await?);
+
+    let days = req.days.unwrap_or(90).clamp(1, MAX_DAYS);
+    let since = (Utc::now() - Duration::days(days)).naive_utc().date();
+
+    let version_downloads = crate_with_permissions
+        .version_downloads(db, since)
+        .await?
+        .into_iter()
+        .map(|(version, date, downloads)| ResponseVersionDownloads {
+            version,
+            date,
+            downloads,
+        })
+        .collect();
+
+    Ok(Json(Response { version_downloads }))
+}
+
+#[derive(Deserialize)]
+pub struct RequestParams {
+    days: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct Response {
+    version_downloads: Vec<ResponseVersionDownloads>,
+}
+
+#[derive(Serialize)]
+pub struct ResponseVersionDownloads {
+    version: String,
+    date: NaiveDate,
+    downloads: i32,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Database(#[from] chartered_db::Error),
+}
+
+impl Error {
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        match self {
+            Self::Database(e) => e.status_code(),
+        }
+    }
+}
+
+define_error_response!(Error);
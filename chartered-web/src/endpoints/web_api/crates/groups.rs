@@ -0,0 +1,136 @@
+//! Handles crate-level group overrides that can add permissions on top of those the group has
+//! been granted across the organisation.
+
+use axum::{extract, Json};
+use chartered_db::{
+    audit::Actor,
+    crates::Crate,
+    permissions::{Role, UserPermission},
+    scopes::TokenScope,
+    users::User,
+    uuid::Uuid,
+    ConnectionPool,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::endpoints::ErrorResponse;
+
+/// Lists all the groups that have been granted permissions on this crate specifically.
+pub async fn handle_get(
+    extract::Path((organisation, name)): extract::Path<(String, String)>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
+) -> Result<Json<GetResponse>, Error> {
+    let crate_with_permissions =
+        Arc::new(Crate::find_by_name(db.clone(), user.id, scope, organisation, name).await?);
+
+    let groups = crate_with_permissions
+        .groups(db)
+        .await?
+        .into_iter()
+        .map(|(group, permissions)| GetResponseGroup {
+            uuid: group.uuid.0,
+            name: group.name,
+            permissions,
+        })
+        .collect();
+
+    Ok(Json(GetResponse {
+        possible_permissions: UserPermission::names(),
+        implied_permissions: UserPermission::implications(),
+        roles: Role::presets(),
+        groups,
+    }))
+}
+
+/// Grants a group permissions on this crate, replacing any it already had.
+pub async fn handle_put(
+    extract::Path((organisation, name)): extract::Path<(String, String)>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    extract::Json(req): extract::Json<PutRequest>,
+) -> Result<Json<ErrorResponse>, Error> {
+    let crate_with_permissions =
+        Arc::new(Crate::find_by_name(db.clone(), user.id, scope, organisation, name).await?);
+
+    crate_with_permissions
+        .set_group_permissions(db, &actor, req.group, req.permissions)
+        .await?;
+
+    Ok(Json(ErrorResponse { error: None }))
+}
+
+/// Removes a group's override from this crate
+pub async fn handle_delete(
+    extract::Path((organisation, name)): extract::Path<(String, String)>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    extract::Json(req): extract::Json<DeleteRequest>,
+) -> Result<Json<ErrorResponse>, Error> {
+    let crate_with_permissions =
+        Arc::new(Crate::find_by_name(db.clone(), user.id, scope, organisation, name).await?);
+
+    if !crate_with_permissions
+        .delete_group_permissions(db, &actor, req.group)
+        .await?
+    {
+        return Err(Error::NonExistentOverride);
+    }
+
+    Ok(Json(ErrorResponse { error: None }))
+}
+
+#[derive(Serialize)]
+pub struct GetResponse {
+    possible_permissions: &'static [&'static str],
+    implied_permissions: &'static [[UserPermission; 2]],
+    roles: Vec<(Role, UserPermission)>,
+    groups: Vec<GetResponseGroup>,
+}
+
+#[derive(Serialize)]
+pub struct GetResponseGroup {
+    uuid: Uuid,
+    name: String,
+    permissions: UserPermission,
+}
+
+#[derive(Deserialize)]
+pub struct PutRequest {
+    group: String,
+    #[serde(deserialize_with = "chartered_db::permissions::deserialize_assignable")]
+    permissions: UserPermission,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteRequest {
+    group: String,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Database(#[from] chartered_db::Error),
+    #[error("The group hasn't been granted any permissions on this crate")]
+    NonExistentOverride,
+}
+
+impl Error {
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        use axum::http::StatusCode;
+
+        match self {
+            Self::Database(e) => e.status_code(),
+            Self::NonExistentOverride => StatusCode::NOT_FOUND,
+        }
+    }
+}
+
+define_error_response!(Error);
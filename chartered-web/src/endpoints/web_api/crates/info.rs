@@ -6,7 +6,9 @@
 //! need to have version-specific info responses - we'll just send an overview of each one.
 
 use axum::{extract, response::IntoResponse, Json};
-use chartered_db::{crates::Crate, permissions::UserPermission, users::User, ConnectionPool};
+use chartered_db::{
+    crates::Crate, permissions::UserPermission, scopes::TokenScope, users::User, ConnectionPool,
+};
 use chartered_types::cargo::CrateVersion;
 use chrono::TimeZone;
 use serde::Serialize;
@@ -17,9 +19,10 @@ pub async fn handle(
     extract::Path((organisation, name)): extract::Path<(String, String)>,
     extract::Extension(db): extract::Extension<ConnectionPool>,
     extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
 ) -> Result<axum::response::Response, Error> {
     let crate_with_permissions =
-        Arc::new(Crate::find_by_name(db.clone(), user.id, organisation, name).await?);
+        Arc::new(Crate::find_by_name(db.clone(), user.id, scope, organisation, name).await?);
 
     // grab all versions of this crate and the person who uploaded them
     let versions = crate_with_permissions
@@ -4,40 +4,59 @@
 
 use axum::{extract, Json};
 use chartered_db::{
-    crates::Crate, permissions::UserPermission, users::User, uuid::Uuid, ConnectionPool,
+    audit::Actor,
+    crates::{Crate, PermissionSource},
+    permissions::{Role, UserPermission},
+    scopes::TokenScope,
+    users::User,
+    uuid::Uuid,
+    ConnectionPool,
 };
+use chrono::{DateTime, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use thiserror::Error;
 
 use crate::endpoints::ErrorResponse;
 
-/// Lists all crate-level members and the permissions they have.
+/// Lists everyone with permissions on the crate along with the permissions they effectively
+/// have, and where each of those permissions were granted.
 ///
-/// These members could be specific to the crate or they could be overrides ontop of the org.
+/// Permissions granted on the organisation are inherited by every crate within it, crate-level
+/// grants add to those and crate-level restrictions take away from them.
 pub async fn handle_get(
     extract::Path((organisation, name)): extract::Path<(String, String)>,
     extract::Extension(db): extract::Extension<ConnectionPool>,
     extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
 ) -> Result<Json<GetResponse>, Error> {
     let crate_with_permissions =
-        Arc::new(Crate::find_by_name(db.clone(), user.id, organisation, name).await?);
+        Arc::new(Crate::find_by_name(db.clone(), user.id, scope, organisation, name).await?);
 
     let members = crate_with_permissions
         .members(db)
         .await?
         .into_iter()
-        .map(|(user, permissions)| GetResponseMember {
-            uuid: user.uuid.0,
-            display_name: user.display_name().to_string(),
-            picture_url: user.picture_url,
-            permissions,
+        .map(|member| GetResponseMember {
+            effective_permissions: member.effective_permissions(),
+            uuid: member.user.uuid.0,
+            display_name: member.user.display_name().to_string(),
+            picture_url: member.user.picture_url,
+            permissions: member.permissions,
+            restricted_permissions: member.restricted_permissions,
+            expires_at: member.expires_at.map(|v| Utc.from_utc_datetime(&v)),
+            grants: member
+                .grants
+                .into_iter()
+                .map(|(source, permissions)| GetResponseGrant::new(source, permissions))
+                .collect(),
         })
         .collect();
 
     Ok(Json(GetResponse {
         possible_permissions: UserPermission::names(),
         implied_permissions: UserPermission::implications(),
+        roles: Role::presets(),
         members,
     }))
 }
@@ -47,17 +66,30 @@ pub async fn handle_patch(
     extract::Path((organisation, name)): extract::Path<(String, String)>,
     extract::Extension(db): extract::Extension<ConnectionPool>,
     extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
     extract::Json(req): extract::Json<PutOrPatchRequest>,
 ) -> Result<Json<ErrorResponse>, Error> {
+    if matches!(req.expires_at, Some(v) if v <= Utc::now()) {
+        return Err(Error::ExpiryInPast);
+    }
+
     let crate_with_permissions =
-        Arc::new(Crate::find_by_name(db.clone(), user.id, organisation, name).await?);
+        Arc::new(Crate::find_by_name(db.clone(), user.id, scope, organisation, name).await?);
 
     let action_user = User::find_by_uuid(db.clone(), req.user_uuid)
         .await?
         .ok_or(Error::InvalidUserId)?;
 
     let affected_rows = crate_with_permissions
-        .update_permissions(db, action_user.id, req.permissions)
+        .update_permissions(
+            db,
+            &actor,
+            action_user.id,
+            req.permissions,
+            req.restricted_permissions,
+            req.expires_at.map(|v| v.naive_utc()),
+        )
         .await?;
     if affected_rows == 0 {
         return Err(Error::UpdateConflictRemoved);
@@ -71,17 +103,30 @@ pub async fn handle_put(
     extract::Path((organisation, name)): extract::Path<(String, String)>,
     extract::Extension(db): extract::Extension<ConnectionPool>,
     extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
     extract::Json(req): extract::Json<PutOrPatchRequest>,
 ) -> Result<Json<ErrorResponse>, Error> {
+    if matches!(req.expires_at, Some(v) if v <= Utc::now()) {
+        return Err(Error::ExpiryInPast);
+    }
+
     let crate_with_permissions =
-        Arc::new(Crate::find_by_name(db.clone(), user.id, organisation, name).await?);
+        Arc::new(Crate::find_by_name(db.clone(), user.id, scope, organisation, name).await?);
 
     let action_user = User::find_by_uuid(db.clone(), req.user_uuid)
         .await?
         .ok_or(Error::InvalidUserId)?;
 
     crate_with_permissions
-        .insert_permissions(db, action_user.id, req.permissions)
+        .insert_permissions(
+            db,
+            &actor,
+            action_user.id,
+            req.permissions,
+            req.restricted_permissions.unwrap_or_default(),
+            req.expires_at.map(|v| v.naive_utc()),
+        )
         .await?;
 
     Ok(Json(ErrorResponse { error: None }))
@@ -92,17 +137,19 @@ pub async fn handle_delete(
     extract::Path((organisation, name)): extract::Path<(String, String)>,
     extract::Extension(db): extract::Extension<ConnectionPool>,
     extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
     extract::Json(req): extract::Json<DeleteRequest>,
 ) -> Result<Json<ErrorResponse>, Error> {
     let crate_with_permissions =
-        Arc::new(Crate::find_by_name(db.clone(), user.id, organisation, name).await?);
+        Arc::new(Crate::find_by_name(db.clone(), user.id, scope, organisation, name).await?);
 
     let action_user = User::find_by_uuid(db.clone(), req.user_uuid)
         .await?
         .ok_or(Error::InvalidUserId)?;
 
     crate_with_permissions
-        .delete_member(db, action_user.id)
+        .delete_member(db, &actor, action_user.id)
         .await?;
 
     Ok(Json(ErrorResponse { error: None }))
@@ -112,6 +159,7 @@ pub async fn handle_delete(
 pub struct GetResponse {
     possible_permissions: &'static [&'static str],
     implied_permissions: &'static [[UserPermission; 2]],
+    roles: Vec<(Role, UserPermission)>,
     members: Vec<GetResponseMember>,
 }
 
@@ -120,13 +168,52 @@ pub struct GetResponseMember {
     uuid: Uuid,
     display_name: String,
     picture_url: Option<String>,
+    /// Permissions granted to the member on the crate itself.
+    permissions: UserPermission,
+    /// Permissions taken away from the member on the crate.
+    restricted_permissions: UserPermission,
+    /// When the permissions granted on the crate itself lapse, if ever.
+    expires_at: Option<DateTime<Utc>>,
+    /// Permissions the member ends up with after inheritance and restrictions are applied.
+    effective_permissions: UserPermission,
+    grants: Vec<GetResponseGrant>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct GetResponseGrant {
+    /// Where the grant came from, one of `crate`, `organisation` or `group`.
+    source: String,
+    /// Name of the group the grant came from, if `source` is `group`.
+    group: Option<String>,
     permissions: UserPermission,
 }
 
+impl GetResponseGrant {
+    fn new(source: PermissionSource, permissions: UserPermission) -> Self {
+        let (source, group) = match source {
+            PermissionSource::Crate => ("crate", None),
+            PermissionSource::Organisation => ("organisation", None),
+            PermissionSource::Group(name) => ("group", Some(name)),
+        };
+
+        Self {
+            source: source.to_string(),
+            group,
+            permissions,
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct PutOrPatchRequest {
     user_uuid: chartered_db::uuid::Uuid,
+    #[serde(deserialize_with = "chartered_db::permissions::deserialize_assignable")]
     permissions: UserPermission,
+    /// Permissions to take away from the member on this crate, even if they've been granted
+    /// them by the organisation or one of their groups. Left as-is on update if not given.
+    restricted_permissions: Option<UserPermission>,
+    /// When the permissions granted on the crate lapse, or `None` if they never should.
+    expires_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Deserialize)]
@@ -142,6 +229,8 @@ pub enum Error {
     UpdateConflictRemoved,
     #[error("An invalid user id was given")]
     InvalidUserId,
+    #[error("Expiry date must be in the future")]
+    ExpiryInPast,
 }
 
 impl Error {
@@ -151,7 +240,7 @@ impl Error {
         match self {
             Self::Database(e) => e.status_code(),
             Self::UpdateConflictRemoved => StatusCode::CONFLICT,
-            Self::InvalidUserId => StatusCode::BAD_REQUEST,
+            Self::InvalidUserId | Self::ExpiryInPast => StatusCode::BAD_REQUEST,
         }
     }
 }
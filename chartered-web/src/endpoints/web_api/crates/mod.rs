@@ -1,19 +1,36 @@
+mod delete;
+mod downloads;
+mod groups;
 mod info;
 mod members;
 mod most_downloaded;
+mod move_crate;
 mod recently_created;
 mod recently_updated;
 mod search;
+mod trusted_publishers;
 
 use crate::middleware::rate_limit::RateLimit;
 use axum::handler::Handler;
-use axum::{routing::get, Router};
+use axum::{
+    routing::{delete, get, post},
+    Router,
+};
 
 pub fn routes(rate_limit: &RateLimit) -> Router {
     Router::new()
         .route(
             "/:org/:crate",
-            get(info::handle.layer(rate_limit.with_cost(1))),
+            get(info::handle.layer(rate_limit.with_cost(1)))
+                .delete(delete::handle_crate.layer(rate_limit.with_cost(100))),
+        )
+        .route(
+            "/:org/:crate/versions/:version",
+            delete(delete::handle_version.layer(rate_limit.with_cost(100))),
+        )
+        .route(
+            "/:org/:crate/downloads",
+            get(downloads::handle.layer(rate_limit.with_cost(1))),
         )
         .route(
             "/:org/:crate/members",
@@ -22,6 +39,25 @@ pub fn routes(rate_limit: &RateLimit) -> Router {
                 .put(members::handle_put.layer(rate_limit.with_cost(10)))
                 .delete(members::handle_delete.layer(rate_limit.with_cost(10))),
         )
+        .route(
+            "/:org/:crate/groups",
+            get(groups::handle_get.layer(rate_limit.with_cost(1)))
+                .put(groups::handle_put.layer(rate_limit.with_cost(10)))
+                .delete(groups::handle_delete.layer(rate_limit.with_cost(10))),
+        )
+        .route(
+            "/:org/:crate/move",
+            post(move_crate::handle.layer(rate_limit.with_cost(100))),
+        )
+        .route(
+            "/:org/:crate/trusted-publishers",
+            get(trusted_publishers::handle_get.layer(rate_limit.with_cost(1)))
+                .put(trusted_publishers::handle_put.layer(rate_limit.with_cost(10))),
+        )
+        .route(
+            "/:org/:crate/trusted-publishers/:id",
+            delete(trusted_publishers::handle_delete.layer(rate_limit.with_cost(10))),
+        )
         .route(
             "/recently-updated",
             get(recently_updated::handle.layer(rate_limit.with_cost(1))),
@@ -39,3 +75,17 @@ pub fn routes(rate_limit: &RateLimit) -> Router {
             get(search::handle.layer(rate_limit.with_cost(5))),
         )
 }
+
+/// Routes that can be used without logging in to view crates in public organisations, if the
+/// server allows anonymous access.
+pub fn anonymous_routes(rate_limit: &RateLimit) -> Router {
+    Router::new()
+        .route(
+            "/:org/:crate",
+            get(info::handle.layer(rate_limit.with_cost(1))),
+        )
+        .route(
+            "/:org/:crate/downloads",
+            get(downloads::handle.layer(rate_limit.with_cost(1))),
+        )
+}
@@ -0,0 +1,71 @@
+//! Moves a crate into another organisation, optionally leaving a redirect behind in the old
+//! organisation's index so existing `Cargo.toml`s keep resolving until it expires.
+
+use axum::{extract, Json};
+use chartered_db::{
+    audit::Actor, crates::Crate, organisations::Organisation, scopes::TokenScope, users::User,
+    ConnectionPool,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::endpoints::ErrorResponse;
+
+pub async fn handle(
+    extract::Path((organisation, name)): extract::Path<(String, String)>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    extract::Json(req): extract::Json<Request>,
+) -> Result<Json<ErrorResponse>, Error> {
+    if matches!(req.redirect_expires_at, Some(v) if v <= Utc::now()) {
+        return Err(Error::ExpiryInPast);
+    }
+
+    let crate_with_permissions = Arc::new(
+        Crate::find_by_name(db.clone(), user.id, scope.clone(), organisation, name).await?,
+    );
+    let target = Arc::new(Organisation::find_by_name(db.clone(), user.id, req.organisation).await?);
+
+    crate_with_permissions
+        .move_to(
+            db,
+            &actor,
+            scope,
+            target,
+            req.redirect_expires_at.map(|v| v.naive_utc()),
+        )
+        .await?;
+
+    Ok(Json(ErrorResponse { error: None }))
+}
+
+#[derive(Deserialize)]
+pub struct Request {
+    /// Name of the organisation to move the crate into.
+    organisation: String,
+    /// When to stop serving the crate through its old organisation, if at all.
+    redirect_expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Database(#[from] chartered_db::Error),
+    #[error("Redirect expiry date must be in the future")]
+    ExpiryInPast,
+}
+
+impl Error {
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        match self {
+            Self::Database(e) => e.status_code(),
+            Self::ExpiryInPast => axum::http::StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+define_error_response!(Error);
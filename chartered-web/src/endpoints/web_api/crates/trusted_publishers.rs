@@ -0,0 +1,158 @@
+//! Handles CRD of a crate's trusted publishing policies, which allow CI jobs matching the policy
+//! to exchange an OIDC ID token for a short-lived publish token.
+//!
+//! Tokens handed out by a policy are owned by the user that created it, so managing policies
+//! requires the same `MANAGE_USERS` permission as managing the crate's members.
+
+use axum::{extract, Json};
+use chartered_db::{
+    audit::Actor, crates::Crate, scopes::TokenScope, trusted_publishers::CrateTrustedPublisher,
+    users::User, uuid::Uuid, ConnectionPool,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::{config::TrustedPublishers, endpoints::ErrorResponse};
+
+pub async fn handle_get(
+    extract::Path((organisation, name)): extract::Path<(String, String)>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
+) -> Result<Json<GetResponse>, Error> {
+    let crate_with_permissions =
+        Arc::new(Crate::find_by_name(db.clone(), user.id, scope, organisation, name).await?);
+
+    let policies = crate_with_permissions
+        .trusted_publishers(db)
+        .await?
+        .into_iter()
+        .map(ResponsePolicy::from)
+        .collect();
+
+    Ok(Json(GetResponse { policies }))
+}
+
+pub async fn handle_put(
+    extract::Path((organisation, name)): extract::Path<(String, String)>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    extract::Extension(trusted_publishers): extract::Extension<Arc<TrustedPublishers>>,
+    extract::Json(req): extract::Json<PutRequest>,
+) -> Result<Json<ResponsePolicy>, Error> {
+    if !trusted_publishers.contains_key(&req.provider) {
+        return Err(Error::UnknownProvider);
+    }
+
+    let repository = req.repository.trim().to_string();
+    if repository.is_empty() {
+        return Err(Error::MissingRepository);
+    }
+
+    // blank values are treated as allowing any workflow or branch
+    let non_empty = |v: Option<String>| v.map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+
+    let crate_with_permissions =
+        Arc::new(Crate::find_by_name(db.clone(), user.id, scope, organisation, name).await?);
+
+    let inserted = crate_with_permissions
+        .insert_trusted_publisher(
+            db,
+            &actor,
+            user.id,
+            req.provider,
+            repository,
+            non_empty(req.workflow),
+            non_empty(req.branch),
+        )
+        .await?;
+
+    Ok(Json(inserted.into()))
+}
+
+pub async fn handle_delete(
+    extract::Path((organisation, name, policy_id)): extract::Path<(String, String, Uuid)>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(scope): extract::Extension<Arc<TokenScope>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+) -> Result<Json<ErrorResponse>, Error> {
+    let crate_with_permissions =
+        Arc::new(Crate::find_by_name(db.clone(), user.id, scope, organisation, name).await?);
+
+    if crate_with_permissions
+        .delete_trusted_publisher(db, &actor, policy_id)
+        .await?
+    {
+        Ok(Json(ErrorResponse { error: None }))
+    } else {
+        Err(Error::NonExistentPolicy)
+    }
+}
+
+#[derive(Serialize)]
+pub struct GetResponse {
+    policies: Vec<ResponsePolicy>,
+}
+
+#[derive(Serialize)]
+pub struct ResponsePolicy {
+    uuid: Uuid,
+    provider: String,
+    repository: String,
+    workflow: Option<String>,
+    branch: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+impl From<CrateTrustedPublisher> for ResponsePolicy {
+    fn from(policy: CrateTrustedPublisher) -> Self {
+        Self {
+            uuid: policy.uuid.0,
+            provider: policy.provider,
+            repository: policy.repository,
+            workflow: policy.workflow,
+            branch: policy.branch,
+            created_at: Utc.from_utc_datetime(&policy.created_at),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PutRequest {
+    provider: String,
+    repository: String,
+    workflow: Option<String>,
+    branch: Option<String>,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Database(#[from] chartered_db::Error),
+    #[error("Unknown trusted publishing provider given")]
+    UnknownProvider,
+    #[error("A repository must be given for the policy")]
+    MissingRepository,
+    #[error("The policy given does not exist")]
+    NonExistentPolicy,
+}
+
+impl Error {
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        use axum::http::StatusCode;
+
+        match self {
+            Self::Database(e) => e.status_code(),
+            Self::UnknownProvider | Self::MissingRepository | Self::NonExistentPolicy => {
+                StatusCode::BAD_REQUEST
+            }
+        }
+    }
+}
+
+define_error_response!(Error);
@@ -0,0 +1,110 @@
+//! Lets users see the invites they've been sent to join organisations and accept or decline
+//! them, or redeem an invite link they've been given.
+
+use axum::{extract, Json};
+use chartered_db::{
+    audit::Actor, invites::OrganisationInvite, permissions::UserPermission, users::User,
+    uuid::Uuid, ConnectionPool,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::endpoints::ErrorResponse;
+
+pub async fn handle_get(
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+) -> Result<Json<GetResponse>, Error> {
+    let invites = OrganisationInvite::list_for_user(db, user.id)
+        .await?
+        .into_iter()
+        .map(|(invite, organisation)| GetResponseInvite {
+            uuid: invite.uuid.0,
+            organisation: organisation.name,
+            permissions: invite.permissions,
+            expires_at: Utc.from_utc_datetime(&invite.expires_at),
+            created_at: Utc.from_utc_datetime(&invite.created_at),
+        })
+        .collect();
+
+    Ok(Json(GetResponse { invites }))
+}
+
+pub async fn handle_accept(
+    extract::Path(invite): extract::Path<Uuid>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+) -> Result<Json<AcceptResponse>, Error> {
+    let organisation = OrganisationInvite::accept(db, &actor, user.id, invite).await?;
+
+    Ok(Json(AcceptResponse {
+        organisation: organisation.name,
+    }))
+}
+
+pub async fn handle_accept_link(
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    extract::Json(req): extract::Json<AcceptLinkRequest>,
+) -> Result<Json<AcceptResponse>, Error> {
+    let organisation = OrganisationInvite::accept_link(db, &actor, user.id, req.token).await?;
+
+    Ok(Json(AcceptResponse {
+        organisation: organisation.name,
+    }))
+}
+
+pub async fn handle_decline(
+    extract::Path(invite): extract::Path<Uuid>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+) -> Result<Json<ErrorResponse>, Error> {
+    OrganisationInvite::decline(db, &actor, user.id, invite).await?;
+
+    Ok(Json(ErrorResponse { error: None }))
+}
+
+#[derive(Serialize)]
+pub struct GetResponse {
+    invites: Vec<GetResponseInvite>,
+}
+
+#[derive(Serialize)]
+pub struct GetResponseInvite {
+    uuid: Uuid,
+    organisation: String,
+    permissions: UserPermission,
+    expires_at: DateTime<Utc>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct AcceptLinkRequest {
+    token: String,
+}
+
+#[derive(Serialize)]
+pub struct AcceptResponse {
+    organisation: String,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Database(#[from] chartered_db::Error),
+}
+
+impl Error {
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        match self {
+            Self::Database(e) => e.status_code(),
+        }
+    }
+}
+
+define_error_response!(Error);
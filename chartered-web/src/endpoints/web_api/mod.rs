@@ -1,16 +1,23 @@
+mod admin;
+mod audit_log;
 mod auth;
+mod cargo_keys;
 mod crates;
+mod invites;
 mod organisations;
 mod sessions;
 mod ssh_key;
+mod tokens;
+mod trusted_publishing;
 mod users;
 
 use crate::RateLimit;
 use axum::{
     handler::Handler,
-    routing::{delete, get},
+    routing::{delete, get, post},
     Router,
 };
+use tower::ServiceBuilder;
 
 pub fn authenticated_routes(rate_limit: &RateLimit) -> Router {
     Router::new()
@@ -19,6 +26,20 @@ pub fn authenticated_routes(rate_limit: &RateLimit) -> Router {
         .nest("/users", users::routes(rate_limit))
         .nest("/auth", auth::authenticated_routes(rate_limit))
         .nest("/sessions", sessions::routes(rate_limit))
+        .nest("/admin", admin::routes(rate_limit))
+        .route(
+            "/audit-log",
+            get(audit_log::handle_get.layer(rate_limit.with_cost(5))),
+        )
+        .route(
+            "/cargo-keys",
+            get(cargo_keys::handle_get.layer(rate_limit.with_cost(1)))
+                .put(cargo_keys::handle_put.layer(rate_limit.with_cost(24))),
+        )
+        .route(
+            "/cargo-keys/:id",
+            delete(cargo_keys::handle_delete.layer(rate_limit.with_cost(24))),
+        )
         .route(
             "/ssh-key",
             get(ssh_key::handle_get.layer(rate_limit.with_cost(1)))
@@ -28,8 +49,46 @@ pub fn authenticated_routes(rate_limit: &RateLimit) -> Router {
             "/ssh-key/:id",
             delete(ssh_key::handle_delete.layer(rate_limit.with_cost(24))),
         )
+        .route(
+            "/invites",
+            get(invites::handle_get.layer(rate_limit.with_cost(1))),
+        )
+        .route(
+            "/invites/accept-link",
+            post(invites::handle_accept_link.layer(rate_limit.with_cost(24))),
+        )
+        .route(
+            "/invites/:id/accept",
+            post(invites::handle_accept.layer(rate_limit.with_cost(10))),
+        )
+        .route(
+            "/invites/:id/decline",
+            post(invites::handle_decline.layer(rate_limit.with_cost(10))),
+        )
+        .route(
+            "/tokens",
+            get(tokens::handle_get.layer(rate_limit.with_cost(1)))
+                .put(tokens::handle_put.layer(rate_limit.with_cost(24))),
+        )
+        .route(
+            "/tokens/:id",
+            delete(tokens::handle_delete.layer(rate_limit.with_cost(24))),
+        )
 }
 
 pub fn unauthenticated_routes(rate_limit: &RateLimit) -> Router {
-    Router::new().nest("/auth", auth::unauthenticated_routes(rate_limit))
+    Router::new()
+        .nest("/auth", auth::unauthenticated_routes(rate_limit))
+        .nest(
+            "/crates",
+            crates::anonymous_routes(rate_limit).layer(
+                ServiceBuilder::new()
+                    .layer_fn(crate::middleware::anonymous::AnonymousMiddleware)
+                    .into_inner(),
+            ),
+        )
+        .route(
+            "/trusted-publishing/:provider",
+            post(trusted_publishing::handle_exchange.layer(rate_limit.with_cost(50))),
+        )
 }
@@ -0,0 +1,41 @@
+//! Paginated view of an organisation's audit log, given the requesting user has the
+//! `READ_AUDIT_LOG` permission at the organisation level.
+
+use crate::endpoints::web_api::audit_log::{Query, Response};
+
+use axum::{extract, Json};
+use chartered_db::{organisations::Organisation, users::User, ConnectionPool};
+use std::sync::Arc;
+use thiserror::Error;
+
+pub async fn handle_get(
+    extract::Path(organisation): extract::Path<String>,
+    extract::Query(query): extract::Query<Query>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+) -> Result<Json<Response>, Error> {
+    let organisation =
+        Arc::new(Organisation::find_by_name(db.clone(), user.id, organisation).await?);
+
+    let (page, per_page) = query.pagination();
+
+    let log = organisation.audit_log(db, page, per_page).await?;
+
+    Ok(Json(Response::new(&log, page, per_page)))
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Database(#[from] chartered_db::Error),
+}
+
+impl Error {
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        match self {
+            Self::Database(e) => e.status_code(),
+        }
+    }
+}
+
+define_error_response!(Error);
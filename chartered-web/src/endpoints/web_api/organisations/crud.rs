@@ -1,20 +1,113 @@
 //! Allows users to create whole organisations. This endpoint currently isn't limited to any
 //! specific users so all users can create an organisation and add people to it.
+//!
+//! Organisations can optionally be created as a read-through mirror of an upstream registry
+//! that serves a sparse index, such as crates.io. Since the server fetches from the upstream on
+//! the user's behalf, only instance administrators can mirror hosts that aren't in the
+//! `allowed_upstream_hosts` config, and upstreams on internal addresses are refused outright.
+//!
+//! Users with the `MANAGE_ORGANISATION` permission can rename the organisation, update its
+//! details or delete it. Deleted organisations can be restored until their grace period is up,
+//! after which their crates' storage objects are removed.
 
 use axum::{extract, Json};
-use chartered_db::{organisations::Organisation, users::User, ConnectionPool};
-use serde::Deserialize;
+use chartered_db::{
+    audit::Actor,
+    organisations::{Organisation, Upstream},
+    users::User,
+    ConnectionPool,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use thiserror::Error;
+use url::Url;
 
-use crate::endpoints::ErrorResponse;
+use crate::{
+    config::Config,
+    endpoints::ErrorResponse,
+    mirror,
+    outbound::{self, Outbound},
+};
 
 pub async fn handle_put(
     extract::Extension(db): extract::Extension<ConnectionPool>,
     extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(config): extract::Extension<Arc<Config>>,
+    extract::Extension(outbound): extract::Extension<Arc<Outbound>>,
     extract::Json(req): extract::Json<PutRequest>,
 ) -> Result<Json<ErrorResponse>, Error> {
-    Organisation::create(db, req.name, req.description, req.public, user.id).await?;
+    let upstream = match req.upstream {
+        Some(upstream) => Some(upstream.validate(&user, &config, &outbound).await?),
+        None => None,
+    };
+
+    Organisation::create(db, req.name, req.description, req.public, upstream, user.id).await?;
+
+    Ok(Json(ErrorResponse { error: None }))
+}
+
+/// Renames the organisation and/or updates its details, the organisation's previous name will
+/// keep working as an alias for it.
+pub async fn handle_patch(
+    extract::Path(organisation): extract::Path<String>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    extract::Json(req): extract::Json<PatchRequest>,
+) -> Result<Json<ErrorResponse>, Error> {
+    let name = req.name.map(|v| v.trim().to_string());
+    if matches!(&name, Some(v) if v.is_empty()) {
+        return Err(Error::MissingName);
+    }
+
+    // stop admins from locking themselves out of their own organisation
+    if req.require_two_factor == Some(true) && !user.has_two_factor(db.clone()).await? {
+        return Err(Error::TwoFactorNotEnabled);
+    }
+
+    let organisation =
+        Arc::new(Organisation::find_by_name(db.clone(), user.id, organisation).await?);
+
+    organisation
+        .update(
+            db,
+            &actor,
+            name,
+            req.description,
+            req.public,
+            req.require_two_factor,
+        )
+        .await?;
+
+    Ok(Json(ErrorResponse { error: None }))
+}
+
+/// Deletes the organisation, it can be restored until the returned time.
+pub async fn handle_delete(
+    extract::Path(organisation): extract::Path<String>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+) -> Result<Json<DeleteResponse>, Error> {
+    let organisation =
+        Arc::new(Organisation::find_by_name(db.clone(), user.id, organisation).await?);
+
+    let restorable_until = organisation.delete(db, &actor).await?;
+
+    Ok(Json(DeleteResponse {
+        restorable_until: Utc.from_utc_datetime(&restorable_until),
+    }))
+}
+
+/// Restores a deleted organisation, so long as its grace period isn't up.
+pub async fn handle_restore(
+    extract::Path(organisation): extract::Path<String>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+) -> Result<Json<ErrorResponse>, Error> {
+    Organisation::restore(db, &actor, user.id, organisation).await?;
 
     Ok(Json(ErrorResponse { error: None }))
 }
@@ -24,18 +117,112 @@ pub struct PutRequest {
     name: String,
     description: String,
     public: bool,
+    upstream: Option<PutUpstream>,
+}
+
+#[derive(Deserialize)]
+pub struct PutUpstream {
+    index_uri: String,
+    download_uri: String,
+}
+
+impl PutUpstream {
+    async fn validate(
+        self,
+        user: &User,
+        config: &Config,
+        outbound: &Outbound,
+    ) -> Result<Upstream, Error> {
+        let mut index_uri = Url::parse(&self.index_uri).map_err(|_| Error::InvalidUpstream)?;
+        if !matches!(index_uri.scheme(), "http" | "https") {
+            return Err(Error::InvalidUpstream);
+        }
+
+        // index files are resolved relative to this so it needs to be a "directory"
+        if !index_uri.path().ends_with('/') {
+            index_uri.set_path(&format!("{}/", index_uri.path()));
+        }
+
+        // the download uri may contain cargo's `{crate}` style markers so we can only
+        // sanity check the scheme
+        if !self.download_uri.starts_with("http://") && !self.download_uri.starts_with("https://") {
+            return Err(Error::InvalidUpstream);
+        }
+
+        // the download uri can only be checked once it's had its markers filled in
+        let download_url = Url::parse(&mirror::download_url(
+            &self.download_uri,
+            "abcd",
+            "0.0.0",
+            "0",
+        ))
+        .map_err(|_| Error::InvalidUpstream)?;
+
+        if !user.admin {
+            let allowed = |url: &Url| {
+                url.host_str().map_or(false, |host| {
+                    config
+                        .allowed_upstream_hosts
+                        .iter()
+                        .any(|v| v.eq_ignore_ascii_case(host))
+                })
+            };
+
+            if !allowed(&index_uri) || !allowed(&download_url) {
+                return Err(Error::UpstreamNotAllowed);
+            }
+        }
+
+        outbound.check(&index_uri).await?;
+        outbound.check(&download_url).await?;
+
+        Ok(Upstream {
+            index_uri: index_uri.to_string(),
+            download_uri: self.download_uri,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PatchRequest {
+    name: Option<String>,
+    description: Option<String>,
+    public: Option<bool>,
+    /// Whether members must have two-factor authentication enabled to use any of the
+    /// permissions granted to them on the organisation.
+    require_two_factor: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct DeleteResponse {
+    restorable_until: DateTime<Utc>,
 }
 
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("{0}")]
     Database(#[from] chartered_db::Error),
+    #[error("Upstream URIs must be valid http or https URLs")]
+    InvalidUpstream,
+    #[error("Only instance administrators can mirror this upstream")]
+    UpstreamNotAllowed,
+    #[error("Upstream URIs must be publicly reachable: {0}")]
+    DisallowedUpstream(#[from] outbound::Error),
+    #[error("A name must be given for the organisation")]
+    MissingName,
+    #[error("You must enable two-factor authentication before requiring it for the organisation")]
+    TwoFactorNotEnabled,
 }
 
 impl Error {
     pub fn status_code(&self) -> axum::http::StatusCode {
         match self {
             Self::Database(e) => e.status_code(),
+            Self::InvalidUpstream
+            | Self::DisallowedUpstream(_)
+            | Self::MissingName
+            | Self::TwoFactorNotEnabled => axum::http::StatusCode::BAD_REQUEST,
+            Self::UpstreamNotAllowed => axum::http::StatusCode::FORBIDDEN,
         }
     }
 }
@@ -0,0 +1,232 @@
+//! Manages the groups within an organisation and their members. Permissions granted to a group
+//! apply to all of its members on top of any they've been granted directly.
+
+use axum::{extract, Json};
+use chartered_db::{
+    audit::Actor,
+    organisations::Organisation,
+    permissions::{Role, UserPermission},
+    users::User,
+    uuid::Uuid,
+    ConnectionPool,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::endpoints::ErrorResponse;
+
+/// Lists all the groups in the organisation along with their members and the permissions
+/// they've been granted across the organisation.
+pub async fn handle_get(
+    extract::Path(organisation): extract::Path<String>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+) -> Result<Json<GetResponse>, Error> {
+    let organisation =
+        Arc::new(Organisation::find_by_name(db.clone(), user.id, organisation).await?);
+
+    let groups = organisation
+        .groups(db)
+        .await?
+        .into_iter()
+        .map(|(group, members)| GetResponseGroup {
+            uuid: group.uuid.0,
+            name: group.name,
+            permissions: group.permissions,
+            members: members
+                .into_iter()
+                .map(|user| GetResponseGroupMember {
+                    uuid: user.uuid.0,
+                    display_name: user.display_name().to_string(),
+                    picture_url: user.picture_url,
+                })
+                .collect(),
+        })
+        .collect();
+
+    Ok(Json(GetResponse {
+        possible_permissions: UserPermission::names(),
+        implied_permissions: UserPermission::implications(),
+        roles: Role::presets(),
+        groups,
+    }))
+}
+
+/// Creates a new, empty, group in the organisation.
+pub async fn handle_put(
+    extract::Path(organisation): extract::Path<String>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    extract::Json(req): extract::Json<PutRequest>,
+) -> Result<Json<ErrorResponse>, Error> {
+    let name = req.name.trim();
+    if name.is_empty() {
+        return Err(Error::MissingName);
+    }
+
+    let organisation =
+        Arc::new(Organisation::find_by_name(db.clone(), user.id, organisation).await?);
+
+    organisation
+        .create_group(db, &actor, name.to_string(), req.permissions)
+        .await?;
+
+    Ok(Json(ErrorResponse { error: None }))
+}
+
+/// Updates the permissions the group has been granted across the organisation.
+pub async fn handle_patch(
+    extract::Path((organisation, group)): extract::Path<(String, String)>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    extract::Json(req): extract::Json<PatchRequest>,
+) -> Result<Json<ErrorResponse>, Error> {
+    let organisation =
+        Arc::new(Organisation::find_by_name(db.clone(), user.id, organisation).await?);
+
+    organisation
+        .update_group(db, &actor, group, req.permissions)
+        .await?;
+
+    Ok(Json(ErrorResponse { error: None }))
+}
+
+/// Deletes the group, its members lose any permissions they only had through it.
+pub async fn handle_delete(
+    extract::Path((organisation, group)): extract::Path<(String, String)>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+) -> Result<Json<ErrorResponse>, Error> {
+    let organisation =
+        Arc::new(Organisation::find_by_name(db.clone(), user.id, organisation).await?);
+
+    organisation.delete_group(db, &actor, group).await?;
+
+    Ok(Json(ErrorResponse { error: None }))
+}
+
+pub async fn handle_put_member(
+    extract::Path((organisation, group)): extract::Path<(String, String)>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    extract::Json(req): extract::Json<MemberRequest>,
+) -> Result<Json<ErrorResponse>, Error> {
+    let organisation =
+        Arc::new(Organisation::find_by_name(db.clone(), user.id, organisation).await?);
+
+    let action_user = User::find_by_uuid(db.clone(), req.user_uuid)
+        .await?
+        .ok_or(Error::InvalidUserId)?;
+
+    if !organisation
+        .insert_group_member(db, &actor, group, action_user.id)
+        .await?
+    {
+        return Err(Error::AlreadyMember);
+    }
+
+    Ok(Json(ErrorResponse { error: None }))
+}
+
+pub async fn handle_delete_member(
+    extract::Path((organisation, group)): extract::Path<(String, String)>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    extract::Json(req): extract::Json<MemberRequest>,
+) -> Result<Json<ErrorResponse>, Error> {
+    let organisation =
+        Arc::new(Organisation::find_by_name(db.clone(), user.id, organisation).await?);
+
+    let action_user = User::find_by_uuid(db.clone(), req.user_uuid)
+        .await?
+        .ok_or(Error::InvalidUserId)?;
+
+    if !organisation
+        .delete_group_member(db, &actor, group, action_user.id)
+        .await?
+    {
+        return Err(Error::NotMember);
+    }
+
+    Ok(Json(ErrorResponse { error: None }))
+}
+
+#[derive(Serialize)]
+pub struct GetResponse {
+    possible_permissions: &'static [&'static str],
+    implied_permissions: &'static [[UserPermission; 2]],
+    roles: Vec<(Role, UserPermission)>,
+    groups: Vec<GetResponseGroup>,
+}
+
+#[derive(Serialize)]
+pub struct GetResponseGroup {
+    uuid: Uuid,
+    name: String,
+    permissions: UserPermission,
+    members: Vec<GetResponseGroupMember>,
+}
+
+#[derive(Serialize)]
+pub struct GetResponseGroupMember {
+    uuid: Uuid,
+    display_name: String,
+    picture_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PutRequest {
+    name: String,
+    #[serde(
+        default,
+        deserialize_with = "chartered_db::permissions::deserialize_assignable"
+    )]
+    permissions: UserPermission,
+}
+
+#[derive(Deserialize)]
+pub struct PatchRequest {
+    #[serde(deserialize_with = "chartered_db::permissions::deserialize_assignable")]
+    permissions: UserPermission,
+}
+
+#[derive(Deserialize)]
+pub struct MemberRequest {
+    user_uuid: Uuid,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Database(#[from] chartered_db::Error),
+    #[error("A name must be given for the group")]
+    MissingName,
+    #[error("An invalid user id was given")]
+    InvalidUserId,
+    #[error("The user is already a member of the group")]
+    AlreadyMember,
+    #[error("The user isn't a member of the group")]
+    NotMember,
+}
+
+impl Error {
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        use axum::http::StatusCode;
+
+        match self {
+            Self::Database(e) => e.status_code(),
+            Self::MissingName | Self::InvalidUserId | Self::AlreadyMember => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::NotMember => StatusCode::NOT_FOUND,
+        }
+    }
+}
+
+define_error_response!(Error);
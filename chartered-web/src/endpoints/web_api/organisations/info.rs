@@ -4,8 +4,13 @@
 
 use axum::{extract, Json};
 use chartered_db::{
-    organisations::Organisation, permissions::UserPermission, users::User, ConnectionPool,
+    organisations::Organisation,
+    passkeys::UserPasskey,
+    permissions::{Role, UserPermission},
+    users::User,
+    ConnectionPool,
 };
+use chrono::{DateTime, TimeZone, Utc};
 use serde::Serialize;
 use std::sync::Arc;
 use thiserror::Error;
@@ -27,14 +32,23 @@ pub async fn handle_get(
     // fetch both crates and members for the organisation at the same time
     let (crates, users) = tokio::try_join!(
         organisation.clone().crates(db.clone()),
-        organisation.clone().members(db),
+        organisation.clone().members(db.clone()),
     )?;
 
+    // members that have registered a passkey have two-factor authentication enabled, even if
+    // they've not enrolled in TOTP
+    let with_passkeys =
+        UserPasskey::users_with_passkeys(db, users.iter().map(|(v, _, _)| v.id).collect()).await?;
+
     Ok(Json(Response {
+        // the organisation's current name, which may differ from the one requested if it's
+        // since been renamed
+        name: organisation.organisation().name.clone(),
         description: organisation.organisation().description.to_string(),
         // all the permissions the requesting user can give out for this organisation
         possible_permissions: can_manage_users.then(UserPermission::all),
         implied_permissions: can_manage_users.then(UserPermission::implications),
+        roles: can_manage_users.then(Role::presets),
         crates: crates
             .into_iter()
             .map(|v| ResponseCrate {
@@ -44,25 +58,36 @@ pub async fn handle_get(
             .collect(),
         members: users
             .into_iter()
-            .map(|(user, perms)| ResponseUser {
+            .map(|(user, perms, expires_at)| ResponseUser {
                 uuid: user.uuid.0,
                 display_name: user.display_name().to_string(),
+                two_factor_enabled: can_manage_users
+                    .then(|| user.has_totp() || with_passkeys.contains(&user.id)),
                 picture_url: user.picture_url,
                 permissions: can_manage_users.then_some(perms),
+                expires_at: expires_at
+                    .filter(|_| can_manage_users)
+                    .map(|v| Utc.from_utc_datetime(&v)),
             })
             .collect(),
         public: organisation.organisation().public,
+        require_two_factor: organisation.organisation().require_two_factor,
+        upstream_index_uri: organisation.organisation().upstream_index_uri.clone(),
     }))
 }
 
 #[derive(Serialize)]
 pub struct Response {
+    name: String,
     description: String,
     possible_permissions: Option<UserPermission>,
     implied_permissions: Option<&'static [[UserPermission; 2]]>,
+    roles: Option<Vec<(Role, UserPermission)>>,
     crates: Vec<ResponseCrate>,
     members: Vec<ResponseUser>,
     public: bool,
+    require_two_factor: bool,
+    upstream_index_uri: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -77,6 +102,11 @@ pub struct ResponseUser {
     display_name: String,
     picture_url: Option<String>,
     permissions: Option<UserPermission>,
+    /// Whether the member has enabled two-factor authentication, only shown to those that can
+    /// manage members.
+    two_factor_enabled: Option<bool>,
+    /// When the member's permissions lapse, only shown to those that can manage members.
+    expires_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Error, Debug)]
@@ -0,0 +1,199 @@
+//! Manages the pending invites to an organisation. Invites are either addressed to an existing
+//! user by their username, or are a single-use link whose token is only returned once when it's
+//! created, for inviting someone who hasn't signed up yet.
+
+use axum::{extract, Json};
+use chartered_db::{
+    audit::Actor,
+    organisations::Organisation,
+    permissions::{Role, UserPermission},
+    users::User,
+    uuid::Uuid,
+    ConnectionPool,
+};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::endpoints::ErrorResponse;
+
+/// How long an invite is valid for if no expiry is given.
+const DEFAULT_EXPIRY_DAYS: i64 = 7;
+
+/// Lists the organisation's pending invites.
+pub async fn handle_get(
+    extract::Path(organisation): extract::Path<String>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+) -> Result<Json<GetResponse>, Error> {
+    let organisation =
+        Arc::new(Organisation::find_by_name(db.clone(), user.id, organisation).await?);
+
+    let invites = organisation
+        .invites(db)
+        .await?
+        .into_iter()
+        .map(|(invite, invitee)| ResponseInvite {
+            uuid: invite.uuid.0,
+            user: invitee.map(|user| ResponseInviteUser {
+                uuid: user.uuid.0,
+                display_name: user.display_name().to_string(),
+                picture_url: user.picture_url,
+            }),
+            permissions: invite.permissions,
+            expires_at: Utc.from_utc_datetime(&invite.expires_at),
+            created_at: Utc.from_utc_datetime(&invite.created_at),
+        })
+        .collect();
+
+    Ok(Json(GetResponse {
+        possible_permissions: UserPermission::names(),
+        implied_permissions: UserPermission::implications(),
+        roles: Role::presets(),
+        invites,
+    }))
+}
+
+/// Invites a user to the organisation by their username, or creates an invite link if no
+/// username is given.
+pub async fn handle_put(
+    extract::Path(organisation): extract::Path<String>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    extract::Json(req): extract::Json<PutRequest>,
+) -> Result<Json<PutResponse>, Error> {
+    let expires_at = req
+        .expires_at
+        .unwrap_or_else(|| Utc::now() + Duration::days(DEFAULT_EXPIRY_DAYS));
+    if expires_at <= Utc::now() {
+        return Err(Error::ExpiryInPast);
+    }
+
+    let organisation =
+        Arc::new(Organisation::find_by_name(db.clone(), user.id, organisation).await?);
+
+    let invitee = if let Some(username) = req.username {
+        Some(
+            User::find_by_username(db.clone(), username)
+                .await?
+                .ok_or(Error::InvalidUsername)?,
+        )
+    } else {
+        None
+    };
+
+    let (invite, token) = organisation
+        .create_invite(
+            db,
+            &actor,
+            invitee.as_ref().map(|user| user.id),
+            req.permissions,
+            expires_at.naive_utc(),
+        )
+        .await?;
+
+    Ok(Json(PutResponse {
+        token,
+        details: ResponseInvite {
+            uuid: invite.uuid.0,
+            user: invitee.map(|user| ResponseInviteUser {
+                uuid: user.uuid.0,
+                display_name: user.display_name().to_string(),
+                picture_url: user.picture_url,
+            }),
+            permissions: invite.permissions,
+            expires_at: Utc.from_utc_datetime(&invite.expires_at),
+            created_at: Utc.from_utc_datetime(&invite.created_at),
+        },
+    }))
+}
+
+/// Revokes a pending invite.
+pub async fn handle_delete(
+    extract::Path((organisation, invite)): extract::Path<(String, Uuid)>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+) -> Result<Json<ErrorResponse>, Error> {
+    let organisation =
+        Arc::new(Organisation::find_by_name(db.clone(), user.id, organisation).await?);
+
+    if organisation.delete_invite(db, &actor, invite).await? {
+        Ok(Json(ErrorResponse { error: None }))
+    } else {
+        Err(Error::NonExistentInvite)
+    }
+}
+
+#[derive(Serialize)]
+pub struct GetResponse {
+    possible_permissions: &'static [&'static str],
+    implied_permissions: &'static [[UserPermission; 2]],
+    roles: Vec<(Role, UserPermission)>,
+    invites: Vec<ResponseInvite>,
+}
+
+#[derive(Serialize)]
+pub struct ResponseInvite {
+    uuid: Uuid,
+    /// The user the invite is addressed to, or `None` if the invite is a link.
+    user: Option<ResponseInviteUser>,
+    permissions: UserPermission,
+    expires_at: DateTime<Utc>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct ResponseInviteUser {
+    uuid: Uuid,
+    display_name: String,
+    picture_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PutRequest {
+    username: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "chartered_db::permissions::deserialize_assignable"
+    )]
+    permissions: UserPermission,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct PutResponse {
+    /// The token to redeem the invite link with, only returned for invites that aren't
+    /// addressed to a user.
+    token: Option<String>,
+    #[serde(flatten)]
+    details: ResponseInvite,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Database(#[from] chartered_db::Error),
+    #[error("Expiry date must be in the future")]
+    ExpiryInPast,
+    #[error("There is no user with the given username")]
+    InvalidUsername,
+    #[error("The invite given does not exist")]
+    NonExistentInvite,
+}
+
+impl Error {
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        use axum::http::StatusCode;
+
+        match self {
+            Self::Database(e) => e.status_code(),
+            Self::ExpiryInPast | Self::InvalidUsername => StatusCode::BAD_REQUEST,
+            Self::NonExistentInvite => StatusCode::NOT_FOUND,
+        }
+    }
+}
+
+define_error_response!(Error);
@@ -3,8 +3,10 @@
 
 use axum::{extract, Json};
 use chartered_db::{
-    organisations::Organisation, permissions::UserPermission, users::User, ConnectionPool,
+    audit::Actor, organisations::Organisation, permissions::UserPermission, users::User,
+    ConnectionPool,
 };
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
 use std::sync::Arc;
 use thiserror::Error;
@@ -16,8 +18,13 @@ pub async fn handle_patch(
     extract::Path(organisation): extract::Path<String>,
     extract::Extension(db): extract::Extension<ConnectionPool>,
     extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
     extract::Json(req): extract::Json<PutOrPatchRequest>,
 ) -> Result<Json<ErrorResponse>, Error> {
+    if matches!(req.expires_at, Some(v) if v <= Utc::now()) {
+        return Err(Error::ExpiryInPast);
+    }
+
     let organisation =
         Arc::new(Organisation::find_by_name(db.clone(), user.id, organisation).await?);
 
@@ -26,7 +33,13 @@ pub async fn handle_patch(
         .ok_or(Error::InvalidUserId)?;
 
     let affected_rows = organisation
-        .update_permissions(db, action_user.id, req.permissions)
+        .update_permissions(
+            db,
+            &actor,
+            action_user.id,
+            req.permissions,
+            req.expires_at.map(|v| v.naive_utc()),
+        )
         .await?;
     if affected_rows == 0 {
         return Err(Error::UpdateConflictRemoved);
@@ -40,8 +53,13 @@ pub async fn handle_put(
     extract::Path(organisation): extract::Path<String>,
     extract::Extension(db): extract::Extension<ConnectionPool>,
     extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
     extract::Json(req): extract::Json<PutOrPatchRequest>,
 ) -> Result<Json<ErrorResponse>, Error> {
+    if matches!(req.expires_at, Some(v) if v <= Utc::now()) {
+        return Err(Error::ExpiryInPast);
+    }
+
     let organisation =
         Arc::new(Organisation::find_by_name(db.clone(), user.id, organisation).await?);
 
@@ -50,7 +68,13 @@ pub async fn handle_put(
         .ok_or(Error::InvalidUserId)?;
 
     organisation
-        .insert_permissions(db, action_user.id, req.permissions)
+        .insert_permissions(
+            db,
+            &actor,
+            action_user.id,
+            req.permissions,
+            req.expires_at.map(|v| v.naive_utc()),
+        )
         .await?;
 
     Ok(Json(ErrorResponse { error: None }))
@@ -61,6 +85,7 @@ pub async fn handle_delete(
     extract::Path(organisation): extract::Path<String>,
     extract::Extension(db): extract::Extension<ConnectionPool>,
     extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
     extract::Json(req): extract::Json<DeleteRequest>,
 ) -> Result<Json<ErrorResponse>, Error> {
     let organisation =
@@ -70,7 +95,9 @@ pub async fn handle_delete(
         .await?
         .ok_or(Error::InvalidUserId)?;
 
-    organisation.delete_member(db, action_user.id).await?;
+    organisation
+        .delete_member(db, &actor, action_user.id)
+        .await?;
 
     Ok(Json(ErrorResponse { error: None }))
 }
@@ -78,7 +105,10 @@ pub async fn handle_delete(
 #[derive(Deserialize)]
 pub struct PutOrPatchRequest {
     user_uuid: chartered_db::uuid::Uuid,
+    #[serde(deserialize_with = "chartered_db::permissions::deserialize_assignable")]
     permissions: UserPermission,
+    /// When the member's permissions lapse, or `None` if they never should.
+    expires_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Deserialize)]
@@ -94,6 +124,8 @@ pub enum Error {
     UpdateConflictRemoved,
     #[error("An invalid user id was given")]
     InvalidUserId,
+    #[error("Expiry date must be in the future")]
+    ExpiryInPast,
 }
 
 impl Error {
@@ -103,7 +135,7 @@ impl Error {
         match self {
             Self::Database(e) => e.status_code(),
             Self::UpdateConflictRemoved => StatusCode::CONFLICT,
-            Self::InvalidUserId => StatusCode::BAD_REQUEST,
+            Self::InvalidUserId | Self::ExpiryInPast => StatusCode::BAD_REQUEST,
         }
     }
 }
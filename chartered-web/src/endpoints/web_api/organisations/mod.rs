@@ -1,12 +1,16 @@
+mod audit_log;
 mod crud;
+mod groups;
 mod info;
+mod invites;
 mod list;
 mod members;
+mod webhooks;
 
 use crate::middleware::rate_limit::RateLimit;
 use axum::{
     handler::Handler,
-    routing::{get, patch},
+    routing::{delete, get, patch, post, put},
     Router,
 };
 
@@ -19,7 +23,13 @@ pub fn routes(rate_limit: &RateLimit) -> Router {
         )
         .route(
             "/:org",
-            get(info::handle_get.layer(rate_limit.with_cost(1))),
+            get(info::handle_get.layer(rate_limit.with_cost(1)))
+                .patch(crud::handle_patch.layer(rate_limit.with_cost(10)))
+                .delete(crud::handle_delete.layer(rate_limit.with_cost(100))),
+        )
+        .route(
+            "/:org/restore",
+            post(crud::handle_restore.layer(rate_limit.with_cost(100))),
         )
         .route(
             "/:org/members",
@@ -28,4 +38,47 @@ pub fn routes(rate_limit: &RateLimit) -> Router {
                 .delete(members::handle_delete)
                 .layer(rate_limit.with_cost(10)),
         )
+        .route(
+            "/:org/groups",
+            get(groups::handle_get.layer(rate_limit.with_cost(1)))
+                .put(groups::handle_put.layer(rate_limit.with_cost(10))),
+        )
+        .route(
+            "/:org/groups/:group",
+            patch(groups::handle_patch)
+                .delete(groups::handle_delete)
+                .layer(rate_limit.with_cost(10)),
+        )
+        .route(
+            "/:org/groups/:group/members",
+            put(groups::handle_put_member)
+                .delete(groups::handle_delete_member)
+                .layer(rate_limit.with_cost(10)),
+        )
+        .route(
+            "/:org/invites",
+            get(invites::handle_get.layer(rate_limit.with_cost(1)))
+                .put(invites::handle_put.layer(rate_limit.with_cost(10))),
+        )
+        .route(
+            "/:org/invites/:invite",
+            delete(invites::handle_delete.layer(rate_limit.with_cost(10))),
+        )
+        .route(
+            "/:org/audit-log",
+            get(audit_log::handle_get.layer(rate_limit.with_cost(5))),
+        )
+        .route(
+            "/:org/webhooks",
+            get(webhooks::handle_get.layer(rate_limit.with_cost(1)))
+                .put(webhooks::handle_put.layer(rate_limit.with_cost(10))),
+        )
+        .route(
+            "/:org/webhooks/:webhook",
+            delete(webhooks::handle_delete.layer(rate_limit.with_cost(10))),
+        )
+        .route(
+            "/:org/webhooks/:webhook/deliveries",
+            get(webhooks::handle_get_deliveries.layer(rate_limit.with_cost(5))),
+        )
 }
@@ -0,0 +1,228 @@
+//! Manages the webhooks an organisation has subscribed to registry events, and lets
+//! administrators inspect recent deliveries to debug failing receivers. Requires the
+//! `MANAGE_WEBHOOKS` permission at the organisation level.
+
+use axum::{extract, Json};
+use chartered_db::{
+    organisations::Organisation,
+    users::User,
+    uuid::Uuid,
+    webhooks::{OrganisationWebhook, WebhookEvent},
+    ConnectionPool,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use thiserror::Error;
+use url::Url;
+
+use crate::{
+    endpoints::ErrorResponse,
+    outbound::{self, Outbound},
+};
+
+/// Number of recent deliveries to return for a webhook.
+const DELIVERY_LIMIT: i64 = 50;
+
+/// Longest URL and secret that'll fit in the database.
+const MAX_URL_LENGTH: usize = 2048;
+const MAX_SECRET_LENGTH: usize = 255;
+
+pub async fn handle_get(
+    extract::Path(organisation): extract::Path<String>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+) -> Result<Json<GetResponse>, Error> {
+    let organisation =
+        Arc::new(Organisation::find_by_name(db.clone(), user.id, organisation).await?);
+
+    let webhooks = organisation
+        .webhooks(db)
+        .await?
+        .into_iter()
+        .map(ResponseWebhook::from)
+        .collect();
+
+    Ok(Json(GetResponse {
+        possible_events: WebhookEvent::names(),
+        webhooks,
+    }))
+}
+
+/// Subscribes a new URL to the organisation's events. Deliveries are signed with the secret if
+/// one is given, and are sent unsigned otherwise.
+pub async fn handle_put(
+    extract::Path(organisation): extract::Path<String>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(outbound): extract::Extension<Arc<Outbound>>,
+    extract::Json(req): extract::Json<PutRequest>,
+) -> Result<Json<PutResponse>, Error> {
+    let url = Url::parse(&req.url).map_err(|_| Error::InvalidUrl)?;
+    if url.as_str().len() > MAX_URL_LENGTH {
+        return Err(Error::InvalidUrl);
+    }
+
+    // stops the webhook being used to poke at services on our own network, deliveries are
+    // checked again when they're sent
+    outbound.check(&url).await?;
+
+    if matches!(&req.secret, Some(v) if v.len() > MAX_SECRET_LENGTH) {
+        return Err(Error::SecretTooLong);
+    }
+
+    // an empty key would let any receiver forge a valid signature, so it's no better than not
+    // signing deliveries at all
+    if matches!(&req.secret, Some(v) if v.trim().is_empty()) {
+        return Err(Error::EmptySecret);
+    }
+
+    let organisation =
+        Arc::new(Organisation::find_by_name(db.clone(), user.id, organisation).await?);
+
+    let webhook = organisation
+        .create_webhook(db, url.to_string(), req.secret, req.events)
+        .await?;
+
+    Ok(Json(PutResponse {
+        webhook: webhook.into(),
+    }))
+}
+
+pub async fn handle_delete(
+    extract::Path((organisation, webhook)): extract::Path<(String, Uuid)>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+) -> Result<Json<ErrorResponse>, Error> {
+    let organisation =
+        Arc::new(Organisation::find_by_name(db.clone(), user.id, organisation).await?);
+
+    organisation.delete_webhook(db, webhook).await?;
+
+    Ok(Json(ErrorResponse { error: None }))
+}
+
+/// Lists the most recent deliveries for a webhook along with each attempt made to send them.
+pub async fn handle_get_deliveries(
+    extract::Path((organisation, webhook)): extract::Path<(String, Uuid)>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+) -> Result<Json<GetDeliveriesResponse>, Error> {
+    let organisation =
+        Arc::new(Organisation::find_by_name(db.clone(), user.id, organisation).await?);
+
+    let deliveries = organisation
+        .webhook_deliveries(db, webhook, DELIVERY_LIMIT)
+        .await?
+        .into_iter()
+        .map(|(delivery, attempts)| ResponseDelivery {
+            uuid: delivery.uuid.0,
+            event: delivery.event,
+            payload: serde_json::from_slice(&delivery.payload).unwrap_or_default(),
+            attempts: attempts
+                .into_iter()
+                .map(|attempt| ResponseDeliveryAttempt {
+                    response_status: attempt.response_status,
+                    error: attempt.error,
+                    duration_ms: attempt.duration_ms,
+                    attempted_at: Utc.from_utc_datetime(&attempt.attempted_at),
+                })
+                .collect(),
+            delivered_at: delivery.delivered_at.map(|v| Utc.from_utc_datetime(&v)),
+            next_attempt_at: delivery.next_attempt_at.map(|v| Utc.from_utc_datetime(&v)),
+            created_at: Utc.from_utc_datetime(&delivery.created_at),
+        })
+        .collect();
+
+    Ok(Json(GetDeliveriesResponse { deliveries }))
+}
+
+#[derive(Deserialize)]
+pub struct PutRequest {
+    url: String,
+    secret: Option<String>,
+    events: WebhookEvent,
+}
+
+#[derive(Serialize)]
+pub struct GetResponse {
+    possible_events: &'static [&'static str],
+    webhooks: Vec<ResponseWebhook>,
+}
+
+#[derive(Serialize)]
+pub struct PutResponse {
+    webhook: ResponseWebhook,
+}
+
+#[derive(Serialize)]
+pub struct ResponseWebhook {
+    uuid: Uuid,
+    url: String,
+    events: WebhookEvent,
+    created_at: DateTime<Utc>,
+}
+
+impl From<OrganisationWebhook> for ResponseWebhook {
+    fn from(webhook: OrganisationWebhook) -> Self {
+        Self {
+            uuid: webhook.uuid.0,
+            url: webhook.url,
+            events: webhook.events,
+            created_at: Utc.from_utc_datetime(&webhook.created_at),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct GetDeliveriesResponse {
+    deliveries: Vec<ResponseDelivery>,
+}
+
+#[derive(Serialize)]
+pub struct ResponseDelivery {
+    uuid: Uuid,
+    event: String,
+    payload: serde_json::Value,
+    attempts: Vec<ResponseDeliveryAttempt>,
+    delivered_at: Option<DateTime<Utc>>,
+    next_attempt_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct ResponseDeliveryAttempt {
+    response_status: Option<i32>,
+    error: Option<String>,
+    duration_ms: i32,
+    attempted_at: DateTime<Utc>,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Database(#[from] chartered_db::Error),
+    #[error("Webhook URL must be a valid http or https URL")]
+    InvalidUrl,
+    #[error("Webhook URL is not allowed: {0}")]
+    DisallowedUrl(#[from] outbound::Error),
+    #[error("Webhook secret must be at most 255 bytes")]
+    SecretTooLong,
+    #[error("Webhook secret can't be empty, leave it out to send deliveries unsigned")]
+    EmptySecret,
+}
+
+impl Error {
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        use axum::http::StatusCode;
+
+        match self {
+            Self::Database(e) => e.status_code(),
+            Self::InvalidUrl | Self::DisallowedUrl(_) | Self::SecretTooLong | Self::EmptySecret => {
+                StatusCode::BAD_REQUEST
+            }
+        }
+    }
+}
+
+define_error_response!(Error);
@@ -1,7 +1,7 @@
 //! Handles CRD of SSH keys for the requesting user, these are not updatable as SSH keys are
 //! immutable.
 
-use chartered_db::{users::User, ConnectionPool};
+use chartered_db::{audit::Actor, users::User, ConnectionPool};
 
 use axum::{extract, Json};
 use chartered_db::uuid::Uuid;
@@ -41,9 +41,10 @@ pub async fn handle_get(
 pub async fn handle_put(
     extract::Extension(db): extract::Extension<ConnectionPool>,
     extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
     extract::Json(req): extract::Json<PutRequest>,
 ) -> Result<Json<ErrorResponse>, Error> {
-    match user.insert_ssh_key(db, &req.key).await {
+    match user.insert_ssh_key(db, &actor, &req.key).await {
         Ok(()) => Ok(Json(ErrorResponse { error: None })),
         Err(e @ chartered_db::Error::KeyParse(_)) => Err(Error::KeyParse(e)),
         Err(e) => Err(Error::Database(e)),
@@ -53,9 +54,12 @@ pub async fn handle_put(
 pub async fn handle_delete(
     extract::Extension(db): extract::Extension<ConnectionPool>,
     extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
     extract::Path(ssh_key_id): extract::Path<Uuid>,
 ) -> Result<Json<ErrorResponse>, Error> {
-    let deleted = user.delete_user_ssh_key_by_uuid(db, ssh_key_id).await?;
+    let deleted = user
+        .delete_user_ssh_key_by_uuid(db, &actor, ssh_key_id)
+        .await?;
 
     if deleted {
         Ok(Json(ErrorResponse { error: None }))
@@ -0,0 +1,145 @@
+//! Handles CRD of long-lived API tokens for the requesting user, which can be given to cargo in
+//! place of a session key. Tokens can only be revoked, not updated, and the token itself is
+//! only returned once when it's created.
+//!
+//! Tokens can optionally be given a scope, restricting them to a subset of the user's
+//! permissions and to particular crates or organisations.
+
+use chartered_db::{
+    api_tokens::UserApiToken, audit::Actor, scopes::TokenScope, users::User, ConnectionPool,
+};
+
+use axum::{extract, Json};
+use chartered_db::uuid::Uuid;
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::endpoints::ErrorResponse;
+
+pub async fn handle_get(
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+) -> Result<Json<GetResponse>, Error> {
+    let tokens = UserApiToken::list(db, user)
+        .await?
+        .into_iter()
+        .map(ResponseToken::from)
+        .collect();
+
+    Ok(Json(GetResponse { tokens }))
+}
+
+pub async fn handle_put(
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    extract::Json(req): extract::Json<PutRequest>,
+) -> Result<Json<PutResponse>, Error> {
+    let name = req.name.trim().to_string();
+    if name.is_empty() {
+        return Err(Error::MissingName);
+    }
+
+    if matches!(req.expires_at, Some(v) if v <= Utc::now()) {
+        return Err(Error::ExpiryInPast);
+    }
+
+    let (token, inserted) = UserApiToken::generate(
+        db,
+        user,
+        &actor,
+        name,
+        req.expires_at.map(|v| v.naive_utc()),
+        req.scope,
+    )
+    .await?;
+
+    Ok(Json(PutResponse {
+        token,
+        details: inserted.into(),
+    }))
+}
+
+pub async fn handle_delete(
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(user): extract::Extension<Arc<User>>,
+    extract::Extension(actor): extract::Extension<Arc<Actor>>,
+    extract::Path(token_id): extract::Path<Uuid>,
+) -> Result<Json<ErrorResponse>, Error> {
+    if UserApiToken::delete_by_uuid(db, user, &actor, token_id).await? {
+        Ok(Json(ErrorResponse { error: None }))
+    } else {
+        Err(Error::NonExistentToken)
+    }
+}
+
+#[derive(Serialize)]
+pub struct GetResponse {
+    tokens: Vec<ResponseToken>,
+}
+
+#[derive(Serialize)]
+pub struct ResponseToken {
+    uuid: Uuid,
+    name: String,
+    expires_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+    scope: Option<TokenScope>,
+}
+
+impl From<UserApiToken> for ResponseToken {
+    fn from(token: UserApiToken) -> Self {
+        Self {
+            uuid: token.uuid.0,
+            name: token.name,
+            expires_at: token.expires_at.map(|v| Utc.from_utc_datetime(&v)),
+            last_used_at: token.last_used_at.map(|v| Utc.from_utc_datetime(&v)),
+            created_at: Utc.from_utc_datetime(&token.created_at),
+            scope: token.scope,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PutRequest {
+    name: String,
+    expires_at: Option<DateTime<Utc>>,
+    scope: Option<TokenScope>,
+}
+
+#[derive(Serialize)]
+pub struct PutResponse {
+    token: String,
+    #[serde(flatten)]
+    details: ResponseToken,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Database(#[from] chartered_db::Error),
+    #[error("A name must be given for the token")]
+    MissingName,
+    #[error("Expiry date must be in the future")]
+    ExpiryInPast,
+    #[error("The token given does not exist")]
+    NonExistentToken,
+}
+
+impl Error {
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        use axum::http::StatusCode;
+
+        match self {
+            Self::Database(e) => e.status_code(),
+            Self::MissingName | Self::ExpiryInPast | Self::NonExistentToken => {
+                StatusCode::BAD_REQUEST
+            }
+        }
+    }
+}
+
+define_error_response!(Error);
@@ -0,0 +1,119 @@
+//! Exchanges an OIDC ID token handed to a CI job by its provider for a short-lived API token
+//! that can only publish a single crate, provided the job matches one of the crate's trust
+//! policies. This lets CI publish crates without needing any stored secret.
+
+use axum::{extract, Json};
+use chartered_db::{
+    api_tokens::UserApiToken, audit::Actor, permissions::UserPermission, scopes::TokenScope,
+    trusted_publishers::CrateTrustedPublisher, ConnectionPool,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::{net::IpAddr, sync::Arc};
+use thiserror::Error;
+
+use crate::{config::TrustedPublishers, trusted_publishing};
+
+/// How long tokens handed out by this endpoint are valid for, only long enough for the job to
+/// publish.
+const TOKEN_LIFETIME_MINUTES: i64 = 15;
+
+pub async fn handle_exchange(
+    extract::Path(provider): extract::Path<String>,
+    extract::Extension(db): extract::Extension<ConnectionPool>,
+    extract::Extension(trusted_publishers): extract::Extension<Arc<TrustedPublishers>>,
+    user_agent: Option<extract::TypedHeader<headers::UserAgent>>,
+    extract::Extension(addr): extract::Extension<IpAddr>,
+    extract::Json(req): extract::Json<ExchangeRequest>,
+) -> Result<Json<ExchangeResponse>, Error> {
+    let client = trusted_publishers
+        .get(&provider)
+        .ok_or(Error::UnknownProvider)?;
+
+    let claims = trusted_publishing::verify(client, &req.token)?;
+
+    // find the first policy on the crate the job satisfies, if there's none then the job isn't
+    // trusted to publish this crate
+    let (_, user) = CrateTrustedPublisher::find_for_crate(
+        db.clone(),
+        req.organisation.clone(),
+        req.crate_name.clone(),
+        provider,
+    )
+    .await?
+    .into_iter()
+    .find(|(policy, _)| claims.matches(policy))
+    .ok_or(Error::NoMatchingPolicy)?;
+
+    // the token is handed out on behalf of the user that created the policy
+    let actor = Actor {
+        user_id: Some(user.id),
+        ip: Some(addr.to_string()),
+        user_agent: user_agent.map(|extract::TypedHeader(v)| v.as_str().to_string()),
+    };
+
+    let scope = TokenScope {
+        permissions: UserPermission::VISIBLE | UserPermission::PUBLISH_VERSION,
+        crates: vec![req.crate_name],
+        organisations: vec![req.organisation],
+    };
+
+    let expires_at = Utc::now() + Duration::minutes(TOKEN_LIFETIME_MINUTES);
+
+    let (token, _) = UserApiToken::generate(
+        db,
+        Arc::new(user),
+        &actor,
+        format!(
+            "Trusted publishing for {}",
+            claims.workflow_ref.as_deref().unwrap_or(&claims.repository)
+        ),
+        Some(expires_at.naive_utc()),
+        Some(scope),
+    )
+    .await?;
+
+    Ok(Json(ExchangeResponse { token, expires_at }))
+}
+
+#[derive(Deserialize)]
+pub struct ExchangeRequest {
+    organisation: String,
+    #[serde(rename = "crate")]
+    crate_name: String,
+    /// The ID token handed to the job by the CI provider.
+    token: String,
+}
+
+#[derive(Serialize)]
+pub struct ExchangeResponse {
+    token: String,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Database(#[from] chartered_db::Error),
+    #[error("Unknown trusted publishing provider given")]
+    UnknownProvider,
+    #[error("{0}")]
+    Token(#[from] trusted_publishing::Error),
+    #[error("No trust policy on the crate allows this job to publish it")]
+    NoMatchingPolicy,
+}
+
+impl Error {
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        use axum::http::StatusCode;
+
+        match self {
+            Self::Database(e) => e.status_code(),
+            Self::UnknownProvider => StatusCode::NOT_FOUND,
+            Self::Token(_) => StatusCode::UNAUTHORIZED,
+            Self::NoMatchingPolicy => StatusCode::FORBIDDEN,
+        }
+    }
+}
+
+define_error_response!(Error);
@@ -0,0 +1,21 @@
+//! Removes permission grants that have lapsed. `chartered-db` already ignores these as soon as
+//! they expire, this just cleans them up and records the lapse in the audit log.
+
+use chartered_db::{grants, ConnectionPool};
+use std::time::Duration;
+use tracing::{debug, error};
+
+/// Periodically removes any expired permission grants.
+pub async fn delete_expired_grants(db: ConnectionPool) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+
+    loop {
+        interval.tick().await;
+
+        match grants::delete_expired(db.clone()).await {
+            Ok(0) => {}
+            Ok(removed) => debug!("Removed {} expired permission grants", removed),
+            Err(e) => error!("Failed to remove expired permission grants: {}", e),
+        }
+    }
+}
@@ -0,0 +1,86 @@
+//! Maps groups users belong to in an external directory, such as an LDAP server or the groups
+//! claim of an `OpenID` Connect provider, onto permissions on organisations. Memberships are
+//! reconciled on every login so users leaving a group lose the access it gave them.
+
+use chartered_db::permissions::{deserialize_assignable, UserPermission};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct GroupMapping {
+    /// Name of the group in the directory, compared case-insensitively.
+    pub group: String,
+    /// Organisation members of the group are added to.
+    pub organisation: String,
+    /// Permissions given to members of the group, as a list of permissions or a role.
+    #[serde(deserialize_with = "deserialize_assignable")]
+    pub permissions: UserPermission,
+}
+
+/// Works out the permissions the user should have on every organisation referenced by
+/// `mappings`, given the groups they belong to. Permissions from every matching group are
+/// combined, and organisations none of their groups match are given empty permissions so the
+/// user is removed from them.
+#[must_use]
+pub fn memberships(mappings: &[GroupMapping], groups: &[String]) -> Vec<(String, UserPermission)> {
+    let mut memberships = BTreeMap::new();
+
+    for mapping in mappings {
+        let permissions = memberships
+            .entry(mapping.organisation.clone())
+            .or_insert_with(UserPermission::empty);
+
+        if groups
+            .iter()
+            .any(|v| v.eq_ignore_ascii_case(&mapping.group))
+        {
+            *permissions |= mapping.permissions;
+        }
+    }
+
+    memberships.into_iter().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{memberships, GroupMapping};
+    use chartered_db::permissions::UserPermission;
+
+    fn mapping(group: &str, organisation: &str, permissions: UserPermission) -> GroupMapping {
+        GroupMapping {
+            group: group.to_string(),
+            organisation: organisation.to_string(),
+            permissions,
+        }
+    }
+
+    #[test]
+    fn combines_groups_and_removes_unmatched_organisations() {
+        let mappings = [
+            mapping("cn=dev,ou=groups", "core", UserPermission::VISIBLE),
+            mapping(
+                "cn=release,ou=groups",
+                "core",
+                UserPermission::PUBLISH_VERSION,
+            ),
+            mapping("cn=ops,ou=groups", "infra", UserPermission::VISIBLE),
+        ];
+
+        let groups = [
+            "CN=Dev,OU=Groups".to_string(),
+            "cn=release,ou=groups".to_string(),
+        ];
+
+        assert_eq!(
+            memberships(&mappings, &groups),
+            vec![
+                (
+                    "core".to_string(),
+                    UserPermission::VISIBLE | UserPermission::PUBLISH_VERSION
+                ),
+                ("infra".to_string(), UserPermission::empty()),
+            ]
+        );
+    }
+}
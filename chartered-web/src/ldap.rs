@@ -0,0 +1,176 @@
+//! Authenticates users against an LDAP directory. The user's entry is found using the
+//! configured search account, then their password is checked by binding as them, so we never
+//! need to read password hashes from the directory.
+//!
+//! The two steps are separate so callers can find out who the directory considers the user to
+//! be, which may differ from what they typed in, before checking their password.
+
+use crate::config::LdapConfig;
+
+use ldap3::{ldap_escape, Ldap, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+use std::collections::HashMap;
+
+/// LDAP result code for a bind with the wrong password, or for an entry that doesn't exist.
+const INVALID_CREDENTIALS: u32 = 49;
+
+/// A user that has successfully authenticated against the directory.
+#[derive(Debug)]
+pub struct DirectoryUser {
+    pub username: String,
+    pub name: Option<String>,
+    pub email: Option<String>,
+    /// DNs of the groups the user belongs to.
+    pub groups: Vec<String>,
+}
+
+/// Builds the filter used to find the user's entry, escaping the username so it can't be used
+/// to inject its own filter.
+fn user_filter(template: &str, username: &str) -> String {
+    template.replace("{username}", &ldap_escape(username))
+}
+
+/// Takes the first value of the attribute from the entry, if it has one.
+fn first(attrs: &mut HashMap<String, Vec<String>>, attribute: &str) -> Option<String> {
+    attrs
+        .remove(attribute)
+        .and_then(|v| v.into_iter().next())
+        .filter(|v| !v.is_empty())
+}
+
+/// A user's entry in the directory, which has been found but not yet authenticated.
+pub struct DirectoryEntry {
+    ldap: Ldap,
+    dn: String,
+    user: DirectoryUser,
+}
+
+impl DirectoryEntry {
+    /// The user's username as the directory has it, which is what their account is keyed on.
+    #[must_use]
+    pub fn username(&self) -> &str {
+        &self.user.username
+    }
+
+    /// Checks the password by binding as the user, returning `None` if it's wrong.
+    pub async fn authenticate(
+        mut self,
+        password: &str,
+    ) -> Result<Option<DirectoryUser>, ldap3::LdapError> {
+        // most servers treat a bind with an empty password as an anonymous bind, which would
+        // succeed for any user
+        if password.is_empty() {
+            return Ok(None);
+        }
+
+        let bind = self.ldap.simple_bind(&self.dn, password).await?;
+        if bind.rc == INVALID_CREDENTIALS {
+            return Ok(None);
+        }
+        bind.success()?;
+
+        let _ = self.ldap.unbind().await;
+
+        Ok(Some(self.user))
+    }
+}
+
+/// Finds the user's entry in the directory, returning `None` if they don't exist.
+pub async fn find(
+    config: &LdapConfig,
+    username: &str,
+) -> Result<Option<DirectoryEntry>, ldap3::LdapError> {
+    let (conn, mut ldap) = LdapConnAsync::with_settings(
+        LdapConnSettings::new().set_starttls(config.starttls),
+        &config.url,
+    )
+    .await?;
+    ldap3::drive!(conn);
+
+    if let (Some(bind_dn), Some(bind_password)) = (&config.bind_dn, &config.bind_password) {
+        ldap.simple_bind(bind_dn, bind_password).await?.success()?;
+    }
+
+    let (entries, _) = ldap
+        .search(
+            &config.user_base_dn,
+            Scope::Subtree,
+            &user_filter(&config.user_filter, username),
+            vec![
+                config.username_attribute.as_str(),
+                config.name_attribute.as_str(),
+                config.email_attribute.as_str(),
+                config.group_attribute.as_str(),
+            ],
+        )
+        .await?
+        .success()?;
+
+    // a filter matching more than one entry is ambiguous, so we don't pick one
+    let mut entry = match <[_; 1]>::try_from(entries) {
+        Ok([entry]) => SearchEntry::construct(entry),
+        Err(_) => return Ok(None),
+    };
+
+    Ok(Some(DirectoryEntry {
+        ldap,
+        user: DirectoryUser {
+            username: first(&mut entry.attrs, &config.username_attribute)
+                .unwrap_or_else(|| username.to_string()),
+            name: first(&mut entry.attrs, &config.name_attribute),
+            email: first(&mut entry.attrs, &config.email_attribute),
+            groups: entry
+                .attrs
+                .remove(&config.group_attribute)
+                .unwrap_or_default(),
+        },
+        dn: entry.dn,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::LdapConfig;
+
+    #[test]
+    fn user_filter_escapes_username() {
+        assert_eq!(
+            super::user_filter("(uid={username})", "jordan"),
+            "(uid=jordan)"
+        );
+        assert_eq!(
+            super::user_filter("(uid={username})", "*)(uid=*"),
+            "(uid=\\2a\\29\\28uid=\\2a)"
+        );
+    }
+
+    /// Runs against a local server, such as the `bitnami/openldap` image started with its
+    /// defaults (`docker run -p 1389:1389 bitnami/openldap`), which creates `user01` with the
+    /// password `bitnami1` under `ou=users,dc=example,dc=org`.
+    #[tokio::test]
+    #[ignore]
+    async fn authenticates_against_local_server() {
+        let config: LdapConfig = toml::from_str(&format!(
+            r#"
+                enabled = true
+                url = "{}"
+                bind_dn = "cn=admin,dc=example,dc=org"
+                bind_password = "adminpassword"
+                user_base_dn = "ou=users,dc=example,dc=org"
+            "#,
+            std::env::var("CHARTERED_TEST_LDAP_URL")
+                .unwrap_or_else(|_| "ldap://127.0.0.1:1389".to_string()),
+        ))
+        .unwrap();
+
+        let find = || async { super::find(&config, "user01").await.unwrap().unwrap() };
+
+        let entry = find().await;
+        assert_eq!(entry.username(), "user01");
+        let user = entry.authenticate("bitnami1").await.unwrap().unwrap();
+        assert_eq!(user.username, "user01");
+
+        assert!(find().await.authenticate("wrong").await.unwrap().is_none());
+        assert!(find().await.authenticate("").await.unwrap().is_none());
+        assert!(super::find(&config, "nobody").await.unwrap().is_none());
+    }
+}
@@ -2,9 +2,23 @@
 #![deny(rust_2018_idioms)]
 #![allow(clippy::module_name_repetitions)]
 
+mod asymmetric_token;
 mod config;
 mod endpoints;
+mod expired_grants;
+mod group_mappings;
+mod ldap;
 mod middleware;
+mod mirror;
+mod outbound;
+mod passkeys;
+mod saml;
+mod storage_deletions;
+#[cfg(all(test, feature = "sqlite"))]
+mod testing;
+mod totp;
+mod trusted_publishing;
+mod webhooks;
 
 use crate::middleware::ip::AddIp;
 use crate::middleware::rate_limit::RateLimit;
@@ -13,14 +27,15 @@ use axum::{
     routing::get,
     Extension, Router,
 };
+use chartered_db::{downloads::DownloadTracker, ConnectionPool};
 use clap::{crate_name, crate_version, Parser};
 use governor::Quota;
 use nonzero_ext::nonzero;
-use std::{fmt::Formatter, path::PathBuf, sync::Arc};
+use std::{fmt::Formatter, path::PathBuf, sync::Arc, time::Duration};
 use thiserror::Error;
 use tower::ServiceBuilder;
 use tower_http::cors::{AllowOrigin, CorsLayer};
-use tracing::info;
+use tracing::{error, info};
 use url::Url;
 
 #[derive(Parser)]
@@ -37,6 +52,54 @@ async fn hello_world() -> &'static str {
     "hello, world!"
 }
 
+/// Periodically writes the download counts aggregated by the `DownloadTracker` out to the
+/// database.
+async fn flush_download_counts(download_tracker: Arc<DownloadTracker>, db: ConnectionPool) {
+    let mut interval = tokio::time::interval(Duration::from_secs(30));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = download_tracker.clone().flush(db.clone()).await {
+            error!("Failed to flush download counts: {}", e);
+        }
+    }
+}
+
+/// Resolves once the server's been asked to shut down, either by ctrl-c or a `SIGTERM` from
+/// whatever is supervising it.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for ctrl-c: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => {},
+        () = terminate => {},
+    }
+
+    info!("Shutting down");
+}
+
 #[tokio::main]
 #[allow(clippy::semicolon_if_nothing_returned)] // lint breaks with tokio::main
 async fn main() -> Result<(), InitError> {
@@ -61,6 +124,7 @@ async fn main() -> Result<(), InitError> {
 
     let bind_address = config.bind_address;
     let pool = chartered_db::init(&config.database_uri)?;
+    chartered_db::users::User::promote_admins(pool.clone(), config.admins.clone()).await?;
 
     // the base stack of middleware that is applied to _all_ routes
     let middleware_stack = ServiceBuilder::new()
@@ -68,12 +132,31 @@ async fn main() -> Result<(), InitError> {
         .into_inner();
 
     let config = Arc::new(config);
-    let http_client = reqwest::Client::builder()
-        .user_agent(format!("{}/{}", crate_name!(), crate_version!()))
-        .build()?;
+    let user_agent = format!("{}/{}", crate_name!(), crate_version!());
+    let http_client = reqwest::Client::builder().user_agent(&user_agent).build()?;
+    let outbound = Arc::new(outbound::Outbound::new(
+        user_agent,
+        config.allowed_internal_hosts.clone(),
+    ));
 
     let rate_limit = RateLimit::new(Quota::per_hour(nonzero!(5000_u32)));
 
+    let download_tracker = Arc::new(DownloadTracker::default());
+    tokio::spawn(flush_download_counts(
+        download_tracker.clone(),
+        pool.clone(),
+    ));
+
+    tokio::spawn(webhooks::deliver_webhooks(pool.clone(), outbound.clone()));
+
+    tokio::spawn(expired_grants::delete_expired_grants(pool.clone()));
+
+    let fs = Arc::new(config.get_file_system().await?);
+    tokio::spawn(storage_deletions::delete_storage_objects(
+        pool.clone(),
+        fs.clone(),
+    ));
+
     let app = Router::new()
         .route("/", get(hello_world))
         .nest(
@@ -96,6 +179,30 @@ async fn main() -> Result<(), InitError> {
                     .into_inner(),
             ),
         )
+        .nest(
+            "/a/:key/o/:organisation/index",
+            endpoints::cargo_index::routes(&rate_limit).layer(
+                ServiceBuilder::new()
+                    .layer_fn(crate::middleware::cargo_auth::CargoAuthMiddleware)
+                    .into_inner(),
+            ),
+        )
+        .nest(
+            "/o/:organisation/api/v1",
+            endpoints::cargo_api::routes(&rate_limit).layer(
+                ServiceBuilder::new()
+                    .layer_fn(crate::middleware::cargo_auth::CargoAuthMiddleware)
+                    .into_inner(),
+            ),
+        )
+        .nest(
+            "/o/:organisation/index",
+            endpoints::cargo_index::routes(&rate_limit).layer(
+                ServiceBuilder::new()
+                    .layer_fn(crate::middleware::cargo_auth::CargoAuthMiddleware)
+                    .into_inner(),
+            ),
+        )
         .layer(middleware_stack)
         .layer(
             CorsLayer::new()
@@ -124,20 +231,38 @@ async fn main() -> Result<(), InitError> {
                 }))
                 .allow_credentials(true),
         )
-        .layer(Extension(pool))
+        .layer(Extension(pool.clone()))
+        .layer(Extension(download_tracker.clone()))
         .layer(Extension(Arc::new(config.create_oidc_clients().await?)))
-        .layer(Extension(Arc::new(config.get_file_system().await?)))
+        .layer(Extension(Arc::new(
+            config.create_saml_providers(&http_client).await?,
+        )))
+        .layer(Extension(Arc::new(
+            config.create_trusted_publishers().await?,
+        )))
+        .layer(Extension(Arc::new(passkeys::build(
+            &config.frontend_base_uri,
+        )?)))
+        .layer(Extension(fs))
         .layer(Extension(config.clone()))
         .layer(Extension(http_client))
+        .layer(Extension(outbound))
         .layer(AddIp::new(config.trusted_ip_header.clone()));
 
     info!("HTTP server listening on {}", bind_address);
 
     axum::Server::bind(&bind_address)
         .serve(app.into_make_service_with_connect_info::<std::net::SocketAddr>())
+        .with_graceful_shutdown(shutdown_signal())
         .await
         .map_err(|e| InitError::ServerSpawn(Box::new(e)))?;
 
+    // in-flight requests have all finished by now, so this picks up every download since the
+    // last periodic flush rather than leaving them to be dropped with the process
+    if let Err(e) = download_tracker.flush(pool).await {
+        error!("Failed to flush download counts on shutdown: {}", e);
+    }
+
     Ok(())
 }
 
@@ -157,6 +282,8 @@ pub enum InitError {
     Cors(axum::http::header::InvalidHeaderValue),
     #[error("Failed to initialise reqwest client: {0}")]
     Reqwest(#[from] reqwest::Error),
+    #[error("Failed to configure WebAuthn: {0}")]
+    WebAuthn(#[from] webauthn_rs::prelude::WebauthnError),
 }
 
 impl std::fmt::Debug for InitError {
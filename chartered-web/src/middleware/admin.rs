@@ -0,0 +1,132 @@
+//! Restricts the wrapped routes to instance administrators. Must run after `WebAuthMiddleware`
+//! so the user is available, and rejects scoped sessions so an API token can never be used to
+//! administer the instance.
+
+use axum::{
+    body::{boxed, Body, BoxBody},
+    http::{Request, Response, StatusCode},
+};
+use chartered_db::{scopes::TokenScope, users::User};
+use futures::future::BoxFuture;
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tower::Service;
+
+use crate::endpoints::ErrorResponse;
+
+#[derive(Clone)]
+pub struct AdminMiddleware<S>(pub S);
+
+impl<S, ReqBody> Service<Request<ReqBody>> for AdminMiddleware<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // best practice is to clone the inner service like this
+        // see https://github.com/tower-rs/tower/issues/547 for details
+        let clone = self.0.clone();
+        let mut inner = std::mem::replace(&mut self.0, clone);
+
+        Box::pin(async move {
+            let is_admin = req
+                .extensions()
+                .get::<Arc<User>>()
+                .map_or(false, |user| user.admin);
+            let is_unscoped = req
+                .extensions()
+                .get::<Arc<TokenScope>>()
+                .map_or(false, |scope| **scope == TokenScope::default());
+
+            if !is_admin || !is_unscoped {
+                return Ok(Response::builder()
+                    .status(StatusCode::FORBIDDEN)
+                    .body(boxed(Body::from(
+                        serde_json::to_vec(&ErrorResponse {
+                            error: Some("Instance administrator access is required".into()),
+                        })
+                        .unwrap(),
+                    )))
+                    .unwrap());
+            }
+
+            // calls handlers/other middleware and drives the request to response
+            let response: Response<BoxBody> = inner.call(req).await?;
+
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AdminMiddleware;
+    use axum::{
+        body::{boxed, Body, BoxBody},
+        http::{Request, Response, StatusCode},
+    };
+    use chartered_db::{permissions::UserPermission, scopes::TokenScope, users::User};
+    use std::{convert::Infallible, sync::Arc};
+    use tower::{service_fn, ServiceExt};
+
+    async fn call(user: Option<User>, scope: Option<TokenScope>) -> StatusCode {
+        let inner = service_fn(|_req: Request<Body>| async {
+            Ok::<_, Infallible>(Response::<BoxBody>::new(boxed(Body::empty())))
+        });
+
+        let mut req = Request::new(Body::empty());
+        if let Some(user) = user {
+            req.extensions_mut().insert(Arc::new(user));
+        }
+        if let Some(scope) = scope {
+            req.extensions_mut().insert(Arc::new(scope));
+        }
+
+        AdminMiddleware(inner).oneshot(req).await.unwrap().status()
+    }
+
+    fn admin() -> User {
+        User {
+            admin: true,
+            ..User::anonymous()
+        }
+    }
+
+    #[tokio::test]
+    async fn admins_with_unscoped_sessions_are_let_through() {
+        assert_eq!(
+            call(Some(admin()), Some(TokenScope::default())).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn everyone_else_is_rejected() {
+        assert_eq!(
+            call(Some(User::anonymous()), Some(TokenScope::default())).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(call(None, None).await, StatusCode::FORBIDDEN);
+        assert_eq!(call(Some(admin()), None).await, StatusCode::FORBIDDEN);
+
+        let scoped = TokenScope {
+            permissions: UserPermission::VISIBLE,
+            ..TokenScope::default()
+        };
+        assert_eq!(
+            call(Some(admin()), Some(scoped)).await,
+            StatusCode::FORBIDDEN
+        );
+    }
+}
@@ -0,0 +1,59 @@
+//! Runs the request as an anonymous user, for endpoints that can be used to browse public
+//! organisations without logging in. Returns a 401 if the server doesn't allow anonymous access.
+
+use axum::{
+    body::{boxed, Body, BoxBody},
+    extract::RequestParts,
+    http::{Request, Response, StatusCode},
+};
+use futures::future::BoxFuture;
+use std::task::{Context, Poll};
+use tower::Service;
+
+use crate::endpoints::ErrorResponse;
+
+#[derive(Clone)]
+pub struct AnonymousMiddleware<S>(pub S);
+
+impl<S, ReqBody> Service<Request<ReqBody>> for AnonymousMiddleware<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // best practice is to clone the inner service like this
+        // see https://github.com/tower-rs/tower/issues/547 for details
+        let clone = self.0.clone();
+        let mut inner = std::mem::replace(&mut self.0, clone);
+
+        Box::pin(async move {
+            let mut req = RequestParts::new(req);
+
+            if !super::insert_anonymous(&mut req) {
+                return Ok(Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .body(boxed(Body::from(
+                        serde_json::to_vec(&ErrorResponse {
+                            error: Some("Anonymous access is disabled".into()),
+                        })
+                        .unwrap(),
+                    )))
+                    .unwrap());
+            }
+
+            // calls handlers/other middleware and drives the request to response
+            let response: Response<BoxBody> = inner.call(req.try_into_request().unwrap()).await?;
+
+            Ok(response)
+        })
+    }
+}
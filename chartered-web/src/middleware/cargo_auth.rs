@@ -1,12 +1,19 @@
-//! Check the API key embedded in the path is valid otherwise returns a 401 for authenticated
-//! endpoints.
+//! Check the API key given by cargo is valid otherwise returns a 401 for authenticated
+//! endpoints. The key can either be a session key handed out by `chartered-git`, one of the
+//! user's long-lived API tokens, or an asymmetric token signed by one of the user's registered
+//! cargo keys.
+//!
+//! Cargo sends the key in the `Authorization` header for registries with `auth-required` set,
+//! otherwise we fall back to the key embedded in the path by older index configs. If no key is
+//! given at all and the server allows anonymous access, the request continues as an anonymous
+//! user instead.
 
 use axum::{
     body::{boxed, Body, BoxBody},
     extract::{self, FromRequest, RequestParts},
-    http::{Request, Response, StatusCode},
+    http::{header, HeaderMap, Request, Response, StatusCode},
 };
-use chartered_db::{users::User, ConnectionPool};
+use chartered_db::{api_tokens::UserApiToken, scopes::TokenScope, users::User, ConnectionPool};
 use futures::future::BoxFuture;
 use std::{
     collections::HashMap,
@@ -15,7 +22,7 @@ use std::{
 };
 use tower::Service;
 
-use crate::endpoints::ErrorResponse;
+use crate::{asymmetric_token, config::Config, endpoints::ErrorResponse};
 
 #[derive(Clone)]
 pub struct CargoAuthMiddleware<S>(pub S);
@@ -44,53 +51,103 @@ where
             let mut req = RequestParts::new(req);
 
             // extracts all parameters from the path so we can get the API key which should
-            // always be named key
+            // always be named key, if the key was given in the path at all
             let params = extract::Path::<HashMap<String, String>>::from_request(&mut req)
                 .await
                 .unwrap();
-            let key = params.get("key").map(String::as_str).unwrap_or_default();
+
+            // an empty header shouldn't stop us from falling back to the key in the path
+            let key = authorization_token(req.headers())
+                .filter(|key| !key.is_empty())
+                .or_else(|| params.get("key").map(String::as_str))
+                .filter(|key| !key.is_empty())
+                .map(ToString::to_string);
+
+            let key = match key {
+                Some(key) => key,
+                None if super::insert_anonymous(&mut req) => {
+                    // anonymous users can only ever see crates in public organisations, so
+                    // they'll fail the permission checks for anything else
+                    return inner.call(req.try_into_request().unwrap()).await;
+                }
+                None => return Ok(unauthorized("Missing auth token")),
+            };
 
             // grab the ConnectionPool from the extensions created when we initialised the
             // server
             let db = req.extensions().get::<ConnectionPool>().unwrap().clone();
 
-            // grab the UserSession that's currently being used for this request and the User that
-            // owns the key, otherwise return a 401 if the key doesn't exist
-            let (session, user) = match User::find_by_session_key(db, String::from(key))
-                .await
-                .unwrap()
-            {
-                Some((session, user)) => (Arc::new(session), Arc::new(user)),
-                None => {
-                    return Ok(Response::builder()
-                        .status(StatusCode::UNAUTHORIZED)
-                        .body(boxed(Body::from(
-                            serde_json::to_vec(&ErrorResponse {
-                                error: Some("Expired auth token".into()),
-                            })
-                            .unwrap(),
-                        )))
-                        .unwrap())
+            if asymmetric_token::is_asymmetric_token(&key) {
+                let organisation = params.get("organisation").map_or("", String::as_str);
+
+                // verify the token was signed by one of the user's registered keys for this
+                // organisation, otherwise return a 401 explaining why it was rejected
+                let config = req.extensions().get::<Arc<Config>>().unwrap().clone();
+
+                let (user, claims) =
+                    match asymmetric_token::authenticate(db, &config, &key, organisation).await {
+                        Ok((user, claims)) => (Arc::new(user), Arc::new(claims)),
+                        Err(e) => return Ok(unauthorized(&e.to_string())),
+                    };
+
+                let actor = Arc::new(super::actor_for_request(&req, &user));
+
+                // keys can't be scoped, the token itself is bound to a single mutation instead
+                // which handlers check against the claims
+                req.extensions_mut().insert(user);
+                req.extensions_mut().insert(actor);
+                req.extensions_mut().insert(Arc::new(TokenScope::default()));
+                req.extensions_mut().insert(claims);
+            } else if UserApiToken::is_api_token(&key) {
+                // grab the API token and the User that owns it, otherwise return a 401 if the
+                // token doesn't exist or has expired
+                let (token, user) = match UserApiToken::find_by_token(db, key).await.unwrap() {
+                    Some((token, user)) => (Arc::new(token), Arc::new(user)),
+                    None => return Ok(unauthorized("Expired auth token")),
+                };
+
+                let actor = Arc::new(super::actor_for_request(&req, &user));
+                let scope = Arc::new(token.scope());
+
+                req.extensions_mut().insert(user);
+                req.extensions_mut().insert(actor);
+                req.extensions_mut().insert(scope);
+                req.extensions_mut().insert(token);
+            } else {
+                // grab the UserSession that's currently being used for this request and the User
+                // that owns the key, otherwise return a 401 if the key doesn't exist
+                let (session, user) = match User::find_by_session_key(db, key).await.unwrap() {
+                    Some((session, user)) => (Arc::new(session), Arc::new(user)),
+                    None => return Ok(unauthorized("Expired auth token")),
+                };
+
+                if session.user_ssh_key_id.is_none() {
+                    // Web sessions can't be used for the Cargo API
+                    return Ok(unauthorized("Invalid auth token"));
                 }
-            };
 
-            if session.user_ssh_key_id.is_none() {
-                // Web sessions can't be used for the Cargo API
-                return Ok(Response::builder()
-                    .status(StatusCode::UNAUTHORIZED)
-                    .body(boxed(Body::from(
-                        serde_json::to_vec(&ErrorResponse {
-                            error: Some("Invalid auth token".into()),
-                        })
-                        .unwrap(),
-                    )))
-                    .unwrap());
+                let actor = Arc::new(super::actor_for_request(&req, &user));
+                let scope = Arc::new(session.scope());
+
+                // insert both the user and the session into extensions so handlers can
+                // get their hands on them, along with the actor any audited actions should be
+                // attributed to and the scope the session is restricted to
+                req.extensions_mut().insert(user);
+                req.extensions_mut().insert(actor);
+                req.extensions_mut().insert(scope);
+                req.extensions_mut().insert(session);
             }
 
-            // insert both the user and the session into extensions so handlers can
-            // get their hands on them
-            req.extensions_mut().insert(user);
-            req.extensions_mut().insert(session);
+            // every way of authenticating above resolves to a user, none of which can be used
+            // by disabled users
+            let disabled = req
+                .extensions()
+                .get::<Arc<User>>()
+                .map_or(false, |user| user.is_disabled());
+
+            if disabled {
+                return Ok(unauthorized("Account disabled"));
+            }
 
             // calls handlers/other middleware and drives the request to response
             let response: Response<BoxBody> = inner.call(req.try_into_request().unwrap()).await?;
@@ -99,3 +156,81 @@ where
         })
     }
 }
+
+fn unauthorized(error: &str) -> Response<BoxBody> {
+    Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .body(boxed(Body::from(
+            serde_json::to_vec(&ErrorResponse {
+                error: Some(error.into()),
+            })
+            .unwrap(),
+        )))
+        .unwrap()
+}
+
+/// Grabs the token from the `Authorization` header. Cargo sends the token as-is, but we also
+/// accept the more conventional `Bearer` scheme.
+fn authorization_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?.trim();
+
+    Some(value.strip_prefix("Bearer ").unwrap_or(value).trim())
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod test {
+    use super::CargoAuthMiddleware;
+    use crate::testing;
+    use axum::{
+        body::Body,
+        http::{header, Request, StatusCode},
+        routing::get,
+        Extension, Router,
+    };
+    use chartered_db::{api_tokens::UserApiToken, audit::Actor, users::User, ConnectionPool};
+    use std::sync::Arc;
+    use tower::{ServiceBuilder, ServiceExt};
+
+    async fn call(db: &ConnectionPool, user: Arc<User>) -> StatusCode {
+        // tokens are revoked when a user is disabled, so this is created afterwards
+        let (token, _) = UserApiToken::generate(
+            db.clone(),
+            user,
+            &Actor::default(),
+            "test".to_string(),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+        let app = Router::new()
+            .nest(
+                "/o/:organisation/api/v1",
+                Router::new().route("/ping", get(|| async { "ok" })).layer(
+                    ServiceBuilder::new()
+                        .layer_fn(CargoAuthMiddleware)
+                        .into_inner(),
+                ),
+            )
+            .layer(Extension(db.clone()));
+
+        let req = Request::builder()
+            .uri("/o/org/api/v1/ping")
+            .header(header::AUTHORIZATION, token)
+            .body(Body::empty())
+            .unwrap();
+
+        app.oneshot(req).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn disabled_users_are_rejected() {
+        let db = testing::pool();
+        let user = testing::user(&db, "user").await;
+        let disabled = testing::disabled_user(&db, "disabled").await;
+
+        assert_eq!(call(&db, user).await, StatusCode::OK);
+        assert_eq!(call(&db, disabled).await, StatusCode::UNAUTHORIZED);
+    }
+}
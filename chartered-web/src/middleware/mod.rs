@@ -1,5 +1,49 @@
+use axum::extract::RequestParts;
+use chartered_db::{audit::Actor, scopes::TokenScope, users::User};
+use std::{net::IpAddr, sync::Arc};
+
+use crate::config::Config;
+
+pub mod admin;
+pub mod anonymous;
 pub mod cargo_auth;
 pub mod ip;
 pub mod logging;
 pub mod rate_limit;
 pub mod web_auth;
+
+/// Builds the `Actor` that any audited actions taken during this request will be attributed to.
+fn actor_for_request<B>(req: &RequestParts<B>, user: &User) -> Actor {
+    Actor {
+        user_id: (!user.is_anonymous()).then(|| user.id),
+        ip: req.extensions().get::<IpAddr>().map(ToString::to_string),
+        user_agent: req
+            .headers()
+            .get(axum::http::header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(ToString::to_string),
+    }
+}
+
+/// Inserts an anonymous user into the request, restricted to viewing crates in public
+/// organisations. Returns `false` if the server doesn't allow anonymous access.
+fn insert_anonymous<B>(req: &mut RequestParts<B>) -> bool {
+    let allow_anonymous = req
+        .extensions()
+        .get::<Arc<Config>>()
+        .map_or(false, |config| config.allow_anonymous);
+
+    if !allow_anonymous {
+        return false;
+    }
+
+    let user = Arc::new(User::anonymous());
+    let actor = Arc::new(actor_for_request(req, &user));
+
+    req.extensions_mut().insert(user);
+    req.extensions_mut().insert(actor);
+    req.extensions_mut()
+        .insert(Arc::new(TokenScope::anonymous()));
+
+    true
+}
@@ -77,6 +77,20 @@ where
                     }
                 };
 
+            if user.is_disabled() {
+                // sessions are revoked when a user is disabled, but make sure any that were
+                // created in the meantime can't be used either
+                return Ok(Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .body(boxed(Body::from(
+                        serde_json::to_vec(&ErrorResponse {
+                            error: Some("Account disabled".into()),
+                        })
+                        .unwrap(),
+                    )))
+                    .unwrap());
+            }
+
             if session.user_ssh_key_id.is_some() {
                 // SSH sessions can't be used for the web API
                 return Ok(Response::builder()
@@ -90,9 +104,15 @@ where
                     .unwrap());
             }
 
+            let actor = Arc::new(super::actor_for_request(&req, &user));
+            let scope = Arc::new(session.scope());
+
             // insert both the user and the session into extensions so handlers can
-            // get their hands on them
+            // get their hands on them, along with the actor any audited actions should be
+            // attributed to and the scope the session is restricted to
             req.extensions_mut().insert(user);
+            req.extensions_mut().insert(actor);
+            req.extensions_mut().insert(scope);
             req.extensions_mut().insert(session);
 
             // calls handlers/other middleware and drives the request to response
@@ -102,3 +122,63 @@ where
         })
     }
 }
+
+#[cfg(all(test, feature = "sqlite"))]
+mod test {
+    use super::WebAuthMiddleware;
+    use crate::testing;
+    use axum::{
+        body::Body,
+        http::{header, Request, StatusCode},
+        routing::get,
+        Extension, Router,
+    };
+    use chartered_db::{audit::Actor, users::UserSession, ConnectionPool};
+    use tower::{ServiceBuilder, ServiceExt};
+
+    async fn call(db: &ConnectionPool, user_id: i32) -> StatusCode {
+        // sessions are revoked when a user is disabled, so this is created afterwards
+        let session = UserSession::generate(
+            db.clone(),
+            &Actor::default(),
+            user_id,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+        let app = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(
+                ServiceBuilder::new()
+                    .layer_fn(WebAuthMiddleware)
+                    .into_inner(),
+            )
+            .layer(Extension(db.clone()));
+
+        let req = Request::builder()
+            .uri("/")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", session.session_key),
+            )
+            .body(Body::empty())
+            .unwrap();
+
+        app.oneshot(req).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn disabled_users_are_rejected() {
+        let db = testing::pool();
+        let user = testing::user(&db, "user").await;
+        let disabled = testing::disabled_user(&db, "disabled").await;
+
+        assert_eq!(call(&db, user.id).await, StatusCode::OK);
+        assert_eq!(call(&db, disabled.id).await, StatusCode::UNAUTHORIZED);
+    }
+}
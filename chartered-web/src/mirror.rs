@@ -0,0 +1,393 @@
+//! Fetches index files and `.crate` files on demand for organisations that mirror an upstream
+//! registry, caching them through `chartered-db` and `chartered-fs` so they're still available
+//! when the upstream isn't.
+//!
+//! Only upstreams that serve a [sparse index][sparse] are supported. Requests to the upstream go
+//! through [`Outbound`] so a mirror can't be pointed at an internal address, even if its DNS
+//! changes after the mirror was created.
+//!
+//! [sparse]: https://doc.rust-lang.org/cargo/reference/registry-index.html#sparse-protocol
+
+use bytes::Bytes;
+use chartered_db::{
+    mirrors::{MirrorCrateFile, MirrorIndexFile},
+    organisations::{OrganisationWithPermissions, Upstream},
+    ConnectionPool,
+};
+use chartered_fs::{FileReference, FileSystem};
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{str::FromStr, sync::Arc};
+use thiserror::Error;
+use tracing::warn;
+use url::Url;
+
+use crate::outbound::{self, Outbound};
+
+/// How long a cached index file is served before we check upstream for a newer version.
+const INDEX_TTL_MINUTES: i64 = 5;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Database(#[from] chartered_db::Error),
+    #[error("Failed to store crate file: {0}")]
+    File(#[from] Box<chartered_fs::Error>),
+    #[error("Failed to contact upstream registry: {0}")]
+    Upstream(#[from] reqwest::Error),
+    #[error("Failed to contact upstream registry: {0}")]
+    Outbound(#[from] outbound::Error),
+    #[error("Upstream registry responded with {0}")]
+    UpstreamStatus(reqwest::StatusCode),
+    #[error("Upstream registry is misconfigured: {0}")]
+    UpstreamUrl(#[from] url::ParseError),
+    #[error("Crate downloaded from upstream didn't match the checksum in its index")]
+    ChecksumMismatch,
+}
+
+impl Error {
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        use axum::http::StatusCode;
+
+        match self {
+            Self::Database(e) => e.status_code(),
+            Self::File(_) | Self::UpstreamUrl(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Upstream(_)
+            | Self::Outbound(_)
+            | Self::UpstreamStatus(_)
+            | Self::ChecksumMismatch => StatusCode::BAD_GATEWAY,
+        }
+    }
+}
+
+/// Returns the index file at `path` for the mirror, fetching it from upstream if we don't have
+/// a fresh copy. If upstream can't be reached, a stale copy is returned if we have one so builds
+/// keep working while offline.
+pub async fn index_file(
+    db: ConnectionPool,
+    outbound: &Outbound,
+    organisation: Arc<OrganisationWithPermissions>,
+    upstream: &Upstream,
+    path: &str,
+) -> Result<Option<Vec<u8>>, Error> {
+    let cached = MirrorIndexFile::find(db.clone(), organisation.clone(), path.to_string()).await?;
+
+    if let Some(cached) = &cached {
+        if cached.fetched_at > Utc::now().naive_utc() - Duration::minutes(INDEX_TTL_MINUTES) {
+            return Ok(Some(cached.content.clone()));
+        }
+    }
+
+    match fetch_index_file(outbound, &upstream.index_uri, path).await {
+        Ok(Some(content)) => {
+            MirrorIndexFile::store(db, organisation, path.to_string(), content.to_vec()).await?;
+            Ok(Some(content.to_vec()))
+        }
+        Ok(None) => Ok(None),
+        Err(e) => {
+            if let Some(cached) = cached {
+                warn!("Serving stale index file {} for mirror: {}", path, e);
+                Ok(Some(cached.content))
+            } else {
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Returns a reference to the `.crate` file for the given version, downloading it from upstream
+/// and verifying it against the checksum in the upstream index if we haven't already got it.
+/// Returns `None` if the version doesn't exist upstream.
+pub async fn crate_file(
+    db: ConnectionPool,
+    outbound: &Outbound,
+    fs: &FileSystem,
+    organisation: Arc<OrganisationWithPermissions>,
+    upstream: &Upstream,
+    name: &str,
+    version: &str,
+) -> Result<Option<FileReference>, Error> {
+    if let Some(cached) = MirrorCrateFile::find(
+        db.clone(),
+        organisation.clone(),
+        name.to_string(),
+        version.to_string(),
+    )
+    .await?
+    {
+        return Ok(Some(
+            FileReference::from_str(&cached.filesystem_object).map_err(Box::new)?,
+        ));
+    }
+
+    let index_path = match index_path(name) {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+
+    let index = match index_file(
+        db.clone(),
+        outbound,
+        organisation.clone(),
+        upstream,
+        &index_path,
+    )
+    .await?
+    {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+
+    let checksum = match find_checksum(&index, version) {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+
+    let content =
+        fetch_crate_file(outbound, &upstream.download_uri, name, version, &checksum).await?;
+
+    let file_ref = fs.write(content).await.map_err(Box::new)?;
+
+    MirrorCrateFile::store(
+        db,
+        organisation,
+        name.to_string(),
+        version.to_string(),
+        checksum,
+        &file_ref,
+    )
+    .await?;
+
+    Ok(Some(file_ref))
+}
+
+/// Fetches a single file from the upstream's sparse index, returning `None` if upstream doesn't
+/// have the file.
+pub async fn fetch_index_file(
+    outbound: &Outbound,
+    index_uri: &str,
+    path: &str,
+) -> Result<Option<Bytes>, Error> {
+    let url = Url::parse(index_uri)?.join(path)?;
+    let res = outbound.get(url).await?;
+
+    match res.status() {
+        // these all mean the crate doesn't exist according to the sparse index spec
+        reqwest::StatusCode::NOT_FOUND
+        | reqwest::StatusCode::GONE
+        | reqwest::StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS => Ok(None),
+        status if status.is_success() => Ok(Some(res.bytes().await?)),
+        status => Err(Error::UpstreamStatus(status)),
+    }
+}
+
+/// Downloads a `.crate` file from upstream, ensuring its checksum matches the one given.
+pub async fn fetch_crate_file(
+    outbound: &Outbound,
+    download_uri: &str,
+    name: &str,
+    version: &str,
+    checksum: &str,
+) -> Result<Bytes, Error> {
+    let url = Url::parse(&download_url(download_uri, name, version, checksum))?;
+    let res = outbound.get(url).await?;
+
+    if !res.status().is_success() {
+        return Err(Error::UpstreamStatus(res.status()));
+    }
+
+    let content = res.bytes().await?;
+
+    if hex::encode(Sha256::digest(&content)) != checksum {
+        return Err(Error::ChecksumMismatch);
+    }
+
+    Ok(content)
+}
+
+/// Path of a crate's file within an index, as per the cargo spec. Returns `None` for names that
+/// can't possibly be valid crates.
+#[must_use]
+pub fn index_path(name: &str) -> Option<String> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return None;
+    }
+
+    let name = name.to_ascii_lowercase();
+
+    Some(match name.len() {
+        1 => format!("1/{}", name),
+        2 => format!("2/{}", name),
+        3 => format!("3/{}/{}", &name[..1], name),
+        _ => format!("{}/{}/{}", &name[..2], &name[2..4], name),
+    })
+}
+
+/// Builds the URL to download a crate from, following the same rules cargo does for the `dl`
+/// key in an index's `config.json`.
+#[must_use]
+pub fn download_url(download_uri: &str, name: &str, version: &str, checksum: &str) -> String {
+    const MARKERS: &[&str] = &[
+        "{crate}",
+        "{version}",
+        "{prefix}",
+        "{lowerprefix}",
+        "{sha256-checksum}",
+    ];
+
+    if !MARKERS.iter().any(|marker| download_uri.contains(marker)) {
+        return format!(
+            "{}/{}/{}/download",
+            download_uri.trim_end_matches('/'),
+            name,
+            version
+        );
+    }
+
+    let prefix = match name.len() {
+        1 => "1".to_string(),
+        2 => "2".to_string(),
+        3 => format!("3/{}", &name[..1]),
+        _ => format!("{}/{}", &name[..2], &name[2..4]),
+    };
+
+    download_uri
+        .replace("{crate}", name)
+        .replace("{version}", version)
+        .replace("{lowerprefix}", &prefix.to_ascii_lowercase())
+        .replace("{prefix}", &prefix)
+        .replace("{sha256-checksum}", checksum)
+}
+
+/// Finds the checksum for the given version in an index file.
+#[must_use]
+pub fn find_checksum(index: &[u8], version: &str) -> Option<String> {
+    #[derive(Deserialize)]
+    struct Entry {
+        vers: String,
+        cksum: String,
+    }
+
+    index
+        .split(|c| *c == b'\n')
+        .filter_map(|line| serde_json::from_slice::<Entry>(line).ok())
+        .find(|entry| entry.vers == version)
+        .map(|entry| entry.cksum)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::outbound::Outbound;
+    use axum::{http::StatusCode, routing::get, Router};
+    use sha2::{Digest, Sha256};
+    use std::net::SocketAddr;
+
+    #[test]
+    fn index_path() {
+        assert_eq!(super::index_path("a").as_deref(), Some("1/a"));
+        assert_eq!(super::index_path("ab").as_deref(), Some("2/ab"));
+        assert_eq!(super::index_path("abc").as_deref(), Some("3/a/abc"));
+        assert_eq!(super::index_path("Serde").as_deref(), Some("se/rd/serde"));
+        assert_eq!(super::index_path(""), None);
+        assert_eq!(super::index_path("../config.json"), None);
+    }
+
+    #[test]
+    fn download_url() {
+        assert_eq!(
+            super::download_url("https://static.crates.io/crates", "serde", "1.0.0", "abc"),
+            "https://static.crates.io/crates/serde/1.0.0/download"
+        );
+        assert_eq!(
+            super::download_url(
+                "https://example.com/{lowerprefix}/{crate}-{version}.crate?{sha256-checksum}",
+                "Serde",
+                "1.0.0",
+                "abc"
+            ),
+            "https://example.com/se/rd/Serde-1.0.0.crate?abc"
+        );
+    }
+
+    #[test]
+    fn find_checksum() {
+        let index = b"{\"name\":\"a\",\"vers\":\"0.1.0\",\"cksum\":\"aaa\"}\n\
+                      {\"name\":\"a\",\"vers\":\"0.2.0\",\"cksum\":\"bbb\"}\n";
+
+        assert_eq!(super::find_checksum(index, "0.2.0").as_deref(), Some("bbb"));
+        assert_eq!(super::find_checksum(index, "0.3.0"), None);
+    }
+
+    /// Spins up a fake upstream registry serving a single crate, returning its base URL.
+    async fn fake_upstream(crate_file: &'static [u8]) -> String {
+        let cksum = hex::encode(Sha256::digest(crate_file));
+        let index = format!(
+            "{{\"name\":\"abc\",\"vers\":\"1.0.0\",\"deps\":[],\"features\":{{}},\"cksum\":\"{}\"}}\n",
+            cksum
+        );
+
+        let app = Router::new()
+            .route("/index/3/a/abc", get(move || async move { index }))
+            .route("/dl/abc/1.0.0/download", get(|| async move { crate_file }))
+            .route(
+                "/dl/abc/2.0.0/download",
+                get(|| async { (StatusCode::OK, "not the crate you're looking for") }),
+            );
+
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn fetch_from_upstream() {
+        const CRATE_FILE: &[u8] = b"totally a tarball";
+
+        let base = fake_upstream(CRATE_FILE).await;
+        let outbound = Outbound::new("chartered-test".to_string(), vec!["127.0.0.1".to_string()]);
+        let index_uri = format!("{}/index/", base);
+        let download_uri = format!("{}/dl", base);
+
+        let index = super::fetch_index_file(&outbound, &index_uri, "3/a/abc")
+            .await
+            .unwrap()
+            .unwrap();
+        let cksum = super::find_checksum(&index, "1.0.0").unwrap();
+
+        assert!(super::fetch_index_file(&outbound, &index_uri, "3/x/xyz")
+            .await
+            .unwrap()
+            .is_none());
+
+        let content = super::fetch_crate_file(&outbound, &download_uri, "abc", "1.0.0", &cksum)
+            .await
+            .unwrap();
+        assert_eq!(&content[..], CRATE_FILE);
+
+        assert!(matches!(
+            super::fetch_crate_file(&outbound, &download_uri, "abc", "2.0.0", &cksum).await,
+            Err(super::Error::ChecksumMismatch)
+        ));
+    }
+
+    #[tokio::test]
+    async fn refuses_internal_upstream() {
+        let base = fake_upstream(b"").await;
+        let outbound = Outbound::new("chartered-test".to_string(), Vec::new());
+
+        assert!(matches!(
+            super::fetch_index_file(&outbound, &format!("{}/index/", base), "3/a/abc").await,
+            Err(super::Error::Outbound(
+                crate::outbound::Error::InternalAddress(_)
+            ))
+        ));
+    }
+}
@@ -0,0 +1,260 @@
+//! Requests to URLs given to us by users, such as webhook receivers, are sent from the server's
+//! own network, so without any checks they could be pointed at services that were never meant
+//! to be reachable from the outside, like a cloud provider's metadata endpoint.
+//!
+//! Before any such request is sent the host is resolved, and refused if it resolves to a
+//! loopback, private or link-local address unless it's been explicitly allowed in the config.
+//! The request is then pinned to the address we checked so the host can't be re-resolved to
+//! somewhere else in the meantime.
+
+use reqwest::{header, redirect::Policy, Client, Response, StatusCode};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use thiserror::Error;
+use url::{Host, Url};
+
+/// Maximum number of redirects followed by [`Outbound::get`].
+const MAX_REDIRECTS: usize = 10;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("URL must be a valid http or https URL")]
+    InvalidUrl,
+    #[error("Failed to resolve host `{0}`")]
+    Resolve(String),
+    #[error("Host `{0}` resolves to an internal address")]
+    InternalAddress(String),
+    #[error("Too many redirects")]
+    TooManyRedirects,
+    #[error("{0}")]
+    Request(#[from] reqwest::Error),
+}
+
+#[derive(Debug, Clone)]
+pub struct Outbound {
+    user_agent: String,
+    /// Hosts that can be sent requests even if they resolve to an internal address.
+    allowed_hosts: Vec<String>,
+}
+
+impl Outbound {
+    #[must_use]
+    pub fn new(user_agent: String, allowed_hosts: Vec<String>) -> Self {
+        Self {
+            user_agent,
+            allowed_hosts,
+        }
+    }
+
+    fn is_allowed(&self, host: &str) -> bool {
+        self.allowed_hosts
+            .iter()
+            .any(|v| v.eq_ignore_ascii_case(host))
+    }
+
+    /// Checks the URL is one we're willing to send requests to, returning the address requests
+    /// to it should be sent to.
+    pub async fn check(&self, url: &Url) -> Result<SocketAddr, Error> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(Error::InvalidUrl);
+        }
+
+        let host = url.host_str().ok_or(Error::InvalidUrl)?;
+        let port = url.port_or_known_default().ok_or(Error::InvalidUrl)?;
+
+        let addrs: Vec<SocketAddr> = match url.host().ok_or(Error::InvalidUrl)? {
+            Host::Ipv4(ip) => vec![SocketAddr::new(ip.into(), port)],
+            Host::Ipv6(ip) => vec![SocketAddr::new(ip.into(), port)],
+            Host::Domain(domain) => tokio::net::lookup_host((domain, port))
+                .await
+                .map_err(|_| Error::Resolve(host.to_string()))?
+                .collect(),
+        };
+
+        // every address is checked, otherwise a host could resolve to a public address and an
+        // internal one and we'd be at the mercy of whichever is tried first
+        if !self.is_allowed(host) && addrs.iter().any(|v| !is_public(v.ip())) {
+            return Err(Error::InternalAddress(host.to_string()));
+        }
+
+        addrs
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::Resolve(host.to_string()))
+    }
+
+    /// Builds a client that'll only send requests for `url` to the address we've checked it
+    /// resolves to. Redirects aren't followed, as they'd escape the check.
+    pub async fn client(&self, url: &Url) -> Result<Client, Error> {
+        let addr = self.check(url).await?;
+
+        let mut builder = Client::builder()
+            .user_agent(&self.user_agent)
+            .redirect(Policy::none());
+
+        if let Some(Host::Domain(domain)) = url.host() {
+            builder = builder.resolve(domain, addr);
+        }
+
+        Ok(builder.build()?)
+    }
+
+    /// Sends a `GET` request to the URL, following any redirects so long as the URL they
+    /// redirect to passes the same checks.
+    pub async fn get(&self, mut url: Url) -> Result<Response, Error> {
+        for _ in 0..=MAX_REDIRECTS {
+            let res = self.client(&url).await?.get(url.clone()).send().await?;
+
+            if !matches!(
+                res.status(),
+                StatusCode::MOVED_PERMANENTLY
+                    | StatusCode::FOUND
+                    | StatusCode::SEE_OTHER
+                    | StatusCode::TEMPORARY_REDIRECT
+                    | StatusCode::PERMANENT_REDIRECT
+            ) {
+                return Ok(res);
+            }
+
+            let location = res
+                .headers()
+                .get(header::LOCATION)
+                .and_then(|v| v.to_str().ok())
+                .ok_or(Error::InvalidUrl)?;
+            url = url.join(location).map_err(|_| Error::InvalidUrl)?;
+        }
+
+        Err(Error::TooManyRedirects)
+    }
+}
+
+/// Whether the address is reachable from the public internet, rather than being on the server
+/// itself or a network it's attached to.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ipv4_mapped(ip) {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // "this" network
+        || a == 0
+        // shared address space used by carrier-grade NAT
+        || (a == 100 && (64..128).contains(&b))
+        // benchmarking
+        || (a == 198 && (b == 18 || b == 19))
+        // reserved
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // unique local
+        || (segments[0] & 0xfe00) == 0xfc00
+        // link-local
+        || (segments[0] & 0xffc0) == 0xfe80
+        // site-local, deprecated but still routed internally by some networks
+        || (segments[0] & 0xffc0) == 0xfec0
+        // documentation
+        || (segments[0] == 0x2001 && segments[1] == 0x0db8))
+}
+
+/// The IPv4 address embedded in an IPv4-mapped IPv6 address, ie. `::ffff:127.0.0.1`.
+fn ipv4_mapped(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    match ip.octets() {
+        [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, a, b, c, d] => Some(Ipv4Addr::new(a, b, c, d)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Error, Outbound};
+    use url::Url;
+
+    fn outbound(allowed_hosts: &[&str]) -> Outbound {
+        Outbound::new(
+            "chartered-test".to_string(),
+            allowed_hosts.iter().map(ToString::to_string).collect(),
+        )
+    }
+
+    #[test]
+    fn is_public() {
+        for ip in ["1.1.1.1", "93.184.216.34", "2606:4700:4700::1111"] {
+            assert!(super::is_public(ip.parse().unwrap()), "{}", ip);
+        }
+
+        for ip in [
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+        ] {
+            assert!(!super::is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn check() {
+        let outbound = outbound(&["127.0.0.1"]);
+
+        for url in [
+            "http://10.0.0.1/",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://[::1]:8080/",
+            "http://localhost/",
+        ] {
+            assert!(
+                matches!(
+                    outbound.check(&Url::parse(url).unwrap()).await,
+                    Err(Error::InternalAddress(_))
+                ),
+                "{}",
+                url
+            );
+        }
+
+        assert!(matches!(
+            outbound
+                .check(&Url::parse("file:///etc/passwd").unwrap())
+                .await,
+            Err(Error::InvalidUrl)
+        ));
+
+        // explicitly allowed hosts can be internal
+        assert_eq!(
+            outbound
+                .check(&Url::parse("http://127.0.0.1:8080/").unwrap())
+                .await
+                .unwrap(),
+            "127.0.0.1:8080".parse().unwrap()
+        );
+    }
+}
@@ -0,0 +1,128 @@
+//! WebAuthn registration and authentication ceremonies for passkeys, built on `webauthn-rs`.
+//!
+//! The relying party is the frontend, since that's where the browser calls into the
+//! authenticator, so credentials are scoped to the host of `frontend_base_uri`. Ceremony state
+//! is handed to the client encrypted rather than stored server-side, the same as OAuth state
+//! and two-factor challenges.
+
+use chartered_db::{passkeys::UserPasskey, users::User};
+use url::Url;
+use webauthn_rs::prelude::{
+    AuthenticationResult, CreationChallengeResponse, Passkey, PasskeyAuthentication,
+    PasskeyRegistration, PublicKeyCredential, RegisterPublicKeyCredential,
+    RequestChallengeResponse, Webauthn, WebauthnBuilder, WebauthnError,
+};
+
+/// Name of the relying party, shown by some authenticators during registration.
+const RP_NAME: &str = "Chartered";
+
+/// Builds the relying party for the frontend at the given URI.
+pub fn build(frontend_base_uri: &Url) -> Result<Webauthn, WebauthnError> {
+    let rp_id = frontend_base_uri
+        .host_str()
+        .ok_or(WebauthnError::Configuration)?;
+
+    WebauthnBuilder::new(rp_id, frontend_base_uri)?
+        .rp_name(RP_NAME)
+        .build()
+}
+
+/// Deserialises the user's stored passkeys, skipping any that are unreadable.
+#[must_use]
+pub fn decode(passkeys: &[UserPasskey]) -> Vec<Passkey> {
+    passkeys
+        .iter()
+        .filter_map(|v| serde_json::from_str(&v.passkey).ok())
+        .collect()
+}
+
+/// Starts registering a new passkey for the user, excluding any they've already registered so
+/// the same authenticator can't be registered twice.
+pub fn start_registration(
+    webauthn: &Webauthn,
+    user: &User,
+    existing: &[Passkey],
+) -> Result<(CreationChallengeResponse, PasskeyRegistration), WebauthnError> {
+    webauthn.start_passkey_registration(
+        user.uuid.0,
+        &user.username,
+        user.display_name(),
+        Some(existing.iter().map(|v| v.cred_id().clone()).collect()),
+    )
+}
+
+/// Completes registration, returning the credential to store.
+pub fn finish_registration(
+    webauthn: &Webauthn,
+    credential: &RegisterPublicKeyCredential,
+    state: &PasskeyRegistration,
+) -> Result<Passkey, WebauthnError> {
+    webauthn.finish_passkey_registration(credential, state)
+}
+
+/// Starts authenticating the user with any of their passkeys.
+pub fn start_authentication(
+    webauthn: &Webauthn,
+    passkeys: &[Passkey],
+) -> Result<(RequestChallengeResponse, PasskeyAuthentication), WebauthnError> {
+    webauthn.start_passkey_authentication(passkeys)
+}
+
+/// Completes authentication, returning the passkey that was used, updated with its new
+/// signature counter if that needs to be stored.
+pub fn finish_authentication(
+    webauthn: &Webauthn,
+    passkeys: &[Passkey],
+    credential: &PublicKeyCredential,
+    state: &PasskeyAuthentication,
+) -> Result<(AuthenticationResult, Option<Passkey>), WebauthnError> {
+    let result = webauthn.finish_passkey_authentication(credential, state)?;
+
+    let updated = passkeys
+        .iter()
+        .find(|v| v.cred_id() == result.cred_id())
+        .cloned()
+        .and_then(|mut v| (v.update_credential(&result) == Some(true)).then_some(v));
+
+    Ok((result, updated))
+}
+
+#[cfg(test)]
+mod test {
+    use url::Url;
+    use webauthn_authenticator_rs::{softpasskey::SoftPasskey, WebauthnAuthenticator};
+    use webauthn_rs::prelude::Uuid;
+
+    #[test]
+    fn register_and_authenticate_with_software_authenticator() {
+        let origin = Url::parse("https://chartered.example.com/").unwrap();
+        let webauthn = super::build(&origin).unwrap();
+        let mut authenticator = WebauthnAuthenticator::new(SoftPasskey::new());
+
+        let (challenge, registration) = webauthn
+            .start_passkey_registration(Uuid::new_v4(), "jordan", "Jordan", None)
+            .unwrap();
+        let credential = authenticator
+            .do_registration(origin.clone(), challenge)
+            .unwrap();
+        let passkey = super::finish_registration(&webauthn, &credential, &registration).unwrap();
+
+        let passkeys = [passkey];
+        let (challenge, authentication) =
+            super::start_authentication(&webauthn, &passkeys).unwrap();
+        let credential = authenticator
+            .do_authentication(origin.clone(), challenge)
+            .unwrap();
+        let (result, _) =
+            super::finish_authentication(&webauthn, &passkeys, &credential, &authentication)
+                .unwrap();
+        assert_eq!(result.cred_id(), passkeys[0].cred_id());
+
+        // the assertion is bound to its challenge, so can't be replayed against a new one
+        let (_, authentication) = super::start_authentication(&webauthn, &passkeys).unwrap();
+        assert!(
+            super::finish_authentication(&webauthn, &passkeys, &credential, &authentication)
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,335 @@
+//! SAML 2.0 single sign-on, acting as a service provider built on `samael`.
+//!
+//! Only the HTTP-Redirect binding is used to send `AuthnRequest`s and only the HTTP-POST binding
+//! is accepted for responses. Responses must be signed by a certificate from the IdP's metadata,
+//! which `samael` checks before we read anything out of them.
+
+use crate::config::SamlConfig;
+
+use chrono::{DateTime, Utc};
+use samael::{
+    metadata::{EntityDescriptor, HTTP_REDIRECT_BINDING},
+    schema::{Assertion, Attribute},
+    service_provider::{ServiceProvider, ServiceProviderBuilder},
+};
+use thiserror::Error;
+use url::Url;
+
+/// `NameID` format for identifiers that change on every login, which can't be used to link the
+/// user back to their account.
+const TRANSIENT_NAME_ID: &str = "urn:oasis:names:tc:SAML:2.0:nameid-format:transient";
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Failed to parse IdP metadata: {0}")]
+    Metadata(String),
+    #[error("IdP metadata doesn't contain a signing certificate")]
+    MissingSigningCertificate,
+    #[error("IdP doesn't support the HTTP-Redirect binding")]
+    MissingRedirectBinding,
+    #[error("Failed to build service provider: {0}")]
+    ServiceProvider(String),
+    #[error("Failed to build SAML request: {0}")]
+    Request(String),
+    #[error("Invalid SAML response: {0}")]
+    InvalidResponse(String),
+    #[error("SAML response doesn't contain a persistent NameID")]
+    MissingNameId,
+}
+
+/// A user the IdP has vouched for in a response we've validated.
+#[derive(Debug)]
+pub struct SamlUser {
+    /// The user's `NameID`, used to link them to their account.
+    pub id: String,
+    pub name: Option<String>,
+    pub email: Option<String>,
+    /// `None` if the IdP didn't send the groups attribute at all, rather than sending it
+    /// without any values.
+    pub groups: Option<Vec<String>>,
+    /// ID of the assertion the user was read from, which is only valid once.
+    pub assertion_id: String,
+    /// When the assertion stops being valid, if the IdP gave it an expiry.
+    pub assertion_expires_at: Option<DateTime<Utc>>,
+}
+
+/// URL our metadata is served at, which is also our entity ID unless one is configured.
+pub fn metadata_url(web_base_uri: &Url, provider: &str) -> Result<Url, url::ParseError> {
+    web_base_uri.join(&format!(
+        "web/v1/public/auth/login/saml/{}/metadata",
+        provider
+    ))
+}
+
+/// URL the IdP posts its responses back to.
+pub fn acs_url(web_base_uri: &Url, provider: &str) -> Result<Url, url::ParseError> {
+    web_base_uri.join(&format!("web/v1/public/auth/login/saml/{}/acs", provider))
+}
+
+fn builder(
+    entity_id: String,
+    metadata_url: &Url,
+    acs_url: &Url,
+    idp_metadata: &str,
+) -> Result<ServiceProviderBuilder, Error> {
+    let idp_metadata: EntityDescriptor =
+        samael::metadata::de::from_str(idp_metadata).map_err(|e| Error::Metadata(e.to_string()))?;
+
+    // samael only checks signatures when the IdP gives it a certificate to check them with, so
+    // without one any response would be accepted
+    if !has_signing_certificate(&idp_metadata) {
+        return Err(Error::MissingSigningCertificate);
+    }
+
+    let mut builder = ServiceProviderBuilder::default();
+    builder
+        .entity_id(entity_id)
+        .metadata_url(metadata_url.to_string())
+        .acs_url(acs_url.to_string())
+        .idp_metadata(idp_metadata)
+        .allow_idp_initiated(false);
+
+    Ok(builder)
+}
+
+/// Builds the service provider for an IdP from its metadata.
+pub fn build(
+    config: &SamlConfig,
+    metadata_url: &Url,
+    acs_url: &Url,
+    idp_metadata: &str,
+) -> Result<ServiceProvider, Error> {
+    let entity_id = config
+        .entity_id
+        .clone()
+        .unwrap_or_else(|| metadata_url.to_string());
+
+    let sp = builder(entity_id, metadata_url, acs_url, idp_metadata)?
+        .build()
+        .map_err(|e| Error::ServiceProvider(e.to_string()))?;
+
+    if sp.sso_binding_location(HTTP_REDIRECT_BINDING).is_none() {
+        return Err(Error::MissingRedirectBinding);
+    }
+
+    Ok(sp)
+}
+
+fn has_signing_certificate(metadata: &EntityDescriptor) -> bool {
+    metadata
+        .idp_sso_descriptors
+        .iter()
+        .flatten()
+        .flat_map(|v| &v.key_descriptors)
+        .filter(|v| v.key_use.as_deref().map_or(true, |v| v == "signing"))
+        .filter_map(|v| v.key_info.x509_data.as_ref())
+        .any(|v| !v.certificates.is_empty())
+}
+
+/// Builds the URL to send the user to the IdP with an `AuthnRequest`, which the IdP will hand
+/// `relay_state` back to us alongside its response.
+pub fn authn_request_url(
+    sp: &ServiceProvider,
+    request_id: &str,
+    relay_state: &str,
+) -> Result<String, Error> {
+    let destination = sp
+        .sso_binding_location(HTTP_REDIRECT_BINDING)
+        .ok_or(Error::MissingRedirectBinding)?;
+
+    let mut request = sp
+        .make_authentication_request(&destination)
+        .map_err(|e| Error::Request(e.to_string()))?;
+    request.id = request_id.to_string();
+
+    request
+        .redirect(relay_state)
+        .map_err(|e| Error::Request(e.to_string()))?
+        .map(|v| v.to_string())
+        .ok_or(Error::MissingRedirectBinding)
+}
+
+/// Validates the base64 encoded response posted back by the IdP, checking its signature, that
+/// it's in response to `request_id`, that it's intended for us and that it hasn't expired.
+pub fn verify(
+    sp: &ServiceProvider,
+    config: &SamlConfig,
+    response: &str,
+    request_id: &str,
+) -> Result<SamlUser, Error> {
+    // some IdPs wrap the encoded response over multiple lines
+    let response: String = response.split_whitespace().collect();
+
+    let assertion = sp
+        .parse_base64_response(&response, Some(&[request_id][..]))
+        .map_err(|e| Error::InvalidResponse(e.to_string()))?;
+
+    user_from_assertion(config, assertion)
+}
+
+fn user_from_assertion(config: &SamlConfig, assertion: Assertion) -> Result<SamlUser, Error> {
+    let assertion_expires_at = assertion
+        .conditions
+        .as_ref()
+        .and_then(|v| v.not_on_or_after);
+
+    let id = assertion
+        .subject
+        .and_then(|v| v.name_id)
+        .filter(|v| v.format.as_deref() != Some(TRANSIENT_NAME_ID))
+        .map(|v| v.value)
+        .filter(|v| !v.is_empty())
+        .ok_or(Error::MissingNameId)?;
+
+    let attributes: Vec<Attribute> = assertion
+        .attribute_statements
+        .into_iter()
+        .flatten()
+        .flat_map(|v| v.attributes)
+        .collect();
+
+    Ok(SamlUser {
+        id,
+        name: attribute_values(&attributes, &config.name_attribute)
+            .into_iter()
+            .next(),
+        email: attribute_values(&attributes, &config.email_attribute)
+            .into_iter()
+            .next(),
+        groups: attributes
+            .iter()
+            .any(|v| attribute_matches(v, &config.groups_attribute))
+            .then(|| attribute_values(&attributes, &config.groups_attribute)),
+        assertion_id: assertion.id,
+        assertion_expires_at,
+    })
+}
+
+/// Whether the attribute has the given name, matched by either its `Name` or `FriendlyName`
+/// since IdPs tend to only give friendly names alongside URN names.
+fn attribute_matches(attribute: &Attribute, name: &str) -> bool {
+    attribute.name.as_deref() == Some(name) || attribute.friendly_name.as_deref() == Some(name)
+}
+
+/// Grabs all the values of the attribute, matched by `attribute_matches`.
+fn attribute_values(attributes: &[Attribute], name: &str) -> Vec<String> {
+    attributes
+        .iter()
+        .filter(|v| attribute_matches(v, name))
+        .flat_map(|v| &v.values)
+        .filter_map(|v| v.value.clone())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::SamlConfig;
+    use samael::service_provider::ServiceProvider;
+    use url::Url;
+
+    /// Metadata for an IdP using a self-signed certificate generated for these tests, which
+    /// was used to sign both the response and assertion of `response.xml`.
+    const IDP_METADATA: &str = include_str!("../fixtures/saml/idp-metadata.xml");
+    const RESPONSE: &str = include_str!("../fixtures/saml/response.xml");
+    const REQUEST_ID: &str = "_0123456789abcdef0123456789abcdef";
+
+    fn config() -> SamlConfig {
+        toml::from_str(
+            r#"
+            enabled = true
+            idp_metadata_uri = "https://idp.example.com/metadata"
+        "#,
+        )
+        .unwrap()
+    }
+
+    /// Builds a service provider matching the one the fixture response was issued to, allowing
+    /// for the response having been issued a long time ago.
+    fn service_provider() -> ServiceProvider {
+        let web_base_uri = Url::parse("https://chartered.example.com/").unwrap();
+        let metadata_url = super::metadata_url(&web_base_uri, "example").unwrap();
+        let acs_url = super::acs_url(&web_base_uri, "example").unwrap();
+
+        super::builder(
+            metadata_url.to_string(),
+            &metadata_url,
+            &acs_url,
+            IDP_METADATA,
+        )
+        .unwrap()
+        .max_issue_delay(chrono::Duration::days(365 * 200))
+        .build()
+        .unwrap()
+    }
+
+    #[test]
+    fn verifies_signed_response() {
+        let user = super::verify(
+            &service_provider(),
+            &config(),
+            &base64::encode(RESPONSE),
+            REQUEST_ID,
+        )
+        .unwrap();
+
+        assert_eq!(user.id, "alice");
+        assert_eq!(user.name.as_deref(), Some("Alice Example"));
+        assert_eq!(user.email.as_deref(), Some("alice@example.com"));
+        assert_eq!(
+            user.groups,
+            Some(vec!["developers".to_string(), "release".to_string()])
+        );
+        assert_eq!(user.assertion_id, "_1a2b3c4d5e6f708192a3b4c5d6e7f809");
+        assert!(user.assertion_expires_at.is_some());
+    }
+
+    #[test]
+    fn missing_groups_attribute_is_distinguished_from_no_groups() {
+        let mut config = config();
+        config.groups_attribute = "missing".to_string();
+
+        let user = super::verify(
+            &service_provider(),
+            &config,
+            &base64::encode(RESPONSE),
+            REQUEST_ID,
+        )
+        .unwrap();
+
+        assert_eq!(user.groups, None);
+    }
+
+    #[test]
+    fn rejects_tampered_and_unsolicited_responses() {
+        let sp = service_provider();
+
+        let tampered = RESPONSE.replace("alice@example.com", "mallory@example.com");
+        assert!(super::verify(&sp, &config(), &base64::encode(tampered), REQUEST_ID).is_err());
+
+        assert!(super::verify(
+            &sp,
+            &config(),
+            &base64::encode(RESPONSE),
+            "_fedcba9876543210fedcba9876543210"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn requires_signing_certificate() {
+        let web_base_uri = Url::parse("https://chartered.example.com/").unwrap();
+        let metadata_url = super::metadata_url(&web_base_uri, "example").unwrap();
+        let acs_url = super::acs_url(&web_base_uri, "example").unwrap();
+
+        let start = IDP_METADATA.find("<md:KeyDescriptor").unwrap();
+        let end = IDP_METADATA.find("</md:KeyDescriptor>").unwrap() + "</md:KeyDescriptor>".len();
+        let unsigned = format!("{}{}", &IDP_METADATA[..start], &IDP_METADATA[end..]);
+
+        assert!(matches!(
+            super::build(&config(), &metadata_url, &acs_url, &unsigned),
+            Err(super::Error::MissingSigningCertificate)
+        ));
+        assert!(super::build(&config(), &metadata_url, &acs_url, IDP_METADATA).is_ok());
+    }
+}
@@ -0,0 +1,53 @@
+//! Removes storage objects scheduled for deletion by `chartered-db`, such as the crate files of
+//! organisations that were deleted and weren't restored within the grace period.
+
+use chartered_db::{storage_deletions::StorageDeletion, ConnectionPool};
+use chartered_fs::{FileReference, FileSystem};
+use std::{str::FromStr, sync::Arc, time::Duration};
+use tracing::{debug, error};
+
+/// Maximum number of objects to remove on each tick.
+const BATCH_SIZE: i64 = 100;
+
+/// Periodically removes any storage objects that are due to be deleted.
+pub async fn delete_storage_objects(db: ConnectionPool, fs: Arc<FileSystem>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+
+    loop {
+        interval.tick().await;
+
+        let due = match StorageDeletion::find_due(db.clone(), BATCH_SIZE).await {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Failed to fetch storage objects due for deletion: {}", e);
+                continue;
+            }
+        };
+
+        for deletion in due {
+            // objects we can't make sense of will never be removable, so there's no point
+            // holding on to them
+            match FileReference::from_str(&deletion.filesystem_object) {
+                Ok(file_ref) => {
+                    if let Err(e) = fs.delete(file_ref).await {
+                        error!(
+                            "Failed to delete storage object {}: {}",
+                            deletion.filesystem_object, e
+                        );
+                        continue;
+                    }
+
+                    debug!("Deleted storage object {}", deletion.filesystem_object);
+                }
+                Err(e) => error!(
+                    "Invalid storage object {} scheduled for deletion: {}",
+                    deletion.filesystem_object, e
+                ),
+            }
+
+            if let Err(e) = Arc::new(deletion).complete(db.clone()).await {
+                error!("Failed to mark storage object as deleted: {}", e);
+            }
+        }
+    }
+}
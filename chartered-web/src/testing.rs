@@ -0,0 +1,69 @@
+//! Helpers for tests that need a real database to run against, each test gets its own sqlite
+//! database in the temp directory with every migration applied.
+
+use crate::config::Config;
+
+use chartered_db::{audit::Actor, users::User, ConnectionPool};
+use std::sync::Arc;
+use webauthn_rs::prelude::Webauthn;
+
+/// A minimal config with password logins enabled.
+pub fn config() -> Arc<Config> {
+    Arc::new(
+        toml::from_str(
+            r#"
+            bind_address = "127.0.0.1:8888"
+            database_uri = "sqlite://:memory:"
+            storage_uri = "file:///tmp/chartered"
+            frontend_base_uri = "http://localhost:5173/"
+            encryption_key = "thisisatestkeythatsonlyfortests!"
+
+            [auth.password]
+            enabled = true
+            "#,
+        )
+        .unwrap(),
+    )
+}
+
+pub fn webauthn(config: &Config) -> Arc<Webauthn> {
+    Arc::new(crate::passkeys::build(&config.frontend_base_uri).unwrap())
+}
+
+/// Creates an empty database with the schema fully migrated.
+pub fn pool() -> ConnectionPool {
+    let path = std::env::temp_dir().join(format!(
+        "chartered-web-test-{}.db",
+        chartered_db::uuid::Uuid::new_v4()
+    ));
+
+    chartered_db::init(&format!("sqlite://{}", path.display())).unwrap()
+}
+
+/// Creates a user that can log in with the password `password`.
+pub async fn user(db: &ConnectionPool, username: &str) -> Arc<User> {
+    let password_hash = bcrypt::hash("password", 4).unwrap();
+
+    User::register(db.clone(), username.to_string(), password_hash)
+        .await
+        .unwrap();
+
+    Arc::new(
+        User::find_by_username(db.clone(), username.to_string())
+            .await
+            .unwrap()
+            .unwrap(),
+    )
+}
+
+/// Creates a user that's since been disabled by an administrator.
+pub async fn disabled_user(db: &ConnectionPool, username: &str) -> Arc<User> {
+    let user = user(db, username).await;
+
+    user.clone()
+        .set_disabled(db.clone(), &Actor::default(), true)
+        .await
+        .unwrap();
+
+    user
+}
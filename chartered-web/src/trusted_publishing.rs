@@ -0,0 +1,180 @@
+//! Verifies the OIDC ID tokens CI providers hand out to jobs, so they can be exchanged for a
+//! short-lived publish token without the job needing any stored secret.
+//!
+//! The token's signature, issuer, audience and expiry are all checked using the same `openid`
+//! client we use for logins, against the JWKS discovered from the issuer. The provider specific
+//! claims describing the job are then read out of the now-trusted payload and matched against
+//! the crate's trust policies.
+
+use chartered_db::trusted_publishers::CrateTrustedPublisher;
+use openid::{DiscoveredClient, IdToken, Jws, StandardClaims};
+use serde::Deserialize;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Malformed ID token")]
+    Malformed,
+    #[error("Invalid ID token: {0}")]
+    Invalid(#[from] openid::error::Error),
+}
+
+/// Claims describing the CI job an ID token was issued to. GitHub Actions' claim names are
+/// used, with GitLab CI's equivalents accepted as aliases.
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+pub struct JobClaims {
+    /// The repository the job is running for, ie. `my-org/my-repo`.
+    #[serde(alias = "project_path")]
+    pub repository: String,
+    /// Reference to the workflow the job is running, ie.
+    /// `my-org/my-repo/.github/workflows/release.yml@refs/heads/main`.
+    #[serde(alias = "ci_config_ref_uri")]
+    pub workflow_ref: Option<String>,
+    /// The git ref the job is running against, ie. `refs/heads/main`.
+    #[serde(rename = "ref")]
+    pub git_ref: Option<String>,
+}
+
+impl JobClaims {
+    /// The file name of the workflow the job is running, ie. `release.yml`.
+    #[must_use]
+    pub fn workflow(&self) -> Option<&str> {
+        let workflow_ref = self.workflow_ref.as_deref()?;
+        let path = workflow_ref.split('@').next()?;
+
+        path.rsplit('/').next()
+    }
+
+    /// Checks the job is allowed to publish by the given policy.
+    #[must_use]
+    pub fn matches(&self, policy: &CrateTrustedPublisher) -> bool {
+        let repository_matches = self.repository.eq_ignore_ascii_case(&policy.repository);
+
+        let workflow_matches = policy
+            .workflow
+            .as_deref()
+            .map_or(true, |workflow| self.workflow() == Some(workflow));
+
+        let branch_matches = policy.branch.as_deref().map_or(true, |branch| {
+            self.git_ref
+                .as_deref()
+                .and_then(|v| v.strip_prefix("refs/heads/"))
+                == Some(branch)
+        });
+
+        repository_matches && workflow_matches && branch_matches
+    }
+}
+
+/// Verifies the ID token was issued by the provider for us and hasn't expired, returning the
+/// claims describing the job it was issued to.
+pub fn verify(client: &DiscoveredClient, token: &str) -> Result<JobClaims, Error> {
+    let mut id_token: IdToken<StandardClaims> = Jws::new_encoded(token);
+
+    // checks the signature against the provider's JWKS, followed by the issuer, audience and
+    // expiry of the token
+    client.decode_token(&mut id_token)?;
+    client.validate_token(&id_token, None, None)?;
+
+    // the standard claims don't know anything about the job, so read them straight out of the
+    // payload we've just verified
+    let payload = token.split('.').nth(1).ok_or(Error::Malformed)?;
+    let payload =
+        base64::decode_config(payload, base64::URL_SAFE_NO_PAD).map_err(|_| Error::Malformed)?;
+
+    serde_json::from_slice(&payload).map_err(|_| Error::Malformed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::JobClaims;
+    use chartered_db::{trusted_publishers::CrateTrustedPublisher, uuid::SqlUuid};
+    use openid::{Discovered, DiscoveredClient};
+
+    const ID_TOKEN: &str = include_str!("../fixtures/trusted_publishing/id_token.jwt");
+
+    /// Builds a client for the fixture issuer without going out to the network for discovery.
+    fn client(audience: &str) -> DiscoveredClient {
+        let config: openid::Config = serde_json::from_str(include_str!(
+            "../fixtures/trusted_publishing/openid-configuration.json"
+        ))
+        .unwrap();
+        let jwks = include_str!("../fixtures/trusted_publishing/jwks.json");
+
+        DiscoveredClient::new(
+            Discovered::from(config),
+            audience.to_string(),
+            String::new(),
+            None,
+            reqwest::Client::new(),
+            Some(serde_json::from_str(jwks).unwrap()),
+        )
+    }
+
+    fn policy(workflow: Option<&str>, branch: Option<&str>) -> CrateTrustedPublisher {
+        CrateTrustedPublisher {
+            id: 1,
+            uuid: SqlUuid::random(),
+            crate_id: 1,
+            user_id: 1,
+            provider: "github".to_string(),
+            repository: "my-org/my-repo".to_string(),
+            workflow: workflow.map(ToString::to_string),
+            branch: branch.map(ToString::to_string),
+            created_at: chrono::Utc::now().naive_utc(),
+        }
+    }
+
+    #[test]
+    fn verify() {
+        let claims = super::verify(&client("chartered"), ID_TOKEN.trim()).unwrap();
+
+        assert_eq!(
+            claims,
+            JobClaims {
+                repository: "my-org/my-repo".to_string(),
+                workflow_ref: Some(
+                    "my-org/my-repo/.github/workflows/release.yml@refs/heads/main".to_string()
+                ),
+                git_ref: Some("refs/heads/main".to_string()),
+            }
+        );
+        assert_eq!(claims.workflow(), Some("release.yml"));
+    }
+
+    #[test]
+    fn verify_wrong_audience() {
+        assert!(super::verify(&client("someone-else"), ID_TOKEN.trim()).is_err());
+    }
+
+    #[test]
+    fn verify_tampered() {
+        let mut parts: Vec<_> = ID_TOKEN
+            .trim()
+            .split('.')
+            .map(ToString::to_string)
+            .collect();
+
+        let payload = base64::decode_config(&parts[1], base64::URL_SAFE_NO_PAD).unwrap();
+        let payload = String::from_utf8(payload)
+            .unwrap()
+            .replace("my-org/my-repo", "evil-org/my-repo");
+        parts[1] = base64::encode_config(payload, base64::URL_SAFE_NO_PAD);
+
+        assert!(super::verify(&client("chartered"), &parts.join(".")).is_err());
+    }
+
+    #[test]
+    fn matches() {
+        let claims = super::verify(&client("chartered"), ID_TOKEN.trim()).unwrap();
+
+        assert!(claims.matches(&policy(None, None)));
+        assert!(claims.matches(&policy(Some("release.yml"), Some("main"))));
+        assert!(!claims.matches(&policy(Some("ci.yml"), None)));
+        assert!(!claims.matches(&policy(None, Some("develop"))));
+
+        let mut other_repository = policy(None, None);
+        other_repository.repository = "my-org/other-repo".to_string();
+        assert!(!claims.matches(&other_repository));
+    }
+}
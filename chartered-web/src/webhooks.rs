@@ -0,0 +1,194 @@
+//! Sends out webhook deliveries queued up by `chartered-db` whenever a registry event happens.
+//!
+//! Each delivery is `POST`ed to the subscribed URL with the following headers:
+//!
+//! - `X-Chartered-Event`: the event name, ie. `publish_version`
+//! - `X-Chartered-Delivery`: a unique id for the delivery, which stays the same across retries
+//! - `X-Chartered-Signature`: `sha256=` followed by the hex-encoded HMAC-SHA256 of the body,
+//!   keyed with the webhook's secret. Only sent if the webhook was created with a secret
+//!
+//! Deliveries are sent at least once, receivers should use the delivery id to dedupe.
+
+use crate::outbound::{self, Outbound};
+
+use chartered_db::{webhooks::WebhookDelivery, ConnectionPool};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{sync::Arc, time::Duration};
+use tracing::{debug, error};
+use url::Url;
+
+/// Maximum number of deliveries to send out on each tick.
+const BATCH_SIZE: i64 = 50;
+
+/// Periodically sends out any deliveries that are due.
+pub async fn deliver_webhooks(db: ConnectionPool, outbound: Arc<Outbound>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(5));
+
+    loop {
+        interval.tick().await;
+
+        let due = match WebhookDelivery::find_due(db.clone(), BATCH_SIZE).await {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Failed to fetch webhook deliveries: {}", e);
+                continue;
+            }
+        };
+
+        for (delivery, webhook) in due {
+            let start = std::time::Instant::now();
+
+            let res = send(
+                &outbound,
+                &webhook.url,
+                webhook.secret.as_deref().map(str::as_bytes),
+                &delivery.event,
+                &delivery.uuid.to_string(),
+                delivery.payload.clone(),
+            )
+            .await;
+
+            let (status, err) = match res {
+                Ok(status) => (Some(status.as_u16()), None),
+                Err(e) => (None, Some(e.to_string())),
+            };
+
+            debug!(
+                "Attempted webhook delivery {} to {}: {:?} {:?}",
+                delivery.uuid, webhook.url, status, err
+            );
+
+            if let Err(e) = Arc::new(delivery)
+                .record_attempt(db.clone(), status, err, start.elapsed())
+                .await
+            {
+                error!("Failed to record webhook delivery attempt: {}", e);
+            }
+        }
+    }
+}
+
+/// Sends a single delivery, signed if a secret is given, returning the status code the receiver
+/// responded with. The URL is checked again on every delivery as what it resolves to may have
+/// changed since the webhook was created.
+pub async fn send(
+    outbound: &Outbound,
+    url: &str,
+    secret: Option<&[u8]>,
+    event: &str,
+    delivery_id: &str,
+    payload: Vec<u8>,
+) -> Result<reqwest::StatusCode, outbound::Error> {
+    let url = Url::parse(url).map_err(|_| outbound::Error::InvalidUrl)?;
+
+    let mut req = outbound
+        .client(&url)
+        .await?
+        .post(url)
+        .timeout(Duration::from_secs(10))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header("X-Chartered-Event", event)
+        .header("X-Chartered-Delivery", delivery_id);
+
+    if let Some(secret) = secret {
+        req = req.header(
+            "X-Chartered-Signature",
+            format!("sha256={}", sign(secret, &payload)),
+        );
+    }
+
+    let res = req.body(payload).send().await?;
+
+    Ok(res.status())
+}
+
+/// Hex-encoded HMAC-SHA256 of `payload` keyed with `secret`.
+#[must_use]
+pub fn sign(secret: &[u8], payload: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC can take keys of any length");
+    mac.update(payload);
+
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::outbound::Outbound;
+    use axum::{http::HeaderMap, routing::post, Extension, Router};
+    use bytes::Bytes;
+    use std::net::SocketAddr;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn sign() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            super::sign(b"Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[tokio::test]
+    async fn send() {
+        let (tx, mut rx) = mpsc::unbounded_channel::<(HeaderMap, Bytes)>();
+
+        let app = Router::new()
+            .route(
+                "/hook",
+                post(
+                    |Extension(tx): Extension<mpsc::UnboundedSender<(HeaderMap, Bytes)>>,
+                     headers: HeaderMap,
+                     body: Bytes| async move {
+                        tx.send((headers, body)).unwrap();
+                        "ok"
+                    },
+                ),
+            )
+            .layer(Extension(tx));
+
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let payload = br#"{"event":"publish_version"}"#.to_vec();
+
+        let status = super::send(
+            &Outbound::new("chartered-test".to_string(), vec!["127.0.0.1".to_string()]),
+            &format!("http://{}/hook", addr),
+            Some(b"secret"),
+            "publish_version",
+            "a-delivery-id",
+            payload.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, reqwest::StatusCode::OK);
+
+        let (headers, body) = rx.recv().await.unwrap();
+        assert_eq!(&body[..], &payload[..]);
+        assert_eq!(headers["x-chartered-event"], "publish_version");
+        assert_eq!(headers["x-chartered-delivery"], "a-delivery-id");
+        assert_eq!(
+            headers["x-chartered-signature"],
+            format!("sha256={}", super::sign(b"secret", &payload))
+        );
+
+        // webhooks created without a secret get unsigned deliveries
+        super::send(
+            &Outbound::new("chartered-test".to_string(), vec!["127.0.0.1".to_string()]),
+            &format!("http://{}/hook", addr),
+            None,
+            "publish_version",
+            "another-delivery-id",
+            payload.clone(),
+        )
+        .await
+        .unwrap();
+
+        let (headers, _) = rx.recv().await.unwrap();
+        assert_eq!(headers["x-chartered-delivery"], "another-delivery-id");
+        assert!(!headers.contains_key("x-chartered-signature"));
+    }
+}
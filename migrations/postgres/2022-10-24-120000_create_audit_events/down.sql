@@ -0,0 +1,2 @@
+DROP INDEX audit_events_organisation_id;
+DROP TABLE audit_events;
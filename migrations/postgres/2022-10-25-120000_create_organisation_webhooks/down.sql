@@ -0,0 +1,4 @@
+DROP TABLE webhook_delivery_attempts;
+DROP INDEX webhook_deliveries_next_attempt_at;
+DROP TABLE webhook_deliveries;
+DROP TABLE organisation_webhooks;
@@ -0,0 +1,4 @@
+DROP TABLE mirror_crate_files;
+DROP TABLE mirror_index_files;
+ALTER TABLE organisations DROP COLUMN upstream_download_uri;
+ALTER TABLE organisations DROP COLUMN upstream_index_uri;
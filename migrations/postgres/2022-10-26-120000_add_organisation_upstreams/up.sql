@@ -0,0 +1,26 @@
+-- organisations with an upstream set are read-only mirrors of another registry, fetching
+-- index files and crates on demand
+ALTER TABLE organisations ADD COLUMN upstream_index_uri VARCHAR(2048);
+ALTER TABLE organisations ADD COLUMN upstream_download_uri VARCHAR(2048);
+
+CREATE TABLE mirror_index_files (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    organisation_id INTEGER NOT NULL,
+    path VARCHAR(255) NOT NULL,
+    content BYTEA NOT NULL,
+    fetched_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (organisation_id, path),
+    FOREIGN KEY (organisation_id) REFERENCES organisations (id)
+);
+
+CREATE TABLE mirror_crate_files (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    organisation_id INTEGER NOT NULL,
+    crate_name VARCHAR(255) NOT NULL,
+    version VARCHAR(255) NOT NULL,
+    checksum VARCHAR(255) NOT NULL,
+    filesystem_object VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (organisation_id, crate_name, version),
+    FOREIGN KEY (organisation_id) REFERENCES organisations (id)
+);
@@ -0,0 +1,2 @@
+DROP TABLE used_cargo_tokens;
+DROP TABLE user_cargo_keys;
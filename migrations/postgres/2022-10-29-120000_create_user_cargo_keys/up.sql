@@ -0,0 +1,19 @@
+CREATE TABLE user_cargo_keys (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    uuid BYTEA NOT NULL UNIQUE,
+    user_id INTEGER NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    public_key VARCHAR(255) NOT NULL,
+    key_id VARCHAR(255) NOT NULL UNIQUE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+CREATE TABLE used_cargo_tokens (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    expires_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX used_cargo_tokens_expires_at ON used_cargo_tokens (expires_at);
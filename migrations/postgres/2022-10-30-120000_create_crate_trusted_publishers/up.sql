@@ -0,0 +1,15 @@
+CREATE TABLE crate_trusted_publishers (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    uuid BYTEA NOT NULL UNIQUE,
+    crate_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    provider VARCHAR(255) NOT NULL,
+    repository VARCHAR(255) NOT NULL,
+    workflow VARCHAR(255),
+    branch VARCHAR(255),
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (crate_id) REFERENCES crates (id),
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+CREATE INDEX crate_trusted_publishers_crate_id ON crate_trusted_publishers (crate_id);
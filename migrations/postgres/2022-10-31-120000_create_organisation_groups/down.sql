@@ -0,0 +1,3 @@
+DROP TABLE group_crate_permissions;
+DROP TABLE organisation_group_members;
+DROP TABLE organisation_groups;
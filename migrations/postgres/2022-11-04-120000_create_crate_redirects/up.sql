@@ -0,0 +1,15 @@
+-- crates that have been moved to another organisation, so they can still be fetched through
+-- the index of the organisation they were moved from until the redirect expires
+CREATE TABLE crate_redirects (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    organisation_id INTEGER NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    crate_id INTEGER NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (organisation_id, name),
+    FOREIGN KEY (organisation_id) REFERENCES organisations (id),
+    FOREIGN KEY (crate_id) REFERENCES crates (id)
+);
+
+CREATE INDEX crate_redirects_crate_id ON crate_redirects (crate_id);
@@ -0,0 +1,15 @@
+-- versions of deleted crates that can't be published again with different content, so anyone
+-- that has already pulled the deleted version isn't silently handed something else
+CREATE TABLE crate_version_reservations (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    organisation_id INTEGER NOT NULL,
+    crate_name VARCHAR(255) NOT NULL,
+    version VARCHAR(255) NOT NULL,
+    checksum VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (organisation_id, crate_name, version),
+    FOREIGN KEY (organisation_id) REFERENCES organisations (id)
+);
+
+-- existing organisation admins keep the ability to clean up after their members
+UPDATE user_organisation_permissions SET permissions = permissions | 64 WHERE permissions & 32 = 32;
@@ -0,0 +1,6 @@
+UPDATE group_crate_permissions SET permissions = permissions & ~256;
+UPDATE user_crate_permissions SET restricted_permissions = restricted_permissions & ~256;
+UPDATE user_crate_permissions SET permissions = permissions & ~256;
+UPDATE organisation_invites SET permissions = permissions & ~896;
+UPDATE organisation_groups SET permissions = permissions & ~896;
+UPDATE user_organisation_permissions SET permissions = permissions & ~896;
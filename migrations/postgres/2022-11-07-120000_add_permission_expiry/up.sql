@@ -0,0 +1,6 @@
+-- grants that should lapse after a while, such as temporary publish rights for contractors
+ALTER TABLE user_organisation_permissions ADD COLUMN expires_at TIMESTAMP;
+ALTER TABLE user_crate_permissions ADD COLUMN expires_at TIMESTAMP;
+
+CREATE INDEX user_organisation_permissions_expires_at ON user_organisation_permissions (expires_at);
+CREATE INDEX user_crate_permissions_expires_at ON user_crate_permissions (expires_at);
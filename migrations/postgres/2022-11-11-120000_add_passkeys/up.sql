@@ -0,0 +1,15 @@
+-- WebAuthn credentials registered by users, which can be used to log in without a password or
+-- in place of a TOTP code. The passkey itself is stored as JSON, as given to us by webauthn-rs
+CREATE TABLE user_passkeys (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    uuid BYTEA NOT NULL UNIQUE,
+    user_id INTEGER NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    credential_id BYTEA NOT NULL UNIQUE,
+    passkey TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+CREATE INDEX user_passkeys_user_id ON user_passkeys (user_id);
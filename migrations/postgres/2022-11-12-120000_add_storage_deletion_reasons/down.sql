@@ -0,0 +1 @@
+ALTER TABLE storage_deletions DROP COLUMN reason;
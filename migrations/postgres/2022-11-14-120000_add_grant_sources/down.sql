@@ -0,0 +1 @@
+ALTER TABLE user_organisation_permissions DROP COLUMN source;
@@ -0,0 +1,3 @@
+-- where each organisation grant came from, so memberships mapped from a directory's groups
+-- only ever replace or remove grants that were mapped from a directory in the first place
+ALTER TABLE user_organisation_permissions ADD COLUMN source VARCHAR(255) NOT NULL DEFAULT 'manual';
@@ -0,0 +1,2 @@
+UPDATE organisation_webhooks SET secret = '' WHERE secret IS NULL;
+ALTER TABLE organisation_webhooks ALTER COLUMN secret SET NOT NULL;
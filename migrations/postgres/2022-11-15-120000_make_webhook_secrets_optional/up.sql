@@ -0,0 +1,4 @@
+-- webhooks created without a secret are delivered unsigned, rather than signed with an empty
+-- key anyone could forge signatures with
+ALTER TABLE organisation_webhooks ALTER COLUMN secret DROP NOT NULL;
+UPDATE organisation_webhooks SET secret = NULL WHERE secret = '';
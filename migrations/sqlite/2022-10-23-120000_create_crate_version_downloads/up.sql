@@ -0,0 +1,8 @@
+CREATE TABLE crate_version_downloads (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    crate_version_id INTEGER NOT NULL,
+    date DATE NOT NULL,
+    downloads INTEGER NOT NULL DEFAULT 0,
+    UNIQUE (crate_version_id, date),
+    FOREIGN KEY (crate_version_id) REFERENCES crate_versions (id)
+);
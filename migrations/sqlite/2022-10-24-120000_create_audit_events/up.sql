@@ -0,0 +1,17 @@
+-- audit events deliberately don't reference crates/organisations with foreign keys so the
+-- history outlives whatever it was recorded against
+CREATE TABLE audit_events (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    uuid BINARY(128) NOT NULL UNIQUE,
+    action VARCHAR(255) NOT NULL,
+    actor_user_id INTEGER,
+    target_user_id INTEGER,
+    organisation_id INTEGER,
+    crate_id INTEGER,
+    details BLOB NOT NULL,
+    ip VARCHAR(255),
+    user_agent VARCHAR(255),
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX audit_events_organisation_id ON audit_events (organisation_id, created_at);
@@ -0,0 +1,35 @@
+CREATE TABLE organisation_webhooks (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    uuid BINARY(128) NOT NULL UNIQUE,
+    organisation_id INTEGER NOT NULL,
+    url VARCHAR(2048) NOT NULL,
+    secret VARCHAR(255) NOT NULL,
+    events INTEGER NOT NULL DEFAULT 0,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (organisation_id) REFERENCES organisations (id)
+);
+
+CREATE TABLE webhook_deliveries (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    uuid BINARY(128) NOT NULL UNIQUE,
+    webhook_id INTEGER NOT NULL,
+    event VARCHAR(255) NOT NULL,
+    payload BLOB NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at DATETIME,
+    delivered_at DATETIME,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (webhook_id) REFERENCES organisation_webhooks (id)
+);
+
+CREATE INDEX webhook_deliveries_next_attempt_at ON webhook_deliveries (next_attempt_at);
+
+CREATE TABLE webhook_delivery_attempts (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    webhook_delivery_id INTEGER NOT NULL,
+    response_status INTEGER,
+    error TEXT,
+    duration_ms INTEGER NOT NULL,
+    attempted_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (webhook_delivery_id) REFERENCES webhook_deliveries (id)
+);
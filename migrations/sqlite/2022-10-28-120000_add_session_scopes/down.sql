@@ -0,0 +1,2 @@
+ALTER TABLE user_api_tokens DROP COLUMN scope;
+ALTER TABLE user_sessions DROP COLUMN scope;
@@ -0,0 +1,2 @@
+ALTER TABLE user_sessions ADD COLUMN scope BLOB;
+ALTER TABLE user_api_tokens ADD COLUMN scope BLOB;
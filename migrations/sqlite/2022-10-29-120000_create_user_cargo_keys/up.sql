@@ -0,0 +1,19 @@
+CREATE TABLE user_cargo_keys (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    uuid BINARY(128) NOT NULL UNIQUE,
+    user_id INTEGER NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    public_key VARCHAR(255) NOT NULL,
+    key_id VARCHAR(255) NOT NULL UNIQUE,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at DATETIME,
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+CREATE TABLE used_cargo_tokens (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    expires_at DATETIME NOT NULL
+);
+
+CREATE INDEX used_cargo_tokens_expires_at ON used_cargo_tokens (expires_at);
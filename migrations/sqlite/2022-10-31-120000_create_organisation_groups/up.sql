@@ -0,0 +1,30 @@
+CREATE TABLE organisation_groups (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    uuid BINARY(128) NOT NULL UNIQUE,
+    organisation_id INTEGER NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    permissions INTEGER NOT NULL DEFAULT 0,
+    UNIQUE (organisation_id, name),
+    FOREIGN KEY (organisation_id) REFERENCES organisations (id)
+);
+
+CREATE TABLE organisation_group_members (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    group_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    UNIQUE (group_id, user_id),
+    FOREIGN KEY (group_id) REFERENCES organisation_groups (id),
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+CREATE INDEX organisation_group_members_user_id ON organisation_group_members (user_id);
+
+CREATE TABLE group_crate_permissions (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    group_id INTEGER NOT NULL,
+    crate_id INTEGER NOT NULL,
+    permissions INTEGER NOT NULL,
+    UNIQUE (group_id, crate_id),
+    FOREIGN KEY (group_id) REFERENCES organisation_groups (id),
+    FOREIGN KEY (crate_id) REFERENCES crates (id)
+);
@@ -0,0 +1 @@
+ALTER TABLE user_crate_permissions DROP COLUMN restricted_permissions;
@@ -0,0 +1,3 @@
+-- permissions taken away from a user on a specific crate, regardless of whether they were
+-- granted at the crate level, inherited from the organisation or granted through a group
+ALTER TABLE user_crate_permissions ADD COLUMN restricted_permissions INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,19 @@
+-- invites are either addressed to an existing user, or have a single-use link that can be
+-- redeemed by anyone who has it, in which case only a hash of the link's token is stored
+CREATE TABLE organisation_invites (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    uuid BINARY(128) NOT NULL UNIQUE,
+    organisation_id INTEGER NOT NULL,
+    user_id INTEGER,
+    token_hash VARCHAR(255) UNIQUE,
+    permissions INTEGER NOT NULL,
+    invited_by_user_id INTEGER NOT NULL,
+    expires_at DATETIME NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (organisation_id) REFERENCES organisations (id),
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    FOREIGN KEY (invited_by_user_id) REFERENCES users (id)
+);
+
+CREATE INDEX organisation_invites_organisation_id ON organisation_invites (organisation_id);
+CREATE INDEX organisation_invites_user_id ON organisation_invites (user_id);
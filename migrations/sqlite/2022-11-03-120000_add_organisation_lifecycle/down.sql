@@ -0,0 +1,4 @@
+UPDATE user_organisation_permissions SET permissions = permissions & ~32;
+DROP TABLE storage_deletions;
+DROP TABLE organisation_aliases;
+ALTER TABLE organisations DROP COLUMN deleted_at;
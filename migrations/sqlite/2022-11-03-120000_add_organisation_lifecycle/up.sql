@@ -0,0 +1,31 @@
+-- organisations are soft deleted so they can be restored until their storage objects have
+-- been cleaned up
+ALTER TABLE organisations ADD COLUMN deleted_at DATETIME;
+
+-- names an organisation was previously known by, so old index URLs keep resolving after a
+-- rename
+CREATE TABLE organisation_aliases (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    organisation_id INTEGER NOT NULL,
+    name VARCHAR(255) NOT NULL UNIQUE,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (organisation_id) REFERENCES organisations (id)
+);
+
+CREATE INDEX organisation_aliases_organisation_id ON organisation_aliases (organisation_id);
+
+-- storage objects belonging to deleted organisations, which are removed from the file
+-- system once `delete_after` has passed
+CREATE TABLE storage_deletions (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    organisation_id INTEGER NOT NULL,
+    filesystem_object VARCHAR(255) NOT NULL UNIQUE,
+    delete_after DATETIME NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (organisation_id) REFERENCES organisations (id)
+);
+
+CREATE INDEX storage_deletions_delete_after ON storage_deletions (delete_after);
+
+-- existing organisation admins keep the ability to manage the organisation itself
+UPDATE user_organisation_permissions SET permissions = permissions | 32 WHERE permissions & 8 = 8;
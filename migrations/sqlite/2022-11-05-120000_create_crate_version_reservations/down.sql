@@ -0,0 +1,2 @@
+UPDATE user_organisation_permissions SET permissions = permissions & ~64;
+DROP TABLE crate_version_reservations;
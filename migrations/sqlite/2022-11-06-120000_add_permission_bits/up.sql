@@ -0,0 +1,10 @@
+-- webhooks, trusted publishing policies and the audit log were previously all gated behind
+-- MANAGE_USERS, so anyone that had it keeps access to them through their new permissions
+UPDATE user_organisation_permissions SET permissions = permissions | 896 WHERE permissions & 8 = 8;
+UPDATE organisation_groups SET permissions = permissions | 896 WHERE permissions & 8 = 8;
+UPDATE organisation_invites SET permissions = permissions | 896 WHERE permissions & 8 = 8;
+
+-- only trusted publishing policies can be managed on a per-crate basis
+UPDATE user_crate_permissions SET permissions = permissions | 256 WHERE permissions & 8 = 8;
+UPDATE user_crate_permissions SET restricted_permissions = restricted_permissions | 256 WHERE restricted_permissions & 8 = 8;
+UPDATE group_crate_permissions SET permissions = permissions | 256 WHERE permissions & 8 = 8;
@@ -0,0 +1,4 @@
+DROP INDEX user_crate_permissions_expires_at;
+DROP INDEX user_organisation_permissions_expires_at;
+ALTER TABLE user_crate_permissions DROP COLUMN expires_at;
+ALTER TABLE user_organisation_permissions DROP COLUMN expires_at;
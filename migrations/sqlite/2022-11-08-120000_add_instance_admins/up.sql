@@ -0,0 +1,5 @@
+-- instance administrators can manage every user and organisation on the server
+ALTER TABLE users ADD COLUMN admin BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- disabled users can no longer authenticate, but are kept around so their history is intact
+ALTER TABLE users ADD COLUMN disabled_at DATETIME;
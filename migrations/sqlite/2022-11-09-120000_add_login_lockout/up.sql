@@ -0,0 +1,5 @@
+-- consecutive failed password logins, reset on a successful login or once the user is locked
+ALTER TABLE users ADD COLUMN failed_login_attempts INTEGER NOT NULL DEFAULT 0;
+
+-- password logins are refused until this time after too many failed attempts
+ALTER TABLE users ADD COLUMN locked_until DATETIME;
@@ -0,0 +1,5 @@
+ALTER TABLE organisations DROP COLUMN require_two_factor;
+DROP TABLE user_recovery_codes;
+ALTER TABLE users DROP COLUMN totp_last_step;
+ALTER TABLE users DROP COLUMN totp_enabled_at;
+ALTER TABLE users DROP COLUMN totp_secret;
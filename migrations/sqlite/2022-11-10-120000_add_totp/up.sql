@@ -0,0 +1,21 @@
+-- the user's TOTP secret, which is set during enrolment but only enforced once the user has
+-- proven they can generate codes from it and totp_enabled_at is set
+ALTER TABLE users ADD COLUMN totp_secret BLOB;
+ALTER TABLE users ADD COLUMN totp_enabled_at DATETIME;
+
+-- the last time step a code was accepted for, so codes can't be replayed
+ALTER TABLE users ADD COLUMN totp_last_step BIGINT;
+
+-- single use codes that can be used in place of a TOTP code if the user loses their device
+CREATE TABLE user_recovery_codes (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    code_hash VARCHAR(255) NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+CREATE INDEX user_recovery_codes_user_id ON user_recovery_codes (user_id);
+
+-- members of organisations requiring two-factor authentication only get their permissions on
+-- the organisation once they've enabled it
+ALTER TABLE organisations ADD COLUMN require_two_factor BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,11 @@
+-- why each storage object was scheduled for deletion, so restoring an organisation only
+-- cancels the deletions its own deletion scheduled and not those of versions that were
+-- deleted individually
+ALTER TABLE storage_deletions ADD COLUMN reason VARCHAR(255) NOT NULL DEFAULT 'version_delete';
+
+UPDATE storage_deletions SET reason = 'organisation_delete'
+WHERE organisation_id IN (
+    SELECT id FROM organisations
+    WHERE organisations.deleted_at IS NOT NULL
+    AND organisations.deleted_at <= storage_deletions.created_at
+);
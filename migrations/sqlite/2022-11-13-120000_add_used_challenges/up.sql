@@ -0,0 +1,9 @@
+-- login challenges, such as passkey ceremonies, that have already been completed, kept until
+-- the challenge would've expired anyway so they can't be replayed
+CREATE TABLE used_challenges (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    challenge_hash VARCHAR(64) NOT NULL UNIQUE,
+    expires_at DATETIME NOT NULL
+);
+
+CREATE INDEX used_challenges_expires_at ON used_challenges (expires_at);
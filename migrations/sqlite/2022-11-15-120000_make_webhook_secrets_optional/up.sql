@@ -0,0 +1,19 @@
+-- webhooks created without a secret are delivered unsigned, rather than signed with an empty
+-- key anyone could forge signatures with. sqlite can't drop NOT NULL in place
+CREATE TABLE organisation_webhooks_new (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    uuid BINARY(128) NOT NULL UNIQUE,
+    organisation_id INTEGER NOT NULL,
+    url VARCHAR(2048) NOT NULL,
+    secret VARCHAR(255),
+    events INTEGER NOT NULL DEFAULT 0,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (organisation_id) REFERENCES organisations (id)
+);
+
+INSERT INTO organisation_webhooks_new (id, uuid, organisation_id, url, secret, events, created_at)
+SELECT id, uuid, organisation_id, url, NULLIF(secret, ''), events, created_at
+FROM organisation_webhooks;
+
+DROP TABLE organisation_webhooks;
+ALTER TABLE organisation_webhooks_new RENAME TO organisation_webhooks;